@@ -0,0 +1,143 @@
+// Latency and throughput of the server, over HTTP like a client. Besides the criterion
+// estimates every benchmark prints the p50, p95 and p99 of the single calls it timed,
+// warm-up included.
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use mcp_bash_server::McpOAuthStore;
+use mcp_bash_server::common::config::TokenFormat;
+use mcp_bash_server::common::jwt::JwtKeys;
+use rmcp::serde_json::json;
+use tokio::runtime::Runtime;
+
+#[allow(dead_code)]
+#[path = "../tests/integration/support.rs"]
+mod support;
+
+use support::{CLIENT_ID, CLIENT_SECRET, spawn_test_server, test_config};
+
+const SEQUENTIAL_COMMANDS: u64 = 1000;
+const PARALLEL_COMMANDS: u64 = 16;
+
+// The durations of single calls of one benchmark
+#[derive(Default)]
+struct Latencies(Vec<Duration>);
+
+impl Latencies {
+    // Times the call and keeps its duration
+    fn time<T>(&mut self, call: impl FnOnce() -> T) -> Duration {
+        let start = Instant::now();
+        call();
+        let elapsed = start.elapsed();
+        self.0.push(elapsed);
+        elapsed
+    }
+
+    fn report(mut self, name: &str) {
+        self.0.sort();
+        let percentile = |p: usize| self.0[(self.0.len() - 1) * p / 100];
+        println!(
+            "{name}: p50 {:?}, p95 {:?}, p99 {:?} over {} calls",
+            percentile(50),
+            percentile(95),
+            percentile(99),
+            self.0.len()
+        );
+    }
+}
+
+fn commands(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let server = rt.block_on(spawn_test_server(test_config()));
+    let token = rt.block_on(server.client_token(CLIENT_ID, CLIENT_SECRET));
+    let mut session = rt.block_on(server.mcp_session(Some(&token)));
+    let mut execute = |command: &str| {
+        let response = rt.block_on(session.call_tool(
+            "all_execute_via_default_shell",
+            json!({ "command": command }),
+        ));
+        assert!(response["result"].is_object(), "{response}");
+    };
+
+    // (1) one no-op command, from the request to the response
+    let mut latencies = Latencies::default();
+    c.bench_function("dispatch/true", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| latencies.time(|| execute("true"))).sum())
+    });
+    latencies.report("dispatch/true");
+
+    // (2) commands one after the other, as an agent runs them
+    let mut group = c.benchmark_group("sequential");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(SEQUENTIAL_COMMANDS));
+    let mut latencies = Latencies::default();
+    group.bench_function("1000x true", |b| {
+        b.iter(|| {
+            for _ in 0..SEQUENTIAL_COMMANDS {
+                latencies.time(|| execute("true"));
+            }
+        })
+    });
+    group.finish();
+    latencies.report("sequential/1000x true");
+
+    // (3) one run_parallel call starting all commands at once
+    let commands: Vec<_> = (0..PARALLEL_COMMANDS)
+        .map(|_| json!({ "cmd": "true" }))
+        .collect();
+    let mut group = c.benchmark_group("parallel");
+    group.throughput(Throughput::Elements(PARALLEL_COMMANDS));
+    let mut latencies = Latencies::default();
+    group.bench_function("run_parallel 16x true", |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| {
+                    latencies.time(|| {
+                        let response = rt.block_on(
+                            session.call_tool("run_parallel", json!({ "commands": commands })),
+                        );
+                        assert!(response["result"].is_object(), "{response}");
+                    })
+                })
+                .sum()
+        })
+    });
+    group.finish();
+    latencies.report("parallel/run_parallel 16x true");
+}
+
+// (4) the lookup or signature check validate_token_middleware does for every request
+fn token_validation(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("validate_token");
+    for format in [TokenFormat::Opaque, TokenFormat::Jwt] {
+        let mut config = test_config().oauth;
+        let mut store = McpOAuthStore::new(&config);
+        if format == TokenFormat::Jwt {
+            config.jwt.secret = Some("a benchmark secret of at least 32 bytes".to_string());
+            store = store.with_jwt(JwtKeys::new(&config.jwt, "http://bench".to_string()).unwrap());
+        }
+        let token = rt
+            .block_on(store.create_client_credentials_token(CLIENT_ID, Some(CLIENT_SECRET), None))
+            .unwrap()
+            .access_token;
+
+        let name = format!("{format:?}").to_lowercase();
+        let mut latencies = Latencies::default();
+        group.bench_function(&name, |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        latencies
+                            .time(|| assert!(rt.block_on(store.validate_token(&token)).is_some()))
+                    })
+                    .sum()
+            })
+        });
+        latencies.report(&format!("validate_token/{name}"));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, commands, token_validation);
+criterion_main!(benches);
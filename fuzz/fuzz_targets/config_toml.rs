@@ -0,0 +1,10 @@
+#![no_main]
+// Config files: whatever they hold, they parse or fail with an error
+use libfuzzer_sys::fuzz_target;
+use mcp_bash_server::Config;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Config::from_toml(text);
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+// Commands through the checks of all_execute_via_default_shell: the argument
+// interpolation, the sudo policy, the blacklist and the working directory policy. The
+// input is an execute request as JSON or else the command itself. Only dry runs, nothing
+// is executed.
+use std::sync::LazyLock;
+
+use libfuzzer_sys::fuzz_target;
+use mcp_bash_server::common::bash_server::DefaultExecuteRequest;
+use mcp_bash_server::{BashServer, Config};
+use tokio::runtime::Runtime;
+
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+// The example config, with its blacklist and sudo policy
+static SERVER: LazyLock<BashServer> = LazyLock::new(|| {
+    let _runtime = RUNTIME.enter();
+    BashServer::from_config(Config::from_toml(include_str!("../../config.toml")).unwrap())
+});
+
+fuzz_target!(|data: &[u8]| {
+    let request = serde_json::from_slice::<DefaultExecuteRequest>(data).unwrap_or_else(|_| {
+        let command = String::from_utf8_lossy(data);
+        serde_json::from_value(serde_json::json!({ "command": command })).unwrap()
+    });
+    let server = &*SERVER;
+    let _ = RUNTIME.block_on(server.dry_run_command(request));
+});
@@ -0,0 +1,56 @@
+#![no_main]
+// Requests to /mcp through validate_token_middleware. The first line of the input is the
+// Authorization header, the rest the JSON-RPC body whose tool calls are checked against
+// the scopes of the token.
+use std::sync::{Arc, LazyLock};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode, header},
+    middleware,
+    routing::post,
+};
+use libfuzzer_sys::fuzz_target;
+use mcp_bash_server::McpOAuthStore;
+use mcp_bash_server::common::{config::OAuth, jwt::JwtKeys, oauth::validate_token_middleware};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+// A store without tokens that checks JWTs, so opaque lookups and signature checks both run
+static APP: LazyLock<Router> = LazyLock::new(|| {
+    let mut config = OAuth::default();
+    config.jwt.secret = Some("the fuzzing secret, at least 32 bytes".to_string());
+    let keys = JwtKeys::new(&config.jwt, "http://fuzz".to_string()).unwrap();
+    let store = Arc::new(McpOAuthStore::new(&config).with_jwt(keys));
+    Router::new()
+        .route("/mcp", post(|| async { StatusCode::OK }))
+        .layer(middleware::from_fn_with_state(
+            store,
+            validate_token_middleware,
+        ))
+});
+
+fuzz_target!(|data: &[u8]| {
+    let (authorization, body) = match data.iter().position(|&byte| byte == b'\n') {
+        Some(end) => (&data[..end], &data[end + 1..]),
+        None => (data, &[][..]),
+    };
+    // no valid header value
+    let Ok(request) = Request::post("/mcp")
+        .header(header::AUTHORIZATION, authorization)
+        .body(Body::from(body.to_vec()))
+    else {
+        return;
+    };
+    let response = RUNTIME.block_on(APP.clone().oneshot(request)).unwrap();
+    // nothing was issued, so nothing may get through
+    assert_ne!(response.status(), StatusCode::OK);
+});
@@ -0,0 +1,74 @@
+// The command line of the binary
+use anyhow::{Result, bail};
+
+pub const DEFAULT_CONFIG: &str = "config.toml";
+
+pub const USAGE: &str = "\
+Usage: mcp-bash-server [OPTIONS] [COMMAND]
+
+Commands:
+  hash-password        Print a hash for [[oauth.users]] of the password read from stdin
+
+Options:
+  -c, --config <PATH>  The config file to read [default: config.toml]
+      --check          Check the config file, print every error and exit, 1 if there were any
+                       (alias --validate)
+      --init           Ask for the main settings and write a commented config file
+      --non-interactive
+                       With --init, write the defaults without asking
+  -h, --help           Print this help and exit
+  -V, --version        Print the version and exit
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    Check,
+    Init,
+    HashPassword,
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub config: String,
+    pub command: Command,
+    pub non_interactive: bool, // of --init
+}
+
+impl Args {
+    // The arguments without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args> {
+        let mut parsed = Args {
+            config: DEFAULT_CONFIG.to_string(),
+            command: Command::Serve,
+            non_interactive: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.command = Command::Help,
+                "-V" | "--version" => parsed.command = Command::Version,
+                "--check" | "--validate" => parsed.command = Command::Check,
+                "--init" => parsed.command = Command::Init,
+                "--non-interactive" => parsed.non_interactive = true,
+                "-c" | "--config" => match args.next() {
+                    Some(path) => parsed.config = path,
+                    None => bail!("{arg} needs a path\n\n{USAGE}"),
+                },
+                "hash-password" if parsed.command == Command::Serve => {
+                    parsed.command = Command::HashPassword
+                }
+                _ => match arg.strip_prefix("--config=") {
+                    Some(path) => parsed.config = path.to_string(),
+                    None => bail!("unexpected argument {arg:?}\n\n{USAGE}"),
+                },
+            }
+        }
+        if parsed.non_interactive && parsed.command != Command::Init {
+            bail!("--non-interactive only goes with --init\n\n{USAGE}");
+        }
+        Ok(parsed)
+    }
+}
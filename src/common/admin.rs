@@ -0,0 +1,257 @@
+// The /admin endpoints of operators, for the bearer token of [admin]
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{ConnectInfo, Path, State},
+    http::{Request, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::common::audit::{AuditEvent, Outcome, digest_fingerprint};
+use crate::common::oauth::McpOAuthStore;
+
+#[derive(Debug)]
+pub struct AdminState {
+    token_hash: [u8; 32], // the digests are compared, not the token
+    pub oauth_store: Arc<McpOAuthStore>,
+}
+
+pub fn admin_router<S: Clone + Send + Sync + 'static>(
+    token: &str,
+    oauth_store: Arc<McpOAuthStore>,
+) -> Router<S> {
+    let state = Arc::new(AdminState {
+        token_hash: Sha256::digest(token).into(),
+        oauth_store,
+    });
+    Router::new()
+        .route("/admin/lockouts/{username}", delete(clear_lockout))
+        .route("/admin/clients", get(list_clients))
+        .route("/admin/clients/{client_id}", delete(remove_client))
+        // the same under the names of the OAuth section
+        .route("/admin/oauth/clients", get(list_clients))
+        .route("/admin/oauth/clients/{client_id}", delete(remove_client))
+        .route("/admin/tokens", get(list_tokens))
+        .route("/admin/tokens/{fingerprint}", delete(revoke_token))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin_middleware,
+        ))
+        .with_state(state)
+}
+
+async fn admin_middleware(
+    State(state): State<Arc<AdminState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| <[u8; 32]>::from(Sha256::digest(token)) == state.token_hash);
+    if !authorized {
+        warn!(
+            "{} {} from {} without the admin token",
+            request.method(),
+            request.uri().path(),
+            addr.ip()
+        );
+        AuditEvent::new("admin_request", Outcome::Failure)
+            .ip(Some(addr.ip()))
+            .reason(format!(
+                "{} {} without the admin token",
+                request.method(),
+                request.uri().path()
+            ))
+            .emit();
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+// DELETE /admin/lockouts/{username}: the user may log in again at once, 404 if it was
+// not locked
+async fn clear_lockout(
+    State(state): State<Arc<AdminState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(username): Path<String>,
+) -> StatusCode {
+    if state.oauth_store.users.unlock(&username) {
+        AuditEvent::new("account_unlocked", Outcome::Success)
+            .user(Some(&username))
+            .ip(Some(addr.ip()))
+            .reason("by an admin")
+            .emit();
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClientSummary {
+    client_id: String,
+    client_name: Option<String>,
+    created_at: Option<String>, // RFC 3339, none for the clients of the config
+    confidential: bool,         // has a secret
+    scopes: Vec<String>,
+    redirect_uris: Vec<String>,
+    access_tokens: usize,
+    refresh_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registered_with: Option<String>, // fingerprint of the initial access token
+}
+
+// A live token, only its audit fingerprint is shown
+#[derive(Debug, Serialize)]
+struct TokenSummary {
+    fingerprint: String,
+    kind: &'static str, // access or refresh
+    client_id: String,
+    grant_id: String,
+    scope: Option<String>,
+    expires_at: String, // RFC 3339
+}
+
+// GET /admin/clients: the registered clients with the number of their live tokens
+async fn list_clients(State(state): State<Arc<AdminState>>) -> Json<Vec<ClientSummary>> {
+    let store = &state.oauth_store;
+    let now = chrono::Utc::now();
+    let registered: Vec<_> = store.clients.read().await.values().cloned().collect();
+    let refresh_tokens = store.refresh_tokens.read().await;
+    let access_tokens = store.access_tokens.read().await;
+    let mut clients: Vec<_> = registered
+        .into_iter()
+        .map(|client| ClientSummary {
+            confidential: client.client_secret.is_some(),
+            access_tokens: access_tokens
+                .values()
+                .filter(|token| token.client_id == client.client_id && token.expires_at > now)
+                .count(),
+            refresh_tokens: refresh_tokens
+                .values()
+                .filter(|token| {
+                    token.client_id == client.client_id && !token.rotated && token.expires_at > now
+                })
+                .count(),
+            client_id: client.client_id,
+            client_name: client.client_name,
+            created_at: client.created_at.map(rfc3339),
+            registered_with: client.registered_with,
+            scopes: client.scopes,
+            redirect_uris: client.redirect_uris,
+        })
+        .collect();
+    clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    Json(clients)
+}
+
+// GET /admin/tokens: the live access and refresh tokens, soonest to expire first
+async fn list_tokens(State(state): State<Arc<AdminState>>) -> Json<Vec<TokenSummary>> {
+    let store = &state.oauth_store;
+    let now = chrono::Utc::now();
+    let mut tokens: Vec<_> = store
+        .refresh_tokens
+        .read()
+        .await
+        .iter()
+        .filter(|(_, record)| !record.rotated && record.expires_at > now)
+        .map(|(digest, record)| {
+            let summary = TokenSummary {
+                fingerprint: digest_fingerprint(digest).to_string(),
+                kind: "refresh",
+                client_id: record.client_id.clone(),
+                grant_id: record.grant_id.clone(),
+                scope: record.scope.clone(),
+                expires_at: rfc3339(record.expires_at),
+            };
+            (record.expires_at, summary)
+        })
+        .collect();
+    tokens.extend(
+        store
+            .access_tokens
+            .read()
+            .await
+            .iter()
+            .filter(|(_, record)| record.expires_at > now)
+            .map(|(digest, record)| {
+                let summary = TokenSummary {
+                    fingerprint: digest_fingerprint(digest).to_string(),
+                    kind: "access",
+                    client_id: record.client_id.clone(),
+                    grant_id: record.grant_id.clone(),
+                    scope: record.scope.clone(),
+                    expires_at: rfc3339(record.expires_at),
+                };
+                (record.expires_at, summary)
+            }),
+    );
+    tokens.sort_by_key(|(expires_at, _)| *expires_at);
+    Json(tokens.into_iter().map(|(_, summary)| summary).collect())
+}
+
+fn rfc3339(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+// DELETE /admin/clients/{client_id}: the client and all its tokens are gone at once, its
+// JWTs are refused too. 404 if it is not registered.
+async fn remove_client(
+    State(state): State<Arc<AdminState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(client_id): Path<String>,
+) -> StatusCode {
+    if state.oauth_store.remove_client(&client_id).await {
+        AuditEvent::new("client_removed", Outcome::Success)
+            .client(&client_id)
+            .ip(Some(addr.ip()))
+            .reason("by an admin")
+            .emit();
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// DELETE /admin/tokens/{fingerprint}: a fingerprint of GET /admin/tokens or the audit
+// log, a refresh token takes its whole grant along. 404 if no token has it.
+async fn revoke_token(
+    State(state): State<Arc<AdminState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(token_fingerprint): Path<String>,
+) -> StatusCode {
+    match state
+        .oauth_store
+        .revoke_fingerprint(&token_fingerprint.to_ascii_lowercase())
+        .await
+    {
+        Some(client_id) => {
+            info!(
+                "an admin revoked token {} of client {}",
+                token_fingerprint, client_id
+            );
+            AuditEvent::new("token_revoked", Outcome::Success)
+                .client(&client_id)
+                .ip(Some(addr.ip()))
+                .reason(format!("token {token_fingerprint} by an admin"))
+                .emit();
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rmcp::model::{Tool, ToolAnnotations};
+
+use crate::common::config::Annotation;
+
+// Tools that only look at the system
+const READ_ONLY_TOOLS: &[&str] = &[
+    "read_file",
+    "list_directory",
+    "tail_file",
+    "checksum",
+    "git_info",
+    "system_info",
+    "list_processes",
+    "get_env",
+    "pty_read",
+    "list_schedules",
+    "fetch_output_page",
+    "get_command_history",
+    "snapshot_session",
+    "unix_get_available_shell",
+    "unix_get_system_info_via_default_shell",
+    "unix_preset_get_system_info_via_default_shell",
+    "unix_preset_get_nic_info_via_default_shell",
+    "unix_preset_get_cpu_info_via_default_shell",
+    "unix_preset_get_disk_free_info_via_default_shell",
+    "unix_preset_get_top10_cpu_processes_via_default_shell",
+    "unix_preset_get_top10_mem_processes_via_default_shell",
+];
+
+// Tools that change files and can lose data
+const DESTRUCTIVE_TOOLS: &[&str] = &["write_file", "apply_patch", "extract_archive"];
+
+pub fn is_read_only_tool(tool: &str) -> bool {
+    READ_ONLY_TOOLS.contains(&tool)
+}
+
+// Behavior hints of the tools, the built-in ones with [tools.annotations] of the
+// config on top. Tools without an entry (the execute tools) are left unannotated.
+#[derive(Debug, Clone, Default)]
+pub struct ToolAnnotationSet {
+    by_tool: HashMap<String, ToolAnnotations>,
+}
+
+impl ToolAnnotationSet {
+    pub fn new(config: &BTreeMap<String, Annotation>) -> Self {
+        let mut by_tool: HashMap<String, Annotation> = HashMap::new();
+        for tool in READ_ONLY_TOOLS {
+            by_tool.insert(
+                tool.to_string(),
+                Annotation {
+                    read_only: Some(true),
+                    open_world: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+        for tool in DESTRUCTIVE_TOOLS {
+            by_tool.insert(
+                tool.to_string(),
+                Annotation {
+                    read_only: Some(false),
+                    destructive: Some(true),
+                    // writing the same content twice ends in the same state
+                    idempotent: Some(*tool == "write_file"),
+                    open_world: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+        for tool in ["set_session_env", "unset_session_env"] {
+            by_tool.insert(
+                tool.to_string(),
+                Annotation {
+                    read_only: Some(false),
+                    destructive: Some(false),
+                    idempotent: Some(true),
+                    open_world: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+        by_tool.insert(
+            "http_request".to_string(),
+            Annotation {
+                open_world: Some(true),
+                ..Default::default()
+            },
+        );
+
+        // the config overrides single fields
+        for (tool, custom) in config {
+            let annotation = by_tool.entry(tool.clone()).or_default();
+            annotation.title = custom.title.clone().or(annotation.title.take());
+            annotation.read_only = custom.read_only.or(annotation.read_only);
+            annotation.destructive = custom.destructive.or(annotation.destructive);
+            annotation.idempotent = custom.idempotent.or(annotation.idempotent);
+            annotation.open_world = custom.open_world.or(annotation.open_world);
+        }
+
+        ToolAnnotationSet {
+            by_tool: by_tool
+                .into_iter()
+                .map(|(tool, annotation)| {
+                    (
+                        tool,
+                        ToolAnnotations {
+                            title: annotation.title,
+                            read_only_hint: annotation.read_only,
+                            destructive_hint: annotation.destructive,
+                            idempotent_hint: annotation.idempotent,
+                            open_world_hint: annotation.open_world,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn annotate(&self, tools: &mut [Tool]) {
+        for tool in tools {
+            if let Some(annotations) = self.by_tool.get(tool.name.as_ref()) {
+                tool.annotations = Some(annotations.clone());
+            }
+        }
+    }
+}
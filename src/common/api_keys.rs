@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::common::audit::{AuditEvent, Outcome, peer_ip};
+use crate::common::config::ApiKey;
+use crate::common::oauth::{McpAccessToken, insufficient_scope};
+use crate::common::scopes::{READ_SCOPE, has_scope};
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    hash: [u8; 32],
+    scope: String, // space separated
+}
+
+// The keys of auth_mode = "api_key", only their SHA-256 is kept
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: Vec<Entry>,
+}
+
+impl ApiKeyStore {
+    // Keys without scopes get all of scopes_supported
+    pub fn new(keys: &[ApiKey], scopes_supported: &[String]) -> Result<Self> {
+        let keys = keys
+            .iter()
+            .map(|key| {
+                let hash = match (&key.key, &key.key_hash) {
+                    (Some(_), Some(_)) => {
+                        bail!("api key {} has both a key and a key_hash", key.name)
+                    }
+                    (Some(secret), None) if !secret.is_empty() => Sha256::digest(secret).into(),
+                    (None, Some(hash)) => match decode_hash(hash) {
+                        Some(hash) => hash,
+                        None => bail!("the key_hash of api key {} is no SHA-256 in hex", key.name),
+                    },
+                    _ => bail!("api key {} needs a key or a key_hash", key.name),
+                };
+                Ok(Entry {
+                    name: key.name.clone(),
+                    hash,
+                    scope: key.scopes.as_deref().unwrap_or(scopes_supported).join(" "),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ApiKeyStore { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // Every key is compared in constant time, a match takes as long as a miss
+    fn find(&self, key: &str) -> Option<&Entry> {
+        let hash: [u8; 32] = Sha256::digest(key).into();
+        self.keys.iter().fold(None, |found, entry| {
+            let equal = entry
+                .hash
+                .iter()
+                .zip(&hash)
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+            if equal { Some(entry) } else { found }
+        })
+    }
+}
+
+fn decode_hash(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+// Auth middleware of auth_mode = "api_key", in place of validate_token_middleware
+pub async fn api_key_middleware(
+    State(keys): State<Arc<ApiKeyStore>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let entry = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|key| keys.find(key.trim()));
+    let Some(entry) = entry else {
+        debug!("request without a valid api key");
+        AuditEvent::new("token_rejected", Outcome::Failure)
+            .ip(peer_ip(request.extensions()))
+            .reason("no valid api key")
+            .emit();
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    };
+
+    // the scope and permission checks of OAuth tokens apply to the record
+    let token = McpAccessToken::verified(
+        "",
+        entry.name.clone(),
+        entry.scope.clone(),
+        chrono::DateTime::<chrono::Utc>::MAX_UTC,
+        format!("api-key:{}", entry.name),
+    );
+    if !has_scope(&token, READ_SCOPE) {
+        return insufficient_scope(READ_SCOPE);
+    }
+    info!(
+        "{} {} with api key {}",
+        request.method(),
+        request.uri().path(),
+        entry.name
+    );
+    request.extensions_mut().insert(token);
+    next.run(request).await
+}
@@ -0,0 +1,526 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().trim_start_matches('.') {
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" | "gz" => Some(ArchiveFormat::TarGz),
+            "tar.zst" | "tzst" | "zst" => Some(ArchiveFormat::TarZst),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        ["tar.gz", "tar.zst", "tgz", "tzst", "tar", "zip"]
+            .into_iter()
+            .find(|ext| name.ends_with(&format!(".{ext}")))
+            .and_then(Self::parse)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractLimits {
+    pub max_entries: usize,
+    pub max_total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ExtractReport {
+    pub extracted: Vec<String>,
+    pub skipped: Vec<SkippedEntry>,
+    pub total_bytes: u64,
+    // set when a limit stopped the extraction early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stopped: Option<String>,
+}
+
+enum EntryKind {
+    Directory,
+    File(u32),
+    Symlink(PathBuf),
+    Other(&'static str),
+}
+
+struct Extractor {
+    destination: PathBuf, // canonicalized
+    limits: ExtractLimits,
+    overwrite: bool,
+    entries: usize,
+    report: ExtractReport,
+}
+
+// Extract the archive below `destination`, which has to exist. Entries that would
+// end up outside of it (.., absolute paths, symlinks pointing out) are skipped.
+pub fn extract(
+    archive: &Path,
+    format: ArchiveFormat,
+    destination: &Path,
+    limits: ExtractLimits,
+    overwrite: bool,
+) -> io::Result<ExtractReport> {
+    let mut extractor = Extractor {
+        destination: fs::canonicalize(destination)?,
+        limits,
+        overwrite,
+        entries: 0,
+        report: ExtractReport::default(),
+    };
+    let file = File::open(archive)?;
+    let result = match format {
+        ArchiveFormat::Tar => extractor.tar(file),
+        ArchiveFormat::TarGz => extractor.tar(flate2::read::GzDecoder::new(file)),
+        ArchiveFormat::TarZst => extractor.tar(zstd::stream::read::Decoder::new(file)?),
+        ArchiveFormat::Zip => extractor.zip(file),
+    };
+    match result {
+        Ok(()) => {}
+        Err(Stop::Limit(reason)) => extractor.report.stopped = Some(reason),
+        Err(Stop::Io(e)) => return Err(e),
+    }
+    Ok(extractor.report)
+}
+
+// Why the extraction ended early, after a limit the rest of the archive is not read
+enum Stop {
+    Limit(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Io(e)
+    }
+}
+
+impl Extractor {
+    fn tar(&mut self, reader: impl Read) -> Result<(), Stop> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let header = entry.header();
+            let kind = match header.entry_type() {
+                tar::EntryType::Directory => EntryKind::Directory,
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    EntryKind::File(header.mode().unwrap_or(0o644))
+                }
+                tar::EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => EntryKind::Symlink(target.into_owned()),
+                    None => EntryKind::Other("symlink without target"),
+                },
+                tar::EntryType::Link => EntryKind::Other("hard links are not extracted"),
+                // pax and GNU long name headers are handled by the tar crate itself
+                _ => EntryKind::Other("unsupported entry type"),
+            };
+            self.entry(&name, kind, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    fn zip(&mut self, file: File) -> Result<(), Stop> {
+        let mut archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(io::Error::other)?;
+            let name = entry.name().to_string();
+            let kind = if entry.is_dir() {
+                EntryKind::Directory
+            } else if entry.is_symlink() {
+                // the content of a symlink entry is its target
+                let mut target = String::new();
+                entry.by_ref().take(4096).read_to_string(&mut target)?;
+                EntryKind::Symlink(PathBuf::from(target))
+            } else {
+                EntryKind::File(entry.unix_mode().unwrap_or(0o644))
+            };
+            self.entry(&name, kind, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, name: &str, reason: impl Into<String>) {
+        let reason = reason.into();
+        warn!("Skip archive entry {name}: {reason}");
+        self.report.skipped.push(SkippedEntry {
+            path: name.to_string(),
+            reason,
+        });
+    }
+
+    fn entry(&mut self, name: &str, kind: EntryKind, reader: &mut dyn Read) -> Result<(), Stop> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(Stop::Limit(format!(
+                "more than {} entries",
+                self.limits.max_entries
+            )));
+        }
+
+        let Some(relative) = contained_path(Path::new(name)) else {
+            self.skip(name, "path escapes the destination");
+            return Ok(());
+        };
+        if relative.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let target = self.destination.join(&relative);
+        // Never write through a symlink that an earlier entry created
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+            if !fs::canonicalize(parent)?.starts_with(&self.destination) {
+                self.skip(name, "parent directory resolves outside the destination");
+                return Ok(());
+            }
+        }
+        if let Ok(existing) = fs::symlink_metadata(&target)
+            && !matches!(kind, EntryKind::Directory)
+        {
+            if !self.overwrite || existing.is_dir() {
+                self.skip(name, "already exists");
+                return Ok(());
+            }
+            fs::remove_file(&target)?;
+        }
+
+        match kind {
+            EntryKind::Directory => {
+                fs::create_dir_all(&target)?;
+                if !fs::canonicalize(&target)?.starts_with(&self.destination) {
+                    self.skip(name, "directory resolves outside the destination");
+                    return Ok(());
+                }
+            }
+            EntryKind::File(mode) => {
+                let remaining = self
+                    .limits
+                    .max_total_bytes
+                    .saturating_sub(self.report.total_bytes);
+                let mut file = File::create(&target)?;
+                // one byte more than allowed tells a full file from a cut one
+                let written = io::copy(&mut reader.take(remaining + 1), &mut file)?;
+                if written > remaining {
+                    drop(file);
+                    fs::remove_file(&target)?;
+                    return Err(Stop::Limit(format!(
+                        "more than {} bytes decompressed",
+                        self.limits.max_total_bytes
+                    )));
+                }
+                // no setuid/setgid/sticky bits from an archive
+                file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+                self.report.total_bytes += written;
+            }
+            EntryKind::Symlink(link) => {
+                let parent = relative.parent().unwrap_or(Path::new(""));
+                if link.is_absolute() || contained_path(&parent.join(&link)).is_none() {
+                    self.skip(name, "symlink points outside the destination");
+                    return Ok(());
+                }
+                std::os::unix::fs::symlink(&link, &target)?;
+                // the lexical check can be fooled by symlinked components, check the real target
+                let escapes = match fs::canonicalize(&target) {
+                    Ok(resolved) => !resolved.starts_with(&self.destination),
+                    Err(_) => link.components().any(|c| c == Component::ParentDir),
+                };
+                if escapes {
+                    fs::remove_file(&target)?;
+                    self.skip(name, "symlink resolves outside the destination");
+                    return Ok(());
+                }
+            }
+            EntryKind::Other(reason) => {
+                self.skip(name, reason);
+                return Ok(());
+            }
+        }
+        self.report
+            .extracted
+            .push(relative.to_string_lossy().into_owned());
+        Ok(())
+    }
+}
+
+// Normalize an entry path lexically, None if it is absolute or climbs above its start
+fn contained_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Entry {
+        Dir(&'static str),
+        Regular(&'static str, &'static [u8]),
+        Symlink(&'static str, &'static str),
+    }
+    use Entry::*;
+
+    // A destination of its own below the temp dir, removed at the end of the test
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(dir.join("dest")).unwrap();
+            Tree(fs::canonicalize(dir).unwrap())
+        }
+
+        fn path(&self, relative: &str) -> PathBuf {
+            self.0.join(relative)
+        }
+
+        // Write the entries as a tar archive, the names are put in the header as they
+        // are, the tar crate itself refuses `..` and absolute paths
+        fn tar(&self, entries: &[Entry]) -> PathBuf {
+            let path = self.path("archive.tar");
+            let mut builder = tar::Builder::new(File::create(&path).unwrap());
+            for entry in entries {
+                let mut header = tar::Header::new_gnu();
+                let (name, data): (&str, &[u8]) = match *entry {
+                    Dir(name) => {
+                        header.set_entry_type(tar::EntryType::Directory);
+                        (name, b"")
+                    }
+                    Regular(name, data) => {
+                        header.set_entry_type(tar::EntryType::Regular);
+                        (name, data)
+                    }
+                    Symlink(name, target) => {
+                        header.set_entry_type(tar::EntryType::Symlink);
+                        header.as_mut_bytes()[157..157 + target.len()]
+                            .copy_from_slice(target.as_bytes());
+                        (name, b"")
+                    }
+                };
+                header.as_mut_bytes()[..100].fill(0);
+                header.as_mut_bytes()[..name.len()].copy_from_slice(name.as_bytes());
+                header.set_mode(0o755);
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, data).unwrap();
+            }
+            builder.finish().unwrap();
+            path
+        }
+
+        fn zip(&self, entries: &[Entry]) -> PathBuf {
+            use std::io::Write;
+            use zip::write::SimpleFileOptions;
+
+            let path = self.path("archive.zip");
+            let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+            let options = SimpleFileOptions::default();
+            for entry in entries {
+                match *entry {
+                    Dir(name) => writer.add_directory(name, options).unwrap(),
+                    Regular(name, data) => {
+                        writer.start_file(name, options).unwrap();
+                        writer.write_all(data).unwrap();
+                    }
+                    Symlink(name, target) => writer.add_symlink(name, target, options).unwrap(),
+                }
+            }
+            writer.finish().unwrap();
+            path
+        }
+
+        fn extract(&self, archive: &Path, limits: ExtractLimits, overwrite: bool) -> ExtractReport {
+            let format = ArchiveFormat::detect(archive).unwrap();
+            extract(archive, format, &self.path("dest"), limits, overwrite).unwrap()
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const LIMITS: ExtractLimits = ExtractLimits {
+        max_entries: 100,
+        max_total_bytes: 1024,
+    };
+
+    fn skipped(report: &ExtractReport) -> Vec<(&str, &str)> {
+        report
+            .skipped
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.reason.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn escaping_paths_are_skipped() {
+        let entries = [
+            Regular("../x", b"out"),
+            Regular("/etc/x", b"out"),
+            Regular("a/../../x", b"out"),
+            Regular("a/./b/../kept", b"in"),
+        ];
+        for format in ["tar", "zip"] {
+            let tree = Tree::new();
+            let archive = match format {
+                "tar" => tree.tar(&entries),
+                _ => tree.zip(&entries),
+            };
+            let report = tree.extract(&archive, LIMITS, false);
+            assert_eq!(report.extracted, ["a/kept"], "{format}");
+            assert_eq!(
+                skipped(&report),
+                [
+                    ("../x", "path escapes the destination"),
+                    ("/etc/x", "path escapes the destination"),
+                    ("a/../../x", "path escapes the destination"),
+                ],
+                "{format}"
+            );
+            assert!(!tree.path("x").exists(), "{format}");
+            assert_eq!(fs::read(tree.path("dest/a/kept")).unwrap(), b"in");
+        }
+    }
+
+    #[test]
+    fn symlinks_pointing_out_are_refused() {
+        let entries = [
+            Dir("a"),
+            Symlink("a/up", ".."),
+            Symlink("out", "../outside"),
+            Symlink("abs", "/etc"),
+            Symlink("through", "a/up/.."),
+        ];
+        for format in ["tar", "zip"] {
+            let tree = Tree::new();
+            let archive = match format {
+                "tar" => tree.tar(&entries),
+                _ => tree.zip(&entries),
+            };
+            let report = tree.extract(&archive, LIMITS, false);
+            assert_eq!(report.extracted, ["a", "a/up"], "{format}");
+            assert_eq!(
+                skipped(&report),
+                [
+                    ("out", "symlink points outside the destination"),
+                    ("abs", "symlink points outside the destination"),
+                    ("through", "symlink resolves outside the destination"),
+                ],
+                "{format}"
+            );
+            for name in ["out", "abs", "through"] {
+                assert!(fs::symlink_metadata(tree.path("dest").join(name)).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn files_are_not_written_through_earlier_symlinks() {
+        let tree = Tree::new();
+        // `up` points into the destination until `d` is replaced, then it points out
+        let archive = tree.tar(&[
+            Dir("a"),
+            Symlink("d", "a"),
+            Symlink("up", "d/.."),
+            Symlink("d", "."),
+            Regular("up/escaped", b"out"),
+            Dir("up/dir"),
+        ]);
+        let report = tree.extract(&archive, LIMITS, true);
+        assert_eq!(report.extracted, ["a", "d", "up", "d"]);
+        assert_eq!(
+            skipped(&report),
+            [
+                (
+                    "up/escaped",
+                    "parent directory resolves outside the destination"
+                ),
+                (
+                    "up/dir",
+                    "parent directory resolves outside the destination"
+                ),
+            ]
+        );
+        assert!(!tree.path("escaped").exists());
+        assert!(!tree.path("dir").exists());
+    }
+
+    #[test]
+    fn limits_stop_the_extraction() {
+        let entries = [
+            Regular("one", b"123456"),
+            Regular("two", b"123456"),
+            Regular("three", b"1"),
+        ];
+        for format in ["tar", "zip"] {
+            let tree = Tree::new();
+            let archive = match format {
+                "tar" => tree.tar(&entries),
+                _ => tree.zip(&entries),
+            };
+            let limits = ExtractLimits {
+                max_entries: 2,
+                ..LIMITS
+            };
+            let report = tree.extract(&archive, limits, false);
+            assert_eq!(report.extracted, ["one", "two"], "{format}");
+            assert_eq!(report.stopped.as_deref(), Some("more than 2 entries"));
+            assert!(!tree.path("dest/three").exists());
+
+            let tree = Tree::new();
+            let archive = match format {
+                "tar" => tree.tar(&entries),
+                _ => tree.zip(&entries),
+            };
+            let limits = ExtractLimits {
+                max_total_bytes: 10,
+                ..LIMITS
+            };
+            let report = tree.extract(&archive, limits, false);
+            assert_eq!(report.extracted, ["one"], "{format}");
+            assert_eq!(report.total_bytes, 6);
+            assert_eq!(
+                report.stopped.as_deref(),
+                Some("more than 10 bytes decompressed")
+            );
+            // the file cut by the limit is removed again
+            assert!(!tree.path("dest/two").exists(), "{format}");
+            assert!(!tree.path("dest/three").exists(), "{format}");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// The audit log of authentication and authorization, one JSON record per event on the
+// tracing target "audit", which main writes to logs/audit.log as well. Tokens and
+// secrets only appear as fingerprints.
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{extract::ConnectInfo, http::Extensions};
+use rmcp::serde_json;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+pub const TARGET: &str = "audit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure, // bad credentials, codes or tokens
+    Denied,  // refused by a user, a limit or a policy
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEvent {
+    timestamp: String, // RFC 3339
+    event: &'static str,
+    outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>, // fingerprint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(event: &'static str, outcome: Outcome) -> Self {
+        AuditEvent {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            event,
+            outcome,
+            client_id: None,
+            user: None,
+            ip: None,
+            token: None,
+            reason: None,
+        }
+    }
+
+    pub fn client(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string()).filter(|id| !id.is_empty());
+        self
+    }
+
+    pub fn user(mut self, user: Option<&str>) -> Self {
+        self.user = user.filter(|user| !user.is_empty()).map(str::to_string);
+        self
+    }
+
+    pub fn ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    // Only the fingerprint of the token is kept
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(fingerprint(token)).filter(|_| !token.is_empty());
+        self
+    }
+
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    pub fn emit(self) {
+        match serde_json::to_string(&self) {
+            Ok(record) => info!(target: TARGET, "{record}"),
+            Err(e) => info!(target: TARGET, "can't serialize audit event {}: {e}", self.event),
+        }
+    }
+}
+
+// The first 12 hex digits of the SHA-256 of a token or secret, enough to tell them apart
+// in the log and useless to a reader of it
+pub fn fingerprint(secret: &str) -> String {
+    digest_fingerprint(&token_digest(secret)).to_string()
+}
+
+// The SHA-256 of a token in hex, what the OAuth store keeps instead of the token. The
+// tokens are random, so no key is needed to keep them from being found from it.
+pub fn token_digest(token: &str) -> String {
+    Sha256::digest(token)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+// The fingerprint of a token of which only the digest is known
+pub fn digest_fingerprint(digest: &str) -> &str {
+    digest.get(..12).unwrap_or(digest)
+}
+
+// The address of the peer, if the server was started with connect info
+pub fn peer_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
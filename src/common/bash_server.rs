@@ -1,26 +1,70 @@
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use regex::Regex;
 use rmcp::model::Content;
 use rmcp::serde_json;
 use rmcp::{
-    RoleServer, ServerHandler, handler::server::tool::IntoCallToolResult, model::*, schemars,
-    serde_json::Value, service::RequestContext, tool,
+    RoleServer, ServerHandler,
+    handler::server::tool::{IntoCallToolResult, ToolCallContext},
+    model::*,
+    schemars,
+    serde_json::Value,
+    service::{NotificationContext, Peer, RequestContext},
+    tool,
 };
 use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
 use std::process::Output;
+use std::sync::{Arc, OnceLock};
 use std::{
     borrow::Cow,
     env,
     fs::{self, File},
-    io::Write,
-    os::unix::fs::PermissionsExt,
-    process::Command,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::{fs::PermissionsExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::common::config::Config;
+use crate::common::annotations::ToolAnnotationSet;
+use crate::common::archive::{self, ArchiveFormat, ExtractLimits};
+use crate::common::checksum::{self, Algorithm};
+use crate::common::completion;
+use crate::common::config::{Config, Resources};
+use crate::common::env::{EnvRedactor, SessionEnv, is_valid_env_key};
+use crate::common::git::{self, GitError};
+use crate::common::history::{CommandHistory, HistoryFilter};
+use crate::common::host::{HostInfo, host_info};
+use crate::common::http::{HttpPolicy, HttpRequest};
+use crate::common::idempotency::IdempotencyCache;
+use crate::common::output::{LimitMode, LineFilter, OutputLimits, strip_ansi};
+use crate::common::pagination::OutputBuffers;
+use crate::common::patch::{self, FilePatch, HunkResult};
+use crate::common::path_policy::PathPolicy;
+use crate::common::permissions::ToolPermissions;
+use crate::common::pinning::CertificatePins;
+use crate::common::processes::{self, JOB_MARKER_ENV, ProcessFilter, job_marker_value};
+use crate::common::progress::ProgressReporter;
+use crate::common::prompts::PromptLibrary;
+use crate::common::pty::{PtySession, PtySessions};
+use crate::common::resources::{self, ResourceSubscriptions};
+use crate::common::sandbox::LandlockSandbox;
+use crate::common::schedule::Scheduler;
+use crate::common::schema::SchemaValidators;
+use crate::common::scopes::{ScopePolicy, access_token};
+use crate::common::scratch::{DEFAULT_SCRATCH_QUOTA_BYTES, ScratchDir};
+use crate::common::session::{SessionHandle, SessionRegistry};
+use crate::common::shell::{self, ShellSelector};
+use crate::common::snapshot::{JobSnapshot, SessionSnapshot};
+use crate::common::streaming::{self, StreamSettings};
+use crate::common::sudo::SudoPolicy;
+use crate::common::tail::FileFollower;
 use crate::common::validator::Validator;
+use crate::common::webhooks::{WebhookEvent, WebhookSender};
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct DefaultExecuteRequest {
@@ -32,6 +76,448 @@ pub struct DefaultExecuteRequest {
     pub env_vars: Option<std::collections::HashMap<String, String>>,
     #[schemars(description = "Timeout in seconds (default: 30)")]
     pub timeout_seconds: Option<u64>,
+    #[schemars(
+        description = "Shell to run the command with, a name like \"zsh\" or an absolute path (optional)"
+    )]
+    pub shell: Option<String>,
+    #[schemars(description = "Data written to the standard input of the command (optional)")]
+    pub stdin: Option<String>,
+    #[schemars(
+        description = "Write stdout/stderr to files in the session scratch directory and return their paths with a short preview instead of the full output (default: false)"
+    )]
+    pub output_to_file: Option<bool>,
+    #[schemars(
+        description = "Only return the matching lines of stdout and stderr, the files of output_to_file keep the full output (optional)"
+    )]
+    pub output_filter: Option<OutputFilter>,
+    #[schemars(
+        description = "Unique key of this call, a retry with the same key returns the result of the first run instead of running the command again (optional)"
+    )]
+    pub idempotency_key: Option<String>,
+    #[schemars(
+        description = "Keep at most this many lines of stdout and of stderr, the rest is replaced by an elision marker (default: server config)"
+    )]
+    pub max_output_lines: Option<usize>,
+    #[schemars(
+        description = "Which lines survive max_output_lines: head, tail or head_tail (default: head_tail)"
+    )]
+    pub output_limit_mode: Option<LimitMode>,
+    #[schemars(description = "Remove ANSI escape sequences from the output (default: false)")]
+    pub strip_ansi: Option<bool>,
+    #[schemars(
+        description = "Values for the {0}, {1}, ... placeholders of the command, each is inserted shell-quoted (optional)"
+    )]
+    pub args: Option<Vec<String>>,
+    #[schemars(
+        description = "Keep the full output in a buffer and return its first page, fetch_output_page returns the others (default: false)"
+    )]
+    pub paginate: Option<bool>,
+    #[schemars(
+        description = "Run all the checks but do not start the command, the result tells whether it would run (default: false)"
+    )]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, schemars::JsonSchema)]
+pub struct OutputFilter {
+    #[schemars(description = "Keep only lines matching this regex")]
+    pub include: Option<String>,
+    #[schemars(description = "Drop lines matching this regex")]
+    pub exclude: Option<String>,
+    #[schemars(description = "Keep the first N of the remaining lines")]
+    pub head: Option<usize>,
+    #[schemars(
+        description = "Keep the last N of the remaining lines, with head both ends are kept"
+    )]
+    pub tail: Option<usize>,
+    #[schemars(description = "Return at most this many lines")]
+    pub max_lines: Option<usize>,
+}
+
+impl OutputFilter {
+    fn compile(&self) -> Result<LineFilter, ErrorData> {
+        let regex = |pattern: &Option<String>, name: &str| {
+            pattern.as_deref().map(Regex::new).transpose().map_err(|e| {
+                ErrorData::invalid_params(format!("Invalid {name} pattern: {e}"), None)
+            })
+        };
+        Ok(LineFilter {
+            include: regex(&self.include, "include")?,
+            exclude: regex(&self.exclude, "exclude")?,
+            head: self.head,
+            tail: self.tail,
+            max_lines: self.max_lines,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReadFileRequest {
+    #[schemars(description = "Path of the file to read")]
+    pub path: String,
+    #[schemars(description = "Byte offset to start reading at (default: 0)")]
+    pub offset: Option<u64>,
+    #[schemars(description = "Maximum number of bytes to read (default and max: 10 MiB)")]
+    pub length: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListDirectoryRequest {
+    #[schemars(description = "Path of the directory to list")]
+    pub path: String,
+    #[schemars(description = "Also list subdirectories (default: false)")]
+    #[serde(default)]
+    pub recursive: bool,
+    #[schemars(
+        description = "How deep to recurse, 1 lists only the directory (default: 3, max: 16)"
+    )]
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryEntry {
+    pub name: String, // relative to the listed directory
+    pub kind: &'static str,
+    pub size: u64,
+    pub modified_timestamp: Option<u64>,
+    pub permissions: String, // octal, e.g. "755"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_target: Option<String>,
+}
+
+// The host facts are top level fields, next to the server details
+#[derive(Debug, Serialize)]
+pub struct SystemInfoResponse {
+    #[serde(flatten)]
+    pub host: HostInfo,
+    pub server: Value,
+    pub security: Value,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ChecksumRequest {
+    #[schemars(description = "File or directory to hash")]
+    pub path: String,
+    #[schemars(description = "sha256 (default) or blake3")]
+    pub algorithm: Option<String>,
+    #[schemars(
+        description = "Glob patterns of relative paths to leave out of a directory manifest, e.g. [\"target/**\", \"*.log\"]"
+    )]
+    pub excludes: Option<Vec<String>>,
+    #[schemars(description = "Refuse to hash more than this many bytes (default and max: 1 GiB)")]
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ApplyPatchRequest {
+    #[schemars(description = "Unified diff, may contain several files")]
+    pub patch: String,
+    #[schemars(description = "Directory the paths in the diff are relative to")]
+    pub base_dir: String,
+    #[schemars(
+        description = "Leading path components to strip from the diff paths, like patch -p (default: 1 for a/ and b/ prefixes)"
+    )]
+    pub strip: Option<usize>,
+    #[schemars(
+        description = "Only report which hunks would apply, change nothing (default: false)"
+    )]
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilePatchResult {
+    pub path: Option<String>,
+    pub action: &'static str, // create, modify, delete or rename
+    pub status: &'static str, // applied, would_apply, rejected or error
+    pub hunks: Vec<HunkResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetEnvRequest {
+    #[schemars(
+        description = "Only return variables whose name starts with this prefix (optional)"
+    )]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SetSessionEnvRequest {
+    #[schemars(description = "Name of the environment variable")]
+    pub key: String,
+    #[schemars(description = "Value of the environment variable")]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UnsetSessionEnvRequest {
+    #[schemars(description = "Name of the environment variable")]
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RestoreSessionRequest {
+    #[schemars(description = "The snapshot string returned by snapshot_session")]
+    pub snapshot: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ExtractArchiveRequest {
+    #[schemars(description = "Path of the .tar, .tar.gz, .tar.zst or .zip archive")]
+    pub archive_path: String,
+    #[schemars(description = "Directory to extract into, created if missing")]
+    pub destination: String,
+    #[schemars(description = "tar, tar.gz, tar.zst or zip (default: from the file extension)")]
+    pub format: Option<String>,
+    #[schemars(description = "Replace files that already exist (default: false)")]
+    #[serde(default)]
+    pub overwrite: bool,
+    #[schemars(description = "Stop after this many entries (default and max: 10000)")]
+    pub max_entries: Option<usize>,
+    #[schemars(description = "Stop after this many decompressed bytes (default and max: 1 GiB)")]
+    pub max_total_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RunScriptRequest {
+    #[schemars(
+        description = "The script, multiple lines are fine. Give either script, script_uri or script_resource"
+    )]
+    pub script: Option<String>,
+    #[schemars(description = "file:// uri of a resource served by this server holding the script")]
+    pub script_uri: Option<String>,
+    #[schemars(description = "The script as an embedded resource with its uri and text")]
+    pub script_resource: Option<ScriptResource>,
+    #[schemars(description = "Arguments passed to the script as $1, $2, ... (optional)")]
+    pub args: Option<Vec<String>>,
+    #[schemars(
+        description = "Interpreter for the script like \"python3\", \"ruby\" or \"node\", found through /usr/bin/env, not a shell (default: the configured shell with `set -euo pipefail`)"
+    )]
+    pub interpreter: Option<String>,
+    #[schemars(description = "Working directory for the script (optional)")]
+    pub working_dir: Option<String>,
+    #[schemars(description = "Environment variables (optional)")]
+    pub env_vars: Option<std::collections::HashMap<String, String>>,
+    #[schemars(description = "Timeout in seconds (default: 30)")]
+    pub timeout_seconds: Option<u64>,
+    #[schemars(description = "Data written to the standard input of the script (optional)")]
+    pub stdin: Option<String>,
+    #[schemars(
+        description = "Run all the checks but do not start the script, the result tells whether it would run (default: false)"
+    )]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ScriptResource {
+    #[schemars(description = "The uri of the resource")]
+    pub uri: String,
+    #[schemars(description = "The text of the resource")]
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FetchOutputPageRequest {
+    #[schemars(description = "The buffer_id of a call with paginate")]
+    pub buffer_id: String,
+    #[schemars(description = "The page to return, the first page is 0")]
+    pub page_number: usize,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CommandHistoryRequest {
+    #[schemars(description = "Only commands that exited with this code (optional)")]
+    pub exit_code: Option<i32>,
+    #[schemars(description = "Only commands started at or after this RFC 3339 time (optional)")]
+    pub since: Option<String>,
+    #[schemars(description = "Only commands started at or before this RFC 3339 time (optional)")]
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RunParallelRequest {
+    #[schemars(description = "The commands, all of them start at once")]
+    pub commands: Vec<CommandSpec>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CommandSpec {
+    #[schemars(description = "The shell command to run")]
+    pub cmd: String,
+    #[schemars(description = "Working directory for the command (optional)")]
+    pub cwd: Option<String>,
+    #[schemars(description = "Timeout of this command in seconds (default: 30)")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandResult {
+    pub cmd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>, // the execute response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ScheduleCommandRequest {
+    #[schemars(description = "The shell command to run")]
+    pub command: String,
+    #[schemars(description = "Run the command this many seconds from now")]
+    pub delay_seconds: Option<u64>,
+    #[schemars(
+        description = "Run the command at this RFC 3339 time, e.g. 2025-01-31T02:00:00Z (instead of delay_seconds)"
+    )]
+    pub run_at: Option<String>,
+    #[schemars(description = "Working directory for the command (optional)")]
+    pub working_dir: Option<String>,
+    #[schemars(description = "Environment variables (optional)")]
+    pub env_vars: Option<std::collections::HashMap<String, String>>,
+    #[schemars(description = "Timeout in seconds once the command runs (default: 30)")]
+    pub timeout_seconds: Option<u64>,
+    #[schemars(description = "Shell to run the command with (optional)")]
+    pub shell: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ScheduleIdRequest {
+    #[schemars(description = "Id returned by schedule_command")]
+    pub schedule_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GitInfoRequest {
+    #[schemars(description = "A directory inside the repository")]
+    pub path: String,
+    #[schemars(description = "Number of recent commits to return (default: 5, max: 100)")]
+    pub commit_count: Option<usize>,
+    #[schemars(
+        description = "Also return the line counts of the unstaged changes (default: false)"
+    )]
+    #[serde(default)]
+    pub diff_stat: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct HttpRequestParams {
+    #[schemars(description = "HTTP method (default: GET)")]
+    pub method: Option<String>,
+    #[schemars(description = "Absolute http or https url")]
+    pub url: String,
+    #[schemars(description = "Request headers")]
+    pub headers: Option<std::collections::BTreeMap<String, String>>,
+    #[schemars(description = "Request body (optional)")]
+    pub body: Option<String>,
+    #[schemars(description = "Encoding of body: utf-8 (default) or base64")]
+    pub body_encoding: Option<String>,
+    #[schemars(description = "Timeout in seconds (default: 30, max: 300)")]
+    pub timeout_seconds: Option<u64>,
+    #[schemars(
+        description = "Cut the response body after this many bytes (default: 1 MiB, max: 10 MiB)"
+    )]
+    pub max_response_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WriteFileRequest {
+    #[schemars(description = "Path of the file to write")]
+    pub path: String,
+    #[schemars(description = "Content of the file")]
+    pub content: String,
+    #[schemars(description = "Encoding of content: utf-8 (default) or base64")]
+    pub encoding: Option<String>,
+    #[schemars(
+        description = "Unix permissions of the file, e.g. 420 (0o644). Defaults to the mode of the replaced file or 0o644"
+    )]
+    pub mode: Option<u32>,
+    #[schemars(description = "Fail instead of replacing an existing file (default: false)")]
+    #[serde(default)]
+    pub create_only: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListProcessesRequest {
+    #[schemars(description = "Only processes owned by this user name (optional)")]
+    pub user: Option<String>,
+    #[schemars(description = "Only processes whose name contains this substring (optional)")]
+    pub filter_name: Option<String>,
+    #[schemars(
+        description = "Only processes started by this server and their descendants (default: false, forced on by the server config)"
+    )]
+    #[serde(default)]
+    pub server_jobs_only: bool,
+    #[schemars(description = "Number of matching processes to skip (default: 0)")]
+    pub offset: Option<usize>,
+    #[schemars(description = "Maximum number of processes to return (default: 200, max: 1000)")]
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct TailFileRequest {
+    #[schemars(description = "Path of the file to follow")]
+    pub path: String,
+    #[schemars(description = "Number of existing lines to return first (default: 10)")]
+    pub initial_lines: Option<usize>,
+    #[schemars(description = "Seconds to follow the file (default: 10, max: 300)")]
+    pub follow_seconds: Option<u64>,
+    #[schemars(description = "Stop as soon as a new line matches this regex (optional)")]
+    pub until_regex: Option<String>,
+    #[schemars(description = "Stop after this many new bytes (default and max: 1 MiB)")]
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct StartPtySessionRequest {
+    #[schemars(
+        description = "Shell to run in the terminal, default is the configured shell (optional)"
+    )]
+    pub shell: Option<String>,
+    #[schemars(description = "Working directory for the shell (optional)")]
+    pub working_dir: Option<String>,
+    #[schemars(description = "Environment variables (optional)")]
+    pub env_vars: Option<std::collections::HashMap<String, String>>,
+    #[schemars(description = "Terminal rows (default: 24)")]
+    pub rows: Option<u16>,
+    #[schemars(description = "Terminal columns (default: 80)")]
+    pub cols: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PtyWriteRequest {
+    #[schemars(description = "The id returned by start_pty_session")]
+    pub pty_session_id: String,
+    #[schemars(
+        description = "Bytes to send to the terminal, include \"\\n\" to press enter or \"\\u0003\" for Ctrl-C"
+    )]
+    pub input: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PtyReadRequest {
+    #[schemars(description = "The id returned by start_pty_session")]
+    pub pty_session_id: String,
+    #[schemars(description = "Milliseconds to wait for new output (default: 100, max: 10000)")]
+    pub wait_ms: Option<u64>,
+    #[schemars(description = "Remove ANSI escape sequences from the output (default: true)")]
+    pub strip_ansi: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PtyResizeRequest {
+    #[schemars(description = "The id returned by start_pty_session")]
+    pub pty_session_id: String,
+    #[schemars(description = "Terminal rows")]
+    pub rows: u16,
+    #[schemars(description = "Terminal columns")]
+    pub cols: u16,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PtySessionRequest {
+    #[schemars(description = "The id returned by start_pty_session")]
+    pub pty_session_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +527,192 @@ pub struct DefaultExecuteResponse {
     pub exit_code: i32,
     pub success: bool,
     pub parsed_data: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_file: Option<OutputFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_file: Option<OutputFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>, // "cancelled" when the client cancelled the call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suppressed_lines: Option<SuppressedLines>, // set when an output_filter was applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elided_lines: Option<SuppressedLines>, // set when the line or byte limit cut the output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_id: Option<String>, // set with paginate, stdout and stderr hold the first page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<usize>,
+}
+
+// Lines an output filter or limit removed from each stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuppressedLines {
+    pub stdout: usize,
+    pub stderr: usize,
+}
+
+impl DefaultExecuteResponse {
+    pub fn from_output(output: &Output) -> Self {
+        DefaultExecuteResponse {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            exit_code: output.status.code().unwrap_or(-1),
+            success: output.status.success(),
+            parsed_data: Value::Null,
+            stdout_file: None,
+            stderr_file: None,
+            status: None,
+            suppressed_lines: None,
+            elided_lines: None,
+            buffer_id: None,
+            page_count: None,
+        }
+    }
+
+    pub fn cancelled() -> Self {
+        DefaultExecuteResponse {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: -1,
+            success: false,
+            parsed_data: Value::Null,
+            stdout_file: None,
+            stderr_file: None,
+            status: Some("cancelled".to_string()),
+            suppressed_lines: None,
+            elided_lines: None,
+            buffer_id: None,
+            page_count: None,
+        }
+    }
+}
+
+// Output captured to a file in the session scratch directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputFile {
+    pub path: String,
+    pub size: u64,
+    pub head: String,
+    pub tail: String,
+    pub truncated: bool, // the file was cut to fit in the scratch quota
+}
+
+// How much of an output file is shown in the preview
+const OUTPUT_PREVIEW_BYTES: u64 = 1024;
+
+impl OutputFile {
+    fn read(path: &Path, truncated: bool) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let mut head = Vec::new();
+        (&mut file)
+            .take(OUTPUT_PREVIEW_BYTES)
+            .read_to_end(&mut head)?;
+        let mut tail = Vec::new();
+        if size > OUTPUT_PREVIEW_BYTES {
+            file.seek(SeekFrom::Start(
+                size.saturating_sub(OUTPUT_PREVIEW_BYTES)
+                    .max(OUTPUT_PREVIEW_BYTES),
+            ))?;
+            file.read_to_end(&mut tail)?;
+        }
+
+        Ok(OutputFile {
+            path: path.to_string_lossy().to_string(),
+            size,
+            head: String::from_utf8_lossy(&head).to_string(),
+            tail: String::from_utf8_lossy(&tail).to_string(),
+            truncated,
+        })
+    }
+}
+
+// The protocol allows at most 100 completion values
+const MAX_COMPLETIONS: usize = 100;
+
+// Upper bound of a single read_file call
+const MAX_READ_BYTES: u64 = 10 * 1024 * 1024;
+
+// Commands of a single run_parallel call without [settings] max_parallel_commands
+const DEFAULT_MAX_PARALLEL_COMMANDS: usize = 8;
+
+// Upper bound of a script given to run_script
+const MAX_SCRIPT_BYTES: u64 = 1024 * 1024;
+
+// Interpreters run_script refuses, their scripts would skip the validator and the sudo
+// policy. Shell scripts are run without interpreter instead.
+const SHELL_INTERPRETERS: &[&str] = &[
+    "ash", "bash", "busybox", "csh", "dash", "env", "fish", "ksh", "mksh", "sh", "tcsh", "zsh",
+];
+
+// Limits of a single list_directory call
+const DEFAULT_LIST_DEPTH: usize = 3;
+const MAX_LIST_DEPTH: usize = 16;
+const MAX_LIST_ENTRIES: usize = 10_000;
+
+// Limits of a single extract_archive call
+const MAX_ARCHIVE_ENTRIES: usize = 10_000;
+const MAX_ARCHIVE_BYTES: u64 = 1024 * 1024 * 1024;
+
+// Limits of a single http_request call
+const DEFAULT_HTTP_RESPONSE_BYTES: u64 = 1024 * 1024;
+const MAX_HTTP_RESPONSE_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_HTTP_TIMEOUT_SECONDS: u64 = 300;
+
+// Limits of a single checksum call
+const MAX_CHECKSUM_BYTES: u64 = 1024 * 1024 * 1024;
+
+// Limits of a single list_processes call
+const DEFAULT_PROCESS_RESULTS: usize = 200;
+const MAX_PROCESS_RESULTS: usize = 1000;
+
+// Limits of a single tail_file call
+const MAX_TAIL_SECONDS: u64 = 300;
+const MAX_TAIL_BYTES: u64 = 1024 * 1024;
+const TAIL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Where the standard streams of a command are connected
+#[derive(Debug, Default)]
+pub struct CommandIo {
+    pub stdin: Option<String>,
+    pub stdout: Option<File>, // captured in Output when not set
+    pub stderr: Option<File>,
+    pub progress: Option<ProgressReporter>, // streams the captured output
+    pub streaming: StreamSettings,
+    pub cancel: Option<CancellationToken>, // set when the client cancels the tool call
+}
+
+// How a command run ended
+pub enum CommandOutcome {
+    Completed(Output),
+    Cancelled,
+}
+
+// How long a terminated command gets between SIGTERM and SIGKILL
+const TERMINATE_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Send a signal to the process group the command leads
+fn signal_group(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill has no memory safety requirements
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
+
+// SIGTERM the whole process group, SIGKILL it if it is still alive after the grace period
+async fn terminate(child: &mut tokio::process::Child) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+    signal_group(pid, libc::SIGTERM);
+    if tokio::time::timeout(TERMINATE_GRACE, child.wait())
+        .await
+        .is_err()
+    {
+        warn!("Command {pid} ignored SIGTERM, sending SIGKILL");
+        signal_group(pid, libc::SIGKILL);
+        let _ = child.wait().await;
+    }
 }
 
 impl IntoCallToolResult for DefaultExecuteResponse {
@@ -67,6 +739,33 @@ impl IntoCallToolResult for DefaultExecuteResponse {
 #[derive(Debug, Clone)]
 pub struct BashServer {
     validator: Option<Validator>,
+    sandbox: Option<LandlockSandbox>,
+    path_policy: PathPolicy,
+    sudo_policy: SudoPolicy,
+    shell: ShellSelector,
+    pty_sessions: PtySessions,
+    scratch: Arc<ScratchDir>,
+    session: Option<Arc<SessionHandle>>,
+    own_processes_only: bool,
+    session_env: SessionEnv,
+    env_redactor: EnvRedactor,
+    streaming: StreamSettings,
+    http_policy: HttpPolicy,
+    resources: Resources,
+    resource_subscriptions: ResourceSubscriptions,
+    idempotency: IdempotencyCache,
+    prompts: Option<Arc<PromptLibrary>>,
+    annotations: ToolAnnotationSet,
+    scheduler: Option<Arc<Scheduler>>,
+    webhooks: Option<Arc<WebhookSender>>,
+    permissions: ToolPermissions,
+    scopes: ScopePolicy,
+    output_limits: OutputLimits,
+    max_parallel_commands: usize,
+    output_buffers: OutputBuffers,
+    profile: Vec<String>, // run before every shell command
+    history: CommandHistory,
+    restored_jobs: Arc<std::sync::Mutex<Vec<JobSnapshot>>>, // pty sessions of a restored snapshot
 }
 
 pub trait CommandRunner {
@@ -91,462 +790,2494 @@ pub trait CommandRunner {
     async fn execute_command_with_timeout(
         timeout: std::time::Duration,
         mut cmd: Command,
-    ) -> Result<Output, ErrorData> {
+        io: CommandIo,
+    ) -> Result<CommandOutcome, ErrorData> {
         let cmd_str = Self::stringify_command(&cmd);
-        // Execute command with timeout
-        let output = tokio::time::timeout(timeout, async {
-            tokio::task::spawn_blocking(move || cmd.output()).await
-        })
-        .await
-        .map_err(|_| ErrorData {
-            code: ErrorCode::INTERNAL_ERROR,
-            message: Cow::Owned("Command execution timed out".to_string()),
-            data: None,
-        })?
-        .map_err(|e| ErrorData {
+
+        // Wire the standard streams, redirected streams are not captured
+        cmd.stdin(if io.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+        cmd.stdout(io.stdout.map_or_else(Stdio::piped, Stdio::from));
+        cmd.stderr(io.stderr.map_or_else(Stdio::piped, Stdio::from));
+        // Lead a new process group, so terminating reaches everything the command started
+        cmd.process_group(0);
+
+        let mut cmd = tokio::process::Command::from(cmd);
+        cmd.kill_on_drop(true);
+        let mut child = cmd.spawn().map_err(|e| ErrorData {
             code: ErrorCode::INTERNAL_ERROR,
             message: Cow::Owned(format!("Failed to spawn command: {e}")),
             data: None,
-        })?
-        .map_err(|e| ErrorData {
-            code: ErrorCode::INTERNAL_ERROR,
-            message: Cow::Owned(format!("Command execution failed: {e}")),
-            data: None,
         })?;
+        // Feed stdin from another task so a full stdout pipe can not deadlock us
+        if let Some(input) = io.stdin
+            && let Some(mut pipe) = child.stdin.take()
+        {
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                let _ = pipe.write_all(input.as_bytes()).await;
+            });
+        }
+
+        // Execute command with timeout, unless the client cancels it first
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let cancel = io.cancel.unwrap_or_default();
+        let run = async {
+            let (stdout, stderr) =
+                streaming::collect_output(stdout, stderr, io.progress, io.streaming).await?;
+            let status = child.wait().await?;
+            Ok::<_, std::io::Error>(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        };
+        let finished = tokio::select! {
+            result = tokio::time::timeout(timeout, run) => Some(result),
+            _ = cancel.cancelled() => None,
+        };
+
+        let output = match finished {
+            Some(Ok(result)) => result.map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Command execution failed: {e}")),
+                data: None,
+            })?,
+            Some(Err(_)) => {
+                terminate(&mut child).await;
+                return Err(ErrorData {
+                    code: ErrorCode::INTERNAL_ERROR,
+                    message: Cow::Owned("Command execution timed out".to_string()),
+                    data: None,
+                });
+            }
+            None => {
+                terminate(&mut child).await;
+                info!("Cancelled command: {cmd_str}");
+                return Ok(CommandOutcome::Cancelled);
+            }
+        };
 
         // log execution of command
         info!("Execute command: {cmd_str}");
-        Ok(output)
+        Ok(CommandOutcome::Completed(output))
     }
 }
 
 impl CommandRunner for BashServer {}
 
+impl Default for BashServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[tool(tool_box)]
 impl BashServer {
     pub fn new() -> Self {
-        if let Ok(config) = Config::read_config("config.toml")
-            .inspect_err(|e| eprintln!("read config.toml fail, error: {e}"))
-        {
-            let blacklist = config.blacklist;
-            BashServer {
-                validator: Some(Validator::new(blacklist)),
+        match Config::read_config("config.toml") {
+            Ok(config) => Self::from_config(config),
+            Err(e) => {
+                eprintln!("read config.toml fail, error: {e}");
+                Self {
+                    validator: None,
+                    sandbox: None,
+                    path_policy: PathPolicy::default(),
+                    sudo_policy: SudoPolicy::default(),
+                    shell: ShellSelector::default(),
+                    pty_sessions: PtySessions::default(),
+                    scratch: Arc::new(ScratchDir::new(DEFAULT_SCRATCH_QUOTA_BYTES)),
+                    session: None,
+                    own_processes_only: false,
+                    session_env: SessionEnv::default(),
+                    env_redactor: EnvRedactor::default(),
+                    streaming: StreamSettings::default(),
+                    http_policy: HttpPolicy::default(),
+                    resources: Resources::default(),
+                    resource_subscriptions: ResourceSubscriptions::default(),
+                    idempotency: IdempotencyCache::default(),
+                    prompts: None,
+                    annotations: ToolAnnotationSet::new(&Default::default()),
+                    scheduler: None,
+                    webhooks: None,
+                    permissions: ToolPermissions::default(),
+                    scopes: ScopePolicy::default(),
+                    output_limits: OutputLimits::default(),
+                    max_parallel_commands: DEFAULT_MAX_PARALLEL_COMMANDS,
+                    output_buffers: OutputBuffers::default(),
+                    profile: Vec::new(),
+                    history: CommandHistory::default(),
+                    restored_jobs: Arc::default(),
+                }
             }
-        } else {
-            Self { validator: None }
         }
     }
 
-    async fn _all_execute_via_default_shell(
-        &self,
-        need_validate: bool,
-        request: DefaultExecuteRequest,
-    ) -> Result<CallToolResult, ErrorData> {
-        let timeout_duration =
-            std::time::Duration::from_secs(request.timeout_seconds.unwrap_or(30));
-
-        let mut cmd = if cfg!(target_os = "linux") {
-            let mut cmd = Command::new("bash");
-            cmd.arg("-c");
-            cmd
-        } else if cfg!(target_os = "windows") {
-            let mut cmd = Command::new("powershell");
-            cmd.arg("-c");
-            cmd
-        } else {
-            let mut cmd = Command::new("sh");
-            cmd.arg("-c");
-            cmd
-        };
-        cmd.arg(&request.command);
-
-        // Set working directory if provided
-        if let Some(working_dir) = &request.working_dir {
-            cmd.current_dir(working_dir);
-        }
-
-        // Set environment variables if provided
-        if let Some(env_vars) = &request.env_vars {
-            for (key, value) in env_vars {
-                cmd.env(key, value);
-            }
+    // A server set up from the given config instead of config.toml
+    pub fn from_config(config: Config) -> Self {
+        let blacklist = config.blacklist;
+        BashServer {
+            validator: Some(Validator::new(blacklist)),
+            sandbox: config.security.landlock.as_ref().map(LandlockSandbox::new),
+            path_policy: PathPolicy::new(&config.security),
+            sudo_policy: SudoPolicy::new(&config.security),
+            shell: ShellSelector::new(&config.bash),
+            pty_sessions: PtySessions::default(),
+            scratch: Arc::new(ScratchDir::new(
+                config
+                    .bash
+                    .scratch_quota_bytes
+                    .unwrap_or(DEFAULT_SCRATCH_QUOTA_BYTES),
+            )),
+            session: None,
+            own_processes_only: config.security.own_processes_only,
+            session_env: SessionEnv::default(),
+            env_redactor: EnvRedactor::new(&config.security.redact_env_patterns),
+            http_policy: HttpPolicy::new(
+                &config.http,
+                CertificatePins::new(&config.security.pin_certificates),
+            ),
+            resources: config.resources,
+            resource_subscriptions: ResourceSubscriptions::default(),
+            idempotency: IdempotencyCache::new(&config.idempotency),
+            prompts: None,
+            annotations: ToolAnnotationSet::new(&config.tools.annotations),
+            scheduler: None,
+            webhooks: None,
+            permissions: ToolPermissions::new(&config.tools.permissions),
+            scopes: ScopePolicy::new(&config.oauth),
+            output_limits: OutputLimits {
+                max_lines: config.bash.max_output_lines,
+                max_bytes: config.bash.max_output_bytes,
+                mode: config.bash.output_limit_mode.unwrap_or_default(),
+            },
+            max_parallel_commands: config
+                .settings
+                .max_parallel_commands
+                .unwrap_or(DEFAULT_MAX_PARALLEL_COMMANDS),
+            output_buffers: OutputBuffers::new(&config.pagination),
+            profile: shell::profile_snippets(&config.bash),
+            history: CommandHistory::new(config.bash.history_size.unwrap_or(100)),
+            restored_jobs: Arc::default(),
+            streaming: StreamSettings {
+                flush_bytes: config.bash.stream_flush_bytes.unwrap_or(4096).max(1),
+                flush_interval: std::time::Duration::from_millis(
+                    config.bash.stream_flush_ms.unwrap_or(100).max(1),
+                ),
+            },
         }
+    }
 
-        // Validate the commands
-        if let Some(validator) = &self.validator
-            && need_validate
-        {
-            let program = cmd.get_program();
-            let mut full_args: Vec<&OsStr> = vec![program];
-            let args: Vec<&OsStr> = cmd.get_args().collect();
-            full_args.extend(args);
-            validator.is_unsafe_command(full_args)?;
-        }
+    // Track this instance as a live MCP session in the registry
+    pub fn with_session_registry(mut self, registry: &Arc<SessionRegistry>) -> Self {
+        self.session = Some(Arc::new(registry.register()));
+        self
+    }
 
-        let output: Output = Self::execute_command_with_timeout(timeout_duration, cmd).await?;
+    // Run scheduled commands with the scheduler shared by all sessions
+    pub fn with_scheduler(mut self, scheduler: &Arc<Scheduler>) -> Self {
+        self.scheduler = Some(scheduler.clone());
+        self
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-        let success = output.status.success();
+    // Notify the configured webhooks of failed commands
+    pub fn with_webhooks(mut self, webhooks: &Arc<WebhookSender>) -> Self {
+        self.webhooks = Some(webhooks.clone());
+        self
+    }
 
-        let response = DefaultExecuteResponse {
-            stdout,
-            stderr,
-            exit_code,
-            success,
-            parsed_data: Value::Null,
-        };
-        Ok(CallToolResult::success(vec![Content::json(response)?]))
+    // The tools as listed without a config, for stand-ins like the MockBashServer
+    pub fn tools() -> Vec<Tool> {
+        let mut tools = Self::tool_box().list();
+        ToolAnnotationSet::new(&Default::default()).annotate(&mut tools);
+        tools
     }
 
-    #[tool(description = "Execute commands using default shell in all kinds of os")]
-    async fn all_execute_via_default_shell(
+    // Everything all_execute_via_default_shell checks before it runs a command, then the
+    // dry run answer instead of running it. For the fuzz targets.
+    pub async fn dry_run_command(
         &self,
-        #[tool(aggr)] request: DefaultExecuteRequest,
+        mut request: DefaultExecuteRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        self._all_execute_via_default_shell(true, request).await
+        request.dry_run = Some(true);
+        self._all_execute_via_default_shell(true, request, None)
+            .await
     }
 
-    #[tool(description = "Execute a python script")]
-    async fn unix_execute_python(
-        &self,
-        #[tool(aggr)] request: DefaultExecuteRequest,
-    ) -> Result<CallToolResult, ErrorData> {
-        let timeout_duration =
-            std::time::Duration::from_secs(request.timeout_seconds.unwrap_or(30));
+    // The tool box is static, so are the schemas compiled from it
+    pub(crate) fn schema_validators() -> &'static SchemaValidators {
+        static VALIDATORS: OnceLock<SchemaValidators> = OnceLock::new();
+        VALIDATORS.get_or_init(|| SchemaValidators::new(&Self::tool_box().list()))
+    }
 
-        // Check if /usr/bin/env exists before proceeding
-        let env_exists = std::path::Path::new("/usr/bin/env").exists();
-        if !env_exists {
-            return Err(ErrorData {
-                code: ErrorCode::INTERNAL_ERROR,
-                message: Cow::Owned(
-                    "Python execution failed: /usr/bin/env not found on system".to_string(),
+    fn scheduler(&self) -> Result<&Arc<Scheduler>, ErrorData> {
+        self.scheduler.as_ref().ok_or_else(|| {
+            ErrorData::invalid_request("Scheduling is not available on this server", None)
+        })
+    }
+
+    // Serve the prompt templates shared by all sessions
+    pub fn with_prompts(mut self, prompts: &Arc<PromptLibrary>) -> Self {
+        self.prompts = Some(prompts.clone());
+        self
+    }
+
+    // Apply the landlock restriction to the command if configured
+    fn apply_sandbox(&self, cmd: &mut Command) -> Result<(), ErrorData> {
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(cmd)?;
+            info!(
+                "Landlock active for command: {}",
+                Self::stringify_command(cmd)
+            );
+        }
+        Ok(())
+    }
+
+    // Ask the client for its roots and narrow the file tools to them
+    async fn refresh_roots(&self, peer: &Peer<RoleServer>) {
+        let supports_roots = peer
+            .peer_info()
+            .is_some_and(|info| info.capabilities.roots.is_some());
+        if !supports_roots {
+            return;
+        }
+        match peer.list_roots().await {
+            Ok(result) => {
+                let roots: Vec<PathBuf> = result
+                    .roots
+                    .iter()
+                    .filter_map(|root| resources::uri_path(&root.uri))
+                    .collect();
+                self.path_policy.set_client_roots(&roots);
+            }
+            Err(e) => warn!("Failed to list the roots of the client: {e:?}"),
+        }
+    }
+
+    // The file behind a resource uri, it has to pass the read policy and be below
+    // one of the resource directories
+    fn resource_path(&self, uri: &str) -> Result<PathBuf, ErrorData> {
+        let path = resources::uri_path(uri)
+            .ok_or_else(|| ErrorData::invalid_params(format!("{uri} is not a file uri"), None))?;
+        let path = self.path_policy.check_read(&path)?;
+        let listed = self.resources.directories.iter().any(|directory| {
+            fs::canonicalize(directory).is_ok_and(|directory| path.starts_with(directory))
+        });
+        if !listed || !path.is_file() {
+            return Err(ErrorData::resource_not_found(
+                format!("{uri} is not a resource of this server"),
+                None,
+            ));
+        }
+        Ok(path)
+    }
+
+    // The text of the script of run_script and the uri it came from
+    fn script_source(
+        &self,
+        request: &RunScriptRequest,
+    ) -> Result<(String, Option<String>), ErrorData> {
+        let too_large = |uri: &str, size: u64| {
+            ErrorData::invalid_params(
+                format!(
+                    "Script {uri} is {size} bytes, scripts may be at most {MAX_SCRIPT_BYTES} bytes"
                 ),
-                data: None,
-            });
+                Some(serde_json::json!({ "uri": uri, "size": size })),
+            )
+        };
+        match (
+            &request.script,
+            &request.script_uri,
+            &request.script_resource,
+        ) {
+            (Some(script), None, None) => Ok((script.clone(), None)),
+            (None, Some(uri), None) => {
+                // served by this server and inside the jail, or it is refused
+                let path = self.resource_path(uri)?;
+                let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+                if size > MAX_SCRIPT_BYTES {
+                    return Err(too_large(uri, size));
+                }
+                let script = fs::read_to_string(&path).map_err(|e| {
+                    ErrorData::invalid_params(
+                        format!("Script {uri} can not be read as text: {e}"),
+                        Some(serde_json::json!({ "uri": uri })),
+                    )
+                })?;
+                Ok((script, Some(uri.clone())))
+            }
+            (None, None, Some(resource)) => {
+                let size = resource.text.len() as u64;
+                if size > MAX_SCRIPT_BYTES {
+                    return Err(too_large(&resource.uri, size));
+                }
+                Ok((resource.text.clone(), Some(resource.uri.clone())))
+            }
+            _ => Err(ErrorData::invalid_params(
+                "Give exactly one of script, script_uri and script_resource",
+                None,
+            )),
+        }
+    }
+
+    // Map a path of the diff below the base directory, refusing absolute and parent components
+    fn patch_target(&self, base: &Path, diff_path: &str, strip: usize) -> Result<PathBuf, String> {
+        let relative: PathBuf = Path::new(diff_path).components().skip(strip).collect();
+        if relative.as_os_str().is_empty()
+            || relative
+                .components()
+                .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(format!(
+                "{diff_path} does not stay below the base directory"
+            ));
         }
+        self.path_policy
+            .check_write(base.join(relative))
+            .map_err(|e| e.message.to_string())
+    }
 
-        let mut cmd = Command::new("/usr/bin/env");
-        cmd.arg("python3").arg("-c").arg(&request.command);
+    fn apply_file_patch(
+        &self,
+        base: &Path,
+        file_patch: &FilePatch,
+        strip: usize,
+        dry_run: bool,
+    ) -> FilePatchResult {
+        let (action, display) = match (&file_patch.old_path, &file_patch.new_path) {
+            (None, Some(new)) => ("create", new),
+            (Some(old), None) => ("delete", old),
+            (Some(old), Some(new))
+                if Path::new(old)
+                    .components()
+                    .skip(strip)
+                    .ne(Path::new(new).components().skip(strip)) =>
+            {
+                ("rename", new)
+            }
+            (Some(_), Some(new)) => ("modify", new),
+            (None, None) => {
+                return FilePatchResult {
+                    path: None,
+                    action: "modify",
+                    status: "error",
+                    hunks: Vec::new(),
+                    error: Some("both sides of the diff are /dev/null".to_string()),
+                };
+            }
+        };
+        let failed = |error: String| FilePatchResult {
+            path: Some(display.clone()),
+            action,
+            status: "error",
+            hunks: Vec::new(),
+            error: Some(error),
+        };
+
+        let source = match &file_patch.old_path {
+            Some(old) => match self.patch_target(base, old, strip) {
+                Ok(path) => Some(path),
+                Err(e) => return failed(e),
+            },
+            None => None,
+        };
+        let target = match &file_patch.new_path {
+            Some(new) => match self.patch_target(base, new, strip) {
+                Ok(path) => Some(path),
+                Err(e) => return failed(e),
+            },
+            None => None,
+        };
+
+        let (content, mode) = match &source {
+            Some(source) => match fs::read_to_string(source) {
+                Ok(content) => {
+                    let mode = fs::metadata(source)
+                        .map(|meta| meta.permissions().mode() & 0o7777)
+                        .unwrap_or(0o644);
+                    (content, mode)
+                }
+                Err(e) => return failed(format!("can not read {}: {e}", source.display())),
+            },
+            None => (String::new(), 0o644),
+        };
+        if source.as_ref() != target.as_ref()
+            && let Some(target) = &target
+            && target.exists()
+        {
+            return failed(format!("{} already exists", target.display()));
+        }
+
+        let (patched, hunks) = patch::apply(&content, file_patch);
+        let Some(patched) = patched else {
+            return FilePatchResult {
+                path: Some(display.clone()),
+                action,
+                status: "rejected",
+                hunks,
+                error: Some("not every hunk applied, the file was left unchanged".to_string()),
+            };
+        };
+        if target.is_none() && !patched.is_empty() {
+            return FilePatchResult {
+                path: Some(display.clone()),
+                action,
+                status: "rejected",
+                hunks,
+                error: Some("the file is not empty after removing the deleted lines".to_string()),
+            };
+        }
+        if dry_run {
+            return FilePatchResult {
+                path: Some(display.clone()),
+                action,
+                status: "would_apply",
+                hunks,
+                error: None,
+            };
+        }
+
+        let written = match &target {
+            Some(target) => target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| write_atomically(target, patched.as_bytes(), mode)),
+            None => Ok(()),
+        }
+        .and_then(|_| match &source {
+            Some(source) if target.as_ref() != Some(source) => fs::remove_file(source),
+            _ => Ok(()),
+        });
+        if let Err(e) = written {
+            return failed(format!("can not write the patched file: {e}"));
+        }
+        info!("Patched {display} ({action})");
+        FilePatchResult {
+            path: Some(display.clone()),
+            action,
+            status: "applied",
+            hunks,
+            error: None,
+        }
+    }
 
+    fn get_pty_session(&self, pty_session_id: &str) -> Result<Arc<PtySession>, ErrorData> {
+        self.pty_sessions.get(pty_session_id).ok_or_else(|| {
+            ErrorData::invalid_params(format!("pty session {pty_session_id} not found"), None)
+        })
+    }
+
+    // Set the working directory and environment variables of the request
+    fn prepare_command(
+        &self,
+        cmd: &mut Command,
+        request: &DefaultExecuteRequest,
+    ) -> Result<(), ErrorData> {
         // Set working directory if provided
         if let Some(working_dir) = &request.working_dir {
-            cmd.current_dir(working_dir);
+            cmd.current_dir(self.path_policy.check(working_dir)?);
         }
 
+        // Session variables first, so the request can override them
+        cmd.envs(self.session_env.snapshot());
+
         // Set environment variables if provided
         if let Some(env_vars) = &request.env_vars {
             for (key, value) in env_vars {
                 cmd.env(key, value);
             }
         }
+        // Set last so list_processes can always recognize the job
+        cmd.env(JOB_MARKER_ENV, job_marker_value());
+        Ok(())
+    }
 
-        let output: Output = Self::execute_command_with_timeout(timeout_duration, cmd).await?;
-
-        // log the execution of python
-        info!("Execute python script: {}", &request.command);
-
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-        let success = output.status.success();
-
-        let response = DefaultExecuteResponse {
-            stdout,
-            stderr,
-            exit_code,
-            success,
-            parsed_data: Value::Null,
+    // Run a prepared command and build the tool result
+    async fn run_command(
+        &self,
+        cmd: Command,
+        request: &DefaultExecuteRequest,
+        context: Option<&RequestContext<RoleServer>>,
+    ) -> Result<CallToolResult, ErrorData> {
+        // a dry run is not worth remembering
+        let key = request
+            .idempotency_key
+            .as_ref()
+            .filter(|_| !request.dry_run.unwrap_or(false));
+        let Some(key) = key else {
+            return self.run_command_once(cmd, request, context).await;
         };
-        Ok(CallToolResult::success(vec![Content::json(response)?]))
+        // the final command line and what else the call chose, so a reused key with
+        // another call is caught
+        let env_vars: Option<std::collections::BTreeMap<_, _>> =
+            request.env_vars.as_ref().map(|vars| vars.iter().collect());
+        let fingerprint = format!(
+            "{} in {:?} with {:?}, env {:?}, shell {:?}",
+            Self::stringify_command(&cmd),
+            cmd.get_current_dir(),
+            request.stdin,
+            env_vars,
+            request.shell
+        );
+        self.idempotency
+            .run(key, fingerprint, || {
+                self.run_command_once(cmd, request, context)
+            })
+            .await
     }
 
-    #[tool(description = "Execute a unix script")]
-    async fn unix_execute_script(
+    async fn run_command_once(
         &self,
-        #[tool(aggr)] request: DefaultExecuteRequest,
+        mut cmd: Command,
+        request: &DefaultExecuteRequest,
+        context: Option<&RequestContext<RoleServer>>,
     ) -> Result<CallToolResult, ErrorData> {
         let timeout_duration =
             std::time::Duration::from_secs(request.timeout_seconds.unwrap_or(30));
+        self.apply_sandbox(&mut cmd)?;
+        // a bad pattern is refused before anything runs
+        let filter = request
+            .output_filter
+            .as_ref()
+            .map(OutputFilter::compile)
+            .transpose()?;
 
-        // Write the string to a temporary file
-        let tmp_dir = env::temp_dir();
-        // Generate a random script name
-        let tmp_name = Uuid::new_v4().to_string();
-        let script_path = tmp_dir.join(format!("{tmp_name}.sh"));
-        {
-            let mut file =
-                File::create(&script_path).expect("Can not create temporary script file");
-            file.write_all(request.command.as_bytes())
-                .expect("Write to script file error");
-            // Set execution mode
-            let mut perms = file.metadata().unwrap().permissions();
-            perms.set_mode(0o755);
-            fs::set_permissions(&script_path, perms).expect("Set mode 755 fail");
+        if request.dry_run.unwrap_or(false) {
+            return self.dry_run(&cmd, request);
         }
 
-        let mut cmd = Command::new(&script_path);
+        let mut io = CommandIo {
+            stdin: request.stdin.clone(),
+            progress: context.map(ProgressReporter::new),
+            streaming: self.streaming,
+            cancel: context.map(|context| context.ct.clone()),
+            ..Default::default()
+        };
 
-        // Set working directory if provided
-        if let Some(working_dir) = &request.working_dir {
-            cmd.current_dir(working_dir);
-        }
+        // Redirect the output into the scratch directory if asked
+        let output_paths = if request.output_to_file.unwrap_or(false) {
+            self.check_scratch_quota()?;
+            let name = Uuid::new_v4().to_string();
+            let (stdout_path, stdout) = self
+                .scratch
+                .create_file(&format!("{name}.stdout"))
+                .map_err(Self::scratch_error)?;
+            let (stderr_path, stderr) = self
+                .scratch
+                .create_file(&format!("{name}.stderr"))
+                .map_err(Self::scratch_error)?;
+            io.stdout = Some(stdout);
+            io.stderr = Some(stderr);
+            Some((stdout_path, stderr_path))
+        } else {
+            None
+        };
 
-        // Set environment variables if provided
-        if let Some(env_vars) = &request.env_vars {
-            for (key, value) in env_vars {
-                cmd.env(key, value);
+        let cwd = cmd
+            .get_current_dir()
+            .map(Path::to_path_buf)
+            .or_else(|| env::current_dir().ok());
+        let started = chrono::Utc::now();
+        let outcome = Self::execute_command_with_timeout(timeout_duration, cmd, io).await;
+        let exit_code = match &outcome {
+            Ok(CommandOutcome::Completed(output)) => output.status.code(),
+            _ => None,
+        };
+        self.notify_command_failed(request, cwd.as_deref(), started, &outcome);
+        self.history
+            .record(request.command.clone(), cwd, exit_code, started);
+
+        let output = match outcome? {
+            CommandOutcome::Completed(output) => output,
+            CommandOutcome::Cancelled => {
+                return Ok(CallToolResult {
+                    content: vec![Content::json(DefaultExecuteResponse::cancelled())?],
+                    is_error: Some(true),
+                });
+            }
+        };
+        let mut response = DefaultExecuteResponse::from_output(&output);
+
+        if let Some((stdout_path, stderr_path)) = output_paths {
+            let truncated = self
+                .scratch
+                .enforce_quota(&[&stdout_path, &stderr_path])
+                .map_err(Self::scratch_error)?;
+            response.stdout_file =
+                Some(OutputFile::read(&stdout_path, truncated).map_err(Self::scratch_error)?);
+            response.stderr_file =
+                Some(OutputFile::read(&stderr_path, truncated).map_err(Self::scratch_error)?);
+            // the files keep everything, the filtered lines are returned inline
+            if filter.is_some() {
+                response.stdout = read_lossy(&stdout_path).map_err(Self::scratch_error)?;
+                response.stderr = read_lossy(&stderr_path).map_err(Self::scratch_error)?;
             }
         }
 
-        let output: Output = Self::execute_command_with_timeout(timeout_duration, cmd).await?;
-        info!("Execute script:\n{}", request.command);
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-        let success = output.status.success();
-
-        let response = DefaultExecuteResponse {
-            stdout,
-            stderr,
-            exit_code,
-            success,
-            parsed_data: Value::Null,
+        // strip first, a colored line is still one line
+        if request.strip_ansi.unwrap_or(false) {
+            response.stdout = strip_ansi(&response.stdout);
+            response.stderr = strip_ansi(&response.stderr);
+        }
+
+        if let Some(filter) = filter {
+            let (stdout, stdout_suppressed) = filter.apply(&response.stdout);
+            let (stderr, stderr_suppressed) = filter.apply(&response.stderr);
+            response.stdout = stdout;
+            response.stderr = stderr;
+            response.suppressed_lines = Some(SuppressedLines {
+                stdout: stdout_suppressed,
+                stderr: stderr_suppressed,
+            });
+        }
+
+        // the buffer keeps the whole output, the limits do not apply to it
+        if request.paginate.unwrap_or(false) {
+            let page = self.output_buffers.store(
+                std::mem::take(&mut response.stdout),
+                std::mem::take(&mut response.stderr),
+            );
+            response.stdout = page.stdout;
+            response.stderr = page.stderr;
+            response.buffer_id = Some(page.buffer_id);
+            response.page_count = Some(page.page_count);
+            return Ok(CallToolResult::success(vec![Content::json(response)?]));
+        }
+
+        let limits = OutputLimits {
+            max_lines: request.max_output_lines.or(self.output_limits.max_lines),
+            max_bytes: self.output_limits.max_bytes,
+            mode: request.output_limit_mode.unwrap_or(self.output_limits.mode),
         };
+        if !limits.is_unlimited() {
+            let (stdout, stdout_elided) = limits.apply(&response.stdout);
+            let (stderr, stderr_elided) = limits.apply(&response.stderr);
+            response.stdout = stdout;
+            response.stderr = stderr;
+            if stdout_elided + stderr_elided > 0 {
+                response.elided_lines = Some(SuppressedLines {
+                    stdout: stdout_elided,
+                    stderr: stderr_elided,
+                });
+            }
+        }
+
         Ok(CallToolResult::success(vec![Content::json(response)?]))
     }
 
-    #[tool(description = "Get system information using bash commands")]
-    async fn unix_get_system_info_via_default_shell(&self) -> Result<CallToolResult, ErrorData> {
-        let command = r#"
-echo "=== System Information ==="
-echo "Hostname: $(hostname)"
-echo "OS: $(uname -s)"
-echo "Kernel: $(uname -r)"
-echo "Architecture: $(uname -m)"
-echo "Uptime: $(uptime)"
-echo "Current User: $(whoami)"
-echo "Current Directory: $(pwd)"
-echo "Date: $(date)"
-echo "Memory Usage:"
-free -h 2>/dev/null || echo "free command not available"
-echo "Disk Usage:"
-df -h / 2>/dev/null || echo "df command not available"
-        "#;
+    // A non-zero exit, a timeout or a failed start, a cancelled call is not a failure
+    fn notify_command_failed(
+        &self,
+        request: &DefaultExecuteRequest,
+        cwd: Option<&Path>,
+        started: chrono::DateTime<chrono::Utc>,
+        outcome: &Result<CommandOutcome, ErrorData>,
+    ) {
+        let Some(webhooks) = &self.webhooks else {
+            return;
+        };
+        let (exit_code, error) = match outcome {
+            Ok(CommandOutcome::Completed(output)) if !output.status.success() => {
+                (output.status.code(), None)
+            }
+            Err(e) => (None, Some(e.message.to_string())),
+            _ => return,
+        };
+        webhooks.send(
+            WebhookEvent::CommandFailed,
+            serde_json::json!({
+                "session_id": self.session.as_ref().map(|session| session.id()),
+                "command": request.command,
+                "working_dir": cwd,
+                "exit_code": exit_code,
+                "error": error,
+                "duration_ms": (chrono::Utc::now() - started).num_milliseconds().max(0),
+            }),
+        );
+    }
 
-        self._all_execute_via_default_shell(
-            false,
-            DefaultExecuteRequest {
-                command: command.to_string(),
-                working_dir: None,
+    fn check_scratch_quota(&self) -> Result<(), ErrorData> {
+        if self.scratch.remaining_bytes() == 0 {
+            return Err(ErrorData::invalid_request(
+                format!(
+                    "Scratch directory quota of {} bytes is exhausted",
+                    self.scratch.quota_bytes()
+                ),
+                None,
+            ));
+        }
+        Ok(())
+    }
+
+    // The checks a run would fail on that are left once the command is built
+    fn dry_run(
+        &self,
+        cmd: &Command,
+        request: &DefaultExecuteRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        if let Some(dir) = cmd.get_current_dir()
+            && !dir.is_dir()
+        {
+            return Err(ErrorData::invalid_params(
+                format!("Working directory {} is not a directory", dir.display()),
+                None,
+            ));
+        }
+        if request.output_to_file.unwrap_or(false) {
+            self.check_scratch_quota()?;
+        }
+        let command = Self::stringify_command(cmd);
+        info!("Dry run of command: {command}");
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "would_execute": true,
+                "command": command,
+                "working_dir": cmd.get_current_dir(),
+                "timeout_seconds": request.timeout_seconds.unwrap_or(30),
+            }),
+        )?]))
+    }
+
+    fn scratch_error(e: std::io::Error) -> ErrorData {
+        ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Scratch directory error: {e}")),
+            data: None,
+        }
+    }
+
+    async fn _all_execute_via_default_shell(
+        &self,
+        need_validate: bool,
+        request: DefaultExecuteRequest,
+        context: Option<&RequestContext<RoleServer>>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let cmd = self.shell_command(need_validate, &request)?;
+        self.run_command(cmd, &request, context).await
+    }
+
+    // The shell invocation of a request, with the sudo policy and the validator applied
+    fn shell_command(
+        &self,
+        need_validate: bool,
+        request: &DefaultExecuteRequest,
+    ) -> Result<Command, ErrorData> {
+        let (mut cmd, command) = self.checked_command(need_validate, request)?;
+        // the profile is server config, it is run but not validated
+        let profile: String = self
+            .profile
+            .iter()
+            .map(|snippet| {
+                if snippet.contains('\n') {
+                    // a group, so the command runs after the whole snippet
+                    format!("{{ {snippet}\n}} && ")
+                } else {
+                    format!("{snippet} && ")
+                }
+            })
+            .collect();
+        cmd.arg(format!("{profile}{command}"));
+        self.prepare_command(&mut cmd, request)?;
+        Ok(cmd)
+    }
+
+    // The shell with its `-c` and the command that passed the checks, still to be added
+    fn checked_command(
+        &self,
+        need_validate: bool,
+        request: &DefaultExecuteRequest,
+    ) -> Result<(Command, String), ErrorData> {
+        let mut cmd = if cfg!(target_os = "windows") {
+            let mut cmd = Command::new("powershell");
+            cmd.arg("-c");
+            cmd
+        } else {
+            let mut cmd = Command::new(self.shell.select(request.shell.as_deref())?);
+            cmd.arg("-c");
+            cmd
+        };
+
+        // Substitute the args first, the final command is what gets checked and logged
+        let command = match &request.args {
+            Some(args) => shell::interpolate_args(&request.command, args)?,
+            None => request.command.clone(),
+        };
+        // Enforce the sudo policy, elevated commands are rewritten to be non-interactive
+        let command = if need_validate {
+            self.sudo_policy.enforce(&command)?
+        } else {
+            command
+        };
+
+        // Validate the commands
+        if let Some(validator) = &self.validator
+            && need_validate
+        {
+            let program = cmd.get_program();
+            let mut full_args: Vec<&OsStr> = vec![program];
+            let args: Vec<&OsStr> = cmd.get_args().collect();
+            full_args.extend(args);
+            full_args.push(OsStr::new(&command));
+            validator.is_unsafe_command(full_args)?;
+        }
+        Ok((cmd, command))
+    }
+
+    #[tool(description = "Execute commands using default shell in all kinds of os")]
+    async fn all_execute_via_default_shell(
+        &self,
+        #[tool(aggr)] request: DefaultExecuteRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self._all_execute_via_default_shell(true, request, Some(&context))
+            .await
+    }
+
+    #[tool(
+        description = "Fetch a page of the output of an execute call made with paginate, by its buffer_id. Buffers expire after a while"
+    )]
+    async fn fetch_output_page(
+        &self,
+        #[tool(aggr)] request: FetchOutputPageRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let page = self
+            .output_buffers
+            .page(&request.buffer_id, request.page_number)?;
+        info!(
+            "Fetch page {} of {} of output buffer {}",
+            page.page_number, page.page_count, page.buffer_id
+        );
+        Ok(CallToolResult::success(vec![Content::json(page)?]))
+    }
+
+    #[tool(
+        description = "Run several independent shell commands at the same time, each with its own working directory and timeout. Returns the results in the order of the commands once all of them are done"
+    )]
+    async fn run_parallel(
+        &self,
+        #[tool(aggr)] request: RunParallelRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        if request.commands.is_empty() || request.commands.len() > self.max_parallel_commands {
+            return Err(ErrorData::invalid_params(
+                format!(
+                    "Give 1 to {} commands (max_parallel_commands)",
+                    self.max_parallel_commands
+                ),
+                None,
+            ));
+        }
+
+        // Every command is checked before the first one starts
+        let mut runs = Vec::with_capacity(request.commands.len());
+        for spec in request.commands {
+            let execute = DefaultExecuteRequest {
+                command: spec.cmd,
+                working_dir: spec.cwd,
                 env_vars: None,
-                timeout_seconds: Some(10),
-            },
-        )
-        .await
+                timeout_seconds: spec.timeout,
+                shell: None,
+                stdin: None,
+                output_to_file: None,
+                output_filter: None,
+                idempotency_key: None,
+                max_output_lines: None,
+                output_limit_mode: None,
+                strip_ansi: None,
+                args: None,
+                paginate: None,
+                dry_run: None,
+            };
+            let cmd = self.shell_command(true, &execute)?;
+            runs.push((cmd, execute));
+        }
+
+        info!("Run {} commands in parallel", runs.len());
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, (cmd, execute)) in runs.into_iter().enumerate() {
+            let server = self.clone();
+            tasks.spawn(async move {
+                let outcome = server.run_command(cmd, &execute, None).await;
+                (index, execute.command, outcome)
+            });
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        while let Some(joined) = tasks.join_next().await {
+            let (index, cmd, outcome) = joined.map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Parallel command failed: {e}")),
+                data: None,
+            })?;
+            let result = match outcome {
+                Ok(result) => CommandResult {
+                    cmd,
+                    result: result
+                        .content
+                        .first()
+                        .and_then(|content| content.as_text())
+                        .map(|text| {
+                            serde_json::from_str(&text.text)
+                                .unwrap_or_else(|_| Value::String(text.text.clone()))
+                        }),
+                    error: None,
+                },
+                Err(e) => CommandResult {
+                    cmd,
+                    result: None,
+                    error: Some(e.message.into_owned()),
+                },
+            };
+            results.push((index, result));
+        }
+        results.sort_by_key(|(index, _)| *index);
+        let results: Vec<CommandResult> = results.into_iter().map(|(_, result)| result).collect();
+        Ok(CallToolResult::success(vec![Content::json(results)?]))
     }
 
-    #[tool(description = "Get system information using bash commands")]
-    async fn unix_preset_get_system_info_via_default_shell(
+    #[tool(
+        description = "Run a shell command once at a later time, given as delay_seconds or an RFC 3339 run_at. Returns a schedule id, the result is fetched with list_schedules. Schedules are kept in memory only and do not survive a server restart"
+    )]
+    async fn schedule_command(
+        &self,
+        #[tool(aggr)] request: ScheduleCommandRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let scheduler = self.scheduler()?;
+        let run_at = match (request.delay_seconds, &request.run_at) {
+            (Some(delay), None) => {
+                chrono::Utc::now() + chrono::TimeDelta::seconds(delay.min(u32::MAX as u64) as i64)
+            }
+            (None, Some(run_at)) => chrono::DateTime::parse_from_rfc3339(run_at)
+                .map_err(|e| ErrorData::invalid_params(format!("Invalid run_at: {e}"), None))?
+                .with_timezone(&chrono::Utc),
+            _ => {
+                return Err(ErrorData::invalid_params(
+                    "Give either delay_seconds or run_at",
+                    None,
+                ));
+            }
+        };
+
+        let execute = DefaultExecuteRequest {
+            command: request.command,
+            working_dir: request.working_dir,
+            env_vars: request.env_vars,
+            timeout_seconds: request.timeout_seconds,
+            shell: request.shell,
+            stdin: None,
+            output_to_file: None,
+            output_filter: None,
+            idempotency_key: None,
+            max_output_lines: None,
+            output_limit_mode: None,
+            strip_ansi: None,
+            args: None,
+            paginate: None,
+            dry_run: None,
+        };
+        // refuse a command the policy would block now instead of at run time
+        self.shell_command(true, &execute)?;
+
+        // The run must not hold the session open, shutdown would wait for it
+        let mut server = self.clone();
+        server.session = None;
+        let command = execute.command.clone();
+        let info = scheduler.add(command, run_at, async move {
+            server
+                ._all_execute_via_default_shell(true, execute, None)
+                .await
+        })?;
+        Ok(CallToolResult::success(vec![Content::json(info)?]))
+    }
+
+    #[tool(
+        description = "List the last commands run in this session, oldest first, with their working directory, exit code, start time and duration. The output of the commands is not kept"
+    )]
+    async fn get_command_history(
+        &self,
+        #[tool(aggr)] request: CommandHistoryRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let time = |name: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| {
+                    chrono::DateTime::parse_from_rfc3339(value)
+                        .map(|time| time.with_timezone(&chrono::Utc))
+                        .map_err(|e| {
+                            ErrorData::invalid_params(format!("Invalid {name}: {e}"), None)
+                        })
+                })
+                .transpose()
+        };
+        let filter = HistoryFilter {
+            exit_code: request.exit_code,
+            since: time("since", &request.since)?,
+            until: time("until", &request.until)?,
+        };
+        let history = self.history.list(&filter);
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "history": history }),
+        )?]))
+    }
+
+    #[tool(
+        description = "List the scheduled commands with their status (pending, running, finished, failed, cancelled) and the result of the finished ones"
+    )]
+    async fn list_schedules(&self) -> Result<CallToolResult, ErrorData> {
+        let schedules = self.scheduler()?.list();
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "schedules": schedules }),
+        )?]))
+    }
+
+    #[tool(description = "Cancel a pending scheduled command")]
+    async fn cancel_schedule(
+        &self,
+        #[tool(aggr)] request: ScheduleIdRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let info = self.scheduler()?.cancel(&request.schedule_id)?;
+        Ok(CallToolResult::success(vec![Content::json(info)?]))
+    }
+
+    #[tool(description = "Execute a python script")]
+    async fn unix_execute_python(
+        &self,
+        #[tool(aggr)] request: DefaultExecuteRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        // Check if /usr/bin/env exists before proceeding
+        let env_exists = std::path::Path::new("/usr/bin/env").exists();
+        if !env_exists {
+            return Err(ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(
+                    "Python execution failed: /usr/bin/env not found on system".to_string(),
+                ),
+                data: None,
+            });
+        }
+
+        let mut cmd = Command::new("/usr/bin/env");
+        cmd.arg("python3").arg("-c").arg(&request.command);
+        self.prepare_command(&mut cmd, &request)?;
+
+        let result = self.run_command(cmd, &request, Some(&context)).await?;
+
+        // log the execution of python
+        info!("Execute python script: {}", &request.command);
+        Ok(result)
+    }
+
+    #[tool(description = "Execute a unix script")]
+    async fn unix_execute_script(
+        &self,
+        #[tool(aggr)] request: DefaultExecuteRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        // Write the string to a temporary file
+        let tmp_dir = env::temp_dir();
+        // Generate a random script name
+        let tmp_name = Uuid::new_v4().to_string();
+        let script_path = tmp_dir.join(format!("{tmp_name}.sh"));
+        {
+            let mut file =
+                File::create(&script_path).expect("Can not create temporary script file");
+            file.write_all(request.command.as_bytes())
+                .expect("Write to script file error");
+            // Set execution mode
+            let mut perms = file.metadata().unwrap().permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(&script_path, perms).expect("Set mode 755 fail");
+        }
+
+        // Run the script through the requested shell, otherwise let the shebang decide
+        let mut cmd = match request.shell.as_deref() {
+            Some(shell) => {
+                let mut cmd = Command::new(self.shell.select(Some(shell))?);
+                cmd.arg(&script_path);
+                cmd
+            }
+            None => Command::new(&script_path),
+        };
+        self.prepare_command(&mut cmd, &request)?;
+
+        let result = self.run_command(cmd, &request, Some(&context)).await?;
+        info!("Execute script:\n{}", request.command);
+        Ok(result)
+    }
+
+    #[tool(
+        description = "Run a multi-line script. Without interpreter it runs in the configured shell with `set -euo pipefail`, otherwise with the given interpreter (python3, ruby, node, ...), shells are not accepted as interpreter. The script is given inline, as the file:// uri of a resource of this server or as an embedded resource. Prefer this over chaining commands with &&"
+    )]
+    async fn run_script(
+        &self,
+        #[tool(aggr)] request: RunScriptRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let (script, uri) = self.script_source(&request)?;
+        let execute = DefaultExecuteRequest {
+            command: script,
+            working_dir: request.working_dir,
+            env_vars: request.env_vars,
+            timeout_seconds: request.timeout_seconds,
+            shell: None,
+            stdin: request.stdin,
+            output_to_file: None,
+            output_filter: None,
+            idempotency_key: None,
+            max_output_lines: None,
+            output_limit_mode: None,
+            strip_ansi: None,
+            args: None,
+            paginate: None,
+            dry_run: request.dry_run,
+        };
+
+        let (content, program) = match request.interpreter.as_deref() {
+            None => {
+                // the same checks as a shell command, with sudo rewritten by the policy
+                let (checked, script) = self.checked_command(true, &execute)?;
+                let shell = checked.get_program().to_owned();
+                // the profile comes before the strict mode, profiles are rarely written for it
+                let profile: String = self
+                    .profile
+                    .iter()
+                    .map(|snippet| format!("{snippet}\n"))
+                    .collect();
+                // pipefail is not POSIX, the other shells get the rest of the strict mode
+                let strict = match Path::new(&shell).file_name().and_then(|n| n.to_str()) {
+                    Some("bash" | "zsh" | "ksh") => "set -euo pipefail",
+                    _ => "set -eu",
+                };
+                let content = format!(
+                    "#!{}\n{profile}{strict}\n{script}\n",
+                    Path::new(&shell).display()
+                );
+                (content, shell)
+            }
+            Some(interpreter) => {
+                let valid = !interpreter.is_empty()
+                    && interpreter
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+                if !valid {
+                    return Err(ErrorData::invalid_params(
+                        format!(
+                            "Invalid interpreter {interpreter:?}, give a program name like python3"
+                        ),
+                        None,
+                    ));
+                }
+                if SHELL_INTERPRETERS.contains(&interpreter) {
+                    return Err(ErrorData::invalid_params(
+                        format!(
+                            "Interpreter {interpreter:?} is a shell, run shell scripts without interpreter"
+                        ),
+                        None,
+                    ));
+                }
+                let content = format!("#!/usr/bin/env {interpreter}\n{}\n", execute.command);
+                (content, OsStr::new(interpreter).to_owned())
+            }
+        };
+
+        let (script_path, mut file) = self
+            .scratch
+            .create_file(&format!("{}.script", Uuid::new_v4()))
+            .map_err(Self::scratch_error)?;
+        let written = file
+            .write_all(content.as_bytes())
+            .and_then(|()| file.set_permissions(fs::Permissions::from_mode(0o700)));
+        drop(file);
+        if let Err(e) = written {
+            let _ = fs::remove_file(&script_path);
+            return Err(Self::scratch_error(e));
+        }
+
+        let mut cmd = match request.interpreter {
+            None => Command::new(&program),
+            Some(_) => {
+                let mut cmd = Command::new("/usr/bin/env");
+                cmd.arg(&program);
+                cmd
+            }
+        };
+        cmd.arg(&script_path);
+        cmd.args(request.args.iter().flatten());
+        let result = match self.prepare_command(&mut cmd, &execute) {
+            Ok(()) => self.run_command(cmd, &execute, Some(&context)).await,
+            Err(e) => Err(e),
+        };
+        // the script is removed whatever the outcome
+        if let Err(e) = fs::remove_file(&script_path) {
+            warn!("Failed to remove script {}: {e}", script_path.display());
+        }
+        match uri {
+            Some(uri) => info!(
+                "Run script {uri} (sha256 {}) with {program:?}",
+                checksum::sha256_hex(content.as_bytes())
+            ),
+            None => info!("Run script with {program:?}:\n{}", execute.command),
+        }
+        result
+    }
+
+    #[tool(description = "Get system information using bash commands")]
+    async fn unix_get_system_info_via_default_shell(&self) -> Result<CallToolResult, ErrorData> {
+        let command = r#"
+echo "=== System Information ==="
+echo "Hostname: $(hostname)"
+echo "OS: $(uname -s)"
+echo "Kernel: $(uname -r)"
+echo "Architecture: $(uname -m)"
+echo "Uptime: $(uptime)"
+echo "Current User: $(whoami)"
+echo "Current Directory: $(pwd)"
+echo "Date: $(date)"
+echo "Memory Usage:"
+free -h 2>/dev/null || echo "free command not available"
+echo "Disk Usage:"
+df -h / 2>/dev/null || echo "df command not available"
+        "#;
+
+        self._all_execute_via_default_shell(
+            false,
+            DefaultExecuteRequest {
+                command: command.to_string(),
+                working_dir: None,
+                env_vars: None,
+                timeout_seconds: Some(10),
+                shell: None,
+                stdin: None,
+                output_to_file: None,
+                output_filter: None,
+                idempotency_key: None,
+                max_output_lines: None,
+                output_limit_mode: None,
+                strip_ansi: None,
+                args: None,
+                paginate: None,
+                dry_run: None,
+            },
+            None,
+        )
+        .await
+    }
+
+    #[tool(description = "Get system information using bash commands")]
+    async fn unix_preset_get_system_info_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"(uname -r ; hostname ; uptime | awk -F "," '{print $1}' | sed  "s/ //g") | sed ":a;N;s/\n/^/g;ta" | awk -F '^' 'BEGIN{print "version hostname uptime"} {print $1, $2, $3}'"#;
+
+        self._all_execute_via_default_shell(
+            false,
+            DefaultExecuteRequest {
+                command: command.to_string(),
+                working_dir: None,
+                env_vars: None,
+                timeout_seconds: Some(10),
+                shell: None,
+                stdin: None,
+                output_to_file: None,
+                output_filter: None,
+                idempotency_key: None,
+                max_output_lines: None,
+                output_limit_mode: None,
+                strip_ansi: None,
+                args: None,
+                paginate: None,
+                dry_run: None,
+            },
+            None,
+        )
+        .await
+    }
+
+    #[tool(description = "Get the available shell in unix-like os")]
+    async fn unix_get_available_shell(&self) -> Result<CallToolResult, ErrorData> {
+        let mut available_shell = vec![];
+        let shells_file = "/etc/shells";
+        match fs::read_to_string(shells_file) {
+            Ok(content) => {
+                for line in content.lines() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    // Return the available shells
+                    if fs::metadata(line).map(|m| m.is_file()).unwrap_or(false) {
+                        available_shell.push(Content::text(line));
+                    }
+                }
+                info!("Read file /etc/shells");
+            }
+            Err(e) => {
+                error!("Failed to read {shells_file}: {e}");
+                return Err(ErrorData {
+                    code: ErrorCode::INTERNAL_ERROR,
+                    message: Cow::Owned(format!("Failed to read {shells_file}: {e}")),
+                    data: None,
+                });
+            }
+        }
+        let result = CallToolResult::success(available_shell);
+        Ok(result)
+    }
+
+    #[tool(description = "Get the nic info through the default shell")]
+    async fn unix_preset_get_nic_info_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"cat /proc/net/dev | tail -n +3 | awk 'BEGIN{ print "interface_name receive_bytes transmit_bytes"} {print $1,$2,$10}'"#;
+        let result = self
+            ._all_execute_via_default_shell(
+                false,
+                DefaultExecuteRequest {
+                    command: command.to_string(),
+                    working_dir: None,
+                    env_vars: None,
+                    timeout_seconds: Some(5),
+                    shell: None,
+                    stdin: None,
+                    output_to_file: None,
+                    output_filter: None,
+                    idempotency_key: None,
+                    max_output_lines: None,
+                    output_limit_mode: None,
+                    strip_ansi: None,
+                    args: None,
+                    paginate: None,
+                    dry_run: None,
+                },
+                None,
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    #[tool(description = "Get the cpu info through the default shell")]
+    async fn unix_preset_get_cpu_info_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"LANG=C lscpu | awk -F: '$1=="Model name" {print $2}';awk '/processor/{core++} END{print core}' /proc/cpuinfo;uptime | sed 's/,/ /g' | awk '{for(i=NF-2;i<=NF;i++)print $i }' | xargs;vmstat 1 1 | awk 'NR==3{print $11}';vmstat 1 1 | awk 'NR==3{print $12}';vmstat 1 2 | awk 'NR==4{print $15}'"#;
+        let mut result = self
+            ._all_execute_via_default_shell(
+                false,
+                DefaultExecuteRequest {
+                    command: command.to_string(),
+                    working_dir: None,
+                    env_vars: None,
+                    timeout_seconds: Some(5),
+                    shell: None,
+                    stdin: None,
+                    output_to_file: None,
+                    output_filter: None,
+                    idempotency_key: None,
+                    max_output_lines: None,
+                    output_limit_mode: None,
+                    strip_ansi: None,
+                    args: None,
+                    paginate: None,
+                    dry_run: None,
+                },
+                None,
+            )
+            .await?;
+        let raw_content = &mut result.content.get_mut(0).unwrap().raw;
+        if let RawContent::Text(RawTextContent { text }) = raw_content {
+            if let Ok(mut response) = serde_json::from_str::<DefaultExecuteResponse>(&text.clone())
+            {
+                // parse response.stdout
+                // output e.g.:
+                //                               Intel(R) Core(TM) i7-10875H CPU @ 2.30GHz
+                // 16
+                // 1.05 0.74 0.72
+                // 1261
+                // 5
+                // 92
+                let lines: Vec<&str> = response
+                    .stdout
+                    .lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .collect();
+                if lines.len() >= 6 {
+                    let cpu_model = lines[0];
+                    let core_count = lines[1].parse::<u32>().unwrap_or(0);
+                    let load: Vec<f32> = lines[2]
+                        .split_whitespace()
+                        .filter_map(|s| s.parse::<f32>().ok())
+                        .collect();
+                    let interrupt = lines[3].parse::<u64>().unwrap_or(0);
+                    let context_switch = lines[4].parse::<u64>().unwrap_or(0);
+                    let idle = lines[5].parse::<u64>().unwrap_or(0);
+
+                    let parsed = serde_json::json!({
+                        "cpu_model": cpu_model,
+                        "core_count": core_count,
+                        "load": load,
+                        "interrupt": interrupt,
+                        "context_switch": context_switch,
+                        "idle": idle
+                    });
+                    response.parsed_data = parsed;
+                    *text = serde_json::to_string(&response).unwrap();
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    #[tool(description = "Get the disk free info through the default shell")]
+    async fn unix_preset_get_disk_free_info_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"df -mP | tail -n +2 | awk 'BEGIN{ print "filesystem used available usage mounted"} {print $1,$3,$4,$5,$6}'"#;
+        let result = self
+            ._all_execute_via_default_shell(
+                false,
+                DefaultExecuteRequest {
+                    command: command.to_string(),
+                    working_dir: None,
+                    env_vars: None,
+                    timeout_seconds: Some(5),
+                    shell: None,
+                    stdin: None,
+                    output_to_file: None,
+                    output_filter: None,
+                    idempotency_key: None,
+                    max_output_lines: None,
+                    output_limit_mode: None,
+                    strip_ansi: None,
+                    args: None,
+                    paginate: None,
+                    dry_run: None,
+                },
+                None,
+            )
+            .await?;
+        Ok(result)
+    }
+
+    #[tool(description = "Get the top 10 cpu processes through the default shell")]
+    async fn unix_preset_get_top10_cpu_processes_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"ps aux | sort -k3nr | awk 'BEGIN{ print "pid cpu_usage mem_usage command" } {printf "%s %s %s ", $2, $3, $4; for (i=11; i<=NF; i++) { printf "%s", $i; if (i < NF) printf " "; } print ""}' | head -n 11"#;
+        let result = self
+            ._all_execute_via_default_shell(
+                false,
+                DefaultExecuteRequest {
+                    command: command.to_string(),
+                    working_dir: None,
+                    env_vars: None,
+                    timeout_seconds: Some(5),
+                    shell: None,
+                    stdin: None,
+                    output_to_file: None,
+                    output_filter: None,
+                    idempotency_key: None,
+                    max_output_lines: None,
+                    output_limit_mode: None,
+                    strip_ansi: None,
+                    args: None,
+                    paginate: None,
+                    dry_run: None,
+                },
+                None,
+            )
+            .await?;
+        Ok(result)
+    }
+
+    #[tool(description = "Get the top 10 mem processes through the default shell")]
+    async fn unix_preset_get_top10_mem_processes_via_default_shell(
+        &self,
+    ) -> Result<CallToolResult, ErrorData> {
+        let command = r#"ps aux | sort -k4nr | awk 'BEGIN{ print "pid cpu_usage mem_usage command" } {printf "%s %s %s ", $2, $3, $4; for (i=11; i<=NF; i++) { printf "%s", $i; if (i < NF) printf " "; } print ""}' | head -n 11"#;
+        let result = self
+            ._all_execute_via_default_shell(
+                false,
+                DefaultExecuteRequest {
+                    command: command.to_string(),
+                    working_dir: None,
+                    env_vars: None,
+                    timeout_seconds: Some(5),
+                    shell: None,
+                    stdin: None,
+                    output_to_file: None,
+                    output_filter: None,
+                    idempotency_key: None,
+                    max_output_lines: None,
+                    output_limit_mode: None,
+                    strip_ansi: None,
+                    args: None,
+                    paginate: None,
+                    dry_run: None,
+                },
+                None,
+            )
+            .await?;
+        Ok(result)
+    }
+
+    #[tool(
+        description = "Read a file, text is returned as UTF-8 and binary content as base64. Supports reading a byte range"
+    )]
+    async fn read_file(
+        &self,
+        #[tool(aggr)] request: ReadFileRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_read(&request.path)?;
+        let offset = request.offset.unwrap_or(0);
+        let length = request.length.unwrap_or(MAX_READ_BYTES).min(MAX_READ_BYTES);
+
+        let read_error = |e: std::io::Error| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to read {}: {e}", path.display())),
+            data: None,
+        };
+        let mut file = File::open(&path).map_err(read_error)?;
+        let size = file.metadata().map_err(read_error)?.len();
+        file.seek(SeekFrom::Start(offset)).map_err(read_error)?;
+        let mut bytes = Vec::new();
+        file.take(length)
+            .read_to_end(&mut bytes)
+            .map_err(read_error)?;
+
+        // Binary content can not travel as a JSON string
+        let read = bytes.len() as u64;
+        let (encoding, content) = match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => ("utf-8", text),
+            Ok(text) => ("base64", BASE64.encode(text.as_bytes())),
+            Err(e) => ("base64", BASE64.encode(e.as_bytes())),
+        };
+
+        info!("Read file {} ({read} bytes at {offset})", path.display());
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "path": path,
+                "size": size,
+                "offset": offset,
+                "length": read,
+                "eof": offset + read >= size,
+                "encoding": encoding,
+                "content": content,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "List a directory as structured entries {name, kind, size, modified_timestamp, permissions}. Symlinks are reported as such and never followed"
+    )]
+    async fn list_directory(
+        &self,
+        #[tool(aggr)] request: ListDirectoryRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_read(&request.path)?;
+        let max_depth = if request.recursive {
+            request
+                .max_depth
+                .unwrap_or(DEFAULT_LIST_DEPTH)
+                .clamp(1, MAX_LIST_DEPTH)
+        } else {
+            1
+        };
+
+        let mut entries = Vec::new();
+        let mut pending = vec![(path.clone(), 1)];
+        let mut truncated = false;
+        while let Some((dir, depth)) = pending.pop() {
+            let read_dir = fs::read_dir(&dir).map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to list {}: {e}", dir.display())),
+                data: None,
+            })?;
+            for entry in read_dir.flatten() {
+                if entries.len() >= MAX_LIST_ENTRIES {
+                    truncated = true;
+                    break;
+                }
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let entry_path = entry.path();
+                let file_type = meta.file_type();
+                // Do not descend into denied subpaths like .ssh
+                if file_type.is_dir()
+                    && depth < max_depth
+                    && self.path_policy.check_read(&entry_path).is_ok()
+                {
+                    pending.push((entry_path.clone(), depth + 1));
+                }
+                entries.push(DirectoryEntry {
+                    name: entry_path
+                        .strip_prefix(&path)
+                        .unwrap_or(&entry_path)
+                        .to_string_lossy()
+                        .into_owned(),
+                    kind: if file_type.is_symlink() {
+                        "symlink"
+                    } else if file_type.is_dir() {
+                        "directory"
+                    } else if file_type.is_file() {
+                        "file"
+                    } else {
+                        "other"
+                    },
+                    size: meta.len(),
+                    modified_timestamp: meta
+                        .modified()
+                        .ok()
+                        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_secs()),
+                    permissions: format!("{:o}", meta.permissions().mode() & 0o7777),
+                    symlink_target: file_type
+                        .is_symlink()
+                        .then(|| fs::read_link(&entry_path).ok())
+                        .flatten()
+                        .map(|target| target.to_string_lossy().into_owned()),
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        info!(
+            "List directory {} ({} entries)",
+            path.display(),
+            entries.len()
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "path": path,
+                "entries": entries,
+                "truncated": truncated,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Hash a file, or build a deterministic manifest (relative path -> hash) of a directory tree. Diff the manifests of two calls to find what changed"
+    )]
+    async fn checksum(
+        &self,
+        #[tool(aggr)] request: ChecksumRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_read(&request.path)?;
+        let algorithm_name = request.algorithm.as_deref().unwrap_or("sha256");
+        let algorithm = Algorithm::parse(algorithm_name).ok_or_else(|| {
+            ErrorData::invalid_params(
+                format!("Unknown algorithm {algorithm_name}, expected sha256 or blake3"),
+                None,
+            )
+        })?;
+        let max_bytes = request
+            .max_bytes
+            .unwrap_or(MAX_CHECKSUM_BYTES)
+            .min(MAX_CHECKSUM_BYTES);
+        let mut excludes = globset::GlobSetBuilder::new();
+        for pattern in request.excludes.unwrap_or_default() {
+            let glob = globset::Glob::new(&pattern).map_err(|e| {
+                ErrorData::invalid_params(format!("Invalid exclude pattern {pattern}: {e}"), None)
+            })?;
+            excludes.add(glob);
+        }
+        let excludes = excludes.build().map_err(|e| {
+            ErrorData::invalid_params(format!("Invalid exclude patterns: {e}"), None)
+        })?;
+
+        let checksum_error = |e: std::io::Error| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to hash {}: {e}", path.display())),
+            data: None,
+        };
+        let too_large = |size: u64| {
+            ErrorData::invalid_request(
+                format!(
+                    "{} holds {size} bytes which is more than max_bytes ({max_bytes}), use excludes or a narrower path",
+                    path.display()
+                ),
+                Some(serde_json::json!({ "size": size, "max_bytes": max_bytes })),
+            )
+        };
+
+        let meta = fs::metadata(&path).map_err(checksum_error)?;
+        if !meta.is_dir() {
+            if meta.len() > max_bytes {
+                return Err(too_large(meta.len()));
+            }
+            let file = path.clone();
+            let hash = tokio::task::spawn_blocking(move || checksum::hash_file(&file, algorithm))
+                .await
+                .map_err(|e| checksum_error(std::io::Error::other(e)))?
+                .map_err(checksum_error)?;
+            info!("Checksum of file {}", path.display());
+            return Ok(CallToolResult::success(vec![Content::json(
+                serde_json::json!({
+                    "path": path,
+                    "kind": "file",
+                    "algorithm": algorithm,
+                    "size": meta.len(),
+                    "hash": hash,
+                }),
+            )?]));
+        }
+
+        // Denied subpaths like .ssh stay out of the manifest
+        let policy = self.path_policy.clone();
+        let root = path.clone();
+        let plan = tokio::task::spawn_blocking(move || {
+            checksum::plan_tree(&root, &excludes, |dir| policy.check_read(dir).is_ok())
+        })
+        .await
+        .map_err(|e| checksum_error(std::io::Error::other(e)))?
+        .map_err(checksum_error)?;
+        if plan.total_bytes > max_bytes {
+            return Err(too_large(plan.total_bytes));
+        }
+        let manifest = tokio::task::spawn_blocking(move || checksum::hash_tree(plan, algorithm))
+            .await
+            .map_err(|e| checksum_error(std::io::Error::other(e)))?
+            .map_err(checksum_error)?;
+
+        info!(
+            "Checksum manifest of {} ({} files, {} bytes)",
+            path.display(),
+            manifest.files.len(),
+            manifest.total_bytes
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "path": path,
+                "kind": "directory",
+                "manifest": manifest,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Apply a unified diff natively, no patch/heredoc needed. Each file is patched atomically and only if all its hunks apply; new and deleted files are supported. Use dry_run to check which hunks would apply"
+    )]
+    async fn apply_patch(
+        &self,
+        #[tool(aggr)] request: ApplyPatchRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let base = self.path_policy.check(&request.base_dir)?;
+        if !base.is_dir() {
+            return Err(ErrorData::invalid_params(
+                format!("{} is not a directory", base.display()),
+                None,
+            ));
+        }
+        let file_patches = patch::parse(&request.patch)
+            .map_err(|e| ErrorData::invalid_params(format!("Invalid patch: {e}"), None))?;
+        let strip = request.strip.unwrap_or(1);
+
+        let files: Vec<FilePatchResult> = file_patches
+            .iter()
+            .map(|file_patch| self.apply_file_patch(&base, file_patch, strip, request.dry_run))
+            .collect();
+        let clean = files
+            .iter()
+            .all(|file| matches!(file.status, "applied" | "would_apply"));
+
+        Ok(CallToolResult {
+            content: vec![Content::json(serde_json::json!({
+                "base_dir": base,
+                "dry_run": request.dry_run,
+                "files": files,
+            }))?],
+            is_error: Some(!clean),
+        })
+    }
+
+    #[tool(
+        description = "Extract a .tar, .tar.gz, .tar.zst or .zip archive natively. Entries escaping the destination (.., absolute paths, symlinks pointing outside) are skipped, entry count and decompressed size are limited"
+    )]
+    async fn extract_archive(
+        &self,
+        #[tool(aggr)] request: ExtractArchiveRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let archive_path = self.path_policy.check_read(&request.archive_path)?;
+        let destination = self.path_policy.check_write(&request.destination)?;
+        let format = match &request.format {
+            Some(format) => ArchiveFormat::parse(format),
+            None => ArchiveFormat::detect(&archive_path),
+        }
+        .ok_or_else(|| {
+            ErrorData::invalid_params(
+                "Unknown archive format, pass format as tar, tar.gz, tar.zst or zip",
+                None,
+            )
+        })?;
+        let limits = ExtractLimits {
+            max_entries: request
+                .max_entries
+                .unwrap_or(MAX_ARCHIVE_ENTRIES)
+                .min(MAX_ARCHIVE_ENTRIES),
+            max_total_bytes: request
+                .max_total_bytes
+                .unwrap_or(MAX_ARCHIVE_BYTES)
+                .min(MAX_ARCHIVE_BYTES),
+        };
+
+        let extract_error = |e: std::io::Error| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to extract {}: {e}", archive_path.display())),
+            data: None,
+        };
+        fs::create_dir_all(&destination).map_err(extract_error)?;
+        let (source, target) = (archive_path.clone(), destination.clone());
+        let report = tokio::task::spawn_blocking(move || {
+            archive::extract(&source, format, &target, limits, request.overwrite)
+        })
+        .await
+        .map_err(|e| extract_error(std::io::Error::other(e)))?
+        .map_err(extract_error)?;
+
+        info!(
+            "Extract {} into {} ({} extracted, {} skipped)",
+            archive_path.display(),
+            destination.display(),
+            report.extracted.len(),
+            report.skipped.len()
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "archive": archive_path,
+                "destination": destination,
+                "format": format,
+                "report": report,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Structured git repository state: branch, upstream with ahead/behind, staged/unstaged/untracked/conflicted files, recent commits and optionally a diff stat. Prefer this over parsing git output"
+    )]
+    async fn git_info(
+        &self,
+        #[tool(aggr)] request: GitInfoRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_read(&request.path)?;
+        let commit_count = request.commit_count.unwrap_or(5).min(100);
+        match git::git_info(&path, commit_count, request.diff_stat).await {
+            Ok(info) => Ok(CallToolResult::success(vec![Content::json(
+                serde_json::json!({
+                    "path": path,
+                    "is_repository": true,
+                    "git": info,
+                }),
+            )?])),
+            Err(GitError::NotARepository) => Ok(CallToolResult::success(vec![Content::json(
+                serde_json::json!({
+                    "path": path,
+                    "is_repository": false,
+                    "message": "not a git repository",
+                }),
+            )?])),
+            Err(GitError::Failed(e)) => Err(ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("git failed in {}: {e}", path.display())),
+                data: None,
+            }),
+        }
+    }
+
+    #[tool(
+        description = "Send an HTTP request and return status, headers and body (text, or base64 for binary content). Use this instead of curl in a shell. The server config decides which urls are reachable"
+    )]
+    async fn http_request(
+        &self,
+        #[tool(aggr)] request: HttpRequestParams,
+    ) -> Result<CallToolResult, ErrorData> {
+        let method = request
+            .method
+            .as_deref()
+            .unwrap_or("GET")
+            .to_ascii_uppercase();
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| {
+            ErrorData::invalid_params(format!("Invalid HTTP method {method}"), None)
+        })?;
+        let body = match (request.body, request.body_encoding.as_deref()) {
+            (None, _) => None,
+            (Some(body), None | Some("utf-8") | Some("utf8")) => Some(body.into_bytes()),
+            (Some(body), Some("base64")) => Some(BASE64.decode(body.as_bytes()).map_err(|e| {
+                ErrorData::invalid_params(format!("Invalid base64 body: {e}"), None)
+            })?),
+            (Some(_), Some(other)) => {
+                return Err(ErrorData::invalid_params(
+                    format!("Unknown body_encoding {other}, expected utf-8 or base64"),
+                    None,
+                ));
+            }
+        };
+
+        let response = self
+            .http_policy
+            .send(HttpRequest {
+                method,
+                url: request.url,
+                headers: request.headers.unwrap_or_default(),
+                body,
+                timeout: std::time::Duration::from_secs(
+                    request
+                        .timeout_seconds
+                        .unwrap_or(30)
+                        .min(MAX_HTTP_TIMEOUT_SECONDS),
+                ),
+                max_response_bytes: request
+                    .max_response_bytes
+                    .unwrap_or(DEFAULT_HTTP_RESPONSE_BYTES)
+                    .min(MAX_HTTP_RESPONSE_BYTES),
+            })
+            .await?;
+        Ok(CallToolResult::success(vec![Content::json(response)?]))
+    }
+
+    #[tool(
+        description = "Write a file atomically: the content goes to a temporary file in the same directory which is then renamed over the target. Supports text and base64 content"
+    )]
+    async fn write_file(
+        &self,
+        #[tool(aggr)] request: WriteFileRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_write(&request.path)?;
+        let content = match request.encoding.as_deref().unwrap_or("utf-8") {
+            "utf-8" | "utf8" | "text" => request.content.into_bytes(),
+            "base64" => BASE64.decode(request.content.as_bytes()).map_err(|e| {
+                ErrorData::invalid_params(format!("Invalid base64 content: {e}"), None)
+            })?,
+            other => {
+                return Err(ErrorData::invalid_params(
+                    format!("Unknown encoding {other}, expected utf-8 or base64"),
+                    None,
+                ));
+            }
+        };
+        if path.file_name().is_none() {
+            return Err(ErrorData::invalid_params(
+                format!("{} is not a file path", path.display()),
+                None,
+            ));
+        }
+
+        let write_error = |e: std::io::Error| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to write {}: {e}", path.display())),
+            data: None,
+        };
+        let existing_mode = match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => {
+                return Err(ErrorData::invalid_params(
+                    format!("{} is a directory", path.display()),
+                    None,
+                ));
+            }
+            Ok(meta) => Some(meta.permissions().mode() & 0o7777),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(write_error(e)),
+        };
+        let mode = request.mode.or(existing_mode).unwrap_or(0o644);
+
+        // Reserve the name first, so create_only never clobbers a file created meanwhile
+        if request.create_only {
+            File::options()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::AlreadyExists => ErrorData::invalid_request(
+                        format!("{} already exists", path.display()),
+                        None,
+                    ),
+                    _ => write_error(e),
+                })?;
+        }
+
+        if let Err(e) = write_atomically(&path, &content, mode) {
+            if request.create_only {
+                let _ = fs::remove_file(&path);
+            }
+            return Err(write_error(e));
+        }
+
+        info!(
+            "Write file {} ({} bytes, mode {mode:o})",
+            path.display(),
+            content.len()
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "path": path,
+                "bytes_written": content.len(),
+                "mode": format!("{mode:o}"),
+                "created": existing_mode.is_none(),
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Follow a file like `tail -f` for a limited time. New lines are streamed as progress notifications; the call ends when until_regex matches, follow_seconds elapse, max_bytes is reached or the request is cancelled. Log rotation is followed"
+    )]
+    async fn tail_file(
+        &self,
+        #[tool(aggr)] request: TailFileRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let path = self.path_policy.check_read(&request.path)?;
+        let until = request
+            .until_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| ErrorData::invalid_params(format!("Invalid until_regex: {e}"), None))?;
+        let follow = std::time::Duration::from_secs(
+            request.follow_seconds.unwrap_or(10).min(MAX_TAIL_SECONDS),
+        );
+        let max_bytes = request
+            .max_bytes
+            .unwrap_or(MAX_TAIL_BYTES)
+            .min(MAX_TAIL_BYTES);
+
+        let tail_error = |e: std::io::Error| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to follow {}: {e}", path.display())),
+            data: None,
+        };
+        let mut follower = FileFollower::open(&path).map_err(tail_error)?;
+        let mut lines = follower
+            .last_lines(request.initial_lines.unwrap_or(10))
+            .map_err(tail_error)?;
+        info!("Follow file {} for {:?}", path.display(), follow);
+
+        let mut progress = ProgressReporter::new(&context);
+        let deadline = tokio::time::Instant::now() + follow;
+        let (mut bytes, mut rotations, mut truncations) = (0u64, 0u32, 0u32);
+        let mut matched = false;
+        let reason = loop {
+            if matched {
+                break "regex_matched";
+            }
+            if bytes >= max_bytes {
+                break "max_bytes";
+            }
+            tokio::select! {
+                _ = context.ct.cancelled() => break "cancelled",
+                _ = tokio::time::sleep_until(deadline) => break "duration_elapsed",
+                _ = tokio::time::sleep(TAIL_POLL_INTERVAL) => {}
+            }
+
+            let poll = follower.poll(max_bytes - bytes).map_err(tail_error)?;
+            rotations += poll.rotated as u32;
+            truncations += poll.truncated as u32;
+            if poll.lines.is_empty() {
+                continue;
+            }
+            bytes += poll.lines.iter().map(|l| l.len() as u64 + 1).sum::<u64>();
+            matched = until
+                .as_ref()
+                .is_some_and(|re| poll.lines.iter().any(|l| re.is_match(l)));
+            if progress.is_enabled() {
+                progress.report(poll.lines.join("\n")).await;
+            }
+            lines.extend(poll.lines);
+        };
+
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "path": path,
+                "lines": lines,
+                "stop_reason": reason,
+                "rotations": rotations,
+                "truncations": truncations,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Describe the host: OS and kernel version, architecture, hostname, CPUs, load, memory, disk usage of the allowed roots and the active security settings of this server. Fields that can not be determined are null. Read-only"
+    )]
+    async fn system_info(&self) -> Result<CallToolResult, ErrorData> {
+        let roots = self.path_policy.allowed_roots().to_vec();
+        let host = tokio::task::spawn_blocking(move || host_info(&roots))
+            .await
+            .map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to collect system info: {e}")),
+                data: None,
+            })?;
+
+        Ok(CallToolResult::success(vec![Content::json(
+            SystemInfoResponse {
+                host,
+                server: serde_json::json!({
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }),
+                security: serde_json::json!({
+                    "command_blacklist": self.validator.is_some(),
+                    "path_jail": self.path_policy.is_enabled(),
+                    "sandbox": if self.sandbox.is_some() { "landlock" } else { "none" },
+                    "sudo": self.sudo_policy.mode(),
+                    "own_processes_only": self.own_processes_only,
+                    "http_request": self.http_policy.is_enabled(),
+                    "client_roots": self.path_policy.client_roots(),
+                }),
+            },
+        )?]))
+    }
+
+    #[tool(
+        description = "List running processes with pid, ppid, user, name, command line, CPU%, memory, status and start time. Prefer this over parsing `ps` output. Requires the OAuth scope processes:read"
+    )]
+    async fn list_processes(
+        &self,
+        #[tool(aggr)] request: ListProcessesRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let filter = ProcessFilter {
+            user: request.user,
+            filter_name: request.filter_name,
+            server_jobs_only: request.server_jobs_only || self.own_processes_only,
+        };
+        let processes = processes::list_processes(&filter).await;
+        let offset = request.offset.unwrap_or(0);
+        let max_results = request
+            .max_results
+            .unwrap_or(DEFAULT_PROCESS_RESULTS)
+            .min(MAX_PROCESS_RESULTS);
+        let total = processes.len();
+        let page: Vec<_> = processes
+            .into_iter()
+            .skip(offset)
+            .take(max_results)
+            .collect();
+
+        info!("List processes ({total} matching, {} returned)", page.len());
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "total": total,
+                "offset": offset,
+                "truncated": offset + page.len() < total,
+                "server_jobs_only": filter.server_jobs_only,
+                "processes": page,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Show the environment commands run with: the server environment and the variables set for this session. Values of sensitive variables are redacted"
+    )]
+    async fn get_env(
+        &self,
+        #[tool(aggr)] request: GetEnvRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let prefix = request.prefix.unwrap_or_default();
+        let matches = |(key, _): &(String, String)| key.starts_with(&prefix);
+        let system = self.env_redactor.redact(env::vars().filter(matches));
+        let session = self
+            .env_redactor
+            .redact(self.session_env.snapshot().into_iter().filter(matches));
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "system": system,
+                "session": session,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Set an environment variable for every following command of this session, on top of the server environment"
+    )]
+    async fn set_session_env(
+        &self,
+        #[tool(aggr)] request: SetSessionEnvRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        if !is_valid_env_key(&request.key) || request.key == JOB_MARKER_ENV {
+            return Err(ErrorData::invalid_params(
+                format!(
+                    "{} can not be used as environment variable name",
+                    request.key
+                ),
+                None,
+            ));
+        }
+        info!("Set session environment variable {}", request.key);
+        let replaced = self
+            .session_env
+            .set(request.key.clone(), request.value)
+            .is_some();
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "key": request.key, "replaced": replaced }),
+        )?]))
+    }
+
+    #[tool(description = "Remove an environment variable set with set_session_env")]
+    async fn unset_session_env(
         &self,
+        #[tool(aggr)] request: UnsetSessionEnvRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"(uname -r ; hostname ; uptime | awk -F "," '{print $1}' | sed  "s/ //g") | sed ":a;N;s/\n/^/g;ta" | awk -F '^' 'BEGIN{print "version hostname uptime"} {print $1, $2, $3}'"#;
-
-        self._all_execute_via_default_shell(
-            false,
-            DefaultExecuteRequest {
-                command: command.to_string(),
-                working_dir: None,
-                env_vars: None,
-                timeout_seconds: Some(10),
-            },
-        )
-        .await
+        let removed = self.session_env.unset(&request.key).is_some();
+        info!("Unset session environment variable {}", request.key);
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "key": request.key, "removed": removed }),
+        )?]))
     }
 
-    #[tool(description = "Get the available shell in unix-like os")]
-    async fn unix_get_available_shell(&self) -> Result<CallToolResult, ErrorData> {
-        let mut available_shell = vec![];
-        let shells_file = "/etc/shells";
-        match fs::read_to_string(shells_file) {
-            Ok(content) => {
-                for line in content.lines() {
-                    let line = line.trim();
-                    if line.is_empty() || line.starts_with('#') {
-                        continue;
-                    }
-                    // Return the available shells
-                    if fs::metadata(line).map(|m| m.is_file()).unwrap_or(false) {
-                        available_shell.push(Content::text(line));
+    #[tool(
+        description = "Save the state of this session as a versioned JSON string for restore_session: the session environment variables, the command history and the pty sessions with their exit code and unread output"
+    )]
+    async fn snapshot_session(&self) -> Result<CallToolResult, ErrorData> {
+        let mut jobs = self.restored_jobs.lock().unwrap().clone();
+        jobs.extend(
+            self.pty_sessions
+                .list()
+                .into_iter()
+                .map(|(pty_session_id, session)| {
+                    let exit_code = session.exit_code();
+                    JobSnapshot {
+                        pty_session_id,
+                        exited: exit_code.is_some(),
+                        exit_code,
+                        output: String::from_utf8_lossy(&session.peek_output()).into_owned(),
+                        restored: false,
                     }
-                }
-                info!("Read file /etc/shells");
-            }
-            Err(e) => {
-                error!("Failed to read {shells_file}: {e}");
-                return Err(ErrorData {
-                    code: ErrorCode::INTERNAL_ERROR,
-                    message: Cow::Owned(format!("Failed to read {shells_file}: {e}")),
-                    data: None,
-                });
-            }
+                }),
+        );
+        let snapshot = SessionSnapshot::new(
+            self.session_env.snapshot(),
+            self.history.list(&HistoryFilter::default()),
+            jobs,
+        );
+        info!(
+            "Snapshot session with {} environment variables and {} jobs",
+            snapshot.env.len(),
+            snapshot.jobs.len()
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "version": snapshot.version,
+                "snapshot": snapshot.to_json()?,
+            }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Restore a session saved with snapshot_session, replacing the session environment variables and the command history. Pty sessions are not restarted, their exit code and output are returned and kept for later snapshots"
+    )]
+    async fn restore_session(
+        &self,
+        #[tool(aggr)] request: RestoreSessionRequest,
+    ) -> Result<CallToolResult, ErrorData> {
+        let snapshot = SessionSnapshot::parse(&request.snapshot)?;
+        if let Some(key) = snapshot
+            .env
+            .keys()
+            .find(|key| !is_valid_env_key(key) || *key == JOB_MARKER_ENV)
+        {
+            return Err(ErrorData::invalid_params(
+                format!("{key} can not be used as environment variable name"),
+                None,
+            ));
         }
-        let result = CallToolResult::success(available_shell);
-        Ok(result)
+
+        let env_vars = snapshot.env.len();
+        let history_entries = snapshot.history.len();
+        let jobs: Vec<JobSnapshot> = snapshot
+            .jobs
+            .into_iter()
+            .map(|job| JobSnapshot {
+                restored: true,
+                ..job
+            })
+            .collect();
+        self.session_env.replace(snapshot.env);
+        self.history.restore(snapshot.history);
+        *self.restored_jobs.lock().unwrap() = jobs.clone();
+        info!(
+            "Restore session snapshot of {} with {env_vars} environment variables",
+            snapshot.created_at
+        );
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "version": snapshot.version,
+                "created_at": snapshot.created_at,
+                "env_vars": env_vars,
+                "history_entries": history_entries,
+                "jobs": jobs,
+            }),
+        )?]))
     }
 
-    #[tool(description = "Get the nic info through the default shell")]
-    async fn unix_preset_get_nic_info_via_default_shell(
+    #[tool(
+        description = "Start an interactive shell in a pseudo-terminal, for programs that need a real terminal. Returns a pty_session_id"
+    )]
+    async fn start_pty_session(
         &self,
+        #[tool(aggr)] request: StartPtySessionRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"cat /proc/net/dev | tail -n +3 | awk 'BEGIN{ print "interface_name receive_bytes transmit_bytes"} {print $1,$2,$10}'"#;
-        let result = self
-            ._all_execute_via_default_shell(
-                false,
-                DefaultExecuteRequest {
-                    command: command.to_string(),
-                    working_dir: None,
-                    env_vars: None,
-                    timeout_seconds: Some(5),
-                },
-            )
-            .await?;
+        // pre_exec hooks are not available for pty children
+        if self.sandbox.is_some() {
+            return Err(ErrorData::invalid_request(
+                "pty sessions are not available while the landlock sandbox is enabled",
+                None,
+            ));
+        }
 
-        Ok(result)
+        let shell = self.shell.select(request.shell.as_deref())?;
+        let working_dir = match &request.working_dir {
+            Some(working_dir) => Some(self.path_policy.check(working_dir)?),
+            None => None,
+        };
+        let mut env_vars: std::collections::HashMap<String, String> =
+            self.session_env.snapshot().into_iter().collect();
+        env_vars.extend(request.env_vars.unwrap_or_default());
+        let session = PtySession::spawn(
+            &shell,
+            working_dir.as_deref(),
+            Some(&env_vars),
+            request.rows.unwrap_or(24),
+            request.cols.unwrap_or(80),
+        )
+        .map_err(|e| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to start pty session: {e}")),
+            data: None,
+        })?;
+
+        let pty_session_id = Uuid::new_v4().to_string();
+        self.pty_sessions.insert(pty_session_id.clone(), session);
+        info!("Start pty session {pty_session_id}");
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "pty_session_id": pty_session_id }),
+        )?]))
     }
 
-    #[tool(description = "Get the cpu info through the default shell")]
-    async fn unix_preset_get_cpu_info_via_default_shell(
+    #[tool(description = "Send input to a pty session")]
+    async fn pty_write(
         &self,
+        #[tool(aggr)] request: PtyWriteRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"LANG=C lscpu | awk -F: '$1=="Model name" {print $2}';awk '/processor/{core++} END{print core}' /proc/cpuinfo;uptime | sed 's/,/ /g' | awk '{for(i=NF-2;i<=NF;i++)print $i }' | xargs;vmstat 1 1 | awk 'NR==3{print $11}';vmstat 1 1 | awk 'NR==3{print $12}';vmstat 1 2 | awk 'NR==4{print $15}'"#;
-        let mut result = self
-            ._all_execute_via_default_shell(
-                false,
-                DefaultExecuteRequest {
-                    command: command.to_string(),
-                    working_dir: None,
-                    env_vars: None,
-                    timeout_seconds: Some(5),
-                },
-            )
-            .await?;
-        let raw_content = &mut result.content.get_mut(0).unwrap().raw;
-        if let RawContent::Text(RawTextContent { text }) = raw_content {
-            if let Ok(mut response) = serde_json::from_str::<DefaultExecuteResponse>(&text.clone())
-            {
-                // parse response.stdout
-                // output e.g.:
-                //                               Intel(R) Core(TM) i7-10875H CPU @ 2.30GHz
-                // 16
-                // 1.05 0.74 0.72
-                // 1261
-                // 5
-                // 92
-                let lines: Vec<&str> = response
-                    .stdout
-                    .lines()
-                    .map(|l| l.trim())
-                    .filter(|l| !l.is_empty())
-                    .collect();
-                if lines.len() >= 6 {
-                    let cpu_model = lines[0];
-                    let core_count = lines[1].parse::<u32>().unwrap_or(0);
-                    let load: Vec<f32> = lines[2]
-                        .split_whitespace()
-                        .filter_map(|s| s.parse::<f32>().ok())
-                        .collect();
-                    let interrupt = lines[3].parse::<u64>().unwrap_or(0);
-                    let context_switch = lines[4].parse::<u64>().unwrap_or(0);
-                    let idle = lines[5].parse::<u64>().unwrap_or(0);
+        let session = self.get_pty_session(&request.pty_session_id)?;
 
-                    let parsed = serde_json::json!({
-                        "cpu_model": cpu_model,
-                        "core_count": core_count,
-                        "load": load,
-                        "interrupt": interrupt,
-                        "context_switch": context_switch,
-                        "idle": idle
-                    });
-                    response.parsed_data = parsed;
-                    *text = serde_json::to_string(&response).unwrap();
-                }
+        // Best effort validation of what is typed into the terminal
+        if let Some(validator) = &self.validator {
+            let args: Vec<&OsStr> = request.input.split_whitespace().map(OsStr::new).collect();
+            if !args.is_empty() {
+                validator.is_unsafe_command(args)?;
             }
         }
 
-        Ok(result)
+        session
+            .write(request.input.as_bytes())
+            .map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to write to pty session: {e}")),
+                data: None,
+            })?;
+        info!(
+            "Write to pty session {}: {:?}",
+            request.pty_session_id, request.input
+        );
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Wrote {} bytes",
+            request.input.len()
+        ))]))
     }
 
-    #[tool(description = "Get the disk free info through the default shell")]
-    async fn unix_preset_get_disk_free_info_via_default_shell(
+    #[tool(description = "Read the pending output of a pty session")]
+    async fn pty_read(
         &self,
+        #[tool(aggr)] request: PtyReadRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"df -mP | tail -n +2 | awk 'BEGIN{ print "filesystem used available usage mounted"} {print $1,$3,$4,$5,$6}'"#;
-        let result = self
-            ._all_execute_via_default_shell(
-                false,
-                DefaultExecuteRequest {
-                    command: command.to_string(),
-                    working_dir: None,
-                    env_vars: None,
-                    timeout_seconds: Some(5),
-                },
-            )
-            .await?;
-        Ok(result)
+        let session = self.get_pty_session(&request.pty_session_id)?;
+
+        // Wait a little for output to arrive
+        let wait = std::time::Duration::from_millis(request.wait_ms.unwrap_or(100).min(10_000));
+        let deadline = tokio::time::Instant::now() + wait;
+        while !session.has_output()
+            && session.exit_code().is_none()
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let raw = String::from_utf8_lossy(&session.take_output()).to_string();
+        let output = if request.strip_ansi.unwrap_or(true) {
+            strip_ansi(&raw)
+        } else {
+            raw
+        };
+        let exit_code = session.exit_code();
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "output": output,
+                "exited": exit_code.is_some(),
+                "exit_code": exit_code,
+            }),
+        )?]))
     }
 
-    #[tool(description = "Get the top 10 cpu processes through the default shell")]
-    async fn unix_preset_get_top10_cpu_processes_via_default_shell(
+    #[tool(description = "Resize the terminal of a pty session")]
+    async fn pty_resize(
         &self,
+        #[tool(aggr)] request: PtyResizeRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"ps aux | sort -k3nr | awk 'BEGIN{ print "pid cpu_usage mem_usage command" } {printf "%s %s %s ", $2, $3, $4; for (i=11; i<=NF; i++) { printf "%s", $i; if (i < NF) printf " "; } print ""}' | head -n 11"#;
-        let result = self
-            ._all_execute_via_default_shell(
-                false,
-                DefaultExecuteRequest {
-                    command: command.to_string(),
-                    working_dir: None,
-                    env_vars: None,
-                    timeout_seconds: Some(5),
-                },
-            )
-            .await?;
-        Ok(result)
+        let session = self.get_pty_session(&request.pty_session_id)?;
+        session
+            .resize(request.rows, request.cols)
+            .map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to resize pty session: {e}")),
+                data: None,
+            })?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Resized to {}x{}",
+            request.cols, request.rows
+        ))]))
     }
 
-    #[tool(description = "Get the top 10 mem processes through the default shell")]
-    async fn unix_preset_get_top10_mem_processes_via_default_shell(
+    #[tool(description = "Terminate the shell of a pty session")]
+    async fn close_pty_session(
         &self,
+        #[tool(aggr)] request: PtySessionRequest,
     ) -> Result<CallToolResult, ErrorData> {
-        let command = r#"ps aux | sort -k4nr | awk 'BEGIN{ print "pid cpu_usage mem_usage command" } {printf "%s %s %s ", $2, $3, $4; for (i=11; i<=NF; i++) { printf "%s", $i; if (i < NF) printf " "; } print ""}' | head -n 11"#;
-        let result = self
-            ._all_execute_via_default_shell(
-                false,
-                DefaultExecuteRequest {
-                    command: command.to_string(),
-                    working_dir: None,
-                    env_vars: None,
-                    timeout_seconds: Some(5),
-                },
-            )
-            .await?;
-        Ok(result)
+        let session = self
+            .pty_sessions
+            .remove(&request.pty_session_id)
+            .ok_or_else(|| {
+                ErrorData::invalid_params(
+                    format!("pty session {} not found", request.pty_session_id),
+                    None,
+                )
+            })?;
+        session.kill();
+        info!("Close pty session {}", request.pty_session_id);
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Closed pty session {}",
+            request.pty_session_id
+        ))]))
     }
 }
 
-#[tool(tool_box)]
 impl ServerHandler for BashServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             protocol_version: ProtocolVersion::LATEST,
             capabilities: ServerCapabilities::builder()
                 .enable_prompts()
+                .enable_prompts_list_changed()
                 .enable_tools()
                 .enable_resources()
+                .enable_resources_subscribe()
                 .enable_logging()
+                .enable_completions()
                 .build(),
             instructions: Some(
                 r#"A Model Context Protocol server that can execute shell commands and scripts in the machine server deployed at.
@@ -560,11 +3291,210 @@ impl ServerHandler for BashServer {
         }
     }
 
+    async fn initialize(
+        &self,
+        request: InitializeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<InitializeResult, ErrorData> {
+        if context.peer.peer_info().is_none() {
+            context.peer.set_peer_info(request);
+        }
+        // Remember the peer so the session can be notified on shutdown
+        if let Some(session) = &self.session {
+            session.attach_peer(context.peer.clone());
+        }
+        Ok(self.get_info())
+    }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        self.refresh_roots(&context.peer).await;
+    }
+
+    async fn on_roots_list_changed(&self, context: NotificationContext<RoleServer>) {
+        self.refresh_roots(&context.peer).await;
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let token = access_token(&context);
+        self.permissions.check(token, &request.name)?;
+        self.scopes.check(token, &request.name)?;
+        Self::schema_validators().validate(&request.name, request.arguments.as_ref())?;
+        let context = ToolCallContext::new(self, request, context);
+        Self::tool_box().call(context).await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        let token = access_token(&context);
+        let mut tools = Self::tool_box().list();
+        tools.retain(|tool| self.permissions.allows(token, &tool.name));
+        self.annotations.annotate(&mut tools);
+        Ok(ListToolsResult {
+            tools,
+            next_cursor: None,
+        })
+    }
+
+    async fn complete(
+        &self,
+        CompleteRequestParam { argument, .. }: CompleteRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, ErrorData> {
+        let (values, total) = if completion::PATH_ARGUMENTS.contains(&argument.name.as_str()) {
+            completion::complete_path(&argument.value, &self.path_policy, MAX_COMPLETIONS)
+        } else {
+            (Vec::new(), 0)
+        };
+        Ok(CompleteResult {
+            completion: CompletionInfo {
+                has_more: Some(total > values.len()),
+                total: Some(total as u32),
+                values,
+            },
+        })
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, ErrorData> {
+        Ok(ListPromptsResult {
+            prompts: self
+                .prompts
+                .as_ref()
+                .map(|prompts| prompts.list())
+                .unwrap_or_default(),
+            next_cursor: None,
+        })
+    }
+
+    async fn get_prompt(
+        &self,
+        GetPromptRequestParam { name, arguments }: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, ErrorData> {
+        let template = self
+            .prompts
+            .as_ref()
+            .and_then(|prompts| prompts.get(&name))
+            .ok_or_else(|| ErrorData::invalid_params(format!("Unknown prompt {name}"), None))?;
+        info!("Get prompt {name}");
+        template.render(arguments.as_ref())
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, ErrorData> {
+        let files = resources::list_files(
+            &self.resources.directories,
+            &self.path_policy,
+            self.resources.max_resources.unwrap_or(1000),
+        );
+        let resources = files
+            .iter()
+            .filter_map(|path| {
+                let uri = resources::file_uri(path)?;
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                let mut resource = RawResource::new(uri, name);
+                resource.description = Some(path.display().to_string());
+                resource.size = fs::metadata(path)
+                    .ok()
+                    .and_then(|meta| u32::try_from(meta.len()).ok());
+                Some(resource.no_annotation())
+            })
+            .collect();
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        ReadResourceRequestParam { uri }: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, ErrorData> {
+        let path = self.resource_path(&uri)?;
+        let read_error = |e: std::io::Error| {
+            ErrorData::resource_not_found(format!("Failed to read {uri}: {e}"), None)
+        };
+        let size = fs::metadata(&path).map_err(read_error)?.len();
+        if size > MAX_READ_BYTES {
+            return Err(ErrorData::invalid_request(
+                format!("{uri} is larger than {MAX_READ_BYTES} bytes, use read_file with a range"),
+                None,
+            ));
+        }
+        let bytes = fs::read(&path).map_err(read_error)?;
+
+        info!("Read resource {}", path.display());
+        let contents = match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => ResourceContents::text(text, uri),
+            Ok(text) => ResourceContents::BlobResourceContents {
+                uri,
+                mime_type: Some("application/octet-stream".to_string()),
+                blob: BASE64.encode(text.as_bytes()),
+            },
+            Err(e) => ResourceContents::BlobResourceContents {
+                uri,
+                mime_type: Some("application/octet-stream".to_string()),
+                blob: BASE64.encode(e.as_bytes()),
+            },
+        };
+        Ok(ReadResourceResult {
+            contents: vec![contents],
+        })
+    }
+
+    async fn subscribe(
+        &self,
+        SubscribeRequestParam { uri }: SubscribeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        let path = self.resource_path(&uri)?;
+        self.resource_subscriptions
+            .subscribe(&context.peer, path, uri)
+            .map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to watch resource: {e}")),
+                data: None,
+            })
+    }
+
+    async fn unsubscribe(
+        &self,
+        UnsubscribeRequestParam { uri }: UnsubscribeRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        self.resource_subscriptions
+            .unsubscribe(&uri)
+            .map(|_| ())
+            .map_err(|e| ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!("Failed to stop watching resource: {e}")),
+                data: None,
+            })
+    }
+
     async fn set_level(
         &self,
         SetLevelRequestParam { level }: SetLevelRequestParam,
         context: RequestContext<RoleServer>,
     ) -> Result<(), ErrorData> {
+        // the level decides which server log records are forwarded to this session
+        if let Some(session) = &self.session {
+            session.set_log_level(level);
+        }
         let params = LoggingMessageNotificationParam {
             level,
             logger: Some("Server".to_string()),
@@ -581,3 +3511,31 @@ impl ServerHandler for BashServer {
             })
     }
 }
+
+// Up to MAX_READ_BYTES of a file as text
+fn read_lossy(path: &Path) -> std::io::Result<String> {
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(MAX_READ_BYTES)
+        .read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Write a complete file next to the target and move it into place
+fn write_atomically(target: &Path, content: &[u8], mode: u32) -> std::io::Result<()> {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = target.with_file_name(format!(".{name}.{}.tmp", Uuid::new_v4().simple()));
+    let written = write_and_rename(&temp, target, content, mode);
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_and_rename(temp: &Path, target: &Path, content: &[u8], mode: u32) -> std::io::Result<()> {
+    let mut file = File::options().write(true).create_new(true).open(temp)?;
+    file.write_all(content)?;
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    file.sync_all()?;
+    fs::rename(temp, target)
+}
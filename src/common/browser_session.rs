@@ -0,0 +1,288 @@
+// The session of a browser that approved clients, named by a signed cookie set on the
+// approval. /grants lists the grants it approved, revoking one there takes its tokens
+// away at once. The sessions live in memory, a restart logs every browser out.
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Form,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rmcp::serde_json;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::common::audit::{AuditEvent, Outcome};
+use crate::common::oauth::{McpOAuthStore, generate_random_string};
+use crate::common::pages;
+
+pub const COOKIE: &str = "mcp_browser_session";
+const LIFETIME: chrono::TimeDelta = chrono::TimeDelta::days(30);
+
+// A grant approved in the browser, its tokens are issued later to the client
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovedGrant {
+    pub grant_id: String,
+    pub client_id: String,
+    pub scope: String,
+    pub approved_at: String, // RFC 3339
+}
+
+#[derive(Debug)]
+struct BrowserSession {
+    user: Option<String>, // who logged in on the last approval, if logins are required
+    csrf_token: String,   // of the forms of /grants
+    grants: Vec<ApprovedGrant>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct BrowserSessions {
+    key: [u8; 32], // signs the cookies, new on every start
+    sessions: Mutex<HashMap<String, BrowserSession>>,
+}
+
+impl std::fmt::Debug for BrowserSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BrowserSessions")
+            .field("sessions", &self.sessions.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for BrowserSessions {
+    fn default() -> Self {
+        let mut key = [0; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        BrowserSessions {
+            key,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl BrowserSessions {
+    fn mac(&self, session_id: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(session_id.as_bytes());
+        mac
+    }
+
+    // The id of a live session of the cookie, none for missing and forged ones
+    fn session_id(&self, headers: &HeaderMap) -> Option<String> {
+        let value = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .find_map(|cookie| cookie.trim().strip_prefix(&format!("{COOKIE}=")))?;
+        let (session_id, signature) = value.split_once('.')?;
+        // verify_slice compares in constant time
+        self.mac(session_id)
+            .verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?)
+            .ok()?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = chrono::Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions
+            .contains_key(session_id)
+            .then(|| session_id.to_string())
+    }
+
+    fn set_cookie(&self, session_id: &str) -> HeaderValue {
+        let value = format!(
+            "{COOKIE}={session_id}.{}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            URL_SAFE_NO_PAD.encode(self.mac(session_id).finalize().into_bytes()),
+            LIFETIME.num_seconds()
+        );
+        HeaderValue::from_str(&value).expect("the cookie is ASCII")
+    }
+
+    // Remember the grant in the session of the browser, a new one without a cookie.
+    // The Set-Cookie header of the answer of the approval.
+    pub fn add_grant(
+        &self,
+        headers: &HeaderMap,
+        user: Option<String>,
+        grant: ApprovedGrant,
+    ) -> HeaderValue {
+        let session_id = self
+            .session_id(headers)
+            .unwrap_or_else(|| generate_random_string(32));
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .entry(session_id.clone())
+            .or_insert_with(|| BrowserSession {
+                user: None,
+                csrf_token: generate_random_string(32),
+                grants: Vec::new(),
+                expires_at: chrono::Utc::now() + LIFETIME,
+            });
+        if user.is_some() {
+            session.user = user;
+        }
+        session.grants.push(grant);
+        session.expires_at = chrono::Utc::now() + LIFETIME;
+        self.set_cookie(&session_id)
+    }
+
+    // Take the grant out of the session, none if it has no such grant or the form is not
+    // of its page
+    fn take_grant(
+        &self,
+        session_id: &str,
+        csrf_token: &str,
+        grant_id: &str,
+    ) -> Option<ApprovedGrant> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|session| session.csrf_token == csrf_token)?;
+        let index = session
+            .grants
+            .iter()
+            .position(|grant| grant.grant_id == grant_id)?;
+        Some(session.grants.remove(index))
+    }
+
+    fn end(&self, session_id: &str, csrf_token: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .get(session_id)
+            .is_some_and(|session| session.csrf_token == csrf_token)
+        {
+            sessions.remove(session_id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// The values of templates/mcp_oauth_grants.html
+#[derive(Serialize, Default)]
+struct GrantsTemplate {
+    signed_in: bool, // the browser has a session
+    user: Option<String>,
+    csrf_token: String,
+    grants: Vec<ApprovedGrant>,
+}
+
+// The grants the browser approved
+#[utoipa::path(
+    get,
+    path = "/grants",
+    tag = "oauth",
+    responses((status = 200, description = "The grants approved in this browser, with buttons to revoke them", content_type = "text/html")),
+)]
+pub async fn oauth_grants(State(state): State<Arc<McpOAuthStore>>, headers: HeaderMap) -> Response {
+    let sessions = &state.browser_sessions;
+    let template = sessions
+        .session_id(&headers)
+        .and_then(|session_id| {
+            let sessions = sessions.sessions.lock().unwrap();
+            let session = sessions.get(&session_id)?;
+            Some(GrantsTemplate {
+                signed_in: true,
+                user: session.user.clone(),
+                csrf_token: session.csrf_token.clone(),
+                grants: session.grants.clone(),
+            })
+        })
+        .unwrap_or_default();
+    state.pages.render(StatusCode::OK, pages::GRANTS, template)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrantForm {
+    #[serde(default)]
+    pub csrf_token: String,
+    #[serde(default)]
+    pub grant_id: String, // only for /grants/revoke
+}
+
+#[utoipa::path(
+    post,
+    path = "/grants/revoke",
+    tag = "oauth",
+    request_body(content = GrantForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Back to /grants, the tokens of the grant are revoked"),
+        (status = 400, description = "No session, a form of another page or a grant of another session", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_revoke_grant(
+    State(state): State<Arc<McpOAuthStore>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<GrantForm>,
+) -> Response {
+    let Some(grant) = state
+        .browser_sessions
+        .session_id(&headers)
+        .and_then(|session_id| {
+            state
+                .browser_sessions
+                .take_grant(&session_id, &form.csrf_token, &form.grant_id)
+        })
+    else {
+        return invalid_grants_form(&state);
+    };
+    state.revoke_grant(&grant.grant_id).await;
+    info!(
+        "grant {} of client {} revoked in the browser",
+        grant.grant_id, grant.client_id
+    );
+    AuditEvent::new("grant_revoked", Outcome::Success)
+        .client(&grant.client_id)
+        .ip(Some(addr.ip()))
+        .reason("in the browser that approved it")
+        .emit();
+    Redirect::to("/grants").into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "oauth",
+    request_body(content = GrantForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Back to /grants, the session cookie is cleared. The grants keep their tokens."),
+        (status = 400, description = "No session or a form of another page", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_logout(
+    State(state): State<Arc<McpOAuthStore>>,
+    headers: HeaderMap,
+    Form(form): Form<GrantForm>,
+) -> Response {
+    let ended = state
+        .browser_sessions
+        .session_id(&headers)
+        .is_some_and(|session_id| state.browser_sessions.end(&session_id, &form.csrf_token));
+    if !ended {
+        return invalid_grants_form(&state);
+    }
+    let cleared = format!("{COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax");
+    ([(header::SET_COOKIE, cleared)], Redirect::to("/grants")).into_response()
+}
+
+fn invalid_grants_form(state: &McpOAuthStore) -> Response {
+    state.pages.render(
+        StatusCode::BAD_REQUEST,
+        pages::ERROR,
+        serde_json::json!({
+            "description": "The form has expired or is not of this browser. Open /grants again."
+        }),
+    )
+}
@@ -0,0 +1,156 @@
+// `mcp-bash-server --check`: everything wrong with a config file, without serving it
+use std::{fs, net::SocketAddr};
+
+use serde::de::DeserializeOwned;
+
+use crate::common::api_keys::ApiKeyStore;
+use crate::common::config::{self, ApiKey, AuthMode, Config, RegistrationMode, TokenFormat};
+use crate::common::jwt::JwtKeys;
+use crate::common::oidc::OidcVerifier;
+use crate::common::pages::Pages;
+use crate::common::pinning::CertificatePins;
+use crate::common::sudo::SudoMode;
+use crate::common::totp::SecondFactor;
+use crate::common::{sandbox, users};
+
+// The errors of the file with the overrides of the environment, empty if it can be served
+pub fn check_config_file(path: &str) -> Vec<String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return vec![format!("can't read {path}: {e}")],
+    };
+    // a syntax error stops the parser, there is nothing more to check
+    let table = match config::toml_with_env(&text, std::env::vars()) {
+        Ok(table) => table,
+        Err(e) => return vec![format!("{e:#}")],
+    };
+    match toml::Value::Table(table.clone()).try_into::<Config>() {
+        Ok(config) => check_config(&config),
+        Err(e) => {
+            let errors = section_errors(&table);
+            if errors.is_empty() {
+                vec![e.to_string()]
+            } else {
+                errors
+            }
+        }
+    }
+}
+
+// Each section on its own, so a bad one doesn't hide the others
+fn section_errors(table: &toml::Table) -> Vec<String> {
+    let mut errors = Vec::new();
+    for required in ["settings", "blacklist"] {
+        if !table.contains_key(required) {
+            errors.push(format!("[{required}] is missing"));
+        }
+    }
+    section::<config::Settings>(table, "settings", &mut errors);
+    section::<config::Blacklist>(table, "blacklist", &mut errors);
+    section::<config::Security>(table, "security", &mut errors);
+    section::<config::Bash>(table, "bash", &mut errors);
+    section::<config::Http>(table, "http", &mut errors);
+    section::<config::Resources>(table, "resources", &mut errors);
+    section::<config::Idempotency>(table, "idempotency", &mut errors);
+    section::<config::Tools>(table, "tools", &mut errors);
+    section::<config::Schedules>(table, "schedules", &mut errors);
+    section::<config::Mcp>(table, "mcp", &mut errors);
+    section::<config::Pagination>(table, "pagination", &mut errors);
+    section::<config::OAuth>(table, "oauth", &mut errors);
+    section::<config::Webhooks>(table, "webhooks", &mut errors);
+    section::<Vec<ApiKey>>(table, "api_keys", &mut errors);
+    section::<config::Oidc>(table, "oidc", &mut errors);
+    section::<config::Admin>(table, "admin", &mut errors);
+    errors
+}
+
+fn section<T: DeserializeOwned>(table: &toml::Table, name: &str, errors: &mut Vec<String>) {
+    if let Some(value) = table.get(name)
+        && let Err(e) = value.clone().try_into::<T>()
+    {
+        errors.push(format!("[{name}] {}", e.to_string().trim_end()));
+    }
+}
+
+// What the server would refuse to start with, or fail on the first command
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+    let settings = &config.settings;
+    let primary = format!("{}:{}", settings.host, settings.port);
+    for addr in std::iter::once(&primary).chain(&settings.additional_bind_addresses) {
+        if addr.parse::<SocketAddr>().is_err() {
+            errors.push(format!("[settings] {addr} is no host:port address"));
+        }
+    }
+    if let Err(e) = settings.external_url() {
+        errors.push(format!("{e:#}"));
+    }
+    if let Some(landlock) = &config.security.landlock
+        && let Err(e) = sandbox::check_landlock(landlock)
+    {
+        errors.push(format!("[security.landlock] {e:#}"));
+    }
+    // restrict_self sets no_new_privs, sudo and doas can't gain privileges after it
+    if config.security.landlock.is_some() && config.security.sudo.mode != SudoMode::Deny {
+        errors.push(
+            "[security.landlock] sandboxed commands can't elevate, sudo and doas fail under it, set [security.sudo] mode = \"deny\" or drop [security.landlock]".into(),
+        );
+    }
+    if let Err(e) = config.security.check_sudo() {
+        errors.push(format!("{e:#}"));
+    }
+    if let Some(shell) = &config.bash.shell
+        && !shell.is_file()
+    {
+        errors.push(format!(
+            "[bash] the shell {} does not exist",
+            shell.display()
+        ));
+    }
+
+    match ApiKeyStore::new(&config.api_keys, &config.oauth.scopes_supported) {
+        Ok(keys) if keys.is_empty() && settings.auth_mode() == AuthMode::ApiKey => {
+            errors.push("auth_mode = \"api_key\" needs at least one [[api_keys]] entry".into())
+        }
+        Ok(_) => {}
+        Err(e) => errors.push(format!("[[api_keys]] {e:#}")),
+    }
+    if settings.auth_mode() == AuthMode::Oidc
+        && let Err(e) = OidcVerifier::new(&config.oidc, &CertificatePins::default())
+    {
+        errors.push(format!("[oidc] {e:#}"));
+    }
+    if config.oauth.jwks_uri.is_some()
+        && let Err(e) = OidcVerifier::remote_jwks(&config.oauth, &CertificatePins::default())
+    {
+        errors.push(format!("{e:#}"));
+    }
+    if config.oauth.registration_mode == RegistrationMode::Token
+        && config
+            .oauth
+            .registration_tokens
+            .iter()
+            .all(String::is_empty)
+    {
+        errors.push("[oauth] registration_mode = \"token\" needs registration_tokens".to_string());
+    }
+    if config.oauth.token_format == TokenFormat::Jwt
+        && let Err(e) = JwtKeys::new(&config.oauth.jwt, primary.clone())
+    {
+        errors.push(format!("[oauth.jwt] {e:#}"));
+    }
+    if config.oauth.totp.enabled
+        && let Err(e) = SecondFactor::check(&config.oauth.totp)
+    {
+        errors.push(format!("{e:#}"));
+    }
+    if let Err(e) = Pages::new(&config.web) {
+        errors.push(format!("{e:#}"));
+    }
+    if let Some(path) = &config.oauth.users_file
+        && let Err(e) = users::read_users_file(path)
+    {
+        errors.push(format!("[oauth] users_file: {e:#}"));
+    }
+    errors
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use globset::GlobSet;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Some(Algorithm::Sha256),
+            "blake3" => Some(Algorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hex(&hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+// The files of a tree and their hashes, keyed by relative path so two manifests
+// of the same tree can be diffed directly
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub algorithm: Algorithm,
+    pub files: BTreeMap<String, String>,
+    pub total_bytes: u64,
+    // hash over the sorted "path hash" lines, equal digests mean equal trees
+    pub digest: String,
+    pub skipped: Vec<String>,
+}
+
+// A tree that was walked but not hashed yet
+pub struct TreePlan {
+    pub files: Vec<(String, PathBuf)>,
+    pub symlinks: Vec<(String, PathBuf)>,
+    pub skipped: Vec<String>,
+    pub total_bytes: u64,
+}
+
+pub fn hash_file(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+// Walk the tree without reading any content, so the size cap can be checked first.
+// Directories rejected by `allow` and paths matching `excludes` are skipped.
+pub fn plan_tree(
+    root: &Path,
+    excludes: &GlobSet,
+    allow: impl Fn(&Path) -> bool,
+) -> io::Result<TreePlan> {
+    let mut plan = TreePlan {
+        files: Vec::new(),
+        symlinks: Vec::new(),
+        skipped: Vec::new(),
+        total_bytes: 0,
+    };
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+            if excludes.is_match(&relative) {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                plan.symlinks.push((relative, path));
+            } else if file_type.is_dir() {
+                if allow(&path) {
+                    pending.push(path);
+                } else {
+                    plan.skipped.push(relative);
+                }
+            } else if file_type.is_file() {
+                plan.total_bytes += entry.metadata()?.len();
+                plan.files.push((relative, path));
+            }
+        }
+    }
+    Ok(plan)
+}
+
+pub fn hash_tree(plan: TreePlan, algorithm: Algorithm) -> io::Result<Manifest> {
+    let mut files = BTreeMap::new();
+    for (relative, path) in plan.files {
+        files.insert(relative, hash_file(&path, algorithm)?);
+    }
+    // A symlink is identified by its target, it is never followed
+    for (relative, path) in plan.symlinks {
+        let target = fs::read_link(&path)?;
+        files.insert(relative, format!("symlink:{}", target.display()));
+    }
+
+    let mut digest = algorithm.hasher();
+    for (relative, hash) in &files {
+        digest.update(format!("{relative} {hash}\n").as_bytes());
+    }
+    Ok(Manifest {
+        algorithm,
+        files,
+        total_bytes: plan.total_bytes,
+        digest: digest.finish(),
+        skipped: plan.skipped,
+    })
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,64 @@
+use std::fs;
+
+use crate::common::path_policy::PathPolicy;
+
+// Arguments of the tools that take a filesystem path
+pub const PATH_ARGUMENTS: &[&str] = &[
+    "path",
+    "working_dir",
+    "working_directory",
+    "base_dir",
+    "archive_path",
+    "destination",
+];
+
+// Entries matching a partial path, directories end with a slash. Only paths the
+// policy lets the file tools read are suggested. Returns the suggestions and the
+// number of matches before the limit.
+pub fn complete_path(partial: &str, policy: &PathPolicy, limit: usize) -> (Vec<String>, usize) {
+    // Nothing typed yet inside a jail, start with its roots
+    if partial.is_empty() && policy.is_enabled() {
+        let roots = policy
+            .client_roots()
+            .unwrap_or_else(|| policy.allowed_roots().to_vec());
+        let mut values: Vec<String> = roots
+            .iter()
+            .map(|root| format!("{}/", root.display()))
+            .collect();
+        values.sort();
+        let total = values.len();
+        values.truncate(limit);
+        return (values, total);
+    }
+
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(index) => (&partial[..=index], &partial[index + 1..]),
+        None => ("", partial),
+    };
+    let Ok(resolved) = policy.check_read(if dir.is_empty() { "." } else { dir }) else {
+        return (Vec::new(), 0);
+    };
+    let Ok(entries) = fs::read_dir(&resolved) else {
+        return (Vec::new(), 0);
+    };
+
+    let show_hidden = prefix.starts_with('.');
+    let mut values: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !show_hidden) {
+                return None;
+            }
+            // denied subpaths and symlinks out of the jail are not suggested
+            let path = resolved.join(&name);
+            policy.check_read(&path).ok()?;
+            let is_dir = path.is_dir();
+            Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    values.sort();
+    let total = values.len();
+    values.truncate(limit);
+    (values, total)
+}
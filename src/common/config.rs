@@ -1,11 +1,458 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::PathBuf;
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::common::output::LimitMode;
+use crate::common::sudo::SudoMode;
+
+// Environment variables starting with this override keys of the config file, the rest of
+// the name is the path of the key: MCP_BASH_SERVER__OAUTH__JWT__SECRET sets the secret of
+// [oauth.jwt]. Values are read as TOML, anything else is a string.
+pub const ENV_PREFIX: &str = "MCP_BASH_SERVER__";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub settings: Settings,
     pub blacklist: Blacklist,
+    #[serde(default)]
+    pub security: Security,
+    #[serde(default)]
+    pub bash: Bash,
+    #[serde(default)]
+    pub http: Http,
+    #[serde(default)]
+    pub resources: Resources,
+    #[serde(default)]
+    pub idempotency: Idempotency,
+    #[serde(default)]
+    pub tools: Tools,
+    #[serde(default)]
+    pub schedules: Schedules,
+    #[serde(default)]
+    pub mcp: Mcp,
+    #[serde(default)]
+    pub pagination: Pagination,
+    #[serde(default)]
+    pub oauth: OAuth,
+    #[serde(default)]
+    pub webhooks: Webhooks,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>, // the keys of auth_mode = "api_key"
+    #[serde(default)]
+    pub oidc: Oidc, // the provider of auth_mode = "oidc"
+    #[serde(default)]
+    pub admin: Admin,
+    #[serde(default)]
+    pub web: Web,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Mcp {
+    #[serde(default)]
+    pub enable_log_forwarding: bool, // send the server log to the clients as MCP log notifications
+}
+
+// Limits of schedule_command, schedules live in memory and are lost on restart
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Schedules {
+    pub max_pending: usize,     // schedules waiting to run at the same time
+    pub max_delay_seconds: u64, // how far ahead a command may be scheduled
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Schedules {
+            max_pending: 20,
+            max_delay_seconds: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+// The /admin endpoints, they are not served without a token
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Admin {
+    pub token: Option<String>, // sent as Authorization: Bearer <token>
+}
+
+// The HTML pages of the index, the approval and the device flow
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Web {
+    pub templates_dir: Option<PathBuf>, // files named like the embedded templates replace them
+    pub server_name: String,            // shown in the title of every page
+}
+
+impl Default for Web {
+    fn default() -> Self {
+        Web {
+            templates_dir: None,
+            server_name: "MCP Bash Server".to_string(),
+        }
+    }
+}
+
+// HTTP POST targets per event, events without a URL are not sent
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Webhooks {
+    pub command_failed: Option<String>, // a command exited non-zero, timed out or did not start
+    pub job_completed: Option<String>,  // a scheduled command finished
+    pub session_closed: Option<String>,
+    pub secret: Option<String>, // HMAC-SHA256 key of the X-Webhook-Signature header
+    pub timeout_seconds: u64,   // per delivery attempt
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Webhooks {
+            command_failed: None,
+            job_completed: None,
+            session_closed: None,
+            secret: None,
+            timeout_seconds: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Tools {
+    #[serde(default)]
+    pub annotations: BTreeMap<String, Annotation>, // by tool name, on top of the built-in hints
+    #[serde(default)]
+    pub permissions: ToolPermissionsConfig,
+}
+
+// Tools each OAuth client may use, lists hold tool names or patterns with `*`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolPermissionsConfig {
+    #[serde(default)]
+    pub clients: BTreeMap<String, Vec<String>>, // by OAuth client id
+    #[serde(default)]
+    pub scopes: BTreeMap<String, Vec<String>>, // by scope of the access token
+    pub default: Option<Vec<String>>, // clients no list applies to, every tool if not set
+}
+
+// Behavior hints shown to clients, an unset field keeps the built-in value
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Annotation {
+    pub title: Option<String>,
+    pub read_only: Option<bool>,   // the tool does not change anything
+    pub destructive: Option<bool>, // the tool may delete or overwrite data
+    pub idempotent: Option<bool>,  // calling it twice with the same arguments does nothing more
+    pub open_world: Option<bool>,  // the tool talks to systems outside of this machine
+}
+
+// How long the results of calls with an idempotency_key are kept for retries
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Idempotency {
+    pub window_seconds: u64,     // a retry after this long runs the call again
+    pub max_entries: usize,      // per session, the oldest results are dropped first
+    pub max_result_bytes: usize, // larger results are not kept, retries get an error instead
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency {
+            window_seconds: 600,
+            max_entries: 100,
+            max_result_bytes: 1024 * 1024,
+        }
+    }
+}
+
+// Tokens issued by the OAuth endpoints
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OAuth {
+    pub token_ttl_seconds: u64, // access tokens are rejected after this, clients refresh them
+    pub refresh_token_rotation: bool, // every refresh issues a new refresh token, a replayed one revokes the grant
+    pub authorization_code_ttl_seconds: u64, // codes not exchanged by then are refused, at most 600
+    pub device_code_ttl_seconds: u64, // how long a device code of /device_authorization waits for approval
+    pub device_poll_interval_seconds: u64, // the least interval of a device polling /token, 5 more after every slow_down
+    pub require_pkce: bool, // refuse authorization requests without an S256 code_challenge
+    pub storage_path: Option<PathBuf>, // JSON file keeping clients and tokens over restarts, memory only if not set
+    pub scopes_supported: Vec<String>, // advertised in the metadata, /authorize refuses other scopes
+    pub default_scopes: Vec<String>,   // granted when an authorization request names no scope
+    pub tool_scopes: BTreeMap<String, String>, // tool name or pattern to the scope a call needs
+    pub introspection_secret: Option<String>, // bearer secret of /introspect callers that are no client, e.g. a gateway
+    pub clients: Vec<OAuthClient>,            // registered at startup, on top of /register
+    pub client_credentials_clients: Option<Vec<String>>, // who may use grant_type=client_credentials, any client with a secret if not set
+    pub loopback_redirect_any_port: bool, // http://127.0.0.1 and http://[::1] redirect uris match on any port (RFC 8252)
+    pub users: Vec<OAuthUser>, // who may approve authorization requests, no login is asked if there are none
+    pub users_file: Option<PathBuf>, // TOML file with more [[users]], read at startup
+    pub max_failed_logins: u32, // failed logins from one address before it is locked out
+    pub login_lockout_seconds: u64, // how long the lockout lasts
+    pub login_backoff_seconds: u64, // wait after a failed login of a username, doubles with every failure up to the lockout
+    pub max_failed_logins_per_user: u32, // failed logins of a user, from any address, before the account is locked, 0 never locks
+    pub user_lockout_seconds: u64,       // how long an account stays locked
+    pub rate_limit: RateLimit,           // of the token, registration and approval endpoints
+    pub token_endpoint_rate_limit: TokenEndpointRateLimit, // a token bucket per client_id on /token
+    pub idempotency: TokenIdempotency,   // the Idempotency-Key header of /token
+    pub token_format: TokenFormat,       // of the access tokens
+    pub jwt: Jwt,                        // keys of token_format = "jwt"
+    pub jwks_uri: Option<String>,        // JWTs are checked against the keys published there first
+    pub jwks_issuer: Option<String>,     // the iss claim of those JWTs, not checked if not set
+    pub jwks_audience: Option<String>,   // their aud claim, not checked if not set
+    pub jwks_refresh_seconds: u64, // how long the fetched keys are used before they are fetched again
+    pub expected_audience: Option<String>, // /mcp refuses JWTs without it in their aud claim
+    pub accept_token_in_query: bool, // the access_token query parameter of legacy clients, refused with 400 if not
+    pub accept_fallback_header: bool, // X-MCP-Authorization of proxies that keep Authorization
+    pub dpop_required: bool, // tokens are only issued and accepted with DPoP proofs (RFC 9449)
+    pub registration_mode: RegistrationMode, // who may use /register
+    pub registration_tokens: Vec<String>, // initial access tokens of registration_mode = "token"
+    pub max_registrations_per_token: u32, // clients one initial access token may register, 0 for no limit
+    pub max_failed_client_authentications: u32, // wrong secrets of a client within the window before it is locked, 0 never locks
+    pub client_failure_window_seconds: u64,     // how long a wrong secret counts
+    pub client_lockout_seconds: u64,            // how long a client stays locked
+    pub totp: Totp,                             // the second factor of approvals
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenFormat {
+    #[default]
+    Opaque, // random strings, only valid on the server that issued them
+    Jwt, // signed JWTs any replica with the keys can check
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    #[default]
+    Open, // anyone reaching /register
+    Token,  // requests with an initial access token (RFC 7591 section 3)
+    Closed, // only the clients of the config
+}
+
+// [oauth.rate_limit], requests counted in a sliding window
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub enabled: bool,
+    pub window_seconds: u64,
+    pub requests_per_ip: u32,     // 0 for no limit
+    pub requests_per_client: u32, // per client_id named by the request, 0 for no limit
+    pub max_tracked_keys: usize, // addresses and client ids remembered, the oldest are dropped first
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            enabled: true,
+            window_seconds: 60,
+            requests_per_ip: 60,
+            requests_per_client: 60,
+            max_tracked_keys: 10_000,
+        }
+    }
+}
+
+// [oauth.token_endpoint_rate_limit], the token requests of a client_id take one token of
+// its bucket each, an empty bucket is answered with 429
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TokenEndpointRateLimit {
+    pub enabled: bool,
+    pub capacity: u32,              // requests of a client at once, 0 for no limit
+    pub refill_per_second: f64,     // tokens the bucket of a client gets back per second
+    pub max_tracked_clients: usize, // buckets remembered, the longest unused are dropped first
+}
+
+impl Default for TokenEndpointRateLimit {
+    fn default() -> Self {
+        TokenEndpointRateLimit {
+            enabled: true,
+            capacity: 30,
+            refill_per_second: 1.0,
+            max_tracked_clients: 10_000,
+        }
+    }
+}
+
+// [oauth.idempotency], retried token requests with the same Idempotency-Key get the first
+// answer
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TokenIdempotency {
+    pub enabled: bool,
+    pub window_seconds: u64, // a retry after this long is a new request
+    pub max_entries: usize,  // answers kept, the least recently used are dropped first
+}
+
+impl Default for TokenIdempotency {
+    fn default() -> Self {
+        TokenIdempotency {
+            enabled: true,
+            window_seconds: 60,
+            max_entries: 1000,
+        }
+    }
+}
+
+// [oauth.totp], approving a client needs a code of an authenticator app (RFC 6238)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Totp {
+    pub enabled: bool,
+    pub secret: Option<String>, // base32, generated and shown on the approval pages if not set
+    pub issuer: String,         // the name of the secret in the authenticator app
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Totp {
+            enabled: false,
+            secret: None,
+            issuer: "MCP Bash Server".to_string(),
+        }
+    }
+}
+
+// [oauth.jwt]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Jwt {
+    pub algorithm: JwtAlgorithm,
+    pub secret: Option<String>,            // HS256 key, at least 32 bytes
+    pub private_key_file: Option<PathBuf>, // PEM, signs the tokens with RS256 and EdDSA
+    pub public_key_file: Option<PathBuf>,  // PEM, checks them and is published as the JWKS
+    pub audience: Option<String>,          // the aud claim, the resource of /mcp if not set
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JwtAlgorithm {
+    #[default]
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+// A user of [[oauth.users]]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuthUser {
+    pub username: String,
+    pub password_hash: String, // argon2 or bcrypt, `mcp-bash-server hash-password` prints one
+}
+
+// A client of [[oauth.clients]]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub redirect_uri: String, // only needed for the authorization code flow
+    #[serde(default)]
+    pub redirect_uris: Vec<String>, // more of them, /authorize sends codes to exact matches only
+    #[serde(default)]
+    pub scopes: Vec<String>, // the most the client is granted, the supported scopes if empty
+}
+
+impl Default for OAuth {
+    fn default() -> Self {
+        OAuth {
+            token_ttl_seconds: 3600,
+            refresh_token_rotation: true,
+            authorization_code_ttl_seconds: 60,
+            device_code_ttl_seconds: 600,
+            device_poll_interval_seconds: 5,
+            require_pkce: false,
+            storage_path: None,
+            scopes_supported: [
+                "mcp:read",
+                "mcp:execute",
+                "processes:read",
+                "profile",
+                "email",
+            ]
+            .map(String::from)
+            .to_vec(),
+            default_scopes: ["mcp:read", "mcp:execute"].map(String::from).to_vec(),
+            tool_scopes: BTreeMap::new(),
+            introspection_secret: None,
+            clients: Vec::new(),
+            client_credentials_clients: None,
+            loopback_redirect_any_port: true,
+            users: Vec::new(),
+            users_file: None,
+            max_failed_logins: 5,
+            login_lockout_seconds: 300,
+            login_backoff_seconds: 1,
+            max_failed_logins_per_user: 10,
+            user_lockout_seconds: 900,
+            rate_limit: RateLimit::default(),
+            token_endpoint_rate_limit: TokenEndpointRateLimit::default(),
+            idempotency: TokenIdempotency::default(),
+            token_format: TokenFormat::Opaque,
+            jwt: Jwt::default(),
+            jwks_uri: None,
+            jwks_issuer: None,
+            jwks_audience: None,
+            jwks_refresh_seconds: 300,
+            expected_audience: None,
+            accept_token_in_query: false,
+            accept_fallback_header: false,
+            dpop_required: false,
+            registration_mode: RegistrationMode::Open,
+            registration_tokens: Vec::new(),
+            max_registrations_per_token: 10,
+            max_failed_client_authentications: 10,
+            client_failure_window_seconds: 600,
+            client_lockout_seconds: 300,
+            totp: Totp::default(),
+        }
+    }
+}
+
+// Output of calls with paginate = true, kept for fetch_output_page
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Pagination {
+    pub page_bytes: usize,  // per stream and page
+    pub ttl_seconds: u64,   // a buffer is dropped this long after the command finished
+    pub max_buffers: usize, // per session, the oldest buffers are dropped first
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination {
+            page_bytes: 64 * 1024,
+            ttl_seconds: 900,
+            max_buffers: 16,
+        }
+    }
+}
+
+// Files advertised as MCP resources
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Resources {
+    #[serde(default)]
+    pub directories: Vec<PathBuf>, // files below these are listed as file:// resources
+    pub max_resources: Option<usize>, // default 1000
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Bash {
+    pub shell: Option<PathBuf>, // default shell, "/bin/bash" if not set
+    #[serde(default)]
+    pub allowed_shells: Vec<PathBuf>, // shells a tool call may select, "/etc/shells" if empty
+    pub scratch_quota_bytes: Option<u64>, // size limit of a session scratch directory, default 100 MiB
+    pub stream_flush_bytes: Option<usize>, // streamed output is sent once this much is buffered, default 4096
+    pub stream_flush_ms: Option<u64>,      // or after this many milliseconds, default 100
+    pub max_output_lines: Option<usize>, // lines of stdout/stderr returned by the execute tools, unlimited if not set
+    pub max_output_bytes: Option<usize>, // bytes of stdout/stderr, cut at whole lines, unlimited if not set
+    pub output_limit_mode: Option<LimitMode>, // "head", "tail" or "head_tail" (default)
+    #[serde(default)]
+    pub load_profile: bool, // source /etc/profile before every command
+    pub profile_script: Option<String>, // init snippet run before every command, after /etc/profile
+    pub history_size: Option<usize>, // commands kept per session for get_command_history, default 100
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -14,17 +461,292 @@ pub struct Blacklist {
     pub operations: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// What the http_request tool may reach
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Http {
+    pub enabled: bool,                 // the tool is refused while false
+    pub allowed_urls: Vec<String>,     // url patterns with `*`, empty allows every url
+    pub denied_urls: Vec<String>,      // url patterns with `*`, checked before the allowed ones
+    pub block_private_addresses: bool, // refuse loopback, private and link-local (metadata) addresses
+    pub follow_redirects: bool,
+    pub max_redirects: Option<usize>, // default 5
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Http {
+            enabled: false,
+            allowed_urls: Vec::new(),
+            denied_urls: Vec::new(),
+            block_private_addresses: true,
+            follow_redirects: false,
+            max_redirects: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Security {
+    pub landlock: Option<Landlock>,
+    #[serde(default)]
+    pub path_jail: PathJail,
+    #[serde(default)]
+    pub sudo: Sudo,
+    #[serde(default)]
+    pub sudo_allowed_commands: Vec<String>, // programs that may run under sudo/doas, empty does not restrict
+    #[serde(default)]
+    pub allowed_read_paths: Vec<String>, // path prefixes the file tools may read, empty allows the whole jail
+    #[serde(default)]
+    pub allowed_write_paths: Vec<String>, // path prefixes the file tools may write, empty allows the whole jail
+    #[serde(default)]
+    pub redact_env_patterns: Vec<String>, // get_env hides the values of matching variable names, e.g. "*TOKEN*"
+    #[serde(default)]
+    pub own_processes_only: bool, // list_processes only shows processes started by the server
+    #[serde(default)]
+    pub pin_certificates: Vec<CertificatePin>, // certificates the outbound HTTP clients expect of these hosts
+}
+
+impl Security {
+    // sudo_allowed_commands narrows a mode that elevates, under "deny" nothing of the list
+    // would ever run
+    pub fn check_sudo(&self) -> Result<()> {
+        if self.sudo.mode == SudoMode::Deny && !self.sudo_allowed_commands.is_empty() {
+            bail!(
+                "[security] sudo_allowed_commands has no effect with [security.sudo] mode = \"deny\", use \"allow_listed\" or \"allow_all\""
+            );
+        }
+        Ok(())
+    }
+}
+
+// [[security.pin_certificates]], a host may be pinned to several certificates to rotate
+// them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CertificatePin {
+    pub host: String, // as in the URLs, e.g. "hooks.example.com"
+    pub sha256_fingerprint: Fingerprint,
+}
+
+// The SHA-256 of a DER certificate, hex with or without colons as printed by
+// `openssl x509 -noout -fingerprint -sha256`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl TryFrom<String> for Fingerprint {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let digits: Vec<u8> = text.bytes().filter(|byte| *byte != b':').collect();
+        let mut fingerprint = [0; 32];
+        if digits.len() != 64 || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(format!(
+                "{text:?} is no SHA-256 fingerprint of 64 hex digits"
+            ));
+        }
+        for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).unwrap_or_default();
+            *byte = u8::from_str_radix(pair, 16).unwrap_or_default();
+        }
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+impl From<Fingerprint> for String {
+    fn from(fingerprint: Fingerprint) -> Self {
+        fingerprint
+            .0
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+// How commands starting with sudo/doas are handled
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Sudo {
+    #[serde(default)]
+    pub mode: SudoMode, // "deny", "allow_listed" or "allow_all"
+    #[serde(default)]
+    pub allowed_commands: Vec<String>, // patterns for "allow_listed", `*` matches anything
+}
+
+// Roots every filesystem path has to resolve into, empty allowed_roots disables the jail
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PathJail {
+    #[serde(default)]
+    pub allowed_roots: Vec<String>,
+    #[serde(default)]
+    pub deny_subpaths: Vec<String>, // relative subpaths denied inside the roots, e.g. ".env"
+}
+
+// Filesystem restriction applied to every spawned command through Landlock
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Landlock {
+    #[serde(default)]
+    pub read_only_paths: Vec<String>,
+    #[serde(default)]
+    pub read_write_paths: Vec<String>,
+    #[serde(default)]
+    pub landlock_required: bool, // refuse to start when the kernel lacks Landlock
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Settings {
     pub port: u16,
     pub host: String,
-    pub env: Option<String>, // "development" or "production"
+    pub env: Option<String>,                // "development" or "production"
+    pub shutdown_timeout_secs: Option<u64>, // seconds to wait for sessions on shutdown, default 30
+    #[serde(default)]
+    pub additional_bind_addresses: Vec<String>, // extra "host:port" sockets served by the same router
+    pub prompts_dir: Option<PathBuf>, // prompt templates, "prompts" if not set
+    pub max_parallel_commands: Option<usize>, // commands of one run_parallel call, default 8
+    pub auth_mode: Option<AuthMode>,  // "none" in development, "oauth" in production if not set
+    pub external_url: Option<String>, // where clients reach the server, http://host:port if not set
+    #[serde(default)]
+    pub trust_proxy_headers: bool, // advertise the X-Forwarded-Proto/Host of requests without external_url
+}
+
+// How requests to /mcp are authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuthMode {
+    #[serde(rename = "oauth")]
+    OAuth, // access tokens of the OAuth endpoints
+    #[serde(rename = "api_key")]
+    ApiKey, // the keys of [[api_keys]], the OAuth endpoints are not served
+    #[serde(rename = "oidc")]
+    Oidc, // access tokens of the [oidc] provider, only the protected-resource metadata is served
+    #[serde(rename = "none")]
+    None,
+}
+
+impl Settings {
+    // external_url without its trailing slash, an error if it is no http(s) URL or has a
+    // query or fragment
+    pub fn external_url(&self) -> Result<Option<String>> {
+        let Some(url) = self.external_url.as_deref().filter(|url| !url.is_empty()) else {
+            return Ok(None);
+        };
+        let parsed = reqwest::Url::parse(url)
+            .with_context(|| format!("[settings] external_url {url} is no URL"))?;
+        if !matches!(parsed.scheme(), "http" | "https")
+            || parsed.query().is_some()
+            || parsed.fragment().is_some()
+        {
+            bail!(
+                "[settings] external_url {url} must be an http(s) URL without query and fragment"
+            );
+        }
+        Ok(Some(url.trim_end_matches('/').to_string()))
+    }
+
+    pub fn auth_mode(&self) -> AuthMode {
+        self.auth_mode
+            .unwrap_or(if self.env.as_deref() == Some("development") {
+                AuthMode::None
+            } else {
+                AuthMode::OAuth
+            })
+    }
+}
+
+// [oidc], an upstream OpenID provider issuing the access tokens of /mcp
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Oidc {
+    pub issuer: String, // the iss claim of the tokens, e.g. https://keycloak.example.com/realms/main
+    pub jwks_uri: Option<String>, // the discovery document of the issuer names it if not set
+    pub audience: Option<String>, // the aud claim the tokens need, not checked if not set
+    pub required_scope: Option<String>, // needed by every token, on top of mcp:read
+    pub required_claims: BTreeMap<String, String>, // claim to the value it has to be or contain
+    pub jwks_refresh_seconds: u64, // how long fetched keys are used before they are fetched again
+}
+
+impl Default for Oidc {
+    fn default() -> Self {
+        Oidc {
+            issuer: String::new(),
+            jwks_uri: None,
+            audience: None,
+            required_scope: None,
+            required_claims: BTreeMap::new(),
+            jwks_refresh_seconds: 300,
+        }
+    }
+}
+
+// A key of [[api_keys]], sent as Authorization: Bearer <key>
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub name: String,                // logged as the client of the requests
+    pub key: Option<String>,         // the key itself, or
+    pub key_hash: Option<String>,    // its SHA-256 in hex, `printf %s "$KEY" | sha256sum`
+    pub scopes: Option<Vec<String>>, // all of [oauth] scopes_supported if not set
 }
 
 impl Config {
+    // The config file with the overrides of the environment
     pub fn read_config(file: &str) -> Result<Config> {
         let toml_str = fs::read_to_string(file)?;
-        let config: Config = toml::from_str(&toml_str)?;
+        Self::from_toml_and_env(&toml_str, std::env::vars())
+    }
+
+    // The config of the text of a config file
+    pub fn from_toml(toml_str: &str) -> Result<Config> {
+        let config: Config = toml::from_str(toml_str)?;
         Ok(config)
     }
+
+    // The config of the text with the variables starting with ENV_PREFIX applied
+    pub fn from_toml_and_env(
+        toml_str: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config> {
+        let overrides: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        if overrides.is_empty() {
+            return Self::from_toml(toml_str);
+        }
+        Ok(toml::Value::Table(toml_with_env(toml_str, overrides)?).try_into()?)
+    }
+}
+
+// The table of the text with the variables starting with ENV_PREFIX applied
+pub fn toml_with_env(
+    toml_str: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<toml::Table> {
+    let mut table: toml::Table = toml::from_str(toml_str)?;
+    for (name, value) in vars {
+        if let Some(path) = name.strip_prefix(ENV_PREFIX) {
+            override_key(&mut table, path, &value)
+                .with_context(|| format!("can't apply {name}"))?;
+        }
+    }
+    Ok(table)
+}
+
+fn override_key(table: &mut toml::Table, path: &str, value: &str) -> Result<()> {
+    let keys: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+    if keys.iter().any(String::is_empty) {
+        bail!("the variable names no key");
+    }
+    let (key, sections) = keys.split_last().expect("split yields a part");
+    let mut table = table;
+    for section in sections {
+        table = table
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .with_context(|| format!("{section} is no table"))?;
+    }
+    let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+    table.insert(key.clone(), value);
+    Ok(())
 }
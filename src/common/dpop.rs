@@ -0,0 +1,123 @@
+// DPoP (RFC 9449): the client signs a proof with its own key for every request. Tokens
+// issued with a proof are bound to the thumbprint of that key, a stolen one is no use
+// without the private key.
+use std::{collections::HashMap, sync::Mutex};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{AlgorithmParameters, Jwk},
+};
+use reqwest::Url;
+use rmcp::serde_json;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+pub const PROOF_HEADER: &str = "dpop";
+
+// Advertised as dpop_signing_alg_values_supported
+pub const ALGORITHMS: [Algorithm; 4] = [
+    Algorithm::ES256,
+    Algorithm::RS256,
+    Algorithm::PS256,
+    Algorithm::EdDSA,
+];
+
+// A proof is accepted this long around its iat, clocks drift
+const MAX_AGE_SECONDS: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    ath: Option<String>, // hash of the access token sent with it
+}
+
+// The jti of the proofs of the last minutes, each proof is good for one request
+#[derive(Debug, Default)]
+pub struct ReplayCache {
+    seen: Mutex<HashMap<String, i64>>, // jti to iat
+}
+
+impl ReplayCache {
+    fn first_use(&self, jti: &str, iat: i64, now: i64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        // older proofs are refused by their iat anyway
+        seen.retain(|_, seen_iat| *seen_iat >= now - 2 * MAX_AGE_SECONDS);
+        seen.insert(jti.to_string(), iat).is_none()
+    }
+}
+
+// The thumbprint of the key of a valid proof for this request. Only the path of htu is
+// compared, behind a proxy the server doesn't know the scheme and host clients use.
+pub fn verify(
+    proof: &str,
+    method: &str,
+    path: &str,
+    access_token: Option<&str>,
+    replay: &ReplayCache,
+) -> Result<String, &'static str> {
+    let header = jsonwebtoken::decode_header(proof).map_err(|_| "the DPoP proof is no JWT")?;
+    if header.typ.as_deref() != Some("dpop+jwt") {
+        return Err("the DPoP proof is not of type dpop+jwt");
+    }
+    if !ALGORITHMS.contains(&header.alg) {
+        return Err("the DPoP proof is signed with an unsupported algorithm");
+    }
+    let jwk = header.jwk.ok_or("the DPoP proof has no jwk")?;
+    let key = DecodingKey::from_jwk(&jwk).map_err(|_| "the jwk of the DPoP proof is unusable")?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims::<&str>(&[]);
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let claims = jsonwebtoken::decode::<ProofClaims>(proof, &key, &validation)
+        .map_err(|_| "the DPoP proof has a bad signature or claims")?
+        .claims;
+
+    if !claims.htm.eq_ignore_ascii_case(method) {
+        return Err("the DPoP proof is for another method");
+    }
+    if !Url::parse(&claims.htu).is_ok_and(|htu| htu.path() == path) {
+        return Err("the DPoP proof is for another URL");
+    }
+    let now = chrono::Utc::now().timestamp();
+    if (claims.iat - now).abs() > MAX_AGE_SECONDS {
+        return Err("the DPoP proof is too old");
+    }
+    if let Some(token) = access_token
+        && claims.ath.as_deref() != Some(URL_SAFE_NO_PAD.encode(Sha256::digest(token)).as_str())
+    {
+        return Err("the DPoP proof is for another access token");
+    }
+    if !replay.first_use(&claims.jti, claims.iat, now) {
+        return Err("the DPoP proof was used before");
+    }
+    thumbprint(&jwk).ok_or("the jwk of the DPoP proof is no public key")
+}
+
+// The JWK thumbprint of RFC 7638, the required members in lexicographic order
+pub fn thumbprint(jwk: &Jwk) -> Option<String> {
+    let text = |value: &str| serde_json::to_string(value).ok();
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(ec) => format!(
+            r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+            serde_json::to_string(&ec.curve).ok()?,
+            text(&ec.x)?,
+            text(&ec.y)?
+        ),
+        AlgorithmParameters::RSA(rsa) => format!(
+            r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+            text(&rsa.e)?,
+            text(&rsa.n)?
+        ),
+        AlgorithmParameters::OctetKeyPair(okp) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":{}}}"#,
+            serde_json::to_string(&okp.curve).ok()?,
+            text(&okp.x)?
+        ),
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+    Some(URL_SAFE_NO_PAD.encode(Sha256::digest(members)))
+}
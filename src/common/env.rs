@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::common::sudo::wildcard_match;
+
+// Shown instead of the value of a redacted variable
+pub const REDACTED: &str = "<redacted>";
+
+// Environment variables set by the client, merged into every command of one MCP session
+#[derive(Clone, Default)]
+pub struct SessionEnv {
+    vars: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl fmt::Debug for SessionEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionEnv")
+            .field("count", &self.vars.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl SessionEnv {
+    pub fn set(&self, key: String, value: String) -> Option<String> {
+        self.vars.lock().unwrap().insert(key, value)
+    }
+
+    pub fn unset(&self, key: &str) -> Option<String> {
+        self.vars.lock().unwrap().remove(key)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, String> {
+        self.vars.lock().unwrap().clone()
+    }
+
+    pub fn replace(&self, vars: BTreeMap<String, String>) {
+        *self.vars.lock().unwrap() = vars;
+    }
+}
+
+// Hide the values of variables whose name matches one of the patterns, e.g. "*TOKEN*"
+#[derive(Debug, Clone, Default)]
+pub struct EnvRedactor {
+    patterns: Vec<String>,
+}
+
+impl EnvRedactor {
+    pub fn new(patterns: &[String]) -> Self {
+        EnvRedactor {
+            patterns: patterns.iter().map(|p| p.to_ascii_uppercase()).collect(),
+        }
+    }
+
+    // Names are compared case-insensitively
+    pub fn is_redacted(&self, key: &str) -> bool {
+        let key = key.to_ascii_uppercase();
+        self.patterns
+            .iter()
+            .any(|pattern| wildcard_match(pattern, &key))
+    }
+
+    pub fn redact(
+        &self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> BTreeMap<String, String> {
+        vars.into_iter()
+            .map(|(key, value)| {
+                if self.is_redacted(&key) {
+                    (key, REDACTED.to_string())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect()
+    }
+}
+
+// What a client may use as a variable name
+pub fn is_valid_env_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(['=', '\0'])
+}
@@ -0,0 +1,207 @@
+use std::{io, path::Path};
+
+use serde::Serialize;
+use tokio::process::Command;
+
+#[derive(Debug, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub status: char, // git status letter: M, A, D, R, C, T or U
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    pub hash: String,
+    pub author: String,
+    pub date: String, // ISO 8601
+    pub subject: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffStat {
+    pub path: String,
+    // None for binary files
+    pub added: Option<u64>,
+    pub deleted: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GitInfo {
+    pub repository: String,
+    pub branch: Option<String>, // None when HEAD is detached
+    pub head: Option<String>,   // None before the first commit
+    pub upstream: Option<String>,
+    pub ahead: Option<u64>,
+    pub behind: Option<u64>,
+    pub staged: Vec<FileChange>,
+    pub unstaged: Vec<FileChange>,
+    pub untracked: Vec<String>,
+    pub conflicted: Vec<String>,
+    pub commits: Vec<Commit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_stat: Option<Vec<DiffStat>>,
+}
+
+pub enum GitError {
+    NotARepository,
+    Failed(String),
+}
+
+impl From<io::Error> for GitError {
+    fn from(e: io::Error) -> Self {
+        GitError::Failed(format!("can not run git: {e}"))
+    }
+}
+
+async fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, GitError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // do not take locks a concurrent git command of the agent could trip over
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .env("LC_ALL", "C")
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(GitError::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+// Collect the state of the repository containing `dir`
+pub async fn git_info(
+    dir: &Path,
+    commit_count: usize,
+    diff_stat: bool,
+) -> Result<GitInfo, GitError> {
+    let toplevel = git(dir, &["rev-parse", "--show-toplevel"])
+        .await
+        .map_err(|_| GitError::NotARepository)?;
+    let mut info = GitInfo {
+        repository: String::from_utf8_lossy(&toplevel).trim().to_string(),
+        ..Default::default()
+    };
+
+    let status = git(dir, &["status", "--porcelain=v2", "--branch", "-z"]).await?;
+    parse_status(&String::from_utf8_lossy(&status), &mut info);
+
+    if commit_count > 0 && info.head.is_some() {
+        let count = format!("-n{commit_count}");
+        let log = git(dir, &["log", &count, "--format=%H%x1f%an%x1f%aI%x1f%s%x1e"]).await?;
+        info.commits = String::from_utf8_lossy(&log)
+            .split('\x1e')
+            .filter_map(|record| {
+                let mut fields = record.trim_start_matches('\n').split('\x1f');
+                Some(Commit {
+                    hash: fields.next().filter(|h| !h.is_empty())?.to_string(),
+                    author: fields.next()?.to_string(),
+                    date: fields.next()?.to_string(),
+                    subject: fields.next()?.to_string(),
+                })
+            })
+            .collect();
+    }
+
+    if diff_stat {
+        let numstat = git(dir, &["diff", "--numstat", "-z"]).await?;
+        info.diff_stat = Some(parse_numstat(&String::from_utf8_lossy(&numstat)));
+    }
+    Ok(info)
+}
+
+// Parse `git status --porcelain=v2 --branch -z`
+fn parse_status(status: &str, info: &mut GitInfo) {
+    let mut records = status.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" if value != "(initial)" => info.head = Some(value.to_string()),
+                "branch.head" if value != "(detached)" => info.branch = Some(value.to_string()),
+                "branch.upstream" => info.upstream = Some(value.to_string()),
+                "branch.ab" => {
+                    for count in value.split_whitespace() {
+                        if let Some(ahead) = count.strip_prefix('+') {
+                            info.ahead = ahead.parse().ok();
+                        } else if let Some(behind) = count.strip_prefix('-') {
+                            info.behind = behind.parse().ok();
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        // "1 XY sub mH mI mW hH hI path", renames ("2") have one more field and the
+        // original path as the next record
+        let kind = record.chars().next().unwrap_or(' ');
+        match kind {
+            '1' | '2' => {
+                let fields = if kind == '1' { 9 } else { 10 };
+                let parts: Vec<&str> = record.splitn(fields, ' ').collect();
+                let (Some(xy), Some(path)) = (parts.get(1), parts.get(fields - 1)) else {
+                    continue;
+                };
+                let original_path = (kind == '2')
+                    .then(|| records.next().map(|p| p.to_string()))
+                    .flatten();
+                let mut xy = xy.chars();
+                let (staged, unstaged) = (xy.next().unwrap_or('.'), xy.next().unwrap_or('.'));
+                if staged != '.' {
+                    info.staged.push(FileChange {
+                        path: path.to_string(),
+                        status: staged,
+                        original_path: original_path.clone(),
+                    });
+                }
+                if unstaged != '.' {
+                    info.unstaged.push(FileChange {
+                        path: path.to_string(),
+                        status: unstaged,
+                        original_path,
+                    });
+                }
+            }
+            'u' => {
+                if let Some(path) = record.splitn(11, ' ').nth(10) {
+                    info.conflicted.push(path.to_string());
+                }
+            }
+            '?' => info.untracked.push(record[2..].to_string()),
+            _ => {}
+        }
+    }
+}
+
+// Parse `git diff --numstat -z`, renames are followed by the old and the new path
+fn parse_numstat(numstat: &str) -> Vec<DiffStat> {
+    let mut stats = Vec::new();
+    let mut records = numstat.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        let mut fields = record.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let path = if path.is_empty() {
+            let _old = records.next();
+            records.next().unwrap_or_default().to_string()
+        } else {
+            path.to_string()
+        };
+        stats.push(DiffStat {
+            path,
+            added: added.parse().ok(),
+            deleted: deleted.parse().ok(),
+        });
+    }
+    stats
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+    pub exit_code: Option<i32>, // none when the command timed out, was cancelled or did not start
+    pub start_time: String,     // RFC 3339
+    pub duration_ms: u64,
+    #[serde(skip)]
+    started: DateTime<Utc>,
+}
+
+// Which entries get_command_history returns
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub exit_code: Option<i32>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+// The last commands of one session, the oldest are dropped first
+#[derive(Clone)]
+pub struct CommandHistory {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<HistoryEntry>>>,
+}
+
+impl std::fmt::Debug for CommandHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandHistory")
+            .field("capacity", &self.capacity)
+            .field("entries", &self.entries.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for CommandHistory {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl CommandHistory {
+    pub fn new(capacity: usize) -> Self {
+        CommandHistory {
+            capacity,
+            entries: Arc::default(),
+        }
+    }
+
+    pub fn record(
+        &self,
+        command: String,
+        cwd: Option<PathBuf>,
+        exit_code: Option<i32>,
+        started: DateTime<Utc>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = HistoryEntry {
+            command,
+            cwd,
+            exit_code,
+            start_time: started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            duration_ms: (Utc::now() - started).num_milliseconds().max(0) as u64,
+            started,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    // Replace the entries, e.g. with those of a restored session snapshot
+    pub fn restore(&self, restored: Vec<HistoryEntry>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        let skip = restored.len().saturating_sub(self.capacity);
+        for mut entry in restored.into_iter().skip(skip) {
+            entry.started = DateTime::parse_from_rfc3339(&entry.start_time)
+                .map(|started| started.with_timezone(&Utc))
+                .unwrap_or_default();
+            entries.push_back(entry);
+        }
+    }
+
+    // Oldest first
+    pub fn list(&self, filter: &HistoryFilter) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| {
+                filter
+                    .exit_code
+                    .is_none_or(|code| entry.exit_code == Some(code))
+            })
+            .filter(|entry| filter.since.is_none_or(|since| entry.started >= since))
+            .filter(|entry| filter.until.is_none_or(|until| entry.started <= until))
+            .cloned()
+            .collect()
+    }
+}
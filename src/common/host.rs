@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+use sysinfo::{CpuRefreshKind, Disks, RefreshKind, System};
+
+#[derive(Debug, Serialize)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiskUsage {
+    pub path: PathBuf,        // the root the usage was asked for
+    pub mount_point: PathBuf, // the mount it lives on
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+// A snapshot of the host, fields that can not be determined are None (null)
+#[derive(Debug, Serialize)]
+pub struct HostInfo {
+    pub os_name: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub architecture: String,
+    pub hostname: Option<String>,
+    pub cpu_count: Option<usize>,
+    pub load_average: Option<LoadAverage>,
+    pub total_memory_bytes: Option<u64>,
+    pub available_memory_bytes: Option<u64>,
+    pub disks: Vec<DiskUsage>,
+}
+
+// Collect the host facts, disk usage is reported for the mounts holding `roots`
+pub fn host_info(roots: &[PathBuf]) -> HostInfo {
+    let mut system =
+        System::new_with_specifics(RefreshKind::nothing().with_cpu(CpuRefreshKind::nothing()));
+    system.refresh_memory();
+
+    let load = System::load_average();
+    let total_memory = system.total_memory();
+    HostInfo {
+        os_name: System::name(),
+        os_version: System::os_version(),
+        kernel_version: System::kernel_version(),
+        architecture: std::env::consts::ARCH.to_string(),
+        hostname: System::host_name(),
+        cpu_count: (!system.cpus().is_empty()).then(|| system.cpus().len()),
+        // sysinfo reports zeros where load averages do not exist
+        load_average: (load.one > 0.0 || load.five > 0.0 || load.fifteen > 0.0).then_some(
+            LoadAverage {
+                one: load.one,
+                five: load.five,
+                fifteen: load.fifteen,
+            },
+        ),
+        total_memory_bytes: (total_memory > 0).then_some(total_memory),
+        available_memory_bytes: (total_memory > 0).then(|| system.available_memory()),
+        disks: disk_usage(roots),
+    }
+}
+
+fn disk_usage(roots: &[PathBuf]) -> Vec<DiskUsage> {
+    let disks = Disks::new_with_refreshed_list();
+    let roots = if roots.is_empty() {
+        vec![PathBuf::from("/")]
+    } else {
+        roots.to_vec()
+    };
+    roots
+        .into_iter()
+        .filter_map(|root| {
+            // the most specific mount point containing the root
+            let disk = disks
+                .list()
+                .iter()
+                .filter(|disk| root.starts_with(disk.mount_point()))
+                .max_by_key(|disk| disk.mount_point().components().count())?;
+            Some(DiskUsage {
+                mount_point: disk.mount_point().to_path_buf(),
+                file_system: disk.file_system().to_string_lossy().into_owned(),
+                total_bytes: disk.total_space(),
+                available_bytes: disk.available_space(),
+                path: root,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,477 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use reqwest::{Method, Url, redirect};
+use rmcp::{
+    model::{ErrorCode, ErrorData},
+    serde_json,
+};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::common::config::Http;
+use crate::common::pinning::CertificatePins;
+use crate::common::sudo::wildcard_match;
+
+// Headers that are only sent to the origin they were given for
+const CREDENTIAL_HEADERS: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+// Which HTTP requests the http_request tool may send
+#[derive(Debug, Clone, Default)]
+pub struct HttpPolicy {
+    enabled: bool,
+    allowed_urls: Vec<String>,
+    denied_urls: Vec<String>,
+    block_private_addresses: bool,
+    follow_redirects: bool,
+    max_redirects: usize,
+    pins: CertificatePins,
+}
+
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Duration,
+    pub max_response_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HttpResponse {
+    pub url: String, // after redirects
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub encoding: &'static str, // utf-8 or base64
+    pub body: String,
+    pub truncated: bool,
+    pub redirects: usize,
+}
+
+impl HttpPolicy {
+    pub fn new(config: &Http, pins: CertificatePins) -> Self {
+        HttpPolicy {
+            enabled: config.enabled,
+            allowed_urls: config.allowed_urls.clone(),
+            denied_urls: config.denied_urls.clone(),
+            block_private_addresses: config.block_private_addresses,
+            follow_redirects: config.follow_redirects,
+            max_redirects: config.max_redirects.unwrap_or(5),
+            pins,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Check the url against the lists and resolve its host, every resolved address
+    // has to pass the address check
+    async fn check(&self, url: &Url) -> Result<Vec<SocketAddr>, ErrorData> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(rejection("scheme", format!("{url} is not http or https")));
+        }
+        let text = url.as_str();
+        if self
+            .denied_urls
+            .iter()
+            .any(|pattern| wildcard_match(pattern, text))
+        {
+            return Err(rejection("denied_urls", format!("{url} is denied")));
+        }
+        if !self.allowed_urls.is_empty()
+            && !self
+                .allowed_urls
+                .iter()
+                .any(|pattern| wildcard_match(pattern, text))
+        {
+            return Err(rejection(
+                "allowed_urls",
+                format!("{url} does not match any allowed url"),
+            ));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| rejection("host", format!("{url} has no host")))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| rejection("resolvable", format!("can not resolve {host}: {e}")))?
+            .collect();
+        if self.block_private_addresses
+            && let Some(addr) = addrs.iter().find(|addr| is_private_address(addr.ip()))
+        {
+            return Err(rejection(
+                "block_private_addresses",
+                format!(
+                    "{host} resolves to the private or local address {}",
+                    addr.ip()
+                ),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    pub async fn send(&self, request: HttpRequest) -> Result<HttpResponse, ErrorData> {
+        if !self.enabled {
+            return Err(ErrorData::invalid_request(
+                "http_request is disabled by the server config (http.enabled = false)",
+                None,
+            ));
+        }
+        let mut url = Url::parse(&request.url)
+            .map_err(|e| ErrorData::invalid_params(format!("Invalid url: {e}"), None))?;
+        let mut method = request.method;
+        let mut body = request.body;
+        let mut headers = request.headers;
+        let mut redirects = 0;
+
+        // Redirects are followed by hand, so every hop goes through the checks
+        let response = loop {
+            let addrs = self.check(&url).await?;
+            let mut builder = self
+                .pins
+                .client_builder()
+                .redirect(redirect::Policy::none())
+                .timeout(request.timeout);
+            // Pin the checked addresses, a second lookup could answer differently
+            if let Some(host) = url.domain() {
+                builder = builder.resolve_to_addrs(host, &addrs);
+            }
+            let client = builder.build().map_err(request_error)?;
+
+            let mut outgoing = client.request(method.clone(), url.clone());
+            for (name, value) in &headers {
+                outgoing = outgoing.header(name, value);
+            }
+            if let Some(body) = &body {
+                outgoing = outgoing.body(body.clone());
+            }
+            let response = outgoing.send().await.map_err(request_error)?;
+
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .map(|location| location.to_string());
+            let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+                break response;
+            };
+            if !self.follow_redirects || redirects >= self.max_redirects {
+                break response;
+            }
+            let next = url
+                .join(&location)
+                .map_err(|e| ErrorData::invalid_request(format!("Invalid redirect: {e}"), None))?;
+            // A redirect to another origin does not get the credentials
+            if next.origin() != url.origin() {
+                headers.retain(|name, _| {
+                    !CREDENTIAL_HEADERS
+                        .iter()
+                        .any(|credential| name.eq_ignore_ascii_case(credential))
+                });
+            }
+            url = next;
+            // Like browsers, 301-303 turn into a GET without body
+            if matches!(response.status().as_u16(), 301..=303) {
+                method = Method::GET;
+                body = None;
+            }
+            redirects += 1;
+        };
+
+        let status = response.status().as_u16();
+        let final_url = response.url().to_string();
+        let headers: BTreeMap<String, String> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        let is_text = headers.get("content-type").is_some_and(|content_type| {
+            content_type.starts_with("text/")
+                || ["json", "xml", "javascript", "x-www-form-urlencoded"]
+                    .iter()
+                    .any(|kind| content_type.contains(kind))
+        });
+
+        let mut response = response;
+        let mut bytes = Vec::new();
+        let mut truncated = false;
+        while let Some(chunk) = response.chunk().await.map_err(request_error)? {
+            let room = request
+                .max_response_bytes
+                .saturating_sub(bytes.len() as u64) as usize;
+            if chunk.len() > room {
+                bytes.extend_from_slice(&chunk[..room]);
+                truncated = true;
+                break;
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        // Sniff the content when the type is missing or unknown
+        let (encoding, body) = match String::from_utf8(bytes) {
+            Ok(text) if is_text || !text.contains('\0') => ("utf-8", text),
+            Ok(text) => ("base64", base64_encode(text.as_bytes())),
+            Err(e) if truncated && e.utf8_error().error_len().is_none() => {
+                // a character cut by the size limit
+                let valid = e.utf8_error().valid_up_to();
+                let mut bytes = e.into_bytes();
+                bytes.truncate(valid);
+                ("utf-8", String::from_utf8(bytes).unwrap_or_default())
+            }
+            Err(e) => ("base64", base64_encode(e.as_bytes())),
+        };
+
+        info!("HTTP {status} from {final_url} ({} bytes)", body.len());
+        Ok(HttpResponse {
+            url: final_url,
+            status,
+            headers,
+            encoding,
+            body,
+            truncated,
+            redirects,
+        })
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    STANDARD.encode(bytes)
+}
+
+// Loopback, private, link-local (cloud metadata lives at 169.254.169.254), CGNAT,
+// unique local and other addresses that are not on the public internet
+pub fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || v6
+                    .to_ipv4_mapped()
+                    .is_some_and(|v4| is_private_address(IpAddr::V4(v4)))
+        }
+    }
+}
+
+fn rejection(rule: &str, message: String) -> ErrorData {
+    error!("HTTP request rejected by rule {rule}: {message}");
+    ErrorData {
+        code: ErrorCode::INVALID_PARAMS,
+        message: Cow::Owned(format!("HTTP request rejected by rule `{rule}`: {message}")),
+        data: Some(serde_json::json!({ "rule": rule })),
+    }
+}
+
+fn request_error(e: reqwest::Error) -> ErrorData {
+    ErrorData {
+        code: ErrorCode::INTERNAL_ERROR,
+        message: Cow::Owned(format!("HTTP request failed: {e}")),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    // A plain HTTP server on loopback, answers every request with the response
+    // `respond` builds from the request head
+    async fn serve<F>(respond: F) -> SocketAddr
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let respond = std::sync::Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => head.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let response = respond(&String::from_utf8_lossy(&head));
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn redirect_to(location: &str) -> String {
+        format!(
+            "HTTP/1.1 302 Found\r\nlocation: {location}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        )
+    }
+
+    // Answers with the request head as the body
+    fn echo(head: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{head}",
+            head.len()
+        )
+    }
+
+    fn policy(denied_urls: Vec<String>) -> HttpPolicy {
+        HttpPolicy::new(
+            &Http {
+                enabled: true,
+                denied_urls,
+                block_private_addresses: false,
+                follow_redirects: true,
+                ..Default::default()
+            },
+            CertificatePins::default(),
+        )
+    }
+
+    fn request(url: String) -> HttpRequest {
+        let headers = [
+            ("Authorization", "Bearer secret"),
+            ("Cookie", "session=secret"),
+            ("Proxy-Authorization", "Basic secret"),
+            ("X-Trace", "kept"),
+        ];
+        HttpRequest {
+            method: Method::GET,
+            url,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: None,
+            timeout: Duration::from_secs(5),
+            max_response_bytes: 64 * 1024,
+        }
+    }
+
+    #[test]
+    fn private_addresses() {
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "100.127.255.254",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "febf::1",
+            "::ffff:10.0.0.1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_private_address(private.parse().unwrap()), "{private}");
+        }
+        for public in [
+            "1.1.1.1",
+            "100.63.255.255",
+            "100.128.0.1",
+            "172.32.0.1",
+            "2606:4700::1111",
+            "fec0::1",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(!is_private_address(public.parse().unwrap()), "{public}");
+        }
+    }
+
+    #[tokio::test]
+    async fn credentials_stay_with_their_origin() {
+        let other = serve(echo).await;
+        let origin = serve(move |head: &str| {
+            if head.starts_with("GET /same ") {
+                echo(head)
+            } else if head.starts_with("GET /here ") {
+                redirect_to("/same")
+            } else {
+                redirect_to(&format!("http://{other}/away"))
+            }
+        })
+        .await;
+        let policy = policy(Vec::new());
+
+        // the same origin still gets every header
+        let response = policy
+            .send(request(format!("http://{origin}/here")))
+            .await
+            .unwrap();
+        assert_eq!(response.redirects, 1);
+        let head = response.body.to_lowercase();
+        assert!(head.starts_with("get /same "), "{head}");
+        for name in [
+            "authorization:",
+            "cookie:",
+            "proxy-authorization:",
+            "x-trace:",
+        ] {
+            assert!(head.contains(name), "{name} missing in {head}");
+        }
+
+        // another port is another origin
+        let response = policy
+            .send(request(format!("http://{origin}/elsewhere")))
+            .await
+            .unwrap();
+        assert_eq!(response.redirects, 1);
+        let head = response.body.to_lowercase();
+        assert!(head.starts_with("get /away "), "{head}");
+        assert!(head.contains("x-trace: kept"), "{head}");
+        for name in ["authorization:", "cookie:"] {
+            assert!(!head.contains(name), "{name} sent in {head}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_to_a_blocked_host_are_refused() {
+        let origin = serve(|_: &str| redirect_to("http://169.254.169.254/latest/meta-data/")).await;
+        let policy = policy(vec!["http://169.254.169.254/*".to_string()]);
+        let error = policy
+            .send(request(format!("http://{origin}/")))
+            .await
+            .unwrap_err();
+        assert_eq!(error.data.unwrap()["rule"], "denied_urls");
+    }
+}
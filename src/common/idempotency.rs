@@ -0,0 +1,159 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rmcp::{
+    model::{CallToolResult, Content, ErrorCode, ErrorData},
+    serde_json,
+};
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::common::config::Idempotency;
+
+type Outcome = Result<CallToolResult, ErrorData>;
+
+const MAX_KEY_BYTES: usize = 256;
+
+struct Entry {
+    fingerprint: String, // what was run, a key may not be reused for a different call
+    created: Instant,
+    outcome: watch::Receiver<Option<Outcome>>,
+}
+
+// Results of the tool calls of one session by idempotency key. A retry with a known
+// key gets the stored result, or waits for the first call while it is still running.
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    window: Duration,
+    max_entries: usize,
+    max_result_bytes: usize,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl std::fmt::Debug for IdempotencyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotencyCache")
+            .field("window", &self.window)
+            .field("entries", &self.entries.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(&Idempotency::default())
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(config: &Idempotency) -> Self {
+        IdempotencyCache {
+            window: Duration::from_secs(config.window_seconds),
+            max_entries: config.max_entries.max(1),
+            max_result_bytes: config.max_result_bytes,
+            entries: Arc::default(),
+        }
+    }
+
+    pub async fn run<F, Fut>(&self, key: &str, fingerprint: String, run: F) -> Outcome
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Outcome>,
+    {
+        if key.is_empty() || key.len() > MAX_KEY_BYTES {
+            return Err(ErrorData::invalid_params(
+                format!("Idempotency key must be 1 to {MAX_KEY_BYTES} bytes long"),
+                None,
+            ));
+        }
+        let mut stored = {
+            let mut entries = self.entries.lock().unwrap();
+            self.evict(&mut entries);
+            match entries.get(key) {
+                Some(entry) if entry.fingerprint != fingerprint => {
+                    return Err(ErrorData::invalid_params(
+                        format!("Idempotency key {key} was already used for a different call"),
+                        None,
+                    ));
+                }
+                Some(entry) => entry.outcome.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    entries.insert(
+                        key.to_string(),
+                        Entry {
+                            fingerprint,
+                            created: Instant::now(),
+                            outcome: rx,
+                        },
+                    );
+                    drop(entries);
+
+                    let outcome = run().await;
+                    let _ = tx.send(Some(self.capped(&outcome)));
+                    return outcome;
+                }
+            }
+        };
+
+        info!("Replay the result of idempotency key {key}");
+        match stored.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone().expect("waited for a result"),
+            // the first call was dropped before it finished
+            Err(_) => Err(ErrorData {
+                code: ErrorCode::INTERNAL_ERROR,
+                message: Cow::Owned(format!(
+                    "The call with idempotency key {key} ended without a result, use a new key to run it again"
+                )),
+                data: None,
+            }),
+        }
+    }
+
+    // Drop expired entries, then the oldest finished ones above max_entries. Running
+    // calls stay so their retries can attach.
+    fn evict(&self, entries: &mut HashMap<String, Entry>) {
+        entries.retain(|_, entry| {
+            // has_changed fails once the first call is gone without a result
+            let running = entry.outcome.borrow().is_none() && entry.outcome.has_changed().is_ok();
+            entry.created.elapsed() < self.window || running
+        });
+        while entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .filter(|(_, entry)| entry.outcome.borrow().is_some())
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(key, _)| key.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    // A result too large to keep is replaced for the retries, the call is still not run
+    // a second time
+    fn capped(&self, outcome: &Outcome) -> Outcome {
+        let Ok(result) = outcome else {
+            return outcome.clone();
+        };
+        let size = serde_json::to_vec(&result.content).map_or(0, |json| json.len());
+        if size <= self.max_result_bytes {
+            return outcome.clone();
+        }
+        let message = format!(
+            "The result of this call ({size} bytes) is larger than the {} bytes kept for idempotency keys. \
+             It was returned to the first request only, retries do not run the command again",
+            self.max_result_bytes
+        );
+        Ok(CallToolResult {
+            content: vec![Content::text(message)],
+            is_error: Some(true),
+        })
+    }
+}
@@ -0,0 +1,268 @@
+// The Idempotency-Key header of /token. A client retrying a token request with the same
+// key gets the answer of the first request instead of another pair of tokens, a retry
+// arriving while the first is still running waits for it. Keys are per client, the
+// answers are kept in memory for window_seconds only, and only those a retry would get
+// again: tokens and the errors of the request itself.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::State,
+    http::{HeaderMap, HeaderValue, Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rmcp::serde_json;
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::common::config::TokenIdempotency;
+use crate::common::oauth::basic_credentials;
+use crate::common::rate_limit::{MAX_FORM_BYTES, client_id_of, read_body};
+
+pub const HEADER: &str = "idempotency-key";
+// Set on the answers of retries, they were not run again
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_BYTES: usize = 256;
+
+// What is kept of the answer of the first request
+#[derive(Debug, Clone)]
+struct Answer {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Answer {
+    fn replayed(&self) -> Response {
+        let mut response = (self.status, self.body.clone()).into_response();
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+struct Entry {
+    fingerprint: [u8; 32], // of the request, a key may not be reused for another one
+    created: Instant,
+    last_used: Instant,
+    answer: watch::Receiver<Option<Answer>>,
+}
+
+enum Claim {
+    First(watch::Sender<Option<Answer>>),
+    Retry(watch::Receiver<Option<Answer>>),
+    Conflict,
+}
+
+pub struct IdempotencyKeys {
+    enabled: bool,
+    window: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(String, String), Entry>>, // by client id and key
+}
+
+impl std::fmt::Debug for IdempotencyKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotencyKeys")
+            .field("window", &self.window)
+            .field("entries", &self.entries.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl IdempotencyKeys {
+    pub fn new(config: &TokenIdempotency) -> Self {
+        IdempotencyKeys {
+            enabled: config.enabled && config.window_seconds > 0,
+            window: Duration::from_secs(config.window_seconds),
+            max_entries: config.max_entries.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Let the next request with the key run again
+    fn forget(&self, key: &(String, String)) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn claim(&self, key: (String, String), fingerprint: [u8; 32]) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        self.evict(&mut entries);
+        let now = Instant::now();
+        match entries.get_mut(&key) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Conflict,
+            Some(entry) => {
+                entry.last_used = now;
+                Claim::Retry(entry.answer.clone())
+            }
+            None => {
+                let (tx, rx) = watch::channel(None);
+                entries.insert(
+                    key,
+                    Entry {
+                        fingerprint,
+                        created: now,
+                        last_used: now,
+                        answer: rx,
+                    },
+                );
+                Claim::First(tx)
+            }
+        }
+    }
+
+    // Drop expired entries and those of requests that ended without an answer, then the
+    // least recently used finished ones above max_entries. Running requests stay so their
+    // retries can wait for them.
+    fn evict(&self, entries: &mut HashMap<(String, String), Entry>) {
+        entries.retain(|_, entry| {
+            let running = entry.answer.borrow().is_none();
+            // has_changed fails once the first request is gone without an answer
+            let dropped = running && entry.answer.has_changed().is_err();
+            !dropped && (entry.created.elapsed() < self.window || running)
+        });
+        while entries.len() >= self.max_entries {
+            let least_recent = entries
+                .iter()
+                .filter(|(_, entry)| entry.answer.borrow().is_some())
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            let Some(least_recent) = least_recent else {
+                break;
+            };
+            entries.remove(&least_recent);
+        }
+    }
+}
+
+// The fingerprint of a token request: its credentials and parameters. The DPoP proof is
+// left out, a retry may come with a fresh one and the tokens are bound to the key anyway.
+fn fingerprint(headers: &HeaderMap, body: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+        hasher.update(authorization.as_bytes());
+    }
+    hasher.update([0]);
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+// Whether a retry may get the answer again. Rate limits, server errors and a device still
+// waiting for its user may come out differently the next time.
+fn replayable(status: StatusCode, body: &[u8]) -> bool {
+    let pending = || {
+        serde_json::from_slice::<serde_json::Value>(body).is_ok_and(|body| {
+            matches!(
+                body["error"].as_str(),
+                Some("authorization_pending" | "slow_down")
+            )
+        })
+    };
+    status.is_success()
+        || (status.is_client_error()
+            && status != StatusCode::TOO_MANY_REQUESTS
+            && status != StatusCode::REQUEST_TIMEOUT
+            && !pending())
+}
+
+fn invalid_key(status: StatusCode, description: String) -> Response {
+    (
+        status,
+        Json(serde_json::json!({
+            "error": "invalid_request",
+            "error_description": description,
+        })),
+    )
+        .into_response()
+}
+
+// Answers a request with the Idempotency-Key of an earlier one of the same client with
+// the answer of that one. Another request with the key is refused with 422.
+pub async fn idempotency_key_middleware(
+    State(keys): State<Arc<IdempotencyKeys>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(key) = request.headers().get(HEADER).filter(|_| keys.enabled) else {
+        return next.run(request).await;
+    };
+    let Some(key) = key
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_BYTES)
+        .map(str::to_string)
+    else {
+        return invalid_key(
+            StatusCode::BAD_REQUEST,
+            format!("the Idempotency-Key header must be 1 to {MAX_KEY_BYTES} visible characters"),
+        );
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match read_body(body, MAX_FORM_BYTES).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let client_id = basic_credentials(&parts.headers)
+        .map(|(client_id, _)| client_id)
+        .or_else(|| client_id_of(&body))
+        .unwrap_or_default();
+    let fingerprint = fingerprint(&parts.headers, &body);
+    let request = Request::from_parts(parts, Body::from(body));
+    let entry = (client_id.clone(), key.clone());
+
+    loop {
+        match keys.claim(entry.clone(), fingerprint) {
+            Claim::First(answer) => {
+                let response = next.run(request).await;
+                let (parts, body) = response.into_parts();
+                let body = match axum::body::to_bytes(body, usize::MAX).await {
+                    Ok(body) => body,
+                    Err(e) => {
+                        // the sender is dropped, a retry runs the request again
+                        error!("can't read the token response: {}", e);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                };
+                if !replayable(parts.status, &body) {
+                    // waiting retries see the sender dropped and run again
+                    keys.forget(&entry);
+                    return Response::from_parts(parts, Body::from(body));
+                }
+                let _ = answer.send(Some(Answer {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                }));
+                return Response::from_parts(parts, Body::from(body));
+            }
+            Claim::Retry(mut answer) => match answer.wait_for(Option::is_some).await {
+                Ok(answer) => {
+                    info!(
+                        "replayed the token response of idempotency key {} of client {:?}",
+                        key, client_id
+                    );
+                    return answer.as_ref().expect("waited for an answer").replayed();
+                }
+                // the first request ended without an answer, this one takes over
+                Err(_) => continue,
+            },
+            Claim::Conflict => {
+                return invalid_key(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Idempotency-Key {key} was already used for a different request"),
+                );
+            }
+        }
+    }
+}
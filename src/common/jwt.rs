@@ -0,0 +1,264 @@
+use std::{fmt, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, errors::ErrorKind};
+use rmcp::serde_json::{self, Value};
+use rsa::{
+    RsaPublicKey, pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::common::config::{Jwt, JwtAlgorithm};
+
+// The DER prefix of an Ed25519 SubjectPublicKeyInfo, the 32 key bytes follow
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+// The claims of an access token (RFC 9068)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String,
+    #[serde(default)]
+    pub scope: String, // space separated
+    pub client_id: String,
+    #[serde(default)]
+    pub grant_id: String, // kept over refreshes, the jti is new every time
+}
+
+// Signs and checks JWT access tokens. Replicas sharing the keys accept each other's
+// tokens, no shared store needed.
+pub struct JwtKeys {
+    header: Header,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+    issuer: String,
+    audience: String,
+    jwk: Option<Value>, // the public key, HS256 has none
+}
+
+impl fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeys")
+            .field("algorithm", &self.header.alg)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtKeys {
+    pub fn new(config: &Jwt, issuer: String) -> Result<Self> {
+        let (algorithm, encoding, decoding, jwk) = match config.algorithm {
+            JwtAlgorithm::Hs256 => {
+                let secret = config
+                    .secret
+                    .as_deref()
+                    .filter(|secret| !secret.is_empty())
+                    .context("[oauth.jwt] needs a secret for HS256")?;
+                if secret.len() < 32 {
+                    bail!("the [oauth.jwt] secret must be at least 32 bytes");
+                }
+                (
+                    Algorithm::HS256,
+                    EncodingKey::from_secret(secret.as_bytes()),
+                    DecodingKey::from_secret(secret.as_bytes()),
+                    None,
+                )
+            }
+            JwtAlgorithm::Rs256 => {
+                let (private, public) = read_key_files(config)?;
+                (
+                    Algorithm::RS256,
+                    EncodingKey::from_rsa_pem(&private).context("invalid RSA private key")?,
+                    DecodingKey::from_rsa_pem(&public).context("invalid RSA public key")?,
+                    Some(rsa_jwk(&public)?),
+                )
+            }
+            JwtAlgorithm::EdDsa => {
+                let (private, public) = read_key_files(config)?;
+                (
+                    Algorithm::EdDSA,
+                    EncodingKey::from_ed_pem(&private).context("invalid Ed25519 private key")?,
+                    DecodingKey::from_ed_pem(&public).context("invalid Ed25519 public key")?,
+                    Some(ed25519_jwk(&public)?),
+                )
+            }
+        };
+
+        let audience = config
+            .audience
+            .clone()
+            .unwrap_or_else(|| format!("{issuer}/mcp"));
+        let mut header = Header::new(algorithm);
+        header.typ = Some("at+jwt".to_string());
+        header.kid = jwk
+            .as_ref()
+            .and_then(|jwk| jwk["kid"].as_str())
+            .map(str::to_string);
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&issuer]);
+        validation.set_audience(&[&audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        Ok(JwtKeys {
+            header,
+            encoding,
+            decoding,
+            validation,
+            issuer,
+            audience,
+            jwk,
+        })
+    }
+
+    pub fn issue(
+        &self,
+        client_id: &str,
+        grant_id: &str,
+        scope: &str,
+        issued_at: chrono::DateTime<chrono::Utc>,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<String> {
+        let claims = AccessClaims {
+            iss: self.issuer.clone(),
+            sub: client_id.to_string(),
+            aud: self.audience.clone(),
+            exp: expires_at.timestamp(),
+            iat: issued_at.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            scope: scope.to_string(),
+            client_id: client_id.to_string(),
+            grant_id: grant_id.to_string(),
+        };
+        jsonwebtoken::encode(&self.header, &claims, &self.encoding)
+            .context("can't sign the access token")
+    }
+
+    // The claims of a token with a valid signature, issuer and audience that has not
+    // expired
+    pub fn verify(&self, token: &str) -> Option<AccessClaims> {
+        jsonwebtoken::decode::<AccessClaims>(token, &self.decoding, &self.validation)
+            .inspect_err(|e| match e.kind() {
+                ErrorKind::InvalidAudience => warn!(
+                    "rejected a jwt for {:?}, this server is {}",
+                    audiences(token),
+                    self.audience
+                ),
+                _ => debug!("rejected jwt: {}", e),
+            })
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    pub fn has_public_key(&self) -> bool {
+        self.jwk.is_some()
+    }
+
+    // The JWK set of /.well-known/jwks.json, empty for HS256
+    pub fn jwks(&self) -> Value {
+        serde_json::json!({ "keys": self.jwk.iter().collect::<Vec<_>>() })
+    }
+}
+
+// Three base64url parts, opaque tokens have no dots
+pub fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+// The aud claim of a JWT, a string or a list. The signature is not checked, only call it
+// for a token that was verified.
+pub fn audiences(token: &str) -> Vec<String> {
+    let claims: Option<Value> = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice(&payload).ok());
+    match claims.as_ref().map(|claims| &claims["aud"]) {
+        Some(Value::String(audience)) => vec![audience.clone()],
+        Some(Value::Array(audiences)) => audiences
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_key_files(config: &Jwt) -> Result<(Vec<u8>, Vec<u8>)> {
+    let read = |path: Option<&Path>, name: &str| -> Result<Vec<u8>> {
+        let path = path.with_context(|| format!("[oauth.jwt] needs a {name}"))?;
+        fs::read(path).with_context(|| format!("can't read {}", path.display()))
+    };
+    Ok((
+        read(config.private_key_file.as_deref(), "private_key_file")?,
+        read(config.public_key_file.as_deref(), "public_key_file")?,
+    ))
+}
+
+fn rsa_jwk(pem: &[u8]) -> Result<Value> {
+    let pem = std::str::from_utf8(pem).context("the RSA public key is no PEM")?;
+    let key = RsaPublicKey::from_public_key_pem(pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+        .context("invalid RSA public key")?;
+    let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+    let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+    // the members of the thumbprint in lexicographic order (RFC 7638)
+    let kid = thumbprint(&format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#));
+    Ok(serde_json::json!({
+        "kty": "RSA",
+        "use": "sig",
+        "alg": "RS256",
+        "kid": kid,
+        "n": n,
+        "e": e,
+    }))
+}
+
+fn ed25519_jwk(pem: &[u8]) -> Result<Value> {
+    let der = pem_der(pem).context("the Ed25519 public key is no PEM")?;
+    let Some(key) = der
+        .strip_prefix(&ED25519_SPKI_PREFIX)
+        .filter(|key| key.len() == 32)
+    else {
+        bail!("the public key is no Ed25519 key");
+    };
+    let x = URL_SAFE_NO_PAD.encode(key);
+    let kid = thumbprint(&format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{x}"}}"#));
+    Ok(serde_json::json!({
+        "kty": "OKP",
+        "crv": "Ed25519",
+        "use": "sig",
+        "alg": "EdDSA",
+        "kid": kid,
+        "x": x,
+    }))
+}
+
+// The base64 between the BEGIN and END lines
+fn pem_der(pem: &[u8]) -> Option<Vec<u8>> {
+    let body: String = std::str::from_utf8(pem)
+        .ok()?
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    STANDARD.decode(body).ok()
+}
+
+fn thumbprint(members: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+}
@@ -0,0 +1,108 @@
+use std::{fmt, sync::Arc};
+
+use rmcp::{
+    model::{LoggingLevel, LoggingMessageNotificationParam},
+    serde_json::Value,
+};
+use tokio::sync::mpsc;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::common::session::SessionRegistry;
+
+pub struct LogRecord {
+    level: LoggingLevel,
+    logger: String,
+    message: String,
+}
+
+// Tracing layer handing the log records of this crate to the forwarding task. Records
+// of other crates (rmcp, hyper) are left out, sending a notification logs itself.
+pub struct McpLogLayer {
+    records: mpsc::UnboundedSender<LogRecord>,
+}
+
+pub fn layer() -> (McpLogLayer, mpsc::UnboundedReceiver<LogRecord>) {
+    let (records, rx) = mpsc::unbounded_channel();
+    (McpLogLayer { records }, rx)
+}
+
+impl<S: Subscriber> Layer<S> for McpLogLayer {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            return;
+        }
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        let _ = self.records.send(LogRecord {
+            level: mcp_level(*metadata.level()),
+            logger: metadata.target().to_string(),
+            message: message.0,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{value:?}"));
+        } else {
+            self.0.push_str(&format!(" {}={value:?}", field.name()));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.insert_str(0, value);
+        } else {
+            self.0.push_str(&format!(" {}={value}", field.name()));
+        }
+    }
+}
+
+fn mcp_level(level: Level) -> LoggingLevel {
+    match level {
+        Level::ERROR => LoggingLevel::Error,
+        Level::WARN => LoggingLevel::Warning,
+        Level::INFO => LoggingLevel::Info,
+        _ => LoggingLevel::Debug,
+    }
+}
+
+// Severity order of the MCP levels, debug is the lowest
+pub fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+// Send every record to the sessions whose logging level it reaches. Failures are not
+// logged, the log line would be forwarded again.
+pub fn forward(mut records: mpsc::UnboundedReceiver<LogRecord>, sessions: Arc<SessionRegistry>) {
+    tokio::spawn(async move {
+        while let Some(record) = records.recv().await {
+            for peer in sessions.log_subscribers(record.level) {
+                let params = LoggingMessageNotificationParam {
+                    level: record.level,
+                    logger: Some(record.logger.clone()),
+                    data: Value::String(record.message.clone()),
+                };
+                let _ = peer.notify_logging_message(params).await;
+            }
+        }
+    });
+}
@@ -1,4 +1,55 @@
+pub mod admin;
+pub mod annotations;
+pub mod api_keys;
+pub mod archive;
+pub mod audit;
 pub mod bash_server;
+pub mod browser_session;
+pub mod check;
+pub mod checksum;
+pub mod completion;
 pub mod config;
+pub mod dpop;
+pub mod env;
+pub mod git;
+pub mod history;
+pub mod host;
+pub mod http;
+pub mod idempotency;
+pub mod idempotency_key;
+pub mod jwt;
+pub mod log_forward;
 pub mod oauth;
+pub mod oauth_storage;
+pub mod oidc;
+pub mod openapi;
+pub mod output;
+pub mod pages;
+pub mod pagination;
+pub mod patch;
+pub mod path_policy;
+pub mod permissions;
+pub mod pinning;
+pub mod processes;
+pub mod progress;
+pub mod prompts;
+pub mod pty;
+pub mod public_url;
+pub mod rate_limit;
+pub mod resources;
+pub mod sandbox;
+pub mod schedule;
+pub mod schema;
+pub mod scopes;
+pub mod scratch;
+pub mod session;
+pub mod session_binding;
+pub mod shell;
+pub mod snapshot;
+pub mod streaming;
+pub mod sudo;
+pub mod tail;
+pub mod totp;
+pub mod users;
 pub mod validator;
+pub mod webhooks;
@@ -1,91 +1,694 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use askama::Template;
 use axum::{
     Json,
     body::Body,
-    extract::{Form, Query, State},
-    http::{Request, StatusCode},
+    extract::{ConnectInfo, Form, Query, State},
+    http::{HeaderValue, Request, StatusCode, Uri, header},
     middleware::Next,
-    response::{Html, IntoResponse, Redirect, Response},
+    response::{IntoResponse, Redirect, Response},
 };
 use chrono;
-use oauth2::{AccessToken, EmptyExtraTokenFields, RefreshToken, StandardTokenResponse};
+use oauth2::{
+    AccessToken, EmptyExtraTokenFields, RefreshToken, StandardTokenResponse, TokenResponse,
+};
 use rand::{Rng, distributions::Alphanumeric};
+use reqwest::Url;
 use rmcp::serde_json::{self, Value};
 use rmcp::transport::auth::{
-    AuthorizationMetadata, ClientRegistrationRequest, ClientRegistrationResponse, OAuthClientConfig,
+    AuthorizationMetadata, ClientRegistrationRequest, ClientRegistrationResponse,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use crate::common::audit::{self, AuditEvent, Outcome, digest_fingerprint, peer_ip, token_digest};
+use crate::common::browser_session::{ApprovedGrant, BrowserSessions};
+use crate::common::config::{OAuth, RegistrationMode};
+use crate::common::dpop::{self, ReplayCache};
+use crate::common::jwt::{self, AccessClaims, JwtKeys, is_jwt};
+use crate::common::oauth_storage::{
+    JsonFileStorage, OAuthSnapshot, OAuthStorage, StoredAccessToken, StoredClient,
+    StoredRefreshToken,
+};
+use crate::common::oidc::OidcVerifier;
+use crate::common::openapi::{
+    ClientRegistration, DeviceAuthorizationResponse, IntrospectionResponse, JwkSet,
+    OAuthErrorResponse, RegisteredClientResponse, TokenResponse,
+};
+use crate::common::pages::{self, Pages};
+use crate::common::public_url::PublicUrl;
+use crate::common::rate_limit::{
+    MAX_FORM_BYTES, TokenBuckets, read_body, retry_after_seconds, too_many_requests,
+};
+use crate::common::scopes::{READ_SCOPE, ScopePolicy, has_scope};
+use crate::common::totp::{self, SecondFactor};
+use crate::common::users::{LoginError, UserStore};
+
 // Type alias for OAuth2 standard token response
 pub type AuthToken = StandardTokenResponse<EmptyExtraTokenFields, oauth2::basic::BasicTokenType>;
 
+// Refresh tokens outlive the access tokens they renew
+const REFRESH_TOKEN_LIFETIME: chrono::TimeDelta = chrono::TimeDelta::days(30);
+// The longest authorization_code_ttl_seconds, RFC 6749 section 4.1.2 recommends 10 minutes
+const MAX_AUTHORIZATION_CODE_LIFETIME: u64 = 600;
+// How long the approval page stays valid, its form is refused after that
+const AUTHORIZATION_REQUEST_LIFETIME: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+// How often expired tokens and codes are pruned from the store
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// The grant_type of a device polling /token (RFC 8628 section 3.4)
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+// Letters of user codes, without vowels so they spell no words (RFC 8628 section 6.1)
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+// The token header of proxies that keep Authorization for themselves
+pub(crate) const FALLBACK_TOKEN_HEADER: &str = "x-mcp-authorization";
+// A device polling too fast waits this much longer from then on (RFC 8628 section 3.5)
+const SLOW_DOWN_INCREMENT: chrono::TimeDelta = chrono::TimeDelta::seconds(5);
+// The largest MCP message read for its tool calls, room for scripts and the base64 of
+// files the size of a read_file
+const MAX_MCP_BODY_BYTES: usize = 16 * 1024 * 1024;
+
 // A easy way to manage MCP OAuth Store for managing tokens and sessions
 #[derive(Clone, Debug)]
 pub struct McpOAuthStore {
-    pub clients: Arc<RwLock<HashMap<String, OAuthClientConfig>>>,
+    pub clients: Arc<RwLock<HashMap<String, RegisteredClient>>>,
     pub auth_sessions: Arc<RwLock<HashMap<String, AuthSession>>>,
+    // approval pages not yet answered, by the CSRF token of their form
+    pub authorization_requests: Arc<RwLock<HashMap<String, AuthorizationRequest>>>,
+    // by the digest of the token, only the client knows the token itself
     pub access_tokens: Arc<RwLock<HashMap<String, McpAccessToken>>>,
+    pub refresh_tokens: Arc<RwLock<HashMap<String, McpRefreshToken>>>,
+    // device authorizations by their device code, until the device gets its tokens
+    pub device_grants: Arc<RwLock<HashMap<String, DeviceGrant>>>,
+    token_ttl: chrono::TimeDelta,
+    refresh_token_rotation: bool,
+    code_ttl: chrono::TimeDelta, // codes not exchanged by then are refused and dropped
+    device_ttl: chrono::TimeDelta,
+    device_interval: chrono::TimeDelta, // the least interval between two polls of a device
+    require_pkce: bool,
+    pub scopes: ScopePolicy,
+    pub scopes_supported: Vec<String>,
+    default_scopes: Vec<String>,
+    introspection_secret: Option<String>,
+    client_credentials_clients: Option<Vec<String>>,
+    loopback_redirect_any_port: bool,
+    pub users: Arc<UserStore>,
+    second_factor: Option<Arc<SecondFactor>>, // the one-time codes of [oauth.totp]
+    max_failed_client_authentications: u32,
+    client_failure_window: chrono::TimeDelta, // how long a wrong secret counts
+    client_lockout: chrono::TimeDelta,
+    client_failures: Arc<std::sync::Mutex<HashMap<String, ClientFailures>>>, // known clients only
+    token_buckets: Arc<TokenBuckets>, // [oauth.token_endpoint_rate_limit]
+    pub pages: Arc<Pages>,            // the approval and device pages
+    jwt: Option<Arc<JwtKeys>>,        // access tokens are JWTs signed with these
+    oidc: Option<Arc<OidcVerifier>>,  // access tokens come from an upstream provider instead
+    remote_jwks: Option<Arc<OidcVerifier>>, // JWTs are checked against [oauth] jwks_uri first
+    // removed clients and when, their JWTs are refused until they have expired
+    removed_clients: Arc<RwLock<HashMap<String, chrono::DateTime<chrono::Utc>>>>,
+    // the same for grants revoked in the browser
+    revoked_grants: Arc<RwLock<HashMap<String, chrono::DateTime<chrono::Utc>>>>,
+    // of the browsers that approved grants
+    pub browser_sessions: Arc<BrowserSessions>,
+    expected_audience: Option<String>, // JWTs without it in their aud are refused
+    accept_token_in_query: bool,
+    accept_fallback_header: bool,
+    dpop_required: bool, // /token and /mcp refuse requests without a DPoP proof
+    // the thumbprint of the DPoP key of each bound grant
+    dpop_bindings: Arc<RwLock<HashMap<String, String>>>,
+    dpop_replay: Arc<ReplayCache>,
+    registration_mode: RegistrationMode,
+    registration_tokens: Vec<String>, // initial access tokens of /register
+    max_registrations_per_token: u32,
+    resource: Option<String>, // the URI of /mcp (RFC 8707), the audience of every token
+    // the base of the metadata, the device verification uri and the challenges of /mcp
+    pub public_url: PublicUrl,
+    storage: Option<Arc<dyn OAuthStorage>>,
+    // one save at a time, so an older snapshot never replaces a newer one
+    save_lock: Arc<Mutex<()>>,
 }
 
 impl McpOAuthStore {
-    pub fn new() -> Self {
+    pub fn new(config: &OAuth) -> Self {
         let mut clients = HashMap::new();
         clients.insert(
             "mcp-client".to_string(),
-            OAuthClientConfig {
+            RegisteredClient {
                 client_id: "mcp-client".to_string(),
                 client_secret: Some("mcp-client-secret".to_string()),
-                scopes: vec!["profile".to_string(), "email".to_string()],
-                redirect_uri: "http://localhost:8080/callback".to_string(),
+                client_name: None,
+                created_at: None,
+                scopes: vec![
+                    "mcp:read".to_string(),
+                    "mcp:execute".to_string(),
+                    "profile".to_string(),
+                    "email".to_string(),
+                    "processes:read".to_string(),
+                ],
+                redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+                registered_with: None,
             },
         );
 
+        // Clients and tokens of the last run, expired tokens are left behind
+        let storage = config
+            .storage_path
+            .as_deref()
+            .map(|path| Arc::new(JsonFileStorage::new(path)) as Arc<dyn OAuthStorage>);
+        let mut access_tokens = HashMap::new();
+        let mut refresh_tokens = HashMap::new();
+        let mut dpop_bindings = HashMap::new();
+        let mut stored_totp = None;
+        match storage.as_ref().map(|storage| storage.load()) {
+            Some(Ok(Some(mut snapshot))) => {
+                // the tokens of an older version are not left on disk as they are
+                if snapshot.hash_tokens() {
+                    match storage.as_ref().map(|storage| storage.save(&snapshot)) {
+                        Some(Err(e)) => {
+                            warn!("can't save the hashed tokens of the oauth store: {}", e)
+                        }
+                        _ => info!("replaced the stored tokens by their digests"),
+                    }
+                }
+                dpop_bindings = std::mem::take(&mut snapshot.dpop_bindings)
+                    .into_iter()
+                    .collect();
+                stored_totp = snapshot
+                    .totp_secret
+                    .take()
+                    .map(|secret| (secret, snapshot.totp_enrolled));
+                Self::restore(
+                    snapshot,
+                    &mut clients,
+                    &mut access_tokens,
+                    &mut refresh_tokens,
+                )
+            }
+            Some(Err(e)) => warn!("can't load the oauth store, starting empty: {}", e),
+            _ => {}
+        }
+        // the config wins over a stored client of the same id
+        for client in &config.clients {
+            clients.insert(
+                client.client_id.clone(),
+                RegisteredClient {
+                    client_id: client.client_id.clone(),
+                    client_secret: Some(client.client_secret.clone()),
+                    client_name: None,
+                    created_at: None,
+                    scopes: client.scopes.clone(),
+                    redirect_uris: merge_redirect_uris(&client.redirect_uri, &client.redirect_uris),
+                    registered_with: None,
+                },
+            );
+        }
+
         Self {
             clients: Arc::new(RwLock::new(clients)),
             auth_sessions: Arc::new(RwLock::new(HashMap::new())),
-            access_tokens: Arc::new(RwLock::new(HashMap::new())),
+            authorization_requests: Arc::new(RwLock::new(HashMap::new())),
+            access_tokens: Arc::new(RwLock::new(access_tokens)),
+            refresh_tokens: Arc::new(RwLock::new(refresh_tokens)),
+            device_grants: Arc::new(RwLock::new(HashMap::new())),
+            token_ttl: chrono::TimeDelta::seconds(
+                config.token_ttl_seconds.min(i64::MAX as u64) as i64
+            ),
+            refresh_token_rotation: config.refresh_token_rotation,
+            code_ttl: chrono::TimeDelta::seconds(
+                config
+                    .authorization_code_ttl_seconds
+                    .min(MAX_AUTHORIZATION_CODE_LIFETIME) as i64,
+            ),
+            device_ttl: chrono::TimeDelta::seconds(
+                config.device_code_ttl_seconds.min(i64::MAX as u64) as i64,
+            ),
+            device_interval: chrono::TimeDelta::seconds(
+                config.device_poll_interval_seconds.min(i64::MAX as u64) as i64,
+            ),
+            require_pkce: config.require_pkce,
+            scopes: ScopePolicy::new(config),
+            scopes_supported: config.scopes_supported.clone(),
+            default_scopes: config.default_scopes.clone(),
+            introspection_secret: config
+                .introspection_secret
+                .clone()
+                .filter(|secret| !secret.is_empty()),
+            client_credentials_clients: config.client_credentials_clients.clone(),
+            loopback_redirect_any_port: config.loopback_redirect_any_port,
+            users: Arc::new(UserStore::new(config)),
+            second_factor: config
+                .totp
+                .enabled
+                .then(|| Arc::new(SecondFactor::new(&config.totp, stored_totp))),
+            max_failed_client_authentications: config.max_failed_client_authentications,
+            client_failure_window: chrono::TimeDelta::seconds(
+                config.client_failure_window_seconds.min(i64::MAX as u64) as i64,
+            ),
+            client_lockout: chrono::TimeDelta::seconds(
+                config.client_lockout_seconds.min(i64::MAX as u64) as i64,
+            ),
+            client_failures: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            token_buckets: Arc::new(TokenBuckets::new(&config.token_endpoint_rate_limit)),
+            pages: Arc::new(Pages::default()),
+            browser_sessions: Arc::new(BrowserSessions::default()),
+            jwt: None,
+            oidc: None,
+            remote_jwks: None,
+            removed_clients: Arc::new(RwLock::new(HashMap::new())),
+            revoked_grants: Arc::new(RwLock::new(HashMap::new())),
+            expected_audience: config
+                .expected_audience
+                .clone()
+                .filter(|audience| !audience.is_empty()),
+            accept_token_in_query: config.accept_token_in_query,
+            accept_fallback_header: config.accept_fallback_header,
+            dpop_required: config.dpop_required,
+            dpop_bindings: Arc::new(RwLock::new(dpop_bindings)),
+            dpop_replay: Arc::new(ReplayCache::default()),
+            registration_mode: config.registration_mode,
+            registration_tokens: config
+                .registration_tokens
+                .iter()
+                .filter(|token| !token.is_empty())
+                .cloned()
+                .collect(),
+            max_registrations_per_token: config.max_registrations_per_token,
+            resource: None,
+            public_url: PublicUrl::default(),
+            storage,
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // Render the pages with the templates of [web]
+    pub fn with_pages(mut self, pages: Pages) -> Self {
+        self.pages = Arc::new(pages);
+        self
+    }
+
+    // Issue JWT access tokens and accept them without looking them up
+    pub fn with_jwt(mut self, keys: JwtKeys) -> Self {
+        self.jwt = Some(Arc::new(keys));
+        self
+    }
+
+    // Accept only the access tokens of an upstream OpenID provider
+    pub fn with_oidc(mut self, verifier: OidcVerifier) -> Self {
+        let verifier = Arc::new(verifier);
+        // known before the first token comes, a failure is logged and retried then
+        tokio::spawn({
+            let verifier = verifier.clone();
+            async move { verifier.fetch().await }
+        });
+        self.oidc = Some(verifier);
+        self
+    }
+
+    // Accept JWTs signed with a key of [oauth] jwks_uri, on top of the tokens of this server
+    pub fn with_remote_jwks(mut self, verifier: OidcVerifier) -> Self {
+        let verifier = Arc::new(verifier);
+        tokio::spawn({
+            let verifier = verifier.clone();
+            async move { verifier.fetch().await }
+        });
+        self.remote_jwks = Some(verifier);
+        self
+    }
+
+    // The issuer of the access tokens, the upstream provider or this server
+    pub fn authorization_server(&self, base_url: &str) -> String {
+        self.oidc
+            .as_ref()
+            .map_or_else(|| base_url.to_string(), |oidc| oidc.issuer().to_string())
+    }
+
+    // Where clients reach the server, the base of what is advertised
+    pub fn with_public_url(mut self, public_url: PublicUrl) -> Self {
+        self.public_url = public_url;
+        self
+    }
+
+    // The resource identifier of /mcp (RFC 8707). Requests for another resource are
+    // refused, the tokens record it and /mcp refuses those issued for another one.
+    pub fn with_resource(mut self, resource: String) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    // The resource parameter of /authorize and /token, only this server may be named: by
+    // its resource or by the /mcp of the URL the request was sent to
+    pub fn check_resource(&self, resource: Option<&str>, base_url: &str) -> Result<(), String> {
+        match (resource, &self.resource) {
+            (Some(requested), Some(resource))
+                if requested != resource && requested != format!("{base_url}/mcp") =>
+            {
+                Err(format!(
+                    "resource {requested} is not served here, only {resource}"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The JWK set of the signing key, empty without one
+    pub fn jwks(&self) -> Value {
+        self.jwt
+            .as_ref()
+            .map_or_else(|| serde_json::json!({ "keys": [] }), |jwt| jwt.jwks())
+    }
+
+    pub fn has_jwks(&self) -> bool {
+        self.jwt.as_ref().is_some_and(|jwt| jwt.has_public_key())
+    }
+
+    // The client_name of its registration, shown on the approval pages
+    async fn client_name(&self, client_id: &str) -> Option<String> {
+        self.clients
+            .read()
+            .await
+            .get(client_id)
+            .and_then(|client| client.client_name.clone())
+    }
+
+    // Whether the metadata names /register, in token mode without tokens nobody can use it
+    pub fn registration_open(&self) -> bool {
+        match self.registration_mode {
+            RegistrationMode::Open => true,
+            RegistrationMode::Token => !self.registration_tokens.is_empty(),
+            RegistrationMode::Closed => false,
+        }
+    }
+
+    // A random opaque token, or a signed JWT with [oauth] token_format = "jwt"
+    fn new_access_token(
+        &self,
+        client_id: &str,
+        grant_id: &str,
+        scope: Option<&str>,
+        issued_at: chrono::DateTime<chrono::Utc>,
+    ) -> String {
+        if let Some(jwt) = &self.jwt {
+            match jwt.issue(
+                client_id,
+                grant_id,
+                scope.unwrap_or_default(),
+                issued_at,
+                issued_at + self.token_ttl,
+            ) {
+                Ok(token) => return token,
+                Err(e) => error!("{:#}, issuing an opaque token", e),
+            }
+        }
+        format!("mcp-token-{}", Uuid::new_v4())
+    }
+
+    fn restore(
+        snapshot: OAuthSnapshot,
+        clients: &mut HashMap<String, RegisteredClient>,
+        access_tokens: &mut HashMap<String, McpAccessToken>,
+        refresh_tokens: &mut HashMap<String, McpRefreshToken>,
+    ) {
+        let now = chrono::Utc::now();
+        let time = |secs: i64| chrono::DateTime::from_timestamp(secs, 0).unwrap_or(now);
+        for client in snapshot.clients {
+            clients.insert(
+                client.client_id.clone(),
+                RegisteredClient {
+                    redirect_uris: merge_redirect_uris(&client.redirect_uri, &client.redirect_uris),
+                    client_id: client.client_id,
+                    client_secret: client.client_secret,
+                    client_name: client.client_name,
+                    created_at: client.created_at.map(time),
+                    scopes: client.scopes,
+                    registered_with: client.registered_with,
+                },
+            );
+        }
+        for token in snapshot.access_tokens {
+            let expires_at = time(token.expires_at);
+            if expires_at <= now {
+                continue;
+            }
+            access_tokens.insert(
+                token.access_token.clone(),
+                McpAccessToken {
+                    access_token: token.access_token,
+                    token_type: "bearer".to_string(),
+                    expires_in: Some((expires_at - now).num_seconds() as u64),
+                    expires_at,
+                    refresh_token: None,
+                    scope: token.scope,
+                    auth_token: token.auth_token,
+                    client_id: token.client_id,
+                    grant_id: token.grant_id,
+                    resource: token.resource,
+                },
+            );
+        }
+        for token in snapshot.refresh_tokens {
+            let expires_at = time(token.expires_at);
+            if expires_at <= now {
+                continue;
+            }
+            refresh_tokens.insert(
+                token.refresh_token,
+                McpRefreshToken {
+                    access_token: token.access_token,
+                    grant_id: token.grant_id,
+                    client_id: token.client_id,
+                    scope: token.scope,
+                    auth_token: token.auth_token,
+                    expires_at,
+                    rotated: token.rotated,
+                },
+            );
+        }
+        info!(
+            "restored {} clients, {} access tokens and {} refresh tokens",
+            clients.len(),
+            access_tokens.len(),
+            refresh_tokens.len()
+        );
+    }
+
+    // Save the clients and tokens if a storage is configured, failures are only logged
+    pub async fn persist(&self) {
+        let Some(storage) = self.storage.clone() else {
+            return;
+        };
+        let _saving = self.save_lock.lock().await;
+        let totp_secret = self
+            .second_factor
+            .as_ref()
+            .and_then(|second_factor| second_factor.generated_secret());
+        let snapshot = {
+            let refresh_tokens = self.refresh_tokens.read().await;
+            let access_tokens = self.access_tokens.read().await;
+            let clients = self.clients.read().await;
+            OAuthSnapshot {
+                clients: clients
+                    .values()
+                    .map(|client| StoredClient {
+                        client_id: client.client_id.clone(),
+                        client_secret: client.client_secret.clone(),
+                        scopes: client.scopes.clone(),
+                        redirect_uris: client.redirect_uris.clone(),
+                        redirect_uri: String::new(),
+                        client_name: client.client_name.clone(),
+                        created_at: client.created_at.map(|time| time.timestamp()),
+                        registered_with: client.registered_with.clone(),
+                    })
+                    .collect(),
+                access_tokens: access_tokens
+                    .iter()
+                    .map(|(access_token, token)| StoredAccessToken {
+                        access_token: access_token.clone(),
+                        scope: token.scope.clone(),
+                        auth_token: token.auth_token.clone(),
+                        client_id: token.client_id.clone(),
+                        grant_id: token.grant_id.clone(),
+                        resource: token.resource.clone(),
+                        expires_at: token.expires_at.timestamp(),
+                    })
+                    .collect(),
+                refresh_tokens: refresh_tokens
+                    .iter()
+                    .map(|(refresh_token, token)| StoredRefreshToken {
+                        refresh_token: refresh_token.clone(),
+                        access_token: token.access_token.clone(),
+                        grant_id: token.grant_id.clone(),
+                        client_id: token.client_id.clone(),
+                        scope: token.scope.clone(),
+                        auth_token: token.auth_token.clone(),
+                        expires_at: token.expires_at.timestamp(),
+                        rotated: token.rotated,
+                    })
+                    .collect(),
+                dpop_bindings: self
+                    .dpop_bindings
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(grant_id, jkt)| (grant_id.clone(), jkt.clone()))
+                    .collect(),
+                totp_secret: totp_secret.as_ref().map(|(secret, _)| secret.clone()),
+                totp_enrolled: totp_secret.is_some_and(|(_, enrolled)| enrolled),
+                token_digests: true,
+            }
+        };
+        match tokio::task::spawn_blocking(move || storage.save(&snapshot)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("can't save the oauth store: {}", e),
+            Err(e) => error!("can't save the oauth store: {}", e),
         }
     }
 
+    // The client, if the redirect uri is one of its registered ones
     pub async fn validate_client(
         &self,
         client_id: &str,
         redirect_uri: &str,
-    ) -> Option<OAuthClientConfig> {
+    ) -> Option<RegisteredClient> {
         let clients = self.clients.read().await;
-        if let Some(client) = clients.get(client_id) {
-            if client.redirect_uri.contains(&redirect_uri.to_string()) {
-                return Some(client.clone());
-            }
-        }
-        None
+        clients
+            .get(client_id)
+            .filter(|client| {
+                client.redirect_uris.iter().any(|registered| {
+                    redirect_uri_matches(registered, redirect_uri, self.loopback_redirect_any_port)
+                })
+            })
+            .cloned()
+    }
+
+    // Remember a request shown on the approval page, the returned CSRF token goes into
+    // its form
+    pub async fn create_authorization_request(&self, request: AuthorizationRequest) -> String {
+        let csrf_token = generate_random_string(32);
+        self.authorization_requests
+            .write()
+            .await
+            .insert(csrf_token.clone(), request);
+        csrf_token
+    }
+
+    // The unexpired request of the form with this CSRF token
+    pub async fn authorization_request(&self, csrf_token: &str) -> Option<AuthorizationRequest> {
+        self.authorization_requests
+            .read()
+            .await
+            .get(csrf_token)
+            .filter(|request| !request.is_expired())
+            .cloned()
+    }
+
+    // Like authorization_request, but the token can't be used again
+    pub async fn take_authorization_request(
+        &self,
+        csrf_token: &str,
+    ) -> Option<AuthorizationRequest> {
+        self.authorization_requests
+            .write()
+            .await
+            .remove(csrf_token)
+            .filter(|request| !request.is_expired())
     }
 
+    // The grant the code is going to start, known before the exchange so the browser that
+    // approved it can revoke it
     pub async fn create_auth_session(
         &self,
         client_id: String,
         scope: Option<String>,
         state: Option<String>,
+        code_challenge: Option<String>,
+        approved_by: Option<String>,
         session_id: String,
     ) -> String {
+        let grant_id = Uuid::new_v4().to_string();
         let session = AuthSession {
             client_id,
             scope,
-            _state: state,
-            _created_at: chrono::Utc::now(),
+            state,
+            code_challenge,
+            approved_by,
+            created_at: chrono::Utc::now(),
             auth_token: None,
+            grant_id: grant_id.clone(),
+            redeemed_grant: None,
         };
 
-        self.auth_sessions
-            .write()
-            .await
-            .insert(session_id.clone(), session);
-        session_id
+        self.auth_sessions.write().await.insert(session_id, session);
+        grant_id
+    }
+
+    // The PKCE parameters of an authorization request (RFC 7636 section 4.3), only S256
+    // is accepted because plain offers no protection against an intercepted code
+    pub fn check_code_challenge(
+        &self,
+        code_challenge: Option<&str>,
+        code_challenge_method: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let Some(code_challenge) = code_challenge.filter(|challenge| !challenge.is_empty()) else {
+            if self.require_pkce {
+                return Err("code_challenge is required");
+            }
+            return Ok(());
+        };
+        // a missing method means plain
+        if code_challenge_method != Some("S256") {
+            return Err("code_challenge_method must be S256");
+        }
+        if !is_pkce_value(code_challenge) {
+            return Err("code_challenge must be 43 to 128 characters of [A-Za-z0-9-._~]");
+        }
+        Ok(())
+    }
+
+    // The scope to grant for an authorization request, the default scopes when it names
+    // none. Unknown scopes are refused (RFC 6749 section 4.1.2.1).
+    pub fn granted_scope(&self, scope: Option<&str>) -> Result<String, String> {
+        let requested: Vec<&str> = scope.unwrap_or_default().split_whitespace().collect();
+        if requested.is_empty() {
+            return Ok(self.default_scopes.join(" "));
+        }
+        if let Some(unknown) = requested
+            .iter()
+            .find(|scope| !self.scopes_supported.iter().any(|s| s == *scope))
+        {
+            return Err(format!("scope {unknown} is not supported"));
+        }
+        Ok(requested.join(" "))
+    }
+
+    // The code_verifier of the token request against the challenge of the code
+    pub async fn check_code_verifier(
+        &self,
+        session_id: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let sessions = self.auth_sessions.read().await;
+        let Some(session) = sessions.get(session_id) else {
+            // unknown codes are reported by the token exchange
+            return Ok(());
+        };
+        let Some(code_challenge) = &session.code_challenge else {
+            if self.require_pkce {
+                return Err("the authorization code was issued without code_challenge");
+            }
+            return Ok(());
+        };
+        let Some(code_verifier) = code_verifier.filter(|verifier| !verifier.is_empty()) else {
+            return Err("code_verifier is required");
+        };
+        if !is_pkce_value(code_verifier) || s256_challenge(code_verifier) != *code_challenge {
+            return Err("code_verifier does not match the code_challenge");
+        }
+        Ok(())
     }
 
     pub async fn update_auth_session_token(
@@ -102,262 +705,2655 @@ impl McpOAuthStore {
         }
     }
 
-    pub async fn create_mcp_token(&self, session_id: &str) -> Result<McpAccessToken, String> {
-        let sessions = self.auth_sessions.read().await;
-        if let Some(session) = sessions.get(session_id) {
-            if let Some(auth_token) = &session.auth_token {
-                let access_token = format!("mcp-token-{}", Uuid::new_v4());
-                let refresh_token = format!("mcp-refresh-{}", Uuid::new_v4());
-
-                let token = McpAccessToken {
-                    access_token: access_token.clone(),
-                    token_type: "Bearer".to_string().to_lowercase(),
-                    expires_in: Some(3600),
-                    refresh_token: Some(refresh_token),
-                    scope: session.scope.clone(),
-                    auth_token: auth_token.clone(),
-                    client_id: session.client_id.clone(),
-                };
-
-                self.access_tokens
-                    .write()
-                    .await
-                    .insert(access_token.clone(), token.clone());
-                Ok(token)
-            } else {
-                Err("No third-party token available for session".to_string())
-            }
-        } else {
-            Err("Session not found".to_string())
+    // grant_type=authorization_code, a code is exchanged once by the client it was
+    // issued to. Exchanging it again revokes the tokens of the first exchange, the code
+    // may have been intercepted (RFC 6749 section 4.1.2).
+    pub async fn create_mcp_token(
+        &self,
+        session_id: &str,
+        client_id: &str,
+    ) -> Result<McpAccessToken, CodeError> {
+        let outcome = self.redeem_code(session_id, client_id).await;
+        if matches!(outcome, Ok(_) | Err(CodeError::GrantRevoked(_))) {
+            self.persist().await;
         }
+        outcome
     }
 
-    pub async fn validate_token(&self, token: &str) -> Option<McpAccessToken> {
-        self.access_tokens.read().await.get(token).cloned()
+    // The code is marked as used under the same locks that insert its tokens, of two
+    // concurrent exchanges only one gets them
+    async fn redeem_code(
+        &self,
+        session_id: &str,
+        client_id: &str,
+    ) -> Result<McpAccessToken, CodeError> {
+        let mut refresh_tokens = self.refresh_tokens.write().await;
+        let mut access_tokens = self.access_tokens.write().await;
+        let mut sessions = self.auth_sessions.write().await;
+        let Some(session) = sessions.get_mut(session_id) else {
+            return Err(CodeError::InvalidGrant(
+                "unknown authorization code".to_string(),
+            ));
+        };
+        if session.client_id != client_id {
+            return Err(CodeError::InvalidGrant(
+                "authorization code was issued to another client".to_string(),
+            ));
+        }
+        if let Some(grant_id) = &session.redeemed_grant {
+            warn!(
+                "authorization code of client {} exchanged again, revoking grant {}",
+                session.client_id, grant_id
+            );
+            refresh_tokens.retain(|_, token| token.grant_id != *grant_id);
+            access_tokens.retain(|_, token| token.grant_id != *grant_id);
+            return Err(CodeError::GrantRevoked(
+                "authorization code was already used, its tokens are revoked".to_string(),
+            ));
+        }
+        if session.created_at + self.code_ttl <= chrono::Utc::now() {
+            sessions.remove(session_id);
+            return Err(CodeError::InvalidGrant(
+                "authorization code has expired".to_string(),
+            ));
+        }
+        let Some(auth_token) = session.auth_token.clone() else {
+            return Err(CodeError::ServerError(
+                "No third-party token available for session".to_string(),
+            ));
+        };
+
+        // every code starts a new grant, its refresh tokens are rotated within it
+        let grant_id = session.grant_id.clone();
+        session.redeemed_grant = Some(grant_id.clone());
+        let token = self.insert_token_pair(
+            &mut refresh_tokens,
+            &mut access_tokens,
+            grant_id,
+            session.client_id.clone(),
+            session.scope.clone(),
+            auth_token,
+        );
+        if let Some(user) = &session.approved_by {
+            info!(
+                "issued tokens to {} approved by {}",
+                session.client_id, user
+            );
+        }
+        Ok(token)
     }
-}
 
-// a simple session record for auth session
-#[derive(Clone, Debug)]
-pub struct AuthSession {
-    pub client_id: String,
-    pub scope: Option<String>,
-    pub _state: Option<String>,
-    pub _created_at: chrono::DateTime<chrono::Utc>,
-    pub auth_token: Option<AuthToken>,
-}
+    // A device authorization of POST /device_authorization (RFC 8628 section 3.2), its
+    // device code is the key of the store
+    pub async fn create_device_grant(
+        &self,
+        client_id: &str,
+        scope: String,
+    ) -> (String, DeviceGrant) {
+        let device_code = format!("mcp-device-{}", Uuid::new_v4());
+        let now = chrono::Utc::now();
+        let mut grants = self.device_grants.write().await;
+        // user codes are short, a pending one is never handed out twice
+        let user_code = loop {
+            let code = generate_user_code();
+            if !grants.values().any(|grant| grant.user_code == code) {
+                break code;
+            }
+        };
+        let grant = DeviceGrant {
+            client_id: client_id.to_string(),
+            scope,
+            user_code,
+            csrf_token: generate_random_string(32),
+            grant_id: Uuid::new_v4().to_string(),
+            expires_at: now + self.device_ttl,
+            interval: self.device_interval,
+            last_poll: None,
+            status: DeviceStatus::Pending,
+        };
+        grants.insert(device_code.clone(), grant.clone());
+        (device_code, grant)
+    }
 
-// a simple token record for mcp token using oauth2 standard token
-#[derive(Clone, Debug, Serialize)]
-pub struct McpAccessToken {
-    pub access_token: String,
-    pub token_type: String,
-    pub expires_in: Option<u64>,
-    pub refresh_token: Option<String>,
-    pub scope: Option<String>,
-    pub auth_token: AuthToken,
-    pub client_id: String,
-}
+    // The unexpired grant of a user code waiting for approval, as the user typed it
+    pub async fn pending_device_grant(&self, user_code: &str) -> Option<DeviceGrant> {
+        let user_code = normalize_user_code(user_code);
+        let now = chrono::Utc::now();
+        self.device_grants
+            .read()
+            .await
+            .values()
+            .find(|grant| {
+                grant.user_code == user_code
+                    && grant.status == DeviceStatus::Pending
+                    && grant.expires_at > now
+            })
+            .cloned()
+    }
 
-#[derive(Debug, Deserialize)]
-pub struct AuthorizeQuery {
-    #[allow(dead_code)]
-    pub response_type: String,
-    pub client_id: String,
-    pub redirect_uri: String,
-    pub scope: Option<String>,
-    pub state: Option<String>,
-}
+    // Approve or deny the pending grant of the user code, false if it is gone or the CSRF
+    // token is not the one of the page that showed it
+    pub async fn decide_device_grant(
+        &self,
+        user_code: &str,
+        csrf_token: &str,
+        status: DeviceStatus,
+    ) -> bool {
+        let user_code = normalize_user_code(user_code);
+        let now = chrono::Utc::now();
+        let mut grants = self.device_grants.write().await;
+        let Some(grant) = grants.values_mut().find(|grant| {
+            grant.user_code == user_code
+                && grant.status == DeviceStatus::Pending
+                && grant.expires_at > now
+        }) else {
+            return false;
+        };
+        if grant.csrf_token != csrf_token {
+            return false;
+        }
+        grant.status = status;
+        true
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct TokenRequest {
-    pub grant_type: String,
-    #[serde(default)]
-    pub code: String,
-    #[serde(default)]
-    pub client_id: String,
-    #[serde(default)]
-    pub client_secret: String,
-    #[serde(default)]
-    pub redirect_uri: String,
-    #[serde(default)]
-    pub code_verifier: Option<String>,
-    #[serde(default)]
-    pub refresh_token: String,
-}
+    // grant_type=urn:ietf:params:oauth:grant-type:device_code (RFC 8628 section 3.4), a
+    // device polls until its user code was approved or denied
+    pub async fn poll_device_grant(
+        &self,
+        device_code: &str,
+        client_id: &str,
+    ) -> Result<McpAccessToken, DeviceError> {
+        let outcome = self.redeem_device_code(device_code, client_id).await;
+        if outcome.is_ok() {
+            self.persist().await;
+        }
+        outcome
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UserInfo {
-    pub sub: String,
-    pub name: String,
-    pub email: String,
-    pub username: String,
-}
+    async fn redeem_device_code(
+        &self,
+        device_code: &str,
+        client_id: &str,
+    ) -> Result<McpAccessToken, DeviceError> {
+        let mut refresh_tokens = self.refresh_tokens.write().await;
+        let mut access_tokens = self.access_tokens.write().await;
+        let mut grants = self.device_grants.write().await;
+        let Some(grant) = grants
+            .get_mut(device_code)
+            .filter(|grant| grant.client_id == client_id)
+        else {
+            return Err(DeviceError::InvalidGrant("unknown device code".to_string()));
+        };
+        let now = chrono::Utc::now();
+        if grant.expires_at <= now {
+            grants.remove(device_code);
+            return Err(DeviceError::ExpiredToken);
+        }
+        let too_soon = grant
+            .last_poll
+            .is_some_and(|last_poll| now - last_poll < grant.interval);
+        grant.last_poll = Some(now);
+        if too_soon {
+            grant.interval += SLOW_DOWN_INCREMENT;
+            return Err(DeviceError::SlowDown(grant.interval));
+        }
+        let approved_by = match grant.status.clone() {
+            DeviceStatus::Pending => return Err(DeviceError::AuthorizationPending),
+            DeviceStatus::Denied => {
+                grants.remove(device_code);
+                return Err(DeviceError::AccessDenied);
+            }
+            DeviceStatus::Approved(user) => user,
+        };
 
-#[derive(Template)]
-#[template(path = "mcp_oauth_authorize.html")]
-pub struct OAuthAuthorizeTemplate {
-    pub client_id: String,
-    pub redirect_uri: String,
-    pub scope: String,
-    pub state: String,
-    pub scopes: String,
-}
+        let Some(grant) = grants.remove(device_code) else {
+            return Err(DeviceError::InvalidGrant("unknown device code".to_string()));
+        };
+        let token = self.insert_token_pair(
+            &mut refresh_tokens,
+            &mut access_tokens,
+            grant.grant_id,
+            grant.client_id,
+            Some(grant.scope.clone()),
+            upstream_token(&grant.scope),
+        );
+        if let Some(user) = approved_by {
+            info!(
+                "issued tokens to the device of {} approved by {}",
+                token.client_id, user
+            );
+        }
+        Ok(token)
+    }
 
-// handle approval of authorization
-#[derive(Debug, Deserialize)]
-pub struct ApprovalForm {
-    pub client_id: String,
-    pub redirect_uri: String,
-    pub scope: String,
-    pub state: String,
-    pub approved: String,
-}
+    // grant_type=client_credentials (RFC 6749 section 4.4), for confidential clients
+    // only. The token comes without a refresh token, the client asks for a new one.
+    pub async fn create_client_credentials_token(
+        &self,
+        client_id: &str,
+        client_secret: Option<&str>,
+        scope: Option<&str>,
+    ) -> Result<McpAccessToken, ClientCredentialsError> {
+        self.authenticate_client(client_id, client_secret)
+            .await
+            .map_err(|e| ClientCredentialsError::InvalidClient(e.to_string()))?;
+        let client = self
+            .clients
+            .read()
+            .await
+            .get(client_id)
+            .cloned()
+            .ok_or_else(|| ClientCredentialsError::InvalidClient("unknown client".to_string()))?;
+        let allowed = self
+            .client_credentials_clients
+            .as_ref()
+            .is_none_or(|clients| clients.iter().any(|id| id == client_id));
+        if !allowed || client.client_secret.as_deref().is_none_or(str::is_empty) {
+            return Err(ClientCredentialsError::UnauthorizedClient(
+                "client may not use the client_credentials grant".to_string(),
+            ));
+        }
 
-pub fn generate_random_string(length: usize) -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(length)
-        .map(char::from)
-        .collect()
-}
+        // the client's own list caps its scopes, the supported ones if it has none
+        let within_client = |scope: &str| {
+            self.scopes_supported.iter().any(|s| s == scope)
+                && (client.scopes.is_empty() || client.scopes.iter().any(|s| s == scope))
+        };
+        let requested: Vec<&str> = scope.unwrap_or_default().split_whitespace().collect();
+        let scope = if requested.is_empty() {
+            let defaults: Vec<&str> = if client.scopes.is_empty() {
+                self.default_scopes.iter().map(String::as_str).collect()
+            } else {
+                client.scopes.iter().map(String::as_str).collect()
+            };
+            defaults
+                .into_iter()
+                .filter(|scope| within_client(scope))
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else if let Some(scope) = requested.iter().find(|scope| !within_client(scope)) {
+            return Err(ClientCredentialsError::InvalidScope(format!(
+                "scope {scope} is not available to this client"
+            )));
+        } else {
+            requested.join(" ")
+        };
 
-// Initial OAuth authorize endpoint
-pub async fn oauth_authorize(
-    Query(params): Query<AuthorizeQuery>,
-    State(state): State<Arc<McpOAuthStore>>,
-) -> impl IntoResponse {
-    debug!("doing oauth_authorize");
-    if let Some(_client) = state
-        .validate_client(&params.client_id, &params.redirect_uri)
-        .await
-    {
-        let template = OAuthAuthorizeTemplate {
-            client_id: params.client_id,
-            redirect_uri: params.redirect_uri,
-            scope: params.scope.clone().unwrap_or_default(),
-            state: params.state.clone().unwrap_or_default(),
-            scopes: params
-                .scope
-                .clone()
-                .unwrap_or_else(|| "Basic scope".to_string()),
+        let now = chrono::Utc::now();
+        let grant_id = Uuid::new_v4().to_string();
+        let access_token = self.new_access_token(client_id, &grant_id, Some(&scope), now);
+        let mut auth_token = StandardTokenResponse::new(
+            AccessToken::new(access_token.clone()), // its digest in the store
+            oauth2::basic::BasicTokenType::Bearer,
+            EmptyExtraTokenFields {},
+        );
+        auth_token.set_scopes(Some(
+            scope
+                .split_whitespace()
+                .map(|s| oauth2::Scope::new(s.to_string()))
+                .collect(),
+        ));
+        let token = McpAccessToken {
+            access_token: access_token.clone(),
+            token_type: "bearer".to_string(),
+            expires_in: Some(self.token_ttl.num_seconds() as u64),
+            expires_at: now + self.token_ttl,
+            refresh_token: None,
+            scope: Some(scope),
+            auth_token,
+            client_id: client_id.to_string(),
+            grant_id,
+            resource: self.resource.clone(),
         };
+        let (digest, record) = token.at_rest();
+        self.access_tokens.write().await.insert(digest, record);
+        self.persist().await;
+        info!("issued client credentials token to {}", client_id);
+        Ok(token)
+    }
+
+    // A new access token with its refresh token, the locks are taken refresh first
+    async fn issue_token_pair(
+        &self,
+        grant_id: String,
+        client_id: String,
+        scope: Option<String>,
+        auth_token: AuthToken,
+    ) -> McpAccessToken {
+        let token = {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            self.insert_token_pair(
+                &mut refresh_tokens,
+                &mut access_tokens,
+                grant_id,
+                client_id,
+                scope,
+                auth_token,
+            )
+        };
+        self.persist().await;
+        token
+    }
+
+    fn insert_token_pair(
+        &self,
+        refresh_tokens: &mut HashMap<String, McpRefreshToken>,
+        access_tokens: &mut HashMap<String, McpAccessToken>,
+        grant_id: String,
+        client_id: String,
+        scope: Option<String>,
+        auth_token: AuthToken,
+    ) -> McpAccessToken {
+        let now = chrono::Utc::now();
+        let access_token = self.new_access_token(&client_id, &grant_id, scope.as_deref(), now);
+        let refresh_token = format!("mcp-refresh-{}", Uuid::new_v4());
+
+        let token = McpAccessToken {
+            access_token: access_token.clone(),
+            token_type: "Bearer".to_string().to_lowercase(),
+            expires_in: Some(self.token_ttl.num_seconds() as u64),
+            expires_at: now + self.token_ttl,
+            refresh_token: Some(refresh_token.clone()),
+            scope,
+            auth_token,
+            client_id,
+            grant_id,
+            resource: self.resource.clone(),
+        };
+        let (digest, record) = token.at_rest();
+        refresh_tokens.insert(
+            token_digest(&refresh_token),
+            McpRefreshToken {
+                access_token: digest.clone(),
+                grant_id: token.grant_id.clone(),
+                client_id: token.client_id.clone(),
+                scope: token.scope.clone(),
+                auth_token: token.auth_token.clone(),
+                expires_at: now + REFRESH_TOKEN_LIFETIME,
+                rotated: false,
+            },
+        );
+        access_tokens.insert(digest, record);
+        token
+    }
+
+    // grant_type=refresh_token (RFC 6749 section 6). The refresh token is rotated and
+    // the old pair stops working, presenting a rotated token again revokes the grant.
+    // Without [oauth] refresh_token_rotation only the access token is renewed.
+    pub async fn refresh_mcp_token(
+        &self,
+        refresh_token: &str,
+        client_id: Option<&str>,
+        client_secret: Option<&str>,
+    ) -> Result<McpAccessToken, RefreshError> {
+        let outcome = self
+            .rotate_refresh_token(refresh_token, client_id, client_secret)
+            .await;
+        // a new pair and a revoked grant both change what has to survive a restart
+        if matches!(outcome, Ok(_) | Err(RefreshError::GrantRevoked(_))) {
+            self.persist().await;
+        }
+        outcome
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+        client_id: Option<&str>,
+        client_secret: Option<&str>,
+    ) -> Result<McpAccessToken, RefreshError> {
+        let refresh_digest = token_digest(refresh_token);
+        let mut refresh_tokens = self.refresh_tokens.write().await;
+        let mut access_tokens = self.access_tokens.write().await;
+        let Some(record) = refresh_tokens.get(&refresh_digest).cloned() else {
+            return Err(RefreshError::InvalidGrant(
+                "unknown refresh token".to_string(),
+            ));
+        };
+
+        // the client the token was issued to has to authenticate
+        let client_id = client_id.unwrap_or(&record.client_id);
+        if client_id != record.client_id {
+            return Err(RefreshError::InvalidGrant(
+                "refresh token was issued to another client".to_string(),
+            ));
+        }
+        self.authenticate_client(client_id, client_secret)
+            .await
+            .map_err(|e| RefreshError::InvalidClient(e.to_string()))?;
+
+        if record.rotated {
+            warn!(
+                "rotated refresh token of client {} used again, revoking grant {}",
+                record.client_id, record.grant_id
+            );
+            refresh_tokens.retain(|_, token| token.grant_id != record.grant_id);
+            access_tokens.retain(|_, token| token.grant_id != record.grant_id);
+            return Err(RefreshError::GrantRevoked(
+                "refresh token was already used, the grant is revoked".to_string(),
+            ));
+        }
+        if record.expires_at <= chrono::Utc::now() {
+            refresh_tokens.remove(&refresh_digest);
+            return Err(RefreshError::InvalidGrant(
+                "refresh token has expired".to_string(),
+            ));
+        }
+
+        access_tokens.remove(&record.access_token);
+        if !self.refresh_token_rotation {
+            let now = chrono::Utc::now();
+            let access_token = self.new_access_token(
+                &record.client_id,
+                &record.grant_id,
+                record.scope.as_deref(),
+                now,
+            );
+            let token = McpAccessToken {
+                access_token: access_token.clone(),
+                token_type: "bearer".to_string(),
+                expires_in: Some(self.token_ttl.num_seconds() as u64),
+                expires_at: now + self.token_ttl,
+                refresh_token: Some(refresh_token.to_string()),
+                scope: record.scope,
+                auth_token: record.auth_token,
+                client_id: record.client_id,
+                grant_id: record.grant_id,
+                resource: self.resource.clone(),
+            };
+            let (digest, kept_token) = token.at_rest();
+            if let Some(kept) = refresh_tokens.get_mut(&refresh_digest) {
+                kept.access_token = digest.clone();
+            }
+            access_tokens.insert(digest, kept_token);
+            info!("renewed access token of client {}", token.client_id);
+            return Ok(token);
+        }
+        // keep the old refresh token marked as rotated to catch a replay
+        if let Some(old) = refresh_tokens.get_mut(&refresh_digest) {
+            old.rotated = true;
+        }
+        let token = self.insert_token_pair(
+            &mut refresh_tokens,
+            &mut access_tokens,
+            record.grant_id,
+            record.client_id,
+            record.scope,
+            record.auth_token,
+        );
+        info!("refreshed access token of client {}", token.client_id);
+        Ok(token)
+    }
+
+    // A client with a secret has to present it, clients registered without one only
+    // identify themselves. Too many wrong secrets within the window lock the client for a
+    // while.
+    pub async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let secret = self
+            .clients
+            .read()
+            .await
+            .get(client_id)
+            .map(|client| client.client_secret.clone())
+            .ok_or("unknown client")?;
+        let Some(secret) = secret.filter(|secret| !secret.is_empty()) else {
+            return Ok(());
+        };
+        let now = chrono::Utc::now();
+        let mut failures = self.client_failures.lock().unwrap();
+        // wrong secrets older than the window no longer count, expired locks are lifted
+        failures.retain(|_, failed| {
+            failed
+                .times
+                .retain(|time| *time + self.client_failure_window > now);
+            !failed.times.is_empty() || failed.locked_until.is_some_and(|until| until > now)
+        });
+        if failures
+            .get(client_id)
+            .is_some_and(|failed| failed.locked_until.is_some_and(|until| until > now))
+        {
+            return Err("client is locked after too many failed authentications");
+        }
+        if client_secret == Some(secret.as_str()) {
+            failures.remove(client_id);
+            return Ok(());
+        }
+        let failed = failures
+            .entry(client_id.to_string())
+            .or_insert(ClientFailures {
+                times: Vec::new(),
+                locked_until: None,
+            });
+        failed.times.push(now);
+        let count = failed.times.len();
+        if self.max_failed_client_authentications > 0
+            && count >= self.max_failed_client_authentications as usize
+        {
+            // the count starts again once the lock is lifted
+            failed.times.clear();
+            failed.locked_until = Some(now + self.client_lockout);
+            warn!(
+                "client {} locked after {} failed authentications",
+                client_id, count
+            );
+            AuditEvent::new("client_locked", Outcome::Denied)
+                .client(client_id)
+                .reason(format!(
+                    "{count} failed authentications within {}s, locked for {}s",
+                    self.client_failure_window.num_seconds(),
+                    self.client_lockout.num_seconds()
+                ))
+                .emit();
+        }
+        Err("client authentication failed")
+    }
+
+    // Revoke a token of the client (RFC 7009). Revoking a refresh token ends its grant,
+    // every access token renewed from it goes too. Returns false for unknown tokens and
+    // tokens of other clients.
+    pub async fn revoke_token(&self, token: &str, client_id: &str) -> bool {
+        let digest = token_digest(token);
+        let revoked = {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            let grant_id = refresh_tokens
+                .get(&digest)
+                .filter(|record| record.client_id == client_id)
+                .map(|record| record.grant_id.clone());
+            if let Some(grant_id) = grant_id {
+                refresh_tokens.retain(|_, record| record.grant_id != grant_id);
+                access_tokens.retain(|_, record| record.grant_id != grant_id);
+                info!("revoked grant {} of client {}", grant_id, client_id);
+                true
+            } else if access_tokens
+                .get(&digest)
+                .is_some_and(|record| record.client_id == client_id)
+            {
+                access_tokens.remove(&digest);
+                info!("revoked an access token of client {}", client_id);
+                true
+            } else {
+                false
+            }
+        };
+        if revoked {
+            self.persist().await;
+        }
+        revoked
+    }
+
+    // Forget a client with its tokens, codes and pending authorizations, false if it was
+    // not registered. A client of [[oauth.clients]] is back after a restart.
+    pub async fn remove_client(&self, client_id: &str) -> bool {
+        if self.clients.write().await.remove(client_id).is_none() {
+            return false;
+        }
+        self.removed_clients
+            .write()
+            .await
+            .insert(client_id.to_string(), chrono::Utc::now());
+        {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            let mut auth_sessions = self.auth_sessions.write().await;
+            refresh_tokens.retain(|_, token| token.client_id != client_id);
+            access_tokens.retain(|_, token| token.client_id != client_id);
+            auth_sessions.retain(|_, session| session.client_id != client_id);
+        }
+        self.authorization_requests
+            .write()
+            .await
+            .retain(|_, request| request.client_id != client_id);
+        self.device_grants
+            .write()
+            .await
+            .retain(|_, grant| grant.client_id != client_id);
+        self.persist().await;
+        info!("removed client {} with its tokens", client_id);
+        true
+    }
+
+    // Revoke every token of the grant with its refresh tokens, and the code or device
+    // code that is yet to start it. False if nothing was left of it.
+    pub async fn revoke_grant(&self, grant_id: &str) -> bool {
+        let revoked = {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            let mut auth_sessions = self.auth_sessions.write().await;
+            let before = refresh_tokens.len() + access_tokens.len() + auth_sessions.len();
+            refresh_tokens.retain(|_, token| token.grant_id != grant_id);
+            access_tokens.retain(|_, token| token.grant_id != grant_id);
+            auth_sessions.retain(|_, session| session.grant_id != grant_id);
+            before != refresh_tokens.len() + access_tokens.len() + auth_sessions.len()
+        };
+        let mut device_grants = self.device_grants.write().await;
+        let pending = device_grants.len();
+        device_grants.retain(|_, grant| grant.grant_id != grant_id);
+        let revoked = revoked || pending != device_grants.len();
+        drop(device_grants);
+        self.dpop_bindings.write().await.remove(grant_id);
+        self.revoked_grants
+            .write()
+            .await
+            .insert(grant_id.to_string(), chrono::Utc::now());
+        self.persist().await;
+        revoked
+    }
+
+    // Revoke the access or refresh token with this audit fingerprint, a refresh token
+    // with its whole grant. The client it was issued to, none if there is no such token.
+    pub async fn revoke_fingerprint(&self, fingerprint: &str) -> Option<String> {
+        let revoked = {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            let matches = |digest: &String| digest_fingerprint(digest) == fingerprint;
+            if let Some(record) = refresh_tokens
+                .iter()
+                .find(|(digest, _)| matches(digest))
+                .map(|(_, record)| record.clone())
+            {
+                refresh_tokens.retain(|_, token| token.grant_id != record.grant_id);
+                access_tokens.retain(|_, token| token.grant_id != record.grant_id);
+                Some(record.client_id)
+            } else if let Some(digest) =
+                access_tokens.keys().find(|digest| matches(digest)).cloned()
+            {
+                access_tokens.remove(&digest).map(|record| record.client_id)
+            } else {
+                None
+            }
+        };
+        if revoked.is_some() {
+            self.persist().await;
+        }
+        revoked
+    }
+
+    // What /introspect reports about an active access or refresh token. Tokens do not
+    // record when they were issued, their lifetime is fixed so it follows from the expiry.
+    pub async fn introspect(&self, token: &str) -> Option<TokenInfo> {
+        let now = chrono::Utc::now();
+        let digest = token_digest(token);
+        if let Some(record) = self
+            .access_tokens
+            .read()
+            .await
+            .get(&digest)
+            .filter(|record| record.expires_at > now)
+        {
+            return Some(TokenInfo {
+                client_id: record.client_id.clone(),
+                scope: record.scope.clone(),
+                exp: record.expires_at.timestamp(),
+                iat: (record.expires_at - self.token_ttl).timestamp(),
+                token_type: "Bearer",
+            });
+        }
+        // a JWT of another replica
+        if let Some(claims) = self
+            .jwt
+            .as_ref()
+            .filter(|_| is_jwt(token))
+            .and_then(|jwt| jwt.verify(token))
+        {
+            return Some(TokenInfo {
+                client_id: claims.client_id,
+                scope: Some(claims.scope),
+                exp: claims.exp,
+                iat: claims.iat,
+                token_type: "Bearer",
+            });
+        }
+        self.refresh_tokens
+            .read()
+            .await
+            .get(&digest)
+            .filter(|record| !record.rotated && record.expires_at > now)
+            .map(|record| TokenInfo {
+                client_id: record.client_id.clone(),
+                scope: record.scope.clone(),
+                exp: record.expires_at.timestamp(),
+                iat: (record.expires_at - REFRESH_TOKEN_LIFETIME).timestamp(),
+                token_type: "refresh_token",
+            })
+    }
+
+    // Why the token is not for this server, if it isn't. JWTs have to name
+    // expected_audience, the tokens of this store the resource it serves now: a token
+    // saved before external_url changed is for the old one.
+    pub fn check_audience(&self, token: &McpAccessToken) -> Result<(), String> {
+        if let Some(expected) = &self.expected_audience
+            && is_jwt(&token.access_token)
+        {
+            let audiences = jwt::audiences(&token.access_token);
+            if !audiences.iter().any(|audience| audience == expected) {
+                return Err(format!(
+                    "the token is for {audiences:?}, not for {expected}"
+                ));
+            }
+        }
+        match (&token.resource, &self.resource) {
+            (Some(issued_for), Some(resource)) if issued_for != resource => Err(format!(
+                "the token was issued for {issued_for}, not for {resource}"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // The DPoP key thumbprint a token of the grant has to be sent with, none if unbound
+    pub async fn dpop_binding(&self, grant_id: &str) -> Option<String> {
+        self.dpop_bindings.read().await.get(grant_id).cloned()
+    }
+
+    async fn refresh_dpop_binding(&self, refresh_token: &str) -> Option<String> {
+        let grant_id = self
+            .refresh_tokens
+            .read()
+            .await
+            .get(&token_digest(refresh_token))?
+            .grant_id
+            .clone();
+        self.dpop_binding(&grant_id).await
+    }
+
+    pub fn is_introspection_secret(&self, secret: &str) -> bool {
+        self.introspection_secret.as_deref() == Some(secret)
+    }
+
+    // An expired token is treated like an unknown one, the pruning task removes it.
+    // Revoked tokens are gone from the map, so they fail on the next request. JWTs
+    // are checked by their signature alone, they are valid until they expire.
+    pub async fn validate_token(&self, token: &str) -> Option<McpAccessToken> {
+        if let Some(oidc) = &self.oidc {
+            return oidc.verify(token).await;
+        }
+        if let Some(remote) = &self.remote_jwks
+            && is_jwt(token)
+            && let Some(token) = remote.verify(token).await
+        {
+            return Some(token);
+        }
+        if let Some(jwt) = &self.jwt
+            && is_jwt(token)
+        {
+            let claims = jwt.verify(token)?;
+            if self
+                .removed_clients
+                .read()
+                .await
+                .contains_key(&claims.client_id)
+                || self
+                    .revoked_grants
+                    .read()
+                    .await
+                    .contains_key(&claims.grant_id)
+            {
+                return None;
+            }
+            return Some(jwt_access_token(token, claims));
+        }
+        // the record has the digest, the tools and the log get the token
+        self.access_tokens
+            .read()
+            .await
+            .get(&token_digest(token))
+            .filter(|record| record.expires_at > chrono::Utc::now())
+            .map(|record| McpAccessToken {
+                access_token: token.to_string(),
+                ..record.clone()
+            })
+    }
+
+    // Drop expired access and refresh tokens and codes that were never exchanged
+    pub async fn prune(&self) {
+        let now = chrono::Utc::now();
+        let (access, refresh, codes) = {
+            let mut refresh_tokens = self.refresh_tokens.write().await;
+            let mut access_tokens = self.access_tokens.write().await;
+            let mut auth_sessions = self.auth_sessions.write().await;
+            let before = (
+                access_tokens.len(),
+                refresh_tokens.len(),
+                auth_sessions.len(),
+            );
+            access_tokens.retain(|_, token| token.expires_at > now);
+            refresh_tokens.retain(|_, token| token.expires_at > now);
+            // used codes stay until then too, to catch a second exchange
+            auth_sessions.retain(|_, session| session.created_at + self.code_ttl > now);
+            self.authorization_requests
+                .write()
+                .await
+                .retain(|_, request| !request.is_expired());
+            self.device_grants
+                .write()
+                .await
+                .retain(|_, grant| grant.expires_at > now);
+            self.removed_clients
+                .write()
+                .await
+                .retain(|_, removed_at| *removed_at + self.token_ttl > now);
+            self.revoked_grants
+                .write()
+                .await
+                .retain(|_, revoked_at| *revoked_at + self.token_ttl > now);
+            self.dpop_bindings.write().await.retain(|grant_id, _| {
+                refresh_tokens
+                    .values()
+                    .any(|token| token.grant_id == *grant_id)
+                    || access_tokens
+                        .values()
+                        .any(|token| token.grant_id == *grant_id)
+            });
+            (
+                before.0 - access_tokens.len(),
+                before.1 - refresh_tokens.len(),
+                before.2 - auth_sessions.len(),
+            )
+        };
+        if access + refresh + codes > 0 {
+            debug!("pruned {access} access tokens, {refresh} refresh tokens and {codes} codes");
+        }
+        if access + refresh > 0 {
+            self.persist().await;
+        }
+    }
+
+    // Prune the store in the background for as long as the server runs
+    pub fn spawn_pruning(self: &Arc<Self>) {
+        let store = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                store.prune().await;
+            }
+        });
+    }
+}
+
+// a simple session record for auth session
+#[derive(Clone, Debug)]
+pub struct AuthSession {
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>, // S256 challenge of PKCE
+    pub approved_by: Option<String>,    // the user who logged in to approve, if logins are required
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub auth_token: Option<AuthToken>,
+    pub grant_id: String,               // of the tokens of the exchange
+    pub redeemed_grant: Option<String>, // the grant of the first exchange, the code is used
+}
+
+// An authorization request the user has yet to approve or reject, checked when it was
+// shown. The approval form only carries its CSRF token, the rest stays here.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,                  // granted on approval
+    pub state: Option<String>,          // sent back to the client verbatim
+    pub code_challenge: Option<String>, // S256 challenge of PKCE
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl AuthorizationRequest {
+    fn is_expired(&self) -> bool {
+        self.created_at + AUTHORIZATION_REQUEST_LIFETIME <= chrono::Utc::now()
+    }
+}
+
+// A device waiting for a user to enter its user code (RFC 8628), in memory only
+#[derive(Debug, Clone)]
+pub struct DeviceGrant {
+    pub client_id: String,
+    pub scope: String,      // granted on approval
+    pub user_code: String,  // without the dash
+    pub csrf_token: String, // of the approval form of the user code
+    pub grant_id: String,   // of the tokens the device gets
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub interval: chrono::TimeDelta, // raised by every slow_down
+    pub last_poll: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: DeviceStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Pending,
+    Approved(Option<String>), // by the user who logged in, if logins are required
+    Denied,
+}
+
+// The error codes of a device polling /token (RFC 8628 section 3.5)
+#[derive(Debug)]
+pub enum DeviceError {
+    InvalidGrant(String),
+    AuthorizationPending,
+    SlowDown(chrono::TimeDelta), // the new interval
+    AccessDenied,
+    ExpiredToken,
+}
+
+// a simple token record for mcp token using oauth2 standard token
+#[derive(Clone, Debug, Serialize)]
+pub struct McpAccessToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    #[serde(skip)]
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub auth_token: AuthToken,
+    pub client_id: String,
+    pub grant_id: String, // shared by all tokens renewed from the same authorization
+    #[serde(skip)]
+    pub resource: Option<String>, // what it was issued for, none for tokens checked by signature
+}
+
+// The record of a JWT checked without the store
+fn jwt_access_token(token: &str, claims: AccessClaims) -> McpAccessToken {
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0).unwrap_or(chrono::Utc::now());
+    McpAccessToken::verified(
+        token,
+        claims.client_id,
+        claims.scope,
+        expires_at,
+        if claims.grant_id.is_empty() {
+            claims.jti
+        } else {
+            claims.grant_id
+        },
+    )
+}
+
+impl McpAccessToken {
+    // The record of a token checked without the store, e.g. a JWT or an API key
+    pub fn verified(
+        token: &str,
+        client_id: String,
+        scope: String,
+        expires_at: chrono::DateTime<chrono::Utc>,
+        grant_id: String,
+    ) -> Self {
+        let mut auth_token = StandardTokenResponse::new(
+            AccessToken::new(token.to_string()),
+            oauth2::basic::BasicTokenType::Bearer,
+            EmptyExtraTokenFields {},
+        );
+        auth_token.set_scopes(Some(
+            scope
+                .split_whitespace()
+                .map(|s| oauth2::Scope::new(s.to_string()))
+                .collect(),
+        ));
+        McpAccessToken {
+            access_token: token.to_string(),
+            token_type: "bearer".to_string(),
+            expires_in: Some((expires_at - chrono::Utc::now()).num_seconds().max(0) as u64),
+            expires_at,
+            refresh_token: None,
+            scope: Some(scope),
+            auth_token,
+            client_id,
+            grant_id,
+            resource: None,
+        }
+    }
+
+    // What the store keeps of an issued token and its key there: the digest instead of
+    // the token, without the refresh token
+    fn at_rest(&self) -> (String, McpAccessToken) {
+        let digest = token_digest(&self.access_token);
+        let mut record = McpAccessToken {
+            access_token: digest.clone(),
+            refresh_token: None,
+            ..self.clone()
+        };
+        if record.auth_token.access_token().secret() == &self.access_token {
+            record
+                .auth_token
+                .set_access_token(AccessToken::new(digest.clone()));
+        }
+        (digest, record)
+    }
+}
+
+// a refresh token record, rotated tokens are kept until they expire to detect reuse
+#[derive(Clone, Debug)]
+pub struct McpRefreshToken {
+    pub access_token: String, // the digest of the access token issued with it
+    pub grant_id: String,
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub auth_token: AuthToken,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub rotated: bool,
+}
+
+// An active token as reported by /introspect (RFC 7662 section 2.2)
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TokenInfo {
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub exp: i64, // unix seconds
+    pub iat: i64,
+    #[schema(value_type = String, example = "Bearer")]
+    pub token_type: &'static str,
+}
+
+#[derive(Debug)]
+pub enum ClientCredentialsError {
+    InvalidClient(String),
+    UnauthorizedClient(String), // authenticated, but not allowed to use the grant
+    InvalidScope(String),
+}
+
+#[derive(Debug)]
+pub enum RefreshError {
+    InvalidClient(String),
+    InvalidGrant(String),
+    GrantRevoked(String), // a rotated refresh token was replayed
+}
+
+#[derive(Debug)]
+pub enum CodeError {
+    InvalidGrant(String),
+    GrantRevoked(String), // the code was exchanged before
+    ServerError(String),
+}
+
+// The wrong secrets of a client within the window
+#[derive(Debug)]
+struct ClientFailures {
+    times: Vec<chrono::DateTime<chrono::Utc>>,
+    locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// A client from the config, /register or the storage
+#[derive(Debug, Clone)]
+pub struct RegisteredClient {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub client_name: Option<String>, // as the client registered itself, unchecked
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>, // of /register, none for the config's
+    pub scopes: Vec<String>,
+    pub redirect_uris: Vec<String>,      // codes only go to these
+    pub registered_with: Option<String>, // fingerprint of the initial access token of /register
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuthorizeQuery {
+    #[allow(dead_code)]
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub resource: Option<String>, // RFC 8707, this server's if given
+}
+
+// POST /revoke (RFC 7009 section 2.1)
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RevokeRequest {
+    #[serde(default)]
+    pub token: String,
+    // both kinds of tokens are looked up, the hint is not needed
+    #[allow(dead_code)]
+    pub token_type_hint: Option<String>,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+}
+
+// POST /introspect (RFC 7662 section 2.1)
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct IntrospectRequest {
+    #[serde(default)]
+    pub token: String,
+    // ignored like the one of /revoke
+    #[allow(dead_code)]
+    pub token_type_hint: Option<String>,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    #[serde(default)]
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub code_verifier: Option<String>,
+    #[serde(default)]
+    pub refresh_token: String,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub device_code: String,
+    #[serde(default)]
+    pub resource: Option<String>, // RFC 8707, this server's if given
+    #[serde(skip)]
+    pub dpop_jkt: Option<String>, // of the DPoP proof header, the tokens are bound to it
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserInfo {
+    pub sub: String,
+    pub name: String,
+    pub email: String,
+    pub username: String,
+}
+
+// The values of templates/mcp_oauth_authorize.html
+#[derive(Serialize)]
+pub struct OAuthAuthorizeTemplate {
+    pub client_id: String,
+    pub client_name: Option<String>, // of the registration, the client id is shown without
+    pub redirect_uri: String,
+    pub redirect_host: String,
+    pub scopes: Vec<String>,
+    pub csrf_token: String,   // of the authorization request
+    pub login_required: bool, // ask for username and password
+    pub error: String,        // of the last login attempt
+    pub totp_required: bool,  // ask for a one-time code
+    pub totp_enrollment: Option<totp::Enrollment>, // the generated secret, until its first code
+}
+
+// The page of the device flow: the user code is asked for, then approved, then the
+// outcome is shown
+#[derive(Serialize, Default)]
+pub struct OAuthDeviceTemplate {
+    pub user_code: String,
+    pub client_id: String, // empty until a pending user code was entered
+    pub client_name: Option<String>,
+    pub scopes: Vec<String>,
+    pub csrf_token: String, // of the device grant
+    pub login_required: bool,
+    pub error: String,
+    pub message: String, // the outcome, nothing else is shown with it
+    pub totp_required: bool,
+    pub totp_enrollment: Option<totp::Enrollment>,
+}
+
+#[derive(Serialize)]
+pub struct OAuthErrorTemplate {
+    pub description: String,
+}
+
+// POST /device_authorization (RFC 8628 section 3.1)
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeviceAuthorizationRequest {
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    #[serde(default)]
+    pub scope: String, // the default scopes if empty
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeviceQuery {
+    pub user_code: Option<String>, // of verification_uri_complete, asked for if missing
+}
+
+// The approval form of a device, sent back with the user code it showed
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeviceApprovalForm {
+    #[serde(default)]
+    pub user_code: String,
+    #[serde(default)]
+    pub csrf_token: String,
+    pub approved: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    #[schema(value_type = String, format = Password)]
+    pub password: Password,
+    #[serde(default)]
+    pub totp_code: String, // with [oauth.totp]
+}
+
+// handle approval of authorization
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ApprovalForm {
+    #[serde(default)]
+    pub csrf_token: String,
+    pub approved: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    #[schema(value_type = String, format = Password)]
+    pub password: Password,
+    #[serde(default)]
+    pub totp_code: String, // with [oauth.totp]
+}
+
+// Never printed, not even in debug logs
+#[derive(Default, Deserialize)]
+#[serde(transparent)]
+pub struct Password(String);
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(<redacted>)")
+    }
+}
+
+// Verifiers and challenges are 43 to 128 unreserved characters
+fn is_pkce_value(value: &str) -> bool {
+    (43..=128).contains(&value.len())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+}
+
+// BASE64URL(SHA256(verifier)) without padding
+fn s256_challenge(code_verifier: &str) -> String {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use sha2::{Digest, Sha256};
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+// The token of a session approved by a user, using the oauth2 standard token
+fn upstream_token(scope: &str) -> AuthToken {
+    let access_token = AccessToken::new(format!("tp-token-{}", Uuid::new_v4()));
+    let refresh_token = RefreshToken::new(format!("tp-refresh-{}", Uuid::new_v4()));
+    let token_type = oauth2::basic::BasicTokenType::Bearer;
+
+    let mut token = StandardTokenResponse::new(access_token, token_type, EmptyExtraTokenFields {});
+    token.set_expires_in(Some(&std::time::Duration::from_secs(3600)));
+    token.set_refresh_token(Some(refresh_token));
+    token.set_scopes(Some(
+        scope
+            .split_whitespace()
+            .map(|s| oauth2::Scope::new(s.to_string()))
+            .collect(),
+    ));
+    token
+}
+
+// Eight letters, about 34 bits, shown as BCDF-GHJK
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| char::from(USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())]))
+        .collect()
+}
+
+// A user code as typed, in any case and with or without the dash
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn format_user_code(user_code: &str) -> String {
+    let (left, right) = user_code.split_at(user_code.len() / 2);
+    format!("{left}-{right}")
+}
+
+pub fn generate_random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+// The redirect_uri of [[oauth.clients]] and older storage files first, then the list
+fn merge_redirect_uris(redirect_uri: &str, redirect_uris: &[String]) -> Vec<String> {
+    Some(redirect_uri)
+        .filter(|uri| !uri.is_empty())
+        .map(str::to_string)
+        .into_iter()
+        .chain(redirect_uris.iter().cloned())
+        .collect()
+}
+
+// Registered redirect uris are absolute and have no fragment (RFC 6749 section 3.1.2)
+fn is_valid_redirect_uri(uri: &str) -> bool {
+    Url::parse(uri).is_ok_and(|url| url.fragment().is_none())
+}
+
+// http://127.0.0.1 or http://[::1], not localhost (RFC 8252 section 8.3)
+fn is_loopback(url: &Url) -> bool {
+    url.scheme() == "http"
+        && url
+            .host_str()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .and_then(|host| host.parse::<std::net::IpAddr>().ok())
+            .is_some_and(|ip| ip.is_loopback())
+}
+
+// The uri has to equal the registered one character for character, no prefixes and no
+// query strings left out. Native apps get a random loopback port (RFC 8252 section 7.3),
+// so an http loopback uri may differ in the port only.
+fn redirect_uri_matches(registered: &str, requested: &str, loopback_any_port: bool) -> bool {
+    if registered == requested {
+        return true;
+    }
+    if !loopback_any_port {
+        return false;
+    }
+    let (Ok(mut registered), Ok(mut requested)) = (Url::parse(registered), Url::parse(requested))
+    else {
+        return false;
+    };
+    if !is_loopback(&registered) || !is_loopback(&requested) {
+        return false;
+    }
+    let _ = registered.set_port(None);
+    let _ = requested.set_port(None);
+    registered == requested
+}
+
+// Errors of requests whose redirect uri is not the client's, never sent there
+fn error_page(state: &McpOAuthStore, description: &str) -> Response {
+    let template = OAuthErrorTemplate {
+        description: description.to_string(),
+    };
+    state
+        .pages
+        .render(StatusCode::BAD_REQUEST, pages::ERROR, template)
+}
+
+// The uri with the parameters added to its query, percent-encoded so a state full of
+// `&`, `=` or `#` arrives as it was sent
+fn with_query(uri: &str, params: &[(&str, &str)]) -> String {
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!(
+        "{uri}{separator}{}",
+        serde_urlencoded::to_string(params).unwrap_or_default()
+    )
+}
+
+// Send an authorization error back to the redirect uri of the client
+fn error_redirect(
+    redirect_uri: &str,
+    error: &str,
+    description: &str,
+    state: Option<&str>,
+) -> Response {
+    let mut query = vec![("error", error), ("error_description", description)];
+    if let Some(state) = state {
+        query.push(("state", state));
+    }
+    Redirect::to(&with_query(redirect_uri, &query)).into_response()
+}
+
+// Initial OAuth authorize endpoint
+#[utoipa::path(
+    get,
+    path = "/authorize",
+    tag = "oauth",
+    params(AuthorizeQuery),
+    responses(
+        (status = 200, description = "The approval page", content_type = "text/html"),
+        (status = 303, description = "An error for the redirect uri of the client, e.g. invalid_scope or invalid_target"),
+        (status = 400, description = "Unknown client or unregistered redirect uri, nothing is redirected", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_authorize(
+    Query(params): Query<AuthorizeQuery>,
+    State(state): State<Arc<McpOAuthStore>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    debug!("doing oauth_authorize");
+    let audit = |outcome| {
+        AuditEvent::new("authorization_request", outcome)
+            .client(&params.client_id)
+            .ip(Some(addr.ip()))
+    };
+    if state
+        .validate_client(&params.client_id, &params.redirect_uri)
+        .await
+        .is_none()
+    {
+        info!(
+            "refused authorization request of {} to {}",
+            params.client_id, params.redirect_uri
+        );
+        audit(Outcome::Failure)
+            .reason("unknown client or redirect uri")
+            .emit();
+        return error_page(
+            &state,
+            "The client is unknown or the redirect uri is not registered for it.",
+        );
+    }
+
+    // the redirect uri is known to be the client's, so errors go back there
+    if let Err(description) = state.check_code_challenge(
+        params.code_challenge.as_deref(),
+        params.code_challenge_method.as_deref(),
+    ) {
+        info!("invalid pkce parameters: {}", description);
+        audit(Outcome::Failure).reason(description).emit();
+        return error_redirect(
+            &params.redirect_uri,
+            "invalid_request",
+            description,
+            params.state.as_deref(),
+        );
+    }
+    if let Err(description) = state.check_resource(
+        params.resource.as_deref(),
+        &state.public_url.of_request(&headers),
+    ) {
+        info!("invalid resource: {}", description);
+        audit(Outcome::Failure).reason(description.as_str()).emit();
+        return error_redirect(
+            &params.redirect_uri,
+            "invalid_target",
+            &description,
+            params.state.as_deref(),
+        );
+    }
+    let scope = match state.granted_scope(params.scope.as_deref()) {
+        Ok(scope) => scope,
+        Err(description) => {
+            info!("invalid scope: {}", description);
+            audit(Outcome::Failure).reason(description.as_str()).emit();
+            return error_redirect(
+                &params.redirect_uri,
+                "invalid_scope",
+                &description,
+                params.state.as_deref(),
+            );
+        }
+    };
+
+    audit(Outcome::Success).emit();
+    let request = AuthorizationRequest {
+        client_id: params.client_id,
+        redirect_uri: params.redirect_uri,
+        scope,
+        state: params.state,
+        code_challenge: params.code_challenge,
+        created_at: chrono::Utc::now(),
+    };
+    let csrf_token = state.create_authorization_request(request.clone()).await;
+    approval_page(
+        &state,
+        StatusCode::OK,
+        &request,
+        csrf_token,
+        state.users.is_login_required(),
+        "",
+    )
+    .await
+}
+
+// The approval page of the request, its form answers with the CSRF token
+async fn approval_page(
+    state: &McpOAuthStore,
+    status: StatusCode,
+    request: &AuthorizationRequest,
+    csrf_token: String,
+    login_required: bool,
+    error: &str,
+) -> Response {
+    let template = OAuthAuthorizeTemplate {
+        client_id: request.client_id.clone(),
+        client_name: state.client_name(&request.client_id).await,
+        redirect_uri: request.redirect_uri.clone(),
+        redirect_host: Url::parse(&request.redirect_uri)
+            .ok()
+            .and_then(|uri| uri.host_str().map(str::to_string))
+            .unwrap_or_default(),
+        scopes: request
+            .scope
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+        csrf_token,
+        login_required,
+        error: error.to_string(),
+        totp_required: state.second_factor.is_some(),
+        totp_enrollment: state
+            .second_factor
+            .as_ref()
+            .and_then(|second_factor| second_factor.enrollment()),
+    };
+    state.pages.render(status, pages::AUTHORIZE, template)
+}
+
+// A refused login, the page of the form is shown again with the error
+struct LoginRefused {
+    status: StatusCode,
+    error: &'static str,
+    retry_after: Option<std::time::Duration>,
+}
+
+impl LoginRefused {
+    fn add_retry_after(&self, response: &mut Response) {
+        if let Some(wait) = self.retry_after {
+            response.headers_mut().insert(
+                axum::http::header::RETRY_AFTER,
+                retry_after_seconds(wait).into(),
+            );
+        }
+    }
+}
+
+// The user whose password came with an approval for the client, None if logins are not
+// required. With [oauth.totp] a valid one-time code has to come with it.
+async fn approving_user(
+    state: &McpOAuthStore,
+    ip: IpAddr,
+    client_id: &str,
+    username: &str,
+    password: Password,
+    totp_code: &str,
+) -> Result<Option<String>, LoginRefused> {
+    let user = logged_in_user(state, ip, client_id, username, password).await?;
+    let Some(second_factor) = &state.second_factor else {
+        return Ok(user);
+    };
+    let audit = |outcome| {
+        AuditEvent::new("second_factor", outcome)
+            .client(client_id)
+            .user(user.as_deref())
+            .ip(Some(ip))
+    };
+    match second_factor.verify(totp_code, ip, user.as_deref()) {
+        Ok(enrolled) => {
+            if enrolled {
+                info!("the TOTP secret is enrolled, the approval pages stop showing it");
+                audit(Outcome::Success)
+                    .reason("the first code of the generated secret")
+                    .emit();
+                state.persist().await;
+            }
+            Ok(user)
+        }
+        Err(totp::CodeError::Invalid) => {
+            info!("wrong one-time code from {}", ip);
+            audit(Outcome::Failure)
+                .reason("invalid one-time code")
+                .emit();
+            Err(LoginRefused {
+                status: StatusCode::UNAUTHORIZED,
+                error: "wrong one-time code",
+                retry_after: None,
+            })
+        }
+        Err(totp::CodeError::LockedOut(wait)) => {
+            info!("one-time code from {} refused for {:?}", ip, wait);
+            audit(Outcome::Denied)
+                .reason("too many wrong one-time codes")
+                .emit();
+            Err(LoginRefused {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                error: "too many wrong one-time codes, try again later",
+                retry_after: Some(wait),
+            })
+        }
+    }
+}
+
+async fn logged_in_user(
+    state: &McpOAuthStore,
+    ip: IpAddr,
+    client_id: &str,
+    username: &str,
+    password: Password,
+) -> Result<Option<String>, LoginRefused> {
+    if !state.users.is_login_required() {
+        return Ok(None);
+    }
+    let users = state.users.clone();
+    let outcome = {
+        let username = username.to_string();
+        tokio::task::spawn_blocking(move || users.verify(ip, &username, &password.0))
+            .await
+            .unwrap_or(Err(LoginError::InvalidCredentials))
+    };
+    let login = |outcome| {
+        AuditEvent::new("login", outcome)
+            .client(client_id)
+            .user(Some(username))
+            .ip(Some(ip))
+    };
+    match outcome {
+        Ok(()) => Ok(Some(username.to_string())),
+        Err(LoginError::InvalidCredentials) => {
+            info!("failed login of {:?} from {}", username, ip);
+            login(Outcome::Failure).reason("invalid credentials").emit();
+            Err(LoginRefused {
+                status: StatusCode::UNAUTHORIZED,
+                error: "wrong username or password",
+                retry_after: None,
+            })
+        }
+        Err(LoginError::LockedOut) => {
+            info!("login from {} refused, too many failed logins", ip);
+            login(Outcome::Denied)
+                .reason("too many failed logins from the address")
+                .emit();
+            Err(LoginRefused {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                error: "too many failed logins, try again later",
+                retry_after: None,
+            })
+        }
+        Err(LoginError::BackingOff(wait)) => {
+            info!("login of {:?} refused for {:?}", username, wait);
+            login(Outcome::Denied)
+                .reason(format!("backing off for {wait:?}"))
+                .emit();
+            Err(LoginRefused {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                error: "too many failed logins of this user, try again in a moment",
+                retry_after: Some(wait),
+            })
+        }
+    }
+}
+
+// A form that was not rendered by oauth_authorize, or was answered already
+fn invalid_approval_form(state: &McpOAuthStore) -> Response {
+    error_page(
+        state,
+        "The approval form has expired or was already used. Start the authorization in the client again.",
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/approve",
+    tag = "oauth",
+    request_body(content = ApprovalForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "To the redirect uri with the code and state, or access_denied"),
+        (status = 400, description = "Unknown, expired or used CSRF token", content_type = "text/html"),
+        (status = 401, description = "Wrong username, password or one-time code, the page again", content_type = "text/html"),
+        (status = 429, description = "Too many failed logins from the address or of the username, too many wrong one-time codes or too many requests", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_approve(
+    State(state): State<Arc<McpOAuthStore>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+    Form(mut form): Form<ApprovalForm>,
+) -> impl IntoResponse {
+    // only forms of our own approval page carry a known CSRF token
+    let Some(request) = state.authorization_request(&form.csrf_token).await else {
+        info!("approval from {} with an unknown csrf token", addr.ip());
+        return invalid_approval_form(&state);
+    };
+    // the client might have gone since the page was shown
+    if state
+        .validate_client(&request.client_id, &request.redirect_uri)
+        .await
+        .is_none()
+    {
+        return error_page(
+            &state,
+            "The client is unknown or the redirect uri is not registered for it.",
+        );
+    }
+
+    if form.approved != "true" {
+        // user rejected the authorization request
+        if state
+            .take_authorization_request(&form.csrf_token)
+            .await
+            .is_none()
+        {
+            return invalid_approval_form(&state);
+        }
+        AuditEvent::new("authorization_denied", Outcome::Denied)
+            .client(&request.client_id)
+            .ip(Some(addr.ip()))
+            .emit();
+        return error_redirect(
+            &request.redirect_uri,
+            "access_denied",
+            "user rejected the authorization request",
+            request.state.as_deref(),
+        );
+    }
+
+    // with users configured, approving needs the password of one of them
+    let approved_by = match approving_user(
+        &state,
+        addr.ip(),
+        &request.client_id,
+        &form.username,
+        std::mem::take(&mut form.password),
+        &form.totp_code,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(refused) => {
+            let mut response = approval_page(
+                &state,
+                refused.status,
+                &request,
+                form.csrf_token,
+                state.users.is_login_required(),
+                refused.error,
+            )
+            .await;
+            refused.add_retry_after(&mut response);
+            return response;
+        }
+    };
+
+    // a second submission of the same form, e.g. from another tab, gets no second code
+    let Some(request) = state.take_authorization_request(&form.csrf_token).await else {
+        return invalid_approval_form(&state);
+    };
+    let scope = request.scope.clone();
+
+    // user approved the authorization request, generate authorization code
+    let session_id = Uuid::new_v4().to_string();
+    let auth_code = format!("mcp-code-{session_id}");
+
+    // create new session record authorization information
+    let grant_id = state
+        .create_auth_session(
+            request.client_id.clone(),
+            Some(scope.clone()),
+            request.state.clone(),
+            request.code_challenge.clone(),
+            approved_by.clone(),
+            session_id.clone(),
+        )
+        .await;
+
+    // update session token
+    if let Err(e) = state
+        .update_auth_session_token(&session_id, upstream_token(&scope))
+        .await
+    {
+        error!("Failed to update session token: {}", e);
+    }
+
+    // redirect back to client, with authorization code
+    let mut query = vec![("code", auth_code.as_str())];
+    if let Some(client_state) = &request.state {
+        query.push(("state", client_state));
+    }
+    let redirect_url = with_query(&request.redirect_uri, &query);
+
+    if let Some(user) = &approved_by {
+        info!(
+            "{} approved {} for scope {:?}",
+            user, request.client_id, scope
+        );
+    }
+    AuditEvent::new("authorization_approved", Outcome::Success)
+        .client(&request.client_id)
+        .user(approved_by.as_deref())
+        .ip(Some(addr.ip()))
+        .reason(format!("scope {scope}"))
+        .emit();
+    info!("authorization approved, redirecting to: {}", redirect_url);
+    let cookie = state.browser_sessions.add_grant(
+        &headers,
+        approved_by,
+        ApprovedGrant {
+            grant_id,
+            client_id: request.client_id,
+            scope,
+            approved_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        },
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to(&redirect_url)).into_response()
+}
+
+// The device authorization endpoint (RFC 8628 section 3.1), for clients on machines
+// without a browser. The user approves on any other machine at the verification uri.
+#[utoipa::path(
+    post,
+    path = "/device_authorization",
+    tag = "oauth",
+    request_body(content = DeviceAuthorizationRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The device code for /token and the user code to enter at the verification uri", body = DeviceAuthorizationResponse),
+        (status = 400, description = "invalid_request or invalid_scope", body = OAuthErrorResponse),
+        (status = 401, description = "invalid_client", body = OAuthErrorResponse),
+        (status = 429, description = "Too many requests, retry after the seconds of Retry-After", body = OAuthErrorResponse),
+    ),
+    security((), ("client_basic" = [])),
+)]
+pub async fn oauth_device_authorization(
+    State(state): State<Arc<McpOAuthStore>>,
+    request: axum::http::Request<Body>,
+) -> impl IntoResponse {
+    let ip = peer_ip(request.extensions());
+    let basic_credentials = basic_credentials(request.headers());
+    let base_url = state.public_url.of_request(request.headers());
+    let bytes = match read_body(request.into_body(), MAX_FORM_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    let device_req = match serde_urlencoded::from_bytes::<DeviceAuthorizationRequest>(&bytes) {
+        Ok(form) => form,
+        Err(e) => {
+            error!("can't parse device authorization request: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": format!("can't parse form data: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let (client_id, client_secret) = match &basic_credentials {
+        Some((client_id, client_secret)) => (client_id.as_str(), Some(client_secret.as_str())),
+        None => (
+            device_req.client_id.as_str(),
+            Some(device_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+        ),
+    };
+    let audit = |outcome| {
+        AuditEvent::new("device_authorization", outcome)
+            .client(client_id)
+            .ip(ip)
+    };
+    if let Err(description) = state.authenticate_client(client_id, client_secret).await {
+        info!("device authorization request with invalid client: {description}");
+        audit(Outcome::Failure).reason(description).emit();
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "invalid_client",
+                "error_description": description
+            })),
+        )
+            .into_response();
+    }
+    let scope = match state.granted_scope(Some(device_req.scope.as_str())) {
+        Ok(scope) => scope,
+        Err(description) => {
+            info!("invalid scope: {}", description);
+            audit(Outcome::Failure).reason(description.as_str()).emit();
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_scope",
+                    "error_description": description
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let (device_code, grant) = state.create_device_grant(client_id, scope).await;
+    audit(Outcome::Success).token(&device_code).emit();
+    let user_code = format_user_code(&grant.user_code);
+    let verification_uri = format!("{base_url}/device");
+    let verification_uri_complete =
+        with_query(&verification_uri, &[("user_code", user_code.as_str())]);
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "device_code": device_code,
+            "user_code": user_code,
+            "verification_uri": verification_uri,
+            "verification_uri_complete": verification_uri_complete,
+            "expires_in": (grant.expires_at - chrono::Utc::now()).num_seconds().max(0),
+            "interval": grant.interval.num_seconds(),
+        })),
+    )
+        .into_response()
+}
+
+// The page asking for the user code, again with the error if it was wrong
+fn device_code_page(
+    state: &McpOAuthStore,
+    status: StatusCode,
+    user_code: &str,
+    error: &str,
+) -> Response {
+    let template = OAuthDeviceTemplate {
+        user_code: user_code.to_string(),
+        error: error.to_string(),
+        ..Default::default()
+    };
+    state.pages.render(status, pages::DEVICE, template)
+}
+
+// The approval page of a pending device grant
+async fn device_approval_page(
+    state: &McpOAuthStore,
+    status: StatusCode,
+    grant: &DeviceGrant,
+    login_required: bool,
+    error: &str,
+) -> Response {
+    let template = OAuthDeviceTemplate {
+        user_code: format_user_code(&grant.user_code),
+        client_id: grant.client_id.clone(),
+        client_name: state.client_name(&grant.client_id).await,
+        scopes: grant.scope.split_whitespace().map(str::to_string).collect(),
+        csrf_token: grant.csrf_token.clone(),
+        login_required,
+        error: error.to_string(),
+        message: String::new(),
+        totp_required: state.second_factor.is_some(),
+        totp_enrollment: state
+            .second_factor
+            .as_ref()
+            .and_then(|second_factor| second_factor.enrollment()),
+    };
+    state.pages.render(status, pages::DEVICE, template)
+}
+
+// The code of a device was approved or denied, there is nothing more to do on the page
+fn device_done_page(state: &McpOAuthStore, message: &str) -> Response {
+    let template = OAuthDeviceTemplate {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    state.pages.render(StatusCode::OK, pages::DEVICE, template)
+}
+
+// A form of a code that is gone, or of a page that did not show it
+fn unknown_device_code(state: &McpOAuthStore, user_code: &str) -> Response {
+    device_code_page(
+        state,
+        StatusCode::BAD_REQUEST,
+        user_code,
+        "The code is unknown, has expired or was already answered.",
+    )
+}
+
+// The verification uri of the device flow, where users enter the code of the device
+#[utoipa::path(
+    get,
+    path = "/device",
+    tag = "oauth",
+    params(DeviceQuery),
+    responses(
+        (status = 200, description = "The page asking for the user code, or the approval page of its device", content_type = "text/html"),
+        (status = 400, description = "Unknown or expired user code, the code is asked for again", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_device(
+    Query(query): Query<DeviceQuery>,
+    State(state): State<Arc<McpOAuthStore>>,
+) -> impl IntoResponse {
+    let Some(user_code) = query.user_code.filter(|code| !code.trim().is_empty()) else {
+        return device_code_page(&state, StatusCode::OK, "", "");
+    };
+    match state.pending_device_grant(&user_code).await {
+        Some(grant) => {
+            device_approval_page(
+                &state,
+                StatusCode::OK,
+                &grant,
+                state.users.is_login_required(),
+                "",
+            )
+            .await
+        }
+        None => unknown_device_code(&state, &user_code),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/device",
+    tag = "oauth",
+    request_body(content = DeviceApprovalForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The device was approved or denied", content_type = "text/html"),
+        (status = 400, description = "Unknown, expired or answered user code, or a CSRF token of another page", content_type = "text/html"),
+        (status = 401, description = "Wrong username, password or one-time code, the page again", content_type = "text/html"),
+        (status = 429, description = "Too many failed logins from the address or of the username, too many wrong one-time codes or too many requests", content_type = "text/html"),
+    ),
+)]
+pub async fn oauth_device_approve(
+    State(state): State<Arc<McpOAuthStore>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+    Form(mut form): Form<DeviceApprovalForm>,
+) -> impl IntoResponse {
+    let Some(grant) = state
+        .pending_device_grant(&form.user_code)
+        .await
+        .filter(|grant| grant.csrf_token == form.csrf_token)
+    else {
+        info!("device approval from {} with an unknown code", addr.ip());
+        return unknown_device_code(&state, &form.user_code);
+    };
+    let audit = |event, outcome| {
+        AuditEvent::new(event, outcome)
+            .client(&grant.client_id)
+            .ip(Some(addr.ip()))
+    };
+
+    if form.approved != "true" {
+        if !state
+            .decide_device_grant(&form.user_code, &form.csrf_token, DeviceStatus::Denied)
+            .await
+        {
+            return unknown_device_code(&state, &form.user_code);
+        }
+        audit("device_denied", Outcome::Denied).emit();
+        return device_done_page(&state, "The device was rejected, it gets no access.");
+    }
+
+    let approved_by = match approving_user(
+        &state,
+        addr.ip(),
+        &grant.client_id,
+        &form.username,
+        std::mem::take(&mut form.password),
+        &form.totp_code,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(refused) => {
+            let mut response = device_approval_page(
+                &state,
+                refused.status,
+                &grant,
+                state.users.is_login_required(),
+                refused.error,
+            )
+            .await;
+            refused.add_retry_after(&mut response);
+            return response;
+        }
+    };
+    // the device gets its tokens on its next poll of /token
+    if !state
+        .decide_device_grant(
+            &form.user_code,
+            &form.csrf_token,
+            DeviceStatus::Approved(approved_by.clone()),
+        )
+        .await
+    {
+        return unknown_device_code(&state, &form.user_code);
+    }
+    audit("device_approved", Outcome::Success)
+        .user(approved_by.as_deref())
+        .reason(format!("scope {}", grant.scope))
+        .emit();
+    let cookie = state.browser_sessions.add_grant(
+        &headers,
+        approved_by,
+        ApprovedGrant {
+            grant_id: grant.grant_id.clone(),
+            client_id: grant.client_id.clone(),
+            scope: grant.scope.clone(),
+            approved_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        },
+    );
+    let mut response = device_done_page(
+        &state,
+        "The device is approved and signs in within a few seconds. You can close this page.",
+    );
+    response.headers_mut().insert(header::SET_COOKIE, cookie);
+    response
+}
+
+// Handle token request from the MCP client
+#[utoipa::path(
+    post,
+    path = "/token",
+    tag = "oauth",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "A retry with the same key and parameters gets the answer of the first request"),
+    ),
+    responses(
+        (status = 200, description = "The tokens", body = TokenResponse),
+        (status = 400, description = "invalid_request, invalid_grant, invalid_scope, invalid_target or unsupported_grant_type", body = OAuthErrorResponse),
+        (status = 401, description = "invalid_client", body = OAuthErrorResponse),
+        (status = 422, description = "The Idempotency-Key was used for another request", body = OAuthErrorResponse),
+        (status = 429, description = "Too many requests, retry after the seconds of Retry-After", body = OAuthErrorResponse),
+    ),
+    security((), ("client_basic" = [])),
+)]
+pub async fn oauth_token(
+    State(state): State<Arc<McpOAuthStore>>,
+    request: axum::http::Request<Body>,
+) -> impl IntoResponse {
+    info!("Received token request");
+
+    let ip = peer_ip(request.extensions());
+    let basic_credentials = basic_credentials(request.headers());
+    let base_url = state.public_url.of_request(request.headers());
+    let dpop_proof = request
+        .headers()
+        .get(dpop::PROOF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let path = request.uri().path().to_string();
+    let bytes = match read_body(request.into_body(), MAX_FORM_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+
+    // the body holds codes and secrets, only the grant type is logged
+    let mut token_req = match serde_urlencoded::from_bytes::<TokenRequest>(&bytes) {
+        Ok(form) => {
+            info!("token request with grant_type {}", form.grant_type);
+            form
+        }
+        Err(e) => {
+            error!("can't parse form data: {}", e);
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": format!("can't parse form data: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+    // the bucket of the client named by the request, before its credentials are checked
+    let requester = basic_credentials
+        .as_ref()
+        .map_or(token_req.client_id.as_str(), |(client_id, _)| client_id);
+    if let Err(wait) = state.token_buckets.take(requester) {
+        info!("token requests of client {} over the limit", requester);
+        AuditEvent::new("rate_limited", Outcome::Denied)
+            .client(requester)
+            .ip(ip)
+            .reason("token_endpoint_rate_limit")
+            .emit();
+        return too_many_requests(wait);
+    }
+    // a DPoP proof binds the tokens to its key (RFC 9449 section 5)
+    let refused = |client_id: &str, description: &str| {
+        AuditEvent::new("token_request", Outcome::Failure)
+            .client(client_id)
+            .ip(ip)
+            .reason(description)
+            .emit();
+        invalid_dpop_proof(description)
+    };
+    match dpop_proof {
+        Some(proof) => match dpop::verify(&proof, "POST", &path, None, &state.dpop_replay) {
+            Ok(jkt) => token_req.dpop_jkt = Some(jkt),
+            Err(description) => return refused(&token_req.client_id, description),
+        },
+        None if state.dpop_required => {
+            return refused(&token_req.client_id, "a DPoP proof is required");
+        }
+        None => {}
+    }
+    // every grant issues tokens for this server only (RFC 8707 section 2.2)
+    if let Err(description) = state.check_resource(token_req.resource.as_deref(), &base_url) {
+        info!("token request for another resource: {description}");
+        AuditEvent::new("token_request", Outcome::Failure)
+            .client(&token_req.client_id)
+            .ip(ip)
+            .reason(description.as_str())
+            .emit();
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "invalid_target",
+                "error_description": description
+            })),
+        )
+            .into_response();
+    }
+    if token_req.grant_type == "refresh_token" {
+        if let Some(bound) = state.refresh_dpop_binding(&token_req.refresh_token).await
+            && token_req.dpop_jkt.as_ref() != Some(&bound)
+        {
+            return refused(
+                &token_req.client_id,
+                "the refresh token is bound to another DPoP key",
+            );
+        }
+        return oauth_refresh_token(&state, &token_req, basic_credentials, ip).await;
+    }
+    if token_req.grant_type == "client_credentials" {
+        return oauth_client_credentials(&state, &token_req, basic_credentials, ip).await;
+    }
+    if token_req.grant_type == DEVICE_CODE_GRANT_TYPE {
+        return oauth_device_code(&state, &token_req, basic_credentials, ip).await;
+    }
+    if token_req.grant_type != "authorization_code" {
+        info!("unsupported grant type: {}", token_req.grant_type);
+        AuditEvent::new("token_request", Outcome::Failure)
+            .client(&token_req.client_id)
+            .ip(ip)
+            .reason(format!("unsupported grant_type {}", token_req.grant_type))
+            .emit();
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "unsupported_grant_type",
+                "error_description": format!("only authorization_code, refresh_token, client_credentials and {DEVICE_CODE_GRANT_TYPE} are supported")
+            })),
+        )
+            .into_response();
+    }
+
+    let audit = |outcome| {
+        AuditEvent::new("code_exchange", outcome)
+            .client(&token_req.client_id)
+            .ip(ip)
+    };
+    // get session_id from code
+    if !token_req.code.starts_with("mcp-code-") {
+        info!("invalid authorization code");
+        audit(Outcome::Failure)
+            .reason("invalid authorization code")
+            .emit();
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "invalid authorization code"
+            })),
+        )
+            .into_response();
+    }
+
+    // handle empty client_id
+    let client_id = if token_req.client_id.is_empty() {
+        "mcp-client".to_string()
+    } else {
+        token_req.client_id.clone()
+    };
+
+    // validate client
+    match state
+        .validate_client(&client_id, &token_req.redirect_uri)
+        .await
+    {
+        Some(_) => {
+            let session_id = token_req.code.replace("mcp-code-", "");
+            info!("got session id: {}", session_id);
+
+            // PKCE, the verifier has to match the challenge sent to /authorize
+            if let Err(description) = state
+                .check_code_verifier(&session_id, token_req.code_verifier.as_deref())
+                .await
+            {
+                info!("pkce verification failed: {}", description);
+                audit(Outcome::Failure).reason(description).emit();
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": "invalid_grant",
+                        "error_description": description
+                    })),
+                )
+                    .into_response();
+            }
+
+            // create mcp access token
+            match state.create_mcp_token(&session_id, &client_id).await {
+                Ok(token) => {
+                    info!("successfully created access token");
+                    audit(Outcome::Success).token(&token.access_token).emit();
+                    token_response(&state, &token, &token_req).await
+                }
+                Err(
+                    CodeError::InvalidGrant(description) | CodeError::GrantRevoked(description),
+                ) => {
+                    info!("invalid authorization code: {description}");
+                    audit(Outcome::Failure).reason(description.as_str()).emit();
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({
+                            "error": "invalid_grant",
+                            "error_description": description
+                        })),
+                    )
+                        .into_response()
+                }
+                Err(CodeError::ServerError(e)) => {
+                    error!("failed to create access token: {}", e);
+                    audit(Outcome::Failure).reason(e.as_str()).emit();
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(serde_json::json!({
+                            "error": "server_error",
+                            "error_description": format!("failed to create access token: {}", e)
+                        })),
+                    )
+                        .into_response()
+                }
+            }
+        }
+        None => {
+            info!(
+                "invalid client id or redirect uri: {} / {}",
+                client_id, token_req.redirect_uri
+            );
+            audit(Outcome::Failure)
+                .reason("invalid client id or redirect uri")
+                .emit();
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_client",
+                    "error_description": "invalid client id or redirect uri"
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+// The tokens of a request with a DPoP proof are bound to its key from now on
+async fn token_response(
+    state: &McpOAuthStore,
+    token: &McpAccessToken,
+    token_req: &TokenRequest,
+) -> Response {
+    let token_type = match &token_req.dpop_jkt {
+        Some(jkt) => {
+            state
+                .dpop_bindings
+                .write()
+                .await
+                .insert(token.grant_id.clone(), jkt.clone());
+            state.persist().await;
+            "DPoP"
+        }
+        None => token.token_type.as_str(),
+    };
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "access_token": token.access_token,
+            "token_type": token_type,
+            "expires_in": token.expires_in,
+            "refresh_token": token.refresh_token,
+            "scope": token.scope,
+        })),
+    )
+        .into_response()
+}
+
+// client_id and client_secret of an Authorization: Basic header (RFC 6749 section 2.3.1)
+pub(crate) fn basic_credentials(headers: &axum::http::HeaderMap) -> Option<(String, String)> {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    let encoded = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    // both parts are form-urlencoded before they are joined
+    let decode = |part: &str| {
+        serde_urlencoded::from_str::<Vec<(String, String)>>(&format!("v={part}"))
+            .ok()
+            .and_then(|mut pairs| pairs.pop())
+            .map(|(_, value)| value)
+    };
+    Some((decode(client_id)?, decode(client_secret)?))
+}
+
+async fn oauth_refresh_token(
+    state: &McpOAuthStore,
+    token_req: &TokenRequest,
+    basic_credentials: Option<(String, String)>,
+    ip: Option<IpAddr>,
+) -> Response {
+    if token_req.refresh_token.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "invalid_request",
+                "error_description": "refresh_token is required"
+            })),
+        )
+            .into_response();
+    }
+    let (client_id, client_secret) = match &basic_credentials {
+        Some((client_id, client_secret)) => {
+            (Some(client_id.as_str()), Some(client_secret.as_str()))
+        }
+        None => (
+            Some(token_req.client_id.as_str()).filter(|id| !id.is_empty()),
+            Some(token_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+        ),
+    };
+
+    let audit = |outcome| {
+        AuditEvent::new("token_refresh", outcome)
+            .client(client_id.unwrap_or_default())
+            .ip(ip)
+            .token(&token_req.refresh_token)
+    };
+    match state
+        .refresh_mcp_token(&token_req.refresh_token, client_id, client_secret)
+        .await
+    {
+        Ok(token) => {
+            audit(Outcome::Success).emit();
+            token_response(state, &token, token_req).await
+        }
+        Err(RefreshError::InvalidClient(description)) => {
+            info!("refresh token request with invalid client: {description}");
+            audit(Outcome::Failure).reason(description.as_str()).emit();
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({
+                    "error": "invalid_client",
+                    "error_description": description
+                })),
+            )
+                .into_response()
+        }
+        Err(RefreshError::InvalidGrant(description) | RefreshError::GrantRevoked(description)) => {
+            info!("invalid refresh token: {description}");
+            audit(Outcome::Failure).reason(description.as_str()).emit();
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_grant",
+                    "error_description": description
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn oauth_client_credentials(
+    state: &McpOAuthStore,
+    token_req: &TokenRequest,
+    basic_credentials: Option<(String, String)>,
+    ip: Option<IpAddr>,
+) -> Response {
+    let (client_id, client_secret) = match &basic_credentials {
+        Some((client_id, client_secret)) => (client_id.as_str(), Some(client_secret.as_str())),
+        None => (
+            token_req.client_id.as_str(),
+            Some(token_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+        ),
+    };
+    let scope = Some(token_req.scope.as_str()).filter(|scope| !scope.is_empty());
+
+    let audit = |outcome| {
+        AuditEvent::new("client_credentials", outcome)
+            .client(client_id)
+            .ip(ip)
+    };
+    let (status, error, description) = match state
+        .create_client_credentials_token(client_id, client_secret, scope)
+        .await
+    {
+        Ok(token) => {
+            audit(Outcome::Success).token(&token.access_token).emit();
+            return token_response(state, &token, token_req).await;
+        }
+        Err(ClientCredentialsError::InvalidClient(description)) => {
+            (StatusCode::UNAUTHORIZED, "invalid_client", description)
+        }
+        Err(ClientCredentialsError::UnauthorizedClient(description)) => {
+            (StatusCode::BAD_REQUEST, "unauthorized_client", description)
+        }
+        Err(ClientCredentialsError::InvalidScope(description)) => {
+            (StatusCode::BAD_REQUEST, "invalid_scope", description)
+        }
+    };
+    info!("client credentials request of {client_id} refused: {description}");
+    audit(Outcome::Failure).reason(description.as_str()).emit();
+    (
+        status,
+        Json(serde_json::json!({
+            "error": error,
+            "error_description": description
+        })),
+    )
+        .into_response()
+}
 
-        Html(template.render().unwrap()).into_response()
-    } else {
-        (
+async fn oauth_device_code(
+    state: &McpOAuthStore,
+    token_req: &TokenRequest,
+    basic_credentials: Option<(String, String)>,
+    ip: Option<IpAddr>,
+) -> Response {
+    if token_req.device_code.is_empty() {
+        return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
                 "error": "invalid_request",
-                "error_description": "invalid client id or redirect uri"
+                "error_description": "device_code is required"
             })),
         )
-            .into_response()
+            .into_response();
     }
-}
-
-pub async fn oauth_approve(
-    State(state): State<Arc<McpOAuthStore>>,
-    Form(form): Form<ApprovalForm>,
-) -> impl IntoResponse {
-    if form.approved != "true" {
-        // user rejected the authorization request
-        let redirect_url = format!(
-            "{}?error=access_denied&error_description={}{}",
-            form.redirect_uri,
-            "user rejected the authorization request",
-            if form.state.is_empty() {
-                "".to_string()
-            } else {
-                format!("&state={}", form.state)
-            }
-        );
-        return Redirect::to(&redirect_url).into_response();
+    let (client_id, client_secret) = match &basic_credentials {
+        Some((client_id, client_secret)) => (client_id.as_str(), Some(client_secret.as_str())),
+        None => (
+            token_req.client_id.as_str(),
+            Some(token_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+        ),
+    };
+    let audit = |outcome| {
+        AuditEvent::new("device_code_exchange", outcome)
+            .client(client_id)
+            .ip(ip)
+    };
+    if let Err(description) = state.authenticate_client(client_id, client_secret).await {
+        info!("device code request with invalid client: {description}");
+        audit(Outcome::Failure).reason(description).emit();
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "invalid_client",
+                "error_description": description
+            })),
+        )
+            .into_response();
     }
 
-    // user approved the authorization request, generate authorization code
-    let session_id = Uuid::new_v4().to_string();
-    let auth_code = format!("mcp-code-{session_id}");
-
-    // create new session record authorization information
-    let session_id = state
-        .create_auth_session(
-            form.client_id.clone(),
-            Some(form.scope.clone()),
-            Some(form.state.clone()),
-            session_id.clone(),
+    let refused = |error: &str, description: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": error,
+                "error_description": description
+            })),
         )
-        .await;
-
-    // create token using oauth2 standard
-    let access_token = AccessToken::new(format!("tp-token-{}", Uuid::new_v4()));
-    let refresh_token = RefreshToken::new(format!("tp-refresh-{}", Uuid::new_v4()));
-    let token_type = oauth2::basic::BasicTokenType::Bearer;
-
-    let mut created_token =
-        StandardTokenResponse::new(access_token, token_type, EmptyExtraTokenFields {});
-    created_token.set_expires_in(Some(&std::time::Duration::from_secs(3600)));
-    created_token.set_refresh_token(Some(refresh_token));
-    created_token.set_scopes(Some(vec![oauth2::Scope::new(form.scope.clone())]));
-
-    // update session token
-    if let Err(e) = state
-        .update_auth_session_token(&session_id, created_token)
+            .into_response()
+    };
+    let (error, description) = match state
+        .poll_device_grant(&token_req.device_code, client_id)
         .await
     {
-        error!("Failed to update session token: {}", e);
-    }
-
-    // redirect back to client, with authorization code
-    let redirect_url = format!(
-        "{}?code={}{}",
-        form.redirect_uri,
-        auth_code,
-        if form.state.is_empty() {
-            "".to_string()
-        } else {
-            format!("&state={}", form.state)
+        Ok(token) => {
+            audit(Outcome::Success).token(&token.access_token).emit();
+            return token_response(state, &token, token_req).await;
         }
-    );
-
-    info!("authorization approved, redirecting to: {}", redirect_url);
-    Redirect::to(&redirect_url).into_response()
+        // the device keeps polling, these are not worth a record
+        Err(DeviceError::AuthorizationPending) => {
+            return refused("authorization_pending", "the user has not answered yet");
+        }
+        Err(DeviceError::SlowDown(interval)) => {
+            return refused(
+                "slow_down",
+                &format!("poll at most every {} seconds", interval.num_seconds()),
+            );
+        }
+        Err(DeviceError::AccessDenied) => {
+            ("access_denied", "the user rejected the device".to_string())
+        }
+        Err(DeviceError::ExpiredToken) => (
+            "expired_token",
+            "the device code has expired, start the device authorization again".to_string(),
+        ),
+        Err(DeviceError::InvalidGrant(description)) => ("invalid_grant", description),
+    };
+    info!("device code request of {client_id} refused: {description}");
+    audit(Outcome::Failure).reason(description.as_str()).emit();
+    refused(error, &description)
 }
 
-// Handle token request from the MCP client
-pub async fn oauth_token(
+// Token revocation endpoint (RFC 7009). Unknown tokens get 200 as well, so the answer
+// tells nothing about which tokens exist.
+#[utoipa::path(
+    post,
+    path = "/revoke",
+    tag = "oauth",
+    request_body(content = RevokeRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Revoked, or the token was unknown"),
+        (status = 400, description = "invalid_request", body = OAuthErrorResponse),
+        (status = 401, description = "invalid_client", body = OAuthErrorResponse),
+    ),
+    security((), ("client_basic" = [])),
+)]
+pub async fn oauth_revoke(
     State(state): State<Arc<McpOAuthStore>>,
     request: axum::http::Request<Body>,
 ) -> impl IntoResponse {
-    info!("Received token request");
-
-    let bytes = match axum::body::to_bytes(request.into_body(), usize::MAX).await {
+    let ip = peer_ip(request.extensions());
+    let basic_credentials = basic_credentials(request.headers());
+    let bytes = match read_body(request.into_body(), MAX_FORM_BYTES).await {
         Ok(bytes) => bytes,
-        Err(e) => {
-            error!("can't read request body: {}", e);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({
-                    "error": "invalid_request",
-                    "error_description": "can't read request body"
-                })),
-            )
-                .into_response();
-        }
+        Err(response) => return response,
     };
-
-    let body_str = String::from_utf8_lossy(&bytes);
-    info!("request body: {}", body_str);
-
-    let token_req = match serde_urlencoded::from_bytes::<TokenRequest>(&bytes) {
-        Ok(form) => {
-            info!("successfully parsed form data: {:?}", form);
-            form
-        }
+    let revoke_req = match serde_urlencoded::from_bytes::<RevokeRequest>(&bytes) {
+        Ok(form) => form,
         Err(e) => {
-            error!("can't parse form data: {}", e);
+            error!("can't parse revocation request: {}", e);
             return (
-                StatusCode::UNPROCESSABLE_ENTITY,
+                StatusCode::BAD_REQUEST,
                 Json(serde_json::json!({
                     "error": "invalid_request",
                     "error_description": format!("can't parse form data: {}", e)
@@ -366,191 +3362,624 @@ pub async fn oauth_token(
                 .into_response();
         }
     };
-    if token_req.grant_type == "refresh_token" {
-        warn!("this easy server only support authorization_code now");
+    if revoke_req.token.is_empty() {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
-                "error": "unsupported_grant_type",
-                "error_description": "only authorization_code is supported"
+                "error": "invalid_request",
+                "error_description": "token is required"
             })),
         )
             .into_response();
     }
-    if token_req.grant_type != "authorization_code" {
-        info!("unsupported grant type: {}", token_req.grant_type);
+
+    let (client_id, client_secret) = match &basic_credentials {
+        Some((client_id, client_secret)) => (client_id.as_str(), Some(client_secret.as_str())),
+        None => (
+            revoke_req.client_id.as_str(),
+            Some(revoke_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+        ),
+    };
+    let audit = |outcome| {
+        AuditEvent::new("token_revocation", outcome)
+            .client(client_id)
+            .ip(ip)
+            .token(&revoke_req.token)
+    };
+    if let Err(description) = state.authenticate_client(client_id, client_secret).await {
+        info!("revocation request with invalid client: {description}");
+        audit(Outcome::Failure).reason(description).emit();
         return (
-            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
             Json(serde_json::json!({
-                "error": "unsupported_grant_type",
-                "error_description": "only authorization_code is supported"
+                "error": "invalid_client",
+                "error_description": description
             })),
         )
             .into_response();
     }
 
-    // get session_id from code
-    if !token_req.code.starts_with("mcp-code-") {
-        info!("invalid authorization code: {}", token_req.code);
+    if state.revoke_token(&revoke_req.token, client_id).await {
+        audit(Outcome::Success).emit();
+    } else {
+        debug!("nothing to revoke for client {}", client_id);
+        audit(Outcome::Failure)
+            .reason("unknown token or a token of another client")
+            .emit();
+    }
+    StatusCode::OK.into_response()
+}
+
+// Token introspection endpoint (RFC 7662). Callers are registered clients, which only
+// learn about their own tokens, or hold the configured introspection secret.
+#[utoipa::path(
+    post,
+    path = "/introspect",
+    tag = "oauth",
+    request_body(content = IntrospectRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Whether the token is active, with its claims if it is", body = IntrospectionResponse),
+        (status = 400, description = "invalid_request", body = OAuthErrorResponse),
+        (status = 401, description = "invalid_client", body = OAuthErrorResponse),
+    ),
+    security((), ("client_basic" = []), ("introspection_secret" = [])),
+)]
+pub async fn oauth_introspect(
+    State(state): State<Arc<McpOAuthStore>>,
+    request: axum::http::Request<Body>,
+) -> impl IntoResponse {
+    let basic_credentials = basic_credentials(request.headers());
+    let bearer = request
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let bytes = match read_body(request.into_body(), MAX_FORM_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    let introspect_req = match serde_urlencoded::from_bytes::<IntrospectRequest>(&bytes) {
+        Ok(form) => form,
+        Err(e) => {
+            error!("can't parse introspection request: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": format!("can't parse form data: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+    if introspect_req.token.is_empty() {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
-                "error": "invalid_grant",
-                "error_description": "invalid authorization code"
+                "error": "invalid_request",
+                "error_description": "token is required"
             })),
         )
             .into_response();
     }
 
-    // handle empty client_id
-    let client_id = if token_req.client_id.is_empty() {
-        "mcp-client".to_string()
+    // None when the caller may see every token
+    let caller = if bearer.is_some_and(|secret| state.is_introspection_secret(&secret)) {
+        None
     } else {
-        token_req.client_id.clone()
-    };
-
-    // validate client
-    match state
-        .validate_client(&client_id, &token_req.redirect_uri)
-        .await
-    {
-        Some(_) => {
-            let session_id = token_req.code.replace("mcp-code-", "");
-            info!("got session id: {}", session_id);
-
-            // create mcp access token
-            match state.create_mcp_token(&session_id).await {
-                Ok(token) => {
-                    info!("successfully created access token");
-                    (
-                        StatusCode::OK,
-                        Json(serde_json::json!({
-                            "access_token": token.access_token,
-                            "token_type": token.token_type,
-                            "expires_in": token.expires_in,
-                            "refresh_token": token.refresh_token,
-                            "scope": token.scope,
-                        })),
-                    )
-                        .into_response()
-                }
-                Err(e) => {
-                    error!("failed to create access token: {}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(serde_json::json!({
-                            "error": "server_error",
-                            "error_description": format!("failed to create access token: {}", e)
-                        })),
-                    )
-                        .into_response()
-                }
-            }
-        }
-        None => {
-            info!(
-                "invalid client id or redirect uri: {} / {}",
-                client_id, token_req.redirect_uri
-            );
-            (
-                StatusCode::BAD_REQUEST,
+        let (client_id, client_secret) = match &basic_credentials {
+            Some((client_id, client_secret)) => (client_id.clone(), Some(client_secret.as_str())),
+            None => (
+                introspect_req.client_id.clone(),
+                Some(introspect_req.client_secret.as_str()).filter(|secret| !secret.is_empty()),
+            ),
+        };
+        if let Err(description) = state.authenticate_client(&client_id, client_secret).await {
+            info!("introspection request with invalid client: {description}");
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(axum::http::header::WWW_AUTHENTICATE, "Basic")],
                 Json(serde_json::json!({
                     "error": "invalid_client",
-                    "error_description": "invalid client id or redirect uri"
+                    "error_description": description
                 })),
             )
-                .into_response()
+                .into_response();
         }
-    }
+        Some(client_id)
+    };
+
+    let info = state
+        .introspect(&introspect_req.token)
+        .await
+        .filter(|info| {
+            caller
+                .as_ref()
+                .is_none_or(|caller| *caller == info.client_id)
+        });
+    let body = match info {
+        Some(info) => {
+            let mut body = serde_json::to_value(info).unwrap_or_default();
+            body["active"] = Value::Bool(true);
+            body
+        }
+        // unknown, expired, revoked and foreign tokens look the same
+        None => serde_json::json!({ "active": false }),
+    };
+    (StatusCode::OK, Json(body)).into_response()
 }
 
 // Auth middleware for StreamableHttp connections
 pub async fn validate_token_middleware(
     State(token_store): State<Arc<McpOAuthStore>>,
-    request: Request<axum::body::Body>,
+    mut request: Request<axum::body::Body>,
     next: Next,
 ) -> Response {
     debug!("validate_token_middleware");
-    // Extract the access token from the Authorization header
-    let auth_header = request.headers().get("Authorization");
-    let token = match auth_header {
-        Some(header) => {
-            let header_str = header.to_str().unwrap_or("");
-            if let Some(stripped) = header_str.strip_prefix("Bearer ") {
-                stripped.to_string()
-            } else {
-                return StatusCode::UNAUTHORIZED.into_response();
-            }
+    let ip = peer_ip(request.extensions());
+    let base_url = token_store.public_url.of_request(request.headers());
+    let rejected = |outcome, reason: &str| {
+        AuditEvent::new("token_rejected", outcome)
+            .ip(ip)
+            .reason(reason)
+    };
+    // a token in the query never goes further than here, it is out of the URI either way
+    let query_token = match without_query_token(request.uri()) {
+        Some(_) if !token_store.accept_token_in_query => {
+            rejected(Outcome::Failure, "the token was sent in the query").emit();
+            return misplaced_token();
         }
-        None => {
-            return StatusCode::UNAUTHORIZED.into_response();
+        Some((token, uri)) => {
+            *request.uri_mut() = uri;
+            Some(token)
+        }
+        None => None,
+    };
+    // The Authorization header, then the fallback header and the query if accepted
+    let headers = request.headers();
+    let token = bearer_token(headers.get(header::AUTHORIZATION), false)
+        .or_else(|| {
+            token_store
+                .accept_fallback_header
+                .then(|| bearer_token(headers.get(FALLBACK_TOKEN_HEADER), true))
+                .flatten()
+        })
+        .or(query_token);
+    let Some(token) = token else {
+        rejected(Outcome::Failure, "no bearer token").emit();
+        return unauthorized(&base_url, None);
+    };
+
+    // Validate the token, the tools read it back for scope checks
+    let Some(token) = token_store.validate_token(&token).await else {
+        rejected(Outcome::Failure, "invalid or expired token")
+            .token(&token)
+            .emit();
+        // tells the client to refresh the token (RFC 6750 section 3.1)
+        return unauthorized(
+            &base_url,
+            Some(
+                r#"error="invalid_token", error_description="The access token is invalid or expired""#,
+            ),
+        );
+    };
+
+    if let Err(reason) = token_store.check_audience(&token) {
+        warn!("refused a token of client {}: {}", token.client_id, reason);
+        rejected(Outcome::Failure, &reason)
+            .client(&token.client_id)
+            .token(&token.access_token)
+            .emit();
+        return unauthorized(
+            &base_url,
+            Some(
+                r#"error="invalid_token", error_description="The access token is for another audience""#,
+            ),
+        );
+    }
+
+    // a bound token comes with a proof of its key for this very request
+    let bound = token_store.dpop_binding(&token.grant_id).await;
+    if bound.is_some() || token_store.dpop_required {
+        let checked = match request
+            .headers()
+            .get(dpop::PROOF_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(proof) => dpop::verify(
+                proof,
+                request.method().as_str(),
+                request.uri().path(),
+                Some(&token.access_token),
+                &token_store.dpop_replay,
+            )
+            .and_then(|jkt| match &bound {
+                Some(bound) if *bound == jkt => Ok(()),
+                Some(_) => Err("the DPoP proof is signed with another key"),
+                None => Err("the access token is not bound to a DPoP key"),
+            }),
+            None => Err("a DPoP proof is required"),
+        };
+        if let Err(description) = checked {
+            rejected(Outcome::Failure, description)
+                .client(&token.client_id)
+                .token(&token.access_token)
+                .emit();
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(
+                    header::WWW_AUTHENTICATE,
+                    format!(
+                        r#"DPoP error="invalid_dpop_proof", error_description="{description}""#
+                    ),
+                )],
+            )
+                .into_response();
+        }
+    }
+
+    // Any use of the server needs mcp:read, a tools/call also the scopes of the tool
+    if !has_scope(&token, READ_SCOPE) {
+        rejected(Outcome::Denied, "the token lacks mcp:read")
+            .client(&token.client_id)
+            .token(&token.access_token)
+            .emit();
+        return insufficient_scope(READ_SCOPE);
+    }
+    if request.method() == axum::http::Method::POST {
+        let (parts, body) = request.into_parts();
+        let body = match read_body(body, MAX_MCP_BODY_BYTES).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        if let Some(scope) = called_tools(&body)
+            .iter()
+            .find_map(|tool| token_store.scopes.missing_scope(&token, tool))
+        {
+            info!("client {} lacks scope {}", token.client_id, scope);
+            rejected(Outcome::Denied, &format!("the token lacks {scope}"))
+                .client(&token.client_id)
+                .token(&token.access_token)
+                .emit();
+            return insufficient_scope(scope);
         }
+        request = Request::from_parts(parts, Body::from(body));
+    }
+    request.extensions_mut().insert(token);
+    next.run(request).await
+}
+
+// The names of the tools called by a JSON-RPC message or batch
+fn called_tools(body: &[u8]) -> Vec<String> {
+    let messages = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(batch)) => batch,
+        Ok(message) => vec![message],
+        Err(_) => return Vec::new(),
     };
+    messages
+        .iter()
+        .filter(|message| message.get("method").and_then(Value::as_str) == Some("tools/call"))
+        .filter_map(|message| message.pointer("/params/name").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect()
+}
+
+// A Bearer challenge with the protected-resource metadata, so a client without a token
+// can discover the authorization server (RFC 9728 section 5.1)
+fn unauthorized(base_url: &str, error: Option<&str>) -> Response {
+    let challenge = std::iter::once(format!(
+        r#"resource_metadata="{base_url}/.well-known/oauth-protected-resource""#
+    ))
+    .chain(error.map(str::to_string))
+    .collect::<Vec<_>>()
+    .join(", ");
+    let challenge = format!("Bearer {challenge}");
+    (
+        StatusCode::UNAUTHORIZED,
+        [(axum::http::header::WWW_AUTHENTICATE, challenge)],
+    )
+        .into_response()
+}
 
-    // Validate the token
-    match token_store.validate_token(&token).await {
-        Some(_) => next.run(request).await,
-        None => StatusCode::UNAUTHORIZED.into_response(),
+// The token of a Bearer or DPoP header value, with raw also the value itself
+fn bearer_token(value: Option<&HeaderValue>, raw: bool) -> Option<String> {
+    let value = value?.to_str().ok()?;
+    match value
+        .strip_prefix("Bearer ")
+        .or_else(|| value.strip_prefix("DPoP "))
+    {
+        Some(token) => Some(token.to_string()),
+        None if raw && !value.is_empty() => Some(value.to_string()),
+        None => None,
     }
 }
 
+// The access_token query parameter (RFC 6750 section 2.3) and the URI without it
+pub(crate) fn without_query_token(uri: &Uri) -> Option<(String, Uri)> {
+    let params: Vec<(String, String)> = serde_urlencoded::from_str(uri.query()?).ok()?;
+    let (tokens, rest): (Vec<_>, Vec<_>) = params
+        .into_iter()
+        .partition(|(name, _)| name == "access_token");
+    let (_, token) = tokens.into_iter().next()?;
+    let query = serde_urlencoded::to_string(&rest).ok()?;
+    let path_and_query = if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{query}", uri.path())
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Some((token, Uri::from_parts(parts).ok()?))
+}
+
+// A token request with a DPoP proof that does not verify (RFC 9449 section 5)
+fn invalid_dpop_proof(description: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({
+            "error": "invalid_dpop_proof",
+            "error_description": description
+        })),
+    )
+        .into_response()
+}
+
+// A token in the query without [oauth] accept_token_in_query (RFC 6750 section 3.1)
+fn misplaced_token() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [(
+            header::WWW_AUTHENTICATE,
+            r#"Bearer error="invalid_request", error_description="Send the access token in the Authorization header as Bearer <token>, not in the query""#,
+        )],
+        "send the access token in the Authorization header as Bearer <token>, not in the query",
+    )
+        .into_response()
+}
+
+// The token is valid but lacks the scope (RFC 6750 section 3.1)
+pub(crate) fn insufficient_scope(scope: &str) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        [(
+            axum::http::header::WWW_AUTHENTICATE,
+            format!(
+                r#"Bearer error="insufficient_scope", scope="{scope}", error_description="The access token lacks the scope {scope}""#
+            ),
+        )],
+        Json(serde_json::json!({
+            "error": "insufficient_scope",
+            "error_description": format!("the access token lacks the scope {scope}"),
+            "scope": scope,
+        })),
+    )
+        .into_response()
+}
+
 // handle oauth server metadata request
-pub async fn oauth_authorization_server(bind_address: &str) -> impl IntoResponse {
+pub async fn oauth_authorization_server(
+    base_url: &str,
+    scopes_supported: &[String],
+    has_jwks: bool,
+    registration_open: bool,
+) -> impl IntoResponse {
     let mut additional_fields = HashMap::new();
     additional_fields.insert(
         "response_types_supported".into(),
         Value::Array(vec![Value::String("code".into())]),
     );
+    additional_fields.insert(
+        "grant_types_supported".into(),
+        Value::Array(vec![
+            Value::String("authorization_code".into()),
+            Value::String("refresh_token".into()),
+            Value::String("client_credentials".into()),
+            Value::String(DEVICE_CODE_GRANT_TYPE.into()),
+        ]),
+    );
     additional_fields.insert(
         "code_challenge_methods_supported".into(),
         Value::Array(vec![Value::String("S256".into())]),
     );
+    additional_fields.insert(
+        "revocation_endpoint".into(),
+        Value::String(format!("{base_url}/revoke")),
+    );
+    additional_fields.insert(
+        "introspection_endpoint".into(),
+        Value::String(format!("{base_url}/introspect")),
+    );
+    additional_fields.insert(
+        "device_authorization_endpoint".into(),
+        Value::String(format!("{base_url}/device_authorization")),
+    );
+    additional_fields.insert(
+        "dpop_signing_alg_values_supported".into(),
+        dpop::ALGORITHMS
+            .iter()
+            .map(|algorithm| Value::from(format!("{algorithm:?}")))
+            .collect(),
+    );
     let metadata = AuthorizationMetadata {
-        authorization_endpoint: format!("http://{bind_address}/authorize"),
-        token_endpoint: format!("http://{bind_address}/token"),
-        scopes_supported: Some(vec!["profile".to_string(), "email".to_string()]),
-        registration_endpoint: format!("http://{bind_address}/register"),
-        issuer: Some(format!("http://{bind_address}")),
-        jwks_uri: Some(format!("http://{bind_address}/jwks")),
+        authorization_endpoint: format!("{base_url}/authorize"),
+        token_endpoint: format!("{base_url}/token"),
+        scopes_supported: Some(scopes_supported.to_vec()),
+        registration_endpoint: format!("{base_url}/register"),
+        issuer: Some(format!("{base_url}")),
+        jwks_uri: has_jwks.then(|| format!("{base_url}/.well-known/jwks.json")),
         additional_fields,
     };
     debug!("metadata: {:?}", metadata);
+    let mut metadata = serde_json::to_value(metadata).unwrap_or_default();
+    if !registration_open && let Some(fields) = metadata.as_object_mut() {
+        fields.remove("registration_endpoint");
+    }
     (StatusCode::OK, Json(metadata))
 }
 
+// handle protected resource metadata request (RFC 9728), /mcp is the resource
+pub async fn oauth_protected_resource(
+    resource: &str,
+    authorization_server: &str,
+    scopes_supported: &[String],
+) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "resource": resource,
+        "authorization_servers": [authorization_server],
+        "bearer_methods_supported": ["header"],
+        "scopes_supported": scopes_supported,
+    }))
+}
+
+// The public key of JWT access tokens (RFC 7517)
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "metadata",
+    responses((status = 200, description = "The public key of JWT access tokens, none for HS256", body = JwkSet)),
+)]
+pub async fn oauth_jwks(State(state): State<Arc<McpOAuthStore>>) -> impl IntoResponse {
+    Json(state.jwks())
+}
+
 // handle client registration request
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "oauth",
+    request_body = ClientRegistration,
+    responses(
+        (status = 201, description = "The registered client", body = RegisteredClientResponse),
+        (status = 400, description = "invalid_redirect_uri", body = OAuthErrorResponse),
+        (status = 401, description = "invalid_token, a missing or unknown initial access token", body = OAuthErrorResponse),
+        (status = 403, description = "access_denied, registration is closed or the initial access token is used up", body = OAuthErrorResponse),
+    ),
+    security((), ("initial_access_token" = [])),
+)]
 pub async fn oauth_register(
     State(state): State<Arc<McpOAuthStore>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
     Json(req): Json<ClientRegistrationRequest>,
 ) -> impl IntoResponse {
     debug!("register request: {:?}", req);
+    let audit = |outcome| AuditEvent::new("client_registration", outcome).ip(Some(addr.ip()));
+    let denied = |description: &str| {
+        (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": "access_denied",
+                "error_description": description
+            })),
+        )
+            .into_response()
+    };
+    // RFC 7591 section 3, the initial access token is a bearer token
+    let initial_token = bearer_token(headers.get(header::AUTHORIZATION), false);
+    match state.registration_mode {
+        RegistrationMode::Open => {}
+        RegistrationMode::Closed => {
+            audit(Outcome::Denied)
+                .reason("registration is closed")
+                .emit();
+            return denied("client registration is closed");
+        }
+        RegistrationMode::Token => {
+            let known = initial_token
+                .as_ref()
+                .is_some_and(|token| state.registration_tokens.contains(token));
+            if !known {
+                let mut event = audit(Outcome::Denied).reason("no valid initial access token");
+                if let Some(token) = &initial_token {
+                    event = event.token(token);
+                }
+                event.emit();
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#)],
+                    Json(serde_json::json!({
+                        "error": "invalid_token",
+                        "error_description": "registration needs a valid initial access token"
+                    })),
+                )
+                    .into_response();
+            }
+        }
+    }
+    // only tokens of token mode are recorded, in open mode a bearer header means nothing
+    let initial_token =
+        initial_token.filter(|_| state.registration_mode == RegistrationMode::Token);
     if req.redirect_uris.is_empty() {
+        audit(Outcome::Failure).reason("no redirect uri").emit();
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
-                "error": "invalid_request",
+                "error": "invalid_redirect_uri",
                 "error_description": "at least one redirect uri is required"
             })),
         )
             .into_response();
     }
+    // RFC 7591 section 3.2.2
+    if let Some(uri) = req
+        .redirect_uris
+        .iter()
+        .find(|uri| !is_valid_redirect_uri(uri))
+    {
+        audit(Outcome::Failure)
+            .reason("invalid redirect uri")
+            .emit();
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "invalid_redirect_uri",
+                "error_description": format!("{uri} is no absolute uri without a fragment")
+            })),
+        )
+            .into_response();
+    }
 
     // generate client id and secret
     let client_id = format!("client-{}", Uuid::new_v4());
     let client_secret = generate_random_string(32);
 
-    let client = OAuthClientConfig {
+    let client = RegisteredClient {
         client_id: client_id.clone(),
         client_secret: Some(client_secret.clone()),
-        redirect_uri: req.redirect_uris[0].clone(),
+        client_name: req.client_name.clone(),
+        created_at: Some(chrono::Utc::now()),
+        redirect_uris: req.redirect_uris.clone(),
         scopes: vec![],
+        registered_with: initial_token.as_deref().map(audit::fingerprint),
     };
 
-    state
-        .clients
-        .write()
-        .await
-        .insert(client_id.clone(), client);
+    {
+        let mut clients = state.clients.write().await;
+        if let Some(fingerprint) = &client.registered_with
+            && state.max_registrations_per_token > 0
+            && clients
+                .values()
+                .filter(|client| client.registered_with.as_ref() == Some(fingerprint))
+                .count()
+                >= state.max_registrations_per_token as usize
+        {
+            drop(clients);
+            let mut event = audit(Outcome::Denied).reason("the initial access token is used up");
+            if let Some(token) = &initial_token {
+                event = event.token(token);
+            }
+            event.emit();
+            return denied("the initial access token registered its maximum of clients");
+        }
+        clients.insert(client_id.clone(), client);
+    }
+    state.persist().await;
+    let mut event = audit(Outcome::Success).client(&client_id);
+    if let Some(token) = &initial_token {
+        event = event.token(token);
+    }
+    event.emit();
 
     // return client information
     let response = ClientRegistrationResponse {
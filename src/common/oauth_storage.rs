@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use oauth2::{AccessToken, TokenResponse};
+use rmcp::serde_json;
+use serde::{Deserialize, Serialize};
+
+use crate::common::audit::token_digest;
+use crate::common::oauth::AuthToken;
+
+// What survives a restart of the OAuth store: the registered clients and the tokens.
+// Authorization codes live for minutes and are not kept.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OAuthSnapshot {
+    pub clients: Vec<StoredClient>,
+    pub access_tokens: Vec<StoredAccessToken>,
+    pub refresh_tokens: Vec<StoredRefreshToken>,
+    #[serde(default)]
+    pub dpop_bindings: BTreeMap<String, String>, // grant id to DPoP key thumbprint
+    // a generated secret of [oauth.totp], the one of the config is not saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub totp_enrolled: bool, // its first code was entered, it is not shown any more
+    // the tokens are their SHA-256, older versions stored the tokens themselves
+    #[serde(default)]
+    pub token_digests: bool,
+}
+
+impl OAuthSnapshot {
+    // Replace the tokens of a snapshot of an older version by their digests, true if it
+    // was one
+    pub fn hash_tokens(&mut self) -> bool {
+        if self.token_digests {
+            return false;
+        }
+        for token in &mut self.access_tokens {
+            // the upstream token of a client credentials token was the token itself
+            if token.auth_token.access_token().secret() == &token.access_token {
+                token
+                    .auth_token
+                    .set_access_token(AccessToken::new(token_digest(&token.access_token)));
+            }
+            token.access_token = token_digest(&token.access_token);
+        }
+        for token in &mut self.refresh_tokens {
+            token.refresh_token = token_digest(&token.refresh_token);
+            token.access_token = token_digest(&token.access_token);
+        }
+        self.token_digests = true;
+        true
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredClient {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    // the only one older versions stored, read but no longer written
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub registered_with: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>, // unix seconds
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredAccessToken {
+    pub access_token: String, // its digest
+    pub scope: Option<String>,
+    pub auth_token: AuthToken,
+    pub client_id: String,
+    pub grant_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>, // older versions stored none
+    pub expires_at: i64, // unix seconds
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredRefreshToken {
+    pub refresh_token: String, // the digests of both
+    pub access_token: String,
+    pub grant_id: String,
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub auth_token: AuthToken,
+    pub expires_at: i64, // unix seconds
+    pub rotated: bool,
+}
+
+// Where the OAuth store keeps its snapshot, saves of the store are serialized so an
+// implementation only has to replace the previous snapshot
+pub trait OAuthStorage: fmt::Debug + Send + Sync {
+    // None when nothing was saved yet
+    fn load(&self) -> io::Result<Option<OAuthSnapshot>>;
+    fn save(&self, snapshot: &OAuthSnapshot) -> io::Result<()>;
+}
+
+// A JSON file replaced atomically, readers never see a half written file
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+}
+
+impl JsonFileStorage {
+    pub fn new(path: &Path) -> Self {
+        JsonFileStorage {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl OAuthStorage for JsonFileStorage {
+    fn load(&self) -> io::Result<Option<OAuthSnapshot>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn save(&self, snapshot: &OAuthSnapshot) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(snapshot)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write a sibling file and rename it over the old one, the file holds secrets
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, &self.path)
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{Jwk, JwkSet},
+};
+use rmcp::serde_json::{self, Map, Value};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::common::config::{OAuth, Oidc};
+use crate::common::oauth::McpAccessToken;
+use crate::common::pinning::CertificatePins;
+
+// Asymmetric algorithms only, a provider never shares an HMAC secret with us
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+// A token naming an unknown key fetches the keys again at most this often
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct CachedKeys {
+    jwks_uri: Option<String>,
+    keys: JwkSet,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+impl CachedKeys {
+    fn new(jwks_uri: Option<String>) -> Self {
+        CachedKeys {
+            jwks_uri,
+            keys: JwkSet { keys: Vec::new() },
+            fetched_at: None,
+            attempted_at: None,
+        }
+    }
+}
+
+fn client(pins: &CertificatePins, section: &str) -> Result<reqwest::Client> {
+    pins.client_builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .with_context(|| format!("can't build the HTTP client of {section}"))
+}
+
+// Checks access tokens of an upstream OpenID provider against its published keys. The
+// keys are cached, rotated keys are picked up on the first token naming them, and while
+// the provider is unreachable the keys fetched last stay in use.
+#[derive(Debug)]
+pub struct OidcVerifier {
+    issuer: String, // compared with the iss claim as it is, not checked if empty
+    audience: Option<String>,
+    required_scope: Option<String>,
+    required_claims: BTreeMap<String, String>,
+    refresh: Duration,
+    client: reqwest::Client,
+    cache: RwLock<CachedKeys>,
+    fetching: Mutex<()>, // one fetch at a time
+}
+
+impl OidcVerifier {
+    pub fn new(config: &Oidc, pins: &CertificatePins) -> Result<Self> {
+        let issuer = config.issuer.clone();
+        if issuer.is_empty() {
+            bail!("auth_mode = \"oidc\" needs the issuer of [oidc]");
+        }
+        if config.audience.is_none() {
+            warn!("[oidc] has no audience, tokens issued for other services are accepted");
+        }
+        Ok(OidcVerifier {
+            issuer,
+            audience: config.audience.clone(),
+            required_scope: config.required_scope.clone(),
+            required_claims: config.required_claims.clone(),
+            refresh: Duration::from_secs(config.jwks_refresh_seconds),
+            client: client(pins, "[oidc]")?,
+            cache: RwLock::new(CachedKeys::new(config.jwks_uri.clone())),
+            fetching: Mutex::new(()),
+        })
+    }
+
+    // The keys of [oauth] jwks_uri, without discovery, scope or claim requirements
+    pub fn remote_jwks(config: &OAuth, pins: &CertificatePins) -> Result<Self> {
+        let Some(jwks_uri) = config.jwks_uri.clone().filter(|uri| !uri.is_empty()) else {
+            bail!("[oauth] has no jwks_uri");
+        };
+        if !jwks_uri.starts_with("https://") && !jwks_uri.starts_with("http://") {
+            bail!("[oauth] jwks_uri {jwks_uri} is no http(s) URL");
+        }
+        Ok(OidcVerifier {
+            issuer: config.jwks_issuer.clone().unwrap_or_default(),
+            audience: config.jwks_audience.clone(),
+            required_scope: None,
+            required_claims: BTreeMap::new(),
+            refresh: Duration::from_secs(config.jwks_refresh_seconds),
+            client: client(pins, "[oauth] jwks_uri")?,
+            cache: RwLock::new(CachedKeys::new(Some(jwks_uri))),
+            fetching: Mutex::new(()),
+        })
+    }
+
+    // The authorization server of the protected-resource metadata
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    // The record of a token with a valid signature, issuer, audience and expiry that has
+    // the required scope and claims
+    pub async fn verify(&self, token: &str) -> Option<McpAccessToken> {
+        let header = jsonwebtoken::decode_header(token)
+            .inspect_err(|e| debug!("rejected token: {}", e))
+            .ok()?;
+        if !ALGORITHMS.contains(&header.alg) {
+            debug!("rejected token signed with {:?}", header.alg);
+            return None;
+        }
+        let key = self.key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&key)
+            .inspect_err(|e| warn!("unusable key in the JWKS: {}", e))
+            .ok()?;
+
+        let mut validation = Validation::new(header.alg);
+        if self.issuer.is_empty() {
+            validation.set_required_spec_claims(&["exp", "sub"]);
+        } else {
+            validation.set_issuer(&[&self.issuer]);
+            validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+            .inspect_err(|e| debug!("rejected token: {}", e))
+            .ok()?
+            .claims;
+
+        let scope = scope_of(&claims);
+        if let Some(required) = &self.required_scope
+            && !scope.split_whitespace().any(|s| s == required)
+        {
+            debug!("rejected token without scope {}", required);
+            return None;
+        }
+        if let Some((name, value)) = self
+            .required_claims
+            .iter()
+            .find(|(name, value)| !claim_matches(claims.get(*name), value))
+        {
+            debug!("rejected token without {} = {}", name, value);
+            return None;
+        }
+
+        let text = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        let client_id = text("azp")
+            .or_else(|| text("client_id"))
+            .or_else(|| text("sub"))
+            .unwrap_or_default();
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_i64)
+            .and_then(|exp| chrono::DateTime::from_timestamp(exp, 0))
+            .unwrap_or_else(chrono::Utc::now);
+        // the subject stands for the grant, a refreshed token has a new jti
+        let grant_id = text("sub").unwrap_or_default();
+        Some(McpAccessToken::verified(
+            token, client_id, scope, expires_at, grant_id,
+        ))
+    }
+
+    // The key of the kid, the only key if the token names none
+    async fn key(&self, kid: Option<&str>) -> Option<Jwk> {
+        let (found, stale, may_refetch) = {
+            let cache = self.cache.read().await;
+            (
+                find_key(&cache.keys, kid),
+                cache
+                    .fetched_at
+                    .is_none_or(|fetched_at| fetched_at.elapsed() >= self.refresh),
+                cache
+                    .attempted_at
+                    .is_none_or(|attempted_at| attempted_at.elapsed() >= MIN_REFETCH_INTERVAL),
+            )
+        };
+        if found.is_some() && !(stale && may_refetch) {
+            return found;
+        }
+        if may_refetch {
+            self.fetch().await;
+        } else {
+            // a fetch in flight, e.g. the first one, may bring the key
+            let _in_flight = self.fetching.lock().await;
+        }
+        find_key(&self.cache.read().await.keys, kid)
+    }
+
+    // Fetches the keys, on failure the cached ones stay
+    pub async fn fetch(&self) {
+        let Ok(_fetching) = self.fetching.try_lock() else {
+            // another request is fetching them, wait for it
+            let _ = self.fetching.lock().await;
+            return;
+        };
+        let jwks_uri = {
+            let mut cache = self.cache.write().await;
+            cache.attempted_at = Some(Instant::now());
+            cache.jwks_uri.clone()
+        };
+        match self.fetch_keys(jwks_uri).await {
+            Ok((jwks_uri, keys)) => {
+                info!("fetched {} keys from {}", keys.keys.len(), jwks_uri);
+                let mut cache = self.cache.write().await;
+                cache.jwks_uri = Some(jwks_uri);
+                cache.keys = keys;
+                cache.fetched_at = Some(Instant::now());
+            }
+            // the error names the URL
+            Err(e) => warn!("can't fetch the signing keys, the cached ones stay in use: {e:#}"),
+        }
+    }
+
+    async fn fetch_keys(&self, jwks_uri: Option<String>) -> Result<(String, JwkSet)> {
+        let jwks_uri = match jwks_uri {
+            Some(jwks_uri) => jwks_uri,
+            None => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.issuer.trim_end_matches('/')
+                );
+                let discovery: Value = self.get_json(&url).await?;
+                discovery["jwks_uri"]
+                    .as_str()
+                    .with_context(|| format!("{url} names no jwks_uri"))?
+                    .to_string()
+            }
+        };
+        let keys = self.get_json(&jwks_uri).await?;
+        Ok((jwks_uri, keys))
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("can't get {url}"))?
+            .bytes()
+            .await
+            .with_context(|| format!("can't read {url}"))?;
+        serde_json::from_slice(&body).with_context(|| format!("can't parse {url}"))
+    }
+}
+
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+// The scope claim is a space separated string, some providers send a scp list instead
+fn scope_of(claims: &Map<String, Value>) -> String {
+    match (claims.get("scope"), claims.get("scp")) {
+        (Some(Value::String(scope)), _) => scope.clone(),
+        (_, Some(Value::Array(scopes))) => scopes
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" "),
+        (_, Some(Value::String(scope))) => scope.clone(),
+        _ => String::new(),
+    }
+}
+
+fn claim_matches(claim: Option<&Value>, value: &str) -> bool {
+    match claim {
+        Some(Value::String(claim)) => claim == value,
+        Some(Value::Array(claims)) => claims.iter().any(|claim| claim.as_str() == Some(value)),
+        Some(Value::Bool(claim)) => claim.to_string() == value,
+        Some(Value::Number(claim)) => claim.to_string() == value,
+        _ => false,
+    }
+}
@@ -0,0 +1,194 @@
+// The OpenAPI description of the HTTP endpoints besides /mcp, served at /openapi.json.
+// The bodies of the OAuth answers are built with json!, their schemas are described here.
+use axum::Json;
+use utoipa::{
+    Modify, OpenApi, ToSchema,
+    openapi::security::{
+        AuthorizationCode, Flow, Http, HttpAuthScheme, OAuth2, Scopes, SecurityScheme,
+    },
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "MCP bash server",
+        description = "The OAuth authorization server of the MCP bash server and its metadata. \
+            MCP itself is served at /mcp with a bearer token of /token."
+    ),
+    paths(
+        crate::server::index,
+        crate::server::version,
+        crate::common::oauth::oauth_authorize,
+        crate::common::oauth::oauth_approve,
+        crate::common::oauth::oauth_device_authorization,
+        crate::common::oauth::oauth_device,
+        crate::common::oauth::oauth_device_approve,
+        crate::common::browser_session::oauth_grants,
+        crate::common::browser_session::oauth_revoke_grant,
+        crate::common::browser_session::oauth_logout,
+        crate::common::oauth::oauth_token,
+        crate::common::oauth::oauth_register,
+        crate::common::oauth::oauth_revoke,
+        crate::common::oauth::oauth_introspect,
+        crate::server::oauth_authorization_server_handler,
+        crate::server::oauth_protected_resource_handler,
+        crate::common::oauth::oauth_jwks,
+        openapi_json,
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "oauth", description = "OAuth 2.1 endpoints"),
+        (name = "metadata", description = "Discovery documents, no authentication"),
+    )
+)]
+pub struct ApiDoc;
+
+// client_basic is client_secret_basic (RFC 6749 section 2.3.1), the form fields
+// client_id and client_secret work too. introspection_secret is [oauth]
+// introspection_secret as a bearer token. oauth2 is what /mcp takes.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "client_basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+        components.add_security_scheme(
+            "introspection_secret",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "initial_access_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "oauth2",
+            SecurityScheme::OAuth2(OAuth2::new([Flow::AuthorizationCode(
+                AuthorizationCode::new(
+                    "/authorize",
+                    "/token",
+                    Scopes::from_iter([
+                        ("mcp:read", "list tools and resources, call read-only tools"),
+                        ("mcp:execute", "call any tool, includes mcp:read"),
+                        ("processes:read", "list_processes"),
+                    ]),
+                ),
+            )])),
+        );
+    }
+}
+
+// GET /openapi.json
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "metadata",
+    responses((status = 200, description = "This document", content_type = "application/json")),
+)]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Answer of /token (RFC 6749 section 5.1)
+#[derive(ToSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[schema(example = "Bearer")]
+    pub token_type: String,
+    pub expires_in: Option<u64>, // seconds
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>, // space separated
+}
+
+// Error answer of the JSON endpoints (RFC 6749 section 5.2)
+#[derive(ToSchema)]
+pub struct OAuthErrorResponse {
+    #[schema(example = "invalid_grant")]
+    pub error: String,
+    pub error_description: Option<String>,
+    pub scope: Option<String>, // the missing one of insufficient_scope
+}
+
+// Answer of /introspect (RFC 7662 section 2.2), only active tokens have claims
+#[derive(ToSchema)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+    pub exp: Option<i64>, // unix seconds
+    pub iat: Option<i64>,
+    pub token_type: Option<String>,
+}
+
+// Body of /register (RFC 7591 section 2)
+#[derive(ToSchema)]
+pub struct ClientRegistration {
+    pub client_name: Option<String>,
+    pub redirect_uris: Vec<String>, // at least one, absolute and without fragment
+}
+
+// Answer of /device_authorization (RFC 8628 section 3.2)
+#[derive(ToSchema)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String, // for /token, never shown to the user
+    #[schema(example = "BCDF-GHJK")]
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String, // with the user code in its query
+    pub expires_in: i64,                   // seconds
+    pub interval: i64,                     // seconds between two polls of /token
+}
+
+// Answer of /register (RFC 7591 section 3.2.1)
+#[derive(ToSchema)]
+pub struct RegisteredClientResponse {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub client_name: Option<String>,
+    pub redirect_uris: Vec<String>,
+}
+
+// Answer of /.well-known/oauth-authorization-server (RFC 8414)
+#[derive(ToSchema)]
+pub struct AuthorizationServerMetadata {
+    pub issuer: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub registration_endpoint: Option<String>, // not with registration_mode = "closed"
+    pub revocation_endpoint: String,
+    pub introspection_endpoint: String,
+    pub device_authorization_endpoint: String,
+    pub jwks_uri: Option<String>, // only with JWT access tokens signed by a key pair
+    pub scopes_supported: Option<Vec<String>>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub dpop_signing_alg_values_supported: Vec<String>, // of DPoP proofs (RFC 9449)
+}
+
+// Answer of /.well-known/oauth-protected-resource (RFC 9728)
+#[derive(ToSchema)]
+pub struct ProtectedResourceMetadata {
+    pub resource: String,
+    pub authorization_servers: Vec<String>,
+    pub bearer_methods_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+}
+
+// Answer of /.well-known/jwks.json (RFC 7517 section 5)
+#[derive(ToSchema)]
+pub struct JwkSet {
+    #[schema(value_type = Vec<Object>)]
+    pub keys: Vec<rmcp::serde_json::Value>,
+}
+
+// Answer of /version
+#[derive(ToSchema)]
+pub struct VersionInfo {
+    #[schema(example = "0.1.0")]
+    pub version: String,
+    pub git_commit: String, // "unknown" if built without git
+    pub build_time: String, // RFC 3339
+}
@@ -0,0 +1,163 @@
+// Helpers to shape command output before it is returned to the client
+
+use regex::Regex;
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+
+// Remove ANSI escape sequences (colors, cursor movement, window titles) from terminal output
+pub fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: ESC [ parameters... final byte in @..~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: ESC ] ... terminated by BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Two character sequences like ESC ( B or ESC =
+            Some('(') | Some(')') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+// Keeps the interesting lines of a command output: the include/exclude patterns pick
+// lines, then head/tail and max_lines cut the result down
+#[derive(Debug, Default)]
+pub struct LineFilter {
+    pub include: Option<Regex>,
+    pub exclude: Option<Regex>,
+    pub head: Option<usize>,
+    pub tail: Option<usize>,
+    pub max_lines: Option<usize>,
+}
+
+impl LineFilter {
+    // The filtered text and how many lines were dropped
+    pub fn apply(&self, text: &str) -> (String, usize) {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut kept: Vec<&str> = lines
+            .iter()
+            .copied()
+            .filter(|line| self.include.as_ref().is_none_or(|re| re.is_match(line)))
+            .filter(|line| !self.exclude.as_ref().is_some_and(|re| re.is_match(line)))
+            .collect();
+
+        match (self.head, self.tail) {
+            (Some(head), Some(tail)) if head + tail < kept.len() => {
+                kept.drain(head..kept.len() - tail);
+            }
+            (Some(head), None) => kept.truncate(head),
+            (None, Some(tail)) => {
+                kept.drain(..kept.len().saturating_sub(tail));
+            }
+            _ => {}
+        }
+        if let Some(max_lines) = self.max_lines {
+            kept.truncate(max_lines);
+        }
+
+        let suppressed = lines.len() - kept.len();
+        let mut filtered = kept.join("\n");
+        if !filtered.is_empty() && text.ends_with('\n') {
+            filtered.push('\n');
+        }
+        (filtered, suppressed)
+    }
+}
+
+// Which end of the output survives a line or byte limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitMode {
+    Head,
+    Tail,
+    #[default]
+    HeadTail,
+}
+
+// Cuts output at whole lines, the first limit that is reached wins
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputLimits {
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub mode: LimitMode,
+}
+
+impl OutputLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_lines.is_none() && self.max_bytes.is_none()
+    }
+
+    // The limited text and how many lines were elided
+    pub fn apply(&self, text: &str) -> (String, usize) {
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let max_lines = self.max_lines.unwrap_or(usize::MAX);
+        let max_bytes = self.max_bytes.unwrap_or(usize::MAX);
+        if lines.len() <= max_lines && text.len() <= max_bytes {
+            return (text.to_string(), 0);
+        }
+
+        let split = |budget: usize| match self.mode {
+            LimitMode::Head => (budget, 0),
+            LimitMode::Tail => (0, budget),
+            LimitMode::HeadTail => (budget - budget / 2, budget / 2),
+        };
+        let (head_lines, tail_lines) = split(max_lines);
+        let (head_bytes, tail_bytes) = split(max_bytes);
+
+        let head = take_lines(lines.iter().copied(), head_lines, head_bytes);
+        let tail = take_lines(lines[head..].iter().rev().copied(), tail_lines, tail_bytes);
+        let elided = lines.len() - head - tail;
+
+        let mut limited: String = lines[..head].concat();
+        if !limited.is_empty() && !limited.ends_with('\n') {
+            limited.push('\n');
+        }
+        limited.push_str(&format!("... [{elided} lines elided] ...\n"));
+        limited.push_str(&lines[lines.len() - tail..].concat());
+        (limited, elided)
+    }
+}
+
+// How many of the lines fit in both budgets
+fn take_lines<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    max_lines: usize,
+    max_bytes: usize,
+) -> usize {
+    let mut bytes = 0;
+    lines
+        .take(max_lines)
+        .take_while(|line| {
+            bytes += line.len();
+            bytes <= max_bytes
+        })
+        .count()
+}
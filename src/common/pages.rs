@@ -0,0 +1,107 @@
+// The HTML pages of the server. The templates of templates/ are embedded in the binary,
+// files of [web] templates_dir with the same name replace them at startup.
+use std::{fmt, fs, io};
+
+use anyhow::Context;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use minijinja::{Environment, Value, context};
+use serde::Serialize;
+use tracing::error;
+
+use crate::common::config::Web;
+
+pub const INDEX: &str = "mcp_oauth_index.html";
+pub const AUTHORIZE: &str = "mcp_oauth_authorize.html";
+pub const DEVICE: &str = "mcp_oauth_device.html";
+pub const ERROR: &str = "mcp_oauth_error.html";
+pub const GRANTS: &str = "mcp_oauth_grants.html";
+
+const EMBEDDED: [(&str, &str); 5] = [
+    (INDEX, include_str!("../../templates/mcp_oauth_index.html")),
+    (
+        AUTHORIZE,
+        include_str!("../../templates/mcp_oauth_authorize.html"),
+    ),
+    (
+        DEVICE,
+        include_str!("../../templates/mcp_oauth_device.html"),
+    ),
+    (ERROR, include_str!("../../templates/mcp_oauth_error.html")),
+    (
+        GRANTS,
+        include_str!("../../templates/mcp_oauth_grants.html"),
+    ),
+];
+
+pub struct Pages {
+    templates: Environment<'static>, // escapes every value, the names end in .html
+    server_name: String,
+}
+
+impl fmt::Debug for Pages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pages")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+impl Default for Pages {
+    fn default() -> Self {
+        Pages::new(&Web::default()).expect("the embedded templates are valid")
+    }
+}
+
+impl Pages {
+    // A template of templates_dir that can't be read or parsed stops the startup
+    pub fn new(web: &Web) -> anyhow::Result<Self> {
+        let mut templates = Environment::new();
+        for (name, source) in EMBEDDED {
+            let source = match &web.templates_dir {
+                Some(dir) => match fs::read_to_string(dir.join(name)) {
+                    Ok(source) => source,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => source.to_string(),
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("[web] can't read {}", dir.join(name).display())
+                        });
+                    }
+                },
+                None => source.to_string(),
+            };
+            templates
+                .add_template_owned(name, source)
+                .with_context(|| format!("[web] template {name}"))?;
+        }
+        Ok(Pages {
+            templates,
+            server_name: web.server_name.clone(),
+        })
+    }
+
+    // A failing template is answered with 500, the cause only goes to the log
+    pub fn render(&self, status: StatusCode, name: &str, values: impl Serialize) -> Response {
+        let values = context! {
+            server_name => self.server_name.as_str(),
+            ..Value::from_serialize(values)
+        };
+        match self
+            .templates
+            .get_template(name)
+            .and_then(|template| template.render(values))
+        {
+            Ok(page) => (status, Html(page)).into_response(),
+            Err(e) => {
+                error!("can't render {name}: {e:#}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "the page could not be rendered",
+                )
+                    .into_response()
+            }
+        }
+    }
+}
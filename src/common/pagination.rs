@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rmcp::model::ErrorData;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::common::config::Pagination;
+
+// One page of a buffered output, both streams are cut at the same page number
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputPage {
+    pub buffer_id: String,
+    pub page_number: usize, // starts at 0
+    pub page_count: usize,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+struct Buffer {
+    stdout: String,
+    stderr: String,
+    created: Instant,
+}
+
+// The full output of the paginated commands of one session by buffer id
+#[derive(Clone)]
+pub struct OutputBuffers {
+    page_bytes: usize,
+    ttl: Duration,
+    max_buffers: usize,
+    buffers: Arc<Mutex<HashMap<String, Buffer>>>,
+}
+
+impl std::fmt::Debug for OutputBuffers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutputBuffers")
+            .field("page_bytes", &self.page_bytes)
+            .field("buffers", &self.buffers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for OutputBuffers {
+    fn default() -> Self {
+        Self::new(&Pagination::default())
+    }
+}
+
+impl OutputBuffers {
+    pub fn new(config: &Pagination) -> Self {
+        OutputBuffers {
+            page_bytes: config.page_bytes.max(1024),
+            ttl: Duration::from_secs(config.ttl_seconds),
+            max_buffers: config.max_buffers.max(1),
+            buffers: Arc::default(),
+        }
+    }
+
+    // Keep the output and return its first page
+    pub fn store(&self, stdout: String, stderr: String) -> OutputPage {
+        let buffer_id = Uuid::new_v4().to_string();
+        let buffer = Buffer {
+            stdout,
+            stderr,
+            created: Instant::now(),
+        };
+        let page = self.cut(&buffer_id, &buffer, 0);
+        let mut buffers = self.buffers.lock().unwrap();
+        self.evict(&mut buffers);
+        buffers.insert(buffer_id, buffer);
+        page
+    }
+
+    pub fn page(&self, buffer_id: &str, page_number: usize) -> Result<OutputPage, ErrorData> {
+        let mut buffers = self.buffers.lock().unwrap();
+        self.evict(&mut buffers);
+        let buffer = buffers.get(buffer_id).ok_or_else(|| {
+            ErrorData::invalid_params(
+                format!("Unknown or expired output buffer {buffer_id}"),
+                None,
+            )
+        })?;
+        let page = self.cut(buffer_id, buffer, page_number);
+        if page_number >= page.page_count {
+            return Err(ErrorData::invalid_params(
+                format!(
+                    "Output buffer {buffer_id} has {} pages, page numbers start at 0",
+                    page.page_count
+                ),
+                None,
+            ));
+        }
+        Ok(page)
+    }
+
+    fn cut(&self, buffer_id: &str, buffer: &Buffer, page_number: usize) -> OutputPage {
+        let stdout = page_bounds(&buffer.stdout, self.page_bytes);
+        let stderr = page_bounds(&buffer.stderr, self.page_bytes);
+        let slice = |text: &str, bounds: &[usize]| match bounds.get(page_number..page_number + 2) {
+            Some([start, end]) => text[*start..*end].to_string(),
+            _ => String::new(),
+        };
+        OutputPage {
+            buffer_id: buffer_id.to_string(),
+            page_number,
+            page_count: (stdout.len().max(stderr.len()) - 1).max(1),
+            stdout: slice(&buffer.stdout, &stdout),
+            stderr: slice(&buffer.stderr, &stderr),
+        }
+    }
+
+    fn evict(&self, buffers: &mut HashMap<String, Buffer>) {
+        buffers.retain(|_, buffer| buffer.created.elapsed() < self.ttl);
+        while buffers.len() >= self.max_buffers {
+            let oldest = buffers
+                .iter()
+                .min_by_key(|(_, buffer)| buffer.created)
+                .map(|(id, _)| id.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            buffers.remove(&oldest);
+        }
+    }
+}
+
+// Byte offsets of the page starts plus the end, pages end on a character boundary
+fn page_bounds(text: &str, page_bytes: usize) -> Vec<usize> {
+    let mut bounds = vec![0];
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = (start + page_bytes).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        bounds.push(end);
+        start = end;
+    }
+    bounds
+}
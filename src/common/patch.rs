@@ -0,0 +1,206 @@
+use serde::Serialize;
+
+// One hunk of a unified diff
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub header: String,
+    old_start: usize,
+    old_lines: Vec<String>,
+    new_lines: Vec<String>,
+    // "\ No newline at end of file" after the last new line
+    new_no_newline: bool,
+}
+
+// The hunks of one file, a None path stands for /dev/null
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HunkResult {
+    pub header: String,
+    pub applied: bool,
+    // lines between where the hunk said it applies and where it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<isize>,
+}
+
+// Split a (possibly multi-file) unified diff into file patches
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(old) = line.strip_prefix("--- ")
+            && let Some(new) = lines.get(i + 1).and_then(|l| l.strip_prefix("+++ "))
+        {
+            patches.push(FilePatch {
+                old_path: parse_path(old),
+                new_path: parse_path(new),
+                hunks: Vec::new(),
+            });
+            i += 2;
+        } else if line.starts_with("@@ ") {
+            let Some(patch) = patches.last_mut() else {
+                return Err(format!("line {}: hunk without a file header", i + 1));
+            };
+            let (hunk, next) = parse_hunk(&lines, i)?;
+            patch.hunks.push(hunk);
+            i = next;
+        } else {
+            // diff --git, index, mode lines and free text between files
+            i += 1;
+        }
+    }
+    if patches.is_empty() {
+        return Err("no file headers (--- / +++) found in the diff".to_string());
+    }
+    Ok(patches)
+}
+
+fn parse_path(header: &str) -> Option<String> {
+    // a tab separates the path from an optional timestamp
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    (path != "/dev/null").then(|| path.to_string())
+}
+
+// "@@ -start,count +start,count @@ section"
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    Some((start.parse().ok()?, count.parse().ok()?))
+}
+
+fn parse_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let header = lines[start];
+    let invalid = || format!("line {}: invalid hunk header {header}", start + 1);
+    let mut parts = header.split_whitespace().skip(1);
+    let (old_start, mut old_count) = parts
+        .next()
+        .and_then(|r| r.strip_prefix('-'))
+        .and_then(parse_range)
+        .ok_or_else(invalid)?;
+    let (_, mut new_count) = parts
+        .next()
+        .and_then(|r| r.strip_prefix('+'))
+        .and_then(parse_range)
+        .ok_or_else(invalid)?;
+
+    let mut hunk = Hunk {
+        header: header.to_string(),
+        old_start,
+        old_lines: Vec::new(),
+        new_lines: Vec::new(),
+        new_no_newline: false,
+    };
+    let mut i = start + 1;
+    let mut last = ' ';
+    while i < lines.len() && (old_count > 0 || new_count > 0 || lines[i].starts_with('\\')) {
+        let line = lines[i];
+        // some editors strip the single space of empty context lines
+        let (kind, text) = match line.chars().next() {
+            Some(kind) => (kind, &line[kind.len_utf8()..]),
+            None => (' ', ""),
+        };
+        match kind {
+            ' ' if old_count > 0 && new_count > 0 => {
+                hunk.old_lines.push(text.to_string());
+                hunk.new_lines.push(text.to_string());
+                old_count -= 1;
+                new_count -= 1;
+            }
+            '-' if old_count > 0 => {
+                hunk.old_lines.push(text.to_string());
+                old_count -= 1;
+            }
+            '+' if new_count > 0 => {
+                hunk.new_lines.push(text.to_string());
+                new_count -= 1;
+            }
+            // only matters when it follows a line of the new side
+            '\\' => hunk.new_no_newline = last != '-',
+            _ => return Err(format!("line {}: unexpected line in hunk: {line}", i + 1)),
+        }
+        last = kind;
+        i += 1;
+    }
+    if old_count > 0 || new_count > 0 {
+        return Err(format!("{header}: the diff ends inside the hunk"));
+    }
+    Ok((hunk, i))
+}
+
+// Apply all hunks to the content. The new content is only returned when every hunk
+// applied, so a file is either fully patched or left alone.
+pub fn apply(content: &str, patch: &FilePatch) -> (Option<String>, Vec<HunkResult>) {
+    let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    let mut trailing_newline = content.is_empty() || content.ends_with('\n');
+    let mut results = Vec::new();
+    let mut all_applied = true;
+    // shift of line numbers caused by the hunks applied so far
+    let mut delta: isize = 0;
+    // hunks may not overlap with the ones before them
+    let mut floor = 0;
+
+    for hunk in &patch.hunks {
+        let expected = (hunk.old_start.saturating_sub(1) as isize + delta).max(0) as usize;
+        let found = if hunk.old_start == 0 && hunk.old_lines.is_empty() {
+            Some(0)
+        } else {
+            find_hunk(&lines, &hunk.old_lines, expected, floor)
+        };
+        let Some(position) = found else {
+            all_applied = false;
+            results.push(HunkResult {
+                header: hunk.header.clone(),
+                applied: false,
+                offset: None,
+            });
+            continue;
+        };
+
+        let end = position + hunk.old_lines.len();
+        if end == lines.len() && (!hunk.old_lines.is_empty() || lines.is_empty()) {
+            trailing_newline = !hunk.new_no_newline;
+        }
+        lines.splice(position..end, hunk.new_lines.iter().cloned());
+        results.push(HunkResult {
+            header: hunk.header.clone(),
+            applied: true,
+            offset: Some(position as isize - expected as isize),
+        });
+        delta += hunk.new_lines.len() as isize - hunk.old_lines.len() as isize;
+        floor = position + hunk.new_lines.len();
+    }
+
+    if !all_applied {
+        return (None, results);
+    }
+    let mut patched = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        patched.push('\n');
+    }
+    (Some(patched), results)
+}
+
+// Look for the old lines at the expected position first, then further and further away
+fn find_hunk(lines: &[String], old: &[String], expected: usize, floor: usize) -> Option<usize> {
+    let fits = |position: usize| {
+        position >= floor
+            && position + old.len() <= lines.len()
+            && lines[position..position + old.len()] == *old
+    };
+    let max_distance = lines.len().max(expected);
+    (0..=max_distance).find_map(|distance| {
+        [
+            expected.checked_add(distance),
+            expected.checked_sub(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&position| fits(position))
+    })
+}
@@ -0,0 +1,474 @@
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use rmcp::{
+    model::{ErrorCode, ErrorData},
+    serde_json,
+};
+use tracing::{error, info, warn};
+
+use crate::common::config::Security;
+
+// Central path jail shared by every tool that touches the filesystem
+#[derive(Debug, Clone, Default)]
+pub struct PathPolicy {
+    // canonicalized roots, an empty list means the jail is disabled
+    allowed_roots: Vec<PathBuf>,
+    // subpaths denied even inside an allowed root, e.g. ".git/config"
+    deny_subpaths: Vec<Vec<OsString>>,
+    // canonicalized prefixes the file tools may read, empty means the whole jail
+    allowed_read_paths: Vec<PathBuf>,
+    // canonicalized prefixes the file tools may write, empty means the whole jail
+    allowed_write_paths: Vec<PathBuf>,
+    // roots announced by the client of the session, the file tools stay below them
+    client_roots: Arc<RwLock<Option<Vec<PathBuf>>>>,
+}
+
+impl PathPolicy {
+    pub fn new(config: &Security) -> Self {
+        let allowed_roots = canonicalize_all(&config.path_jail.allowed_roots, "path jail root");
+        let allowed_read_paths = canonicalize_all(&config.allowed_read_paths, "allowed read path");
+        let allowed_write_paths =
+            canonicalize_all(&config.allowed_write_paths, "allowed write path");
+        let deny_subpaths = config
+            .path_jail
+            .deny_subpaths
+            .iter()
+            .map(|subpath| {
+                Path::new(subpath)
+                    .components()
+                    .filter_map(|c| match c {
+                        Component::Normal(name) => Some(name.to_os_string()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|components| !components.is_empty())
+            .collect();
+        PathPolicy {
+            allowed_roots,
+            deny_subpaths,
+            allowed_read_paths,
+            allowed_write_paths,
+            client_roots: Arc::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_roots.is_empty()
+    }
+
+    pub fn allowed_roots(&self) -> &[PathBuf] {
+        &self.allowed_roots
+    }
+
+    // Resolve the path and check it against the jail, return the resolved path on success
+    pub fn check(&self, path: impl AsRef<Path>) -> Result<PathBuf, ErrorData> {
+        let path = path.as_ref();
+        let resolved = resolve_path(path).map_err(|e| {
+            Self::rejection(
+                "resolvable",
+                format!("can not resolve {}: {e}", path.display()),
+            )
+        })?;
+
+        if !self.is_enabled() {
+            return Ok(resolved);
+        }
+
+        let Some(root) = self
+            .allowed_roots
+            .iter()
+            .find(|root| resolved.starts_with(root))
+        else {
+            return Err(Self::rejection(
+                "allowed_roots",
+                format!(
+                    "{} resolves to {} which is outside the allowed roots",
+                    path.display(),
+                    resolved.display()
+                ),
+            ));
+        };
+
+        let relative: Vec<OsString> = resolved
+            .strip_prefix(root)
+            .unwrap_or(&resolved)
+            .components()
+            .map(|c| c.as_os_str().to_os_string())
+            .collect();
+        for denied in &self.deny_subpaths {
+            if relative
+                .windows(denied.len())
+                .any(|window| window == denied.as_slice())
+            {
+                let denied = denied.iter().collect::<PathBuf>();
+                return Err(Self::rejection(
+                    "deny_subpaths",
+                    format!(
+                        "{} matches the denied subpath {}",
+                        path.display(),
+                        denied.display()
+                    ),
+                ));
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    // Check a path the file tools want to read, on top of the jail it has to be
+    // below one of the allowed read paths
+    pub fn check_read(&self, path: impl AsRef<Path>) -> Result<PathBuf, ErrorData> {
+        self.check_prefixes(
+            path.as_ref(),
+            &self.allowed_read_paths,
+            "allowed_read_paths",
+        )
+    }
+
+    // Same as check_read for the paths the file tools want to write
+    pub fn check_write(&self, path: impl AsRef<Path>) -> Result<PathBuf, ErrorData> {
+        self.check_prefixes(
+            path.as_ref(),
+            &self.allowed_write_paths,
+            "allowed_write_paths",
+        )
+    }
+
+    // Narrow the file tools to the roots of the client. Roots the server would not let
+    // them read are dropped, without any root left the server config applies alone.
+    pub fn set_client_roots(&self, roots: &[PathBuf]) -> Vec<PathBuf> {
+        let kept: Vec<PathBuf> = roots
+            .iter()
+            .filter_map(|root| {
+                self.check_server_prefixes(root, &self.allowed_read_paths, "allowed_read_paths")
+                    .ok()
+            })
+            .collect();
+        info!(
+            "Client roots {:?}, {} of {} within the server policy",
+            kept,
+            kept.len(),
+            roots.len()
+        );
+        *self.client_roots.write().unwrap() = (!kept.is_empty()).then(|| kept.clone());
+        kept
+    }
+
+    pub fn client_roots(&self) -> Option<Vec<PathBuf>> {
+        self.client_roots.read().unwrap().clone()
+    }
+
+    fn check_prefixes(
+        &self,
+        path: &Path,
+        prefixes: &[PathBuf],
+        rule: &str,
+    ) -> Result<PathBuf, ErrorData> {
+        let resolved = self.check_server_prefixes(path, prefixes, rule)?;
+        if let Some(roots) = self.client_roots.read().unwrap().as_ref()
+            && !roots.iter().any(|root| resolved.starts_with(root))
+        {
+            return Err(Self::rejection(
+                "client_roots",
+                format!(
+                    "{} resolves to {} which is outside the roots of the client",
+                    path.display(),
+                    resolved.display()
+                ),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    fn check_server_prefixes(
+        &self,
+        path: &Path,
+        prefixes: &[PathBuf],
+        rule: &str,
+    ) -> Result<PathBuf, ErrorData> {
+        let resolved = self.check(path)?;
+        if !prefixes.is_empty() && !prefixes.iter().any(|allowed| resolved.starts_with(allowed)) {
+            return Err(Self::rejection(
+                rule,
+                format!(
+                    "{} resolves to {} which is not below one of the {rule}",
+                    path.display(),
+                    resolved.display()
+                ),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    fn rejection(rule: &str, message: String) -> ErrorData {
+        error!("Path rejected by rule {rule}: {message}");
+        ErrorData {
+            code: ErrorCode::INVALID_PARAMS,
+            message: Cow::Owned(format!("Path rejected by rule `{rule}`: {message}")),
+            data: Some(serde_json::json!({ "rule": rule })),
+        }
+    }
+}
+
+fn canonicalize_all(paths: &[String], kind: &str) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter_map(|path| match fs::canonicalize(path) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!("Ignore {kind} {path}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+// Resolve symlinks component by component, the part of the path that does not exist yet
+// is normalized lexically on top of its deepest existing parent
+pub fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let mut resolved = PathBuf::from("/");
+    let mut missing: Vec<OsString> = Vec::new();
+    for component in absolute.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                resolved = PathBuf::from("/");
+                missing.clear();
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if missing.pop().is_none() {
+                    resolved.pop();
+                }
+            }
+            Component::Normal(name) if missing.is_empty() => {
+                let candidate = resolved.join(name);
+                match fs::canonicalize(&candidate) {
+                    Ok(real) => resolved = real,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        // a dangling symlink could point anywhere once it is created
+                        if fs::symlink_metadata(&candidate).is_ok() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("{} is a dangling symlink", candidate.display()),
+                            ));
+                        }
+                        missing.push(name.to_os_string());
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Component::Normal(name) => missing.push(name.to_os_string()),
+        }
+    }
+    resolved.extend(missing);
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::common::config::PathJail;
+
+    // A directory tree of its own below the temp dir, removed at the end of the test
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("path-policy-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(dir.join("app/src")).unwrap();
+            fs::create_dir_all(dir.join("app2")).unwrap();
+            fs::create_dir_all(dir.join("outside")).unwrap();
+            fs::write(dir.join("app/src/main.rs"), "").unwrap();
+            fs::write(dir.join("outside/secret"), "").unwrap();
+            Tree(fs::canonicalize(dir).unwrap())
+        }
+
+        fn path(&self, relative: &str) -> PathBuf {
+            self.0.join(relative)
+        }
+
+        // A jail with the app directory as its root, given as `root`
+        fn policy(&self, root: &str) -> PathPolicy {
+            PathPolicy::new(&Security {
+                path_jail: PathJail {
+                    allowed_roots: vec![root.to_string()],
+                    deny_subpaths: vec![".git/config".into(), ".env".into(), ".ssh".into()],
+                },
+                ..Default::default()
+            })
+        }
+
+        fn jail(&self) -> PathPolicy {
+            self.policy(&self.path("app").to_string_lossy())
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn rule(result: Result<PathBuf, ErrorData>) -> String {
+        let error = result.expect_err("the path is rejected");
+        error.data.unwrap()["rule"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn resolve_follows_symlinks() {
+        let tree = Tree::new();
+        symlink(tree.path("outside"), tree.path("app/link")).unwrap();
+        assert_eq!(
+            resolve_path(&tree.path("app/link/secret")).unwrap(),
+            tree.path("outside/secret")
+        );
+        // the part that does not exist yet is appended to the real parent
+        assert_eq!(
+            resolve_path(&tree.path("app/link/new/file")).unwrap(),
+            tree.path("outside/new/file")
+        );
+    }
+
+    #[test]
+    fn resolve_refuses_dangling_symlinks() {
+        let tree = Tree::new();
+        symlink(tree.path("nowhere"), tree.path("app/dangling")).unwrap();
+        let error = resolve_path(&tree.path("app/dangling")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(resolve_path(&tree.path("app/dangling/file")).is_err());
+        assert_eq!(
+            rule(tree.jail().check(tree.path("app/dangling"))),
+            "resolvable"
+        );
+    }
+
+    #[test]
+    fn resolve_applies_parent_dirs() {
+        let tree = Tree::new();
+        assert_eq!(
+            resolve_path(&tree.path("app/../outside/secret")).unwrap(),
+            tree.path("outside/secret")
+        );
+        // `..` inside the missing part takes back the missing components first
+        assert_eq!(
+            resolve_path(&tree.path("app/new/../file")).unwrap(),
+            tree.path("app/file")
+        );
+        assert_eq!(
+            resolve_path(&tree.path("app/new/../../outside/file")).unwrap(),
+            tree.path("outside/file")
+        );
+    }
+
+    #[test]
+    fn symlinks_out_of_the_root_are_rejected() {
+        let tree = Tree::new();
+        symlink(tree.path("outside"), tree.path("app/link")).unwrap();
+        let jail = tree.jail();
+        assert_eq!(
+            rule(jail.check(tree.path("app/link/secret"))),
+            "allowed_roots"
+        );
+        assert_eq!(
+            rule(jail.check(tree.path("app/link/missing"))),
+            "allowed_roots"
+        );
+        // a symlink staying inside the root is fine
+        symlink(tree.path("app/src"), tree.path("app/inside")).unwrap();
+        assert_eq!(
+            jail.check(tree.path("app/inside/main.rs")).unwrap(),
+            tree.path("app/src/main.rs")
+        );
+    }
+
+    #[test]
+    fn parent_dirs_out_of_the_root_are_rejected() {
+        let tree = Tree::new();
+        let jail = tree.jail();
+        assert_eq!(
+            rule(jail.check(tree.path("app/../outside/secret"))),
+            "allowed_roots"
+        );
+        assert_eq!(
+            rule(jail.check(tree.path("app/../../../etc/passwd"))),
+            "allowed_roots"
+        );
+        assert_eq!(
+            rule(jail.check(tree.path("app/new/../../outside"))),
+            "allowed_roots"
+        );
+        assert_eq!(
+            jail.check(tree.path("app/new/../src/main.rs")).unwrap(),
+            tree.path("app/src/main.rs")
+        );
+    }
+
+    #[test]
+    fn roots_match_whole_components() {
+        let tree = Tree::new();
+        for root in ["app", "app/"] {
+            let jail = tree.policy(&format!("{}/{root}", tree.0.display()));
+            assert_eq!(jail.check(tree.path("app")).unwrap(), tree.path("app"));
+            assert_eq!(jail.check(tree.path("app/")).unwrap(), tree.path("app"));
+            // app2 starts with the characters of app but is a sibling
+            assert_eq!(rule(jail.check(tree.path("app2"))), "allowed_roots");
+            assert_eq!(rule(jail.check(tree.path("app2/file"))), "allowed_roots");
+        }
+    }
+
+    #[test]
+    fn missing_files_are_checked_by_their_parent() {
+        let tree = Tree::new();
+        let jail = tree.jail();
+        assert_eq!(
+            jail.check(tree.path("app/src/new.rs")).unwrap(),
+            tree.path("app/src/new.rs")
+        );
+        assert_eq!(
+            jail.check(tree.path("app/new/dir/file")).unwrap(),
+            tree.path("app/new/dir/file")
+        );
+        assert_eq!(
+            rule(jail.check(tree.path("outside/new.rs"))),
+            "allowed_roots"
+        );
+    }
+
+    #[test]
+    fn denied_subpaths_are_rejected() {
+        let tree = Tree::new();
+        let jail = tree.jail();
+        for denied in [
+            "app/.env",
+            "app/src/.env",
+            "app/.git/config",
+            "app/.ssh",
+            "app/.ssh/id_ed25519",
+        ] {
+            assert_eq!(
+                rule(jail.check(tree.path(denied))),
+                "deny_subpaths",
+                "{denied}"
+            );
+        }
+        // only whole components match
+        for allowed in ["app/.envrc", "app/.git/HEAD", "app/config/.git"] {
+            assert!(jail.check(tree.path(allowed)).is_ok(), "{allowed}");
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+use rmcp::model::ErrorData;
+use rmcp::serde_json;
+use tracing::error;
+
+use crate::common::config::ToolPermissionsConfig;
+use crate::common::oauth::McpAccessToken;
+use crate::common::scopes::has_scope;
+use crate::common::sudo::wildcard_match;
+
+// Which tools an OAuth client may see and call. The lists of the client id and of
+// every scope of its token add up. Requests without a token (development mode) and
+// clients no list applies to get every tool, unless a default list is configured.
+#[derive(Debug, Clone, Default)]
+pub struct ToolPermissions {
+    clients: BTreeMap<String, Vec<String>>,
+    scopes: BTreeMap<String, Vec<String>>,
+    default: Option<Vec<String>>,
+}
+
+impl ToolPermissions {
+    pub fn new(config: &ToolPermissionsConfig) -> Self {
+        ToolPermissions {
+            clients: config.clients.clone(),
+            scopes: config.scopes.clone(),
+            default: config.default.clone(),
+        }
+    }
+
+    pub fn allows(&self, token: Option<&McpAccessToken>, tool: &str) -> bool {
+        let Some(token) = token else {
+            return true;
+        };
+        let mut lists = self
+            .clients
+            .get(&token.client_id)
+            .into_iter()
+            .chain(
+                self.scopes
+                    .iter()
+                    .filter(|(scope, _)| has_scope(token, scope))
+                    .map(|(_, tools)| tools),
+            )
+            .peekable();
+        if lists.peek().is_none() {
+            return self
+                .default
+                .as_ref()
+                .is_none_or(|tools| matches_any(tools, tool));
+        }
+        lists.any(|tools| matches_any(tools, tool))
+    }
+
+    pub fn check(&self, token: Option<&McpAccessToken>, tool: &str) -> Result<(), ErrorData> {
+        if self.allows(token, tool) {
+            return Ok(());
+        }
+        let client = token.map_or("", |token| token.client_id.as_str());
+        error!("Client {client} is not permitted to call {tool}");
+        Err(ErrorData::invalid_request(
+            format!("Client {client} is not permitted to call {tool}"),
+            Some(serde_json::json!({ "tool": tool, "rule": "tools.permissions" })),
+        ))
+    }
+}
+
+// Tool names may use `*`, e.g. "unix_preset_*"
+fn matches_any(patterns: &[String], tool: &str) -> bool {
+    patterns.iter().any(|pattern| wildcard_match(pattern, tool))
+}
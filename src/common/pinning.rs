@@ -0,0 +1,126 @@
+// [security] pin_certificates for the outbound HTTP clients: the certificate of a pinned
+// host has to be one of its pins, on top of the CA check every host gets
+use std::sync::Arc;
+
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::common::config::CertificatePin;
+
+// The TLS config of the clients, none without pins so reqwest keeps its own
+#[derive(Debug, Clone, Default)]
+pub struct CertificatePins {
+    tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl CertificatePins {
+    pub fn new(pins: &[CertificatePin]) -> Self {
+        if pins.is_empty() {
+            return CertificatePins::default();
+        }
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let verifier = PinningVerifier {
+            inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .expect("the web pki roots are valid trust anchors"),
+            pins: pins
+                .iter()
+                .map(|pin| (pin.host.to_ascii_lowercase(), pin.sha256_fingerprint.0))
+                .collect(),
+        };
+        let tls = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        CertificatePins {
+            tls: Some(Arc::new(tls)),
+        }
+    }
+
+    // A builder of a client that checks the pins
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder();
+        match &self.tls {
+            Some(tls) => builder.use_preconfigured_tls(rustls::ClientConfig::clone(tls)),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<(String, [u8; 32])>, // by lowercase host
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => std::net::IpAddr::from(*ip).to_string(),
+            _ => return Ok(verified),
+        };
+        let mut pins = self
+            .pins
+            .iter()
+            .filter(|(pinned, _)| *pinned == host)
+            .peekable();
+        if pins.peek().is_none() {
+            return Ok(verified);
+        }
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if pins.any(|(_, pin)| *pin == fingerprint) {
+            Ok(verified)
+        } else {
+            warn!("the certificate of {host} matches none of its pins");
+            Err(Error::General(format!(
+                "the certificate of {host} matches none of its pins"
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,113 @@
+use std::ffi::OsString;
+
+use serde::Serialize;
+use sysinfo::{
+    MINIMUM_CPU_UPDATE_INTERVAL, Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System, Users,
+};
+
+// Every command started by the server inherits this variable, so its descendants
+// can be recognized even after they were reparented
+pub const JOB_MARKER_ENV: &str = "MCP_BASH_SERVER_PID";
+
+pub fn job_marker_value() -> String {
+    std::process::id().to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub user: Option<String>,
+    pub name: String,
+    pub command_line: Vec<String>,
+    pub cpu_percent: f32,
+    pub memory_bytes: u64, // resident set size
+    pub status: String,
+    pub start_time: u64, // seconds since the unix epoch
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessFilter {
+    pub user: Option<String>,
+    pub filter_name: Option<String>,
+    pub server_jobs_only: bool,
+}
+
+// Take a snapshot of the visible processes, sorted by pid
+pub async fn list_processes(filter: &ProcessFilter) -> Vec<ProcessInfo> {
+    let mut system = System::new();
+    refresh(&mut system);
+    // cpu usage is computed between two refreshes
+    tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+    refresh(&mut system);
+
+    let users = Users::new_with_refreshed_list();
+    let server_pid = Pid::from_u32(std::process::id());
+    let marker = OsString::from(format!("{JOB_MARKER_ENV}={}", job_marker_value()));
+
+    let mut processes: Vec<ProcessInfo> = system
+        .processes()
+        .values()
+        // threads show up as processes on linux
+        .filter(|process| process.thread_kind().is_none())
+        .filter(|process| {
+            !filter.server_jobs_only
+                || (process.pid() != server_pid
+                    && (process.environ().contains(&marker)
+                        || is_descendant(&system, process, server_pid)))
+        })
+        .map(|process| ProcessInfo {
+            pid: process.pid().as_u32(),
+            ppid: process.parent().map(|pid| pid.as_u32()),
+            user: process
+                .user_id()
+                .and_then(|uid| users.get_user_by_id(uid))
+                .map(|user| user.name().to_string()),
+            name: process.name().to_string_lossy().into_owned(),
+            command_line: process
+                .cmd()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            cpu_percent: process.cpu_usage(),
+            memory_bytes: process.memory(),
+            status: process.status().to_string(),
+            start_time: process.start_time(),
+        })
+        .filter(|info| {
+            filter
+                .user
+                .as_ref()
+                .is_none_or(|user| info.user.as_ref() == Some(user))
+        })
+        .filter(|info| {
+            filter
+                .filter_name
+                .as_ref()
+                .is_none_or(|needle| info.name.contains(needle.as_str()))
+        })
+        .collect();
+    processes.sort_by_key(|info| info.pid);
+    processes
+}
+
+fn refresh(system: &mut System) {
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::everything(),
+    );
+}
+
+fn is_descendant(system: &System, process: &Process, ancestor: Pid) -> bool {
+    let mut parent = process.parent();
+    // bounded walk in case the snapshot contains a cycle
+    for _ in 0..1024 {
+        match parent {
+            Some(pid) if pid == ancestor => return true,
+            Some(pid) => parent = system.process(pid).and_then(|p| p.parent()),
+            None => return false,
+        }
+    }
+    false
+}
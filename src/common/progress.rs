@@ -0,0 +1,55 @@
+use std::fmt;
+
+use rmcp::{
+    RoleServer,
+    model::{ProgressNotificationParam, ProgressToken},
+    service::{Peer, RequestContext},
+};
+use tracing::warn;
+
+// Send MCP progress notifications for one request, only if the client asked for them
+pub struct ProgressReporter {
+    peer: Peer<RoleServer>,
+    token: Option<ProgressToken>,
+    progress: u32,
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("token", &self.token)
+            .field("progress", &self.progress)
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    pub fn new(context: &RequestContext<RoleServer>) -> Self {
+        ProgressReporter {
+            peer: context.peer.clone(),
+            token: context.meta.get_progress_token(),
+            progress: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    // Each report increments the progress counter, the message carries the payload
+    pub async fn report(&mut self, message: String) {
+        let Some(token) = &self.token else {
+            return;
+        };
+        self.progress += 1;
+        let params = ProgressNotificationParam {
+            progress_token: token.clone(),
+            progress: self.progress,
+            total: None,
+            message: Some(message),
+        };
+        if let Err(e) = self.peer.notify_progress(params).await {
+            warn!("Failed to send progress notification: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use rmcp::model::{
+    ErrorData, GetPromptResult, JsonObject, Prompt, PromptArgument, PromptMessage,
+    PromptMessageRole,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::common::session::SessionRegistry;
+
+// prompts/<name>.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptTemplate {
+    #[serde(default)]
+    pub name: String, // the file name without .toml if not set
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<TemplateArgument>,
+    pub messages: Vec<TemplateMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateArgument {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateMessage {
+    #[serde(default = "default_role")]
+    pub role: String, // "user" or "assistant"
+    pub text: String, // {{argument}} is replaced by the value of the argument
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+impl PromptTemplate {
+    pub fn prompt(&self) -> Prompt {
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|argument| PromptArgument {
+                name: argument.name.clone(),
+                description: argument.description.clone(),
+                required: Some(argument.required),
+            })
+            .collect::<Vec<_>>();
+        Prompt::new(
+            &self.name,
+            self.description.as_deref(),
+            (!arguments.is_empty()).then_some(arguments),
+        )
+    }
+
+    pub fn render(&self, values: Option<&JsonObject>) -> Result<GetPromptResult, ErrorData> {
+        let value = |name: &str| {
+            values
+                .and_then(|values| values.get(name))
+                .map(|value| match value.as_str() {
+                    Some(text) => text.to_string(),
+                    None => value.to_string(),
+                })
+        };
+        if let Some(missing) = self
+            .arguments
+            .iter()
+            .find(|argument| argument.required && value(&argument.name).is_none())
+        {
+            return Err(ErrorData::invalid_params(
+                format!("Prompt {} needs the argument {}", self.name, missing.name),
+                None,
+            ));
+        }
+
+        let messages = self
+            .messages
+            .iter()
+            .map(|message| {
+                let mut text = message.text.clone();
+                for argument in &self.arguments {
+                    let placeholder = format!("{{{{{}}}}}", argument.name);
+                    text = text.replace(&placeholder, &value(&argument.name).unwrap_or_default());
+                }
+                let role = if message.role == "assistant" {
+                    PromptMessageRole::Assistant
+                } else {
+                    PromptMessageRole::User
+                };
+                PromptMessage::new_text(role, text)
+            })
+            .collect();
+        Ok(GetPromptResult {
+            description: self.description.clone(),
+            messages,
+        })
+    }
+}
+
+// The prompt templates of the prompts directory, shared by all sessions and reloaded
+// when a file in the directory changes
+pub struct PromptLibrary {
+    templates: Arc<RwLock<BTreeMap<String, PromptTemplate>>>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl fmt::Debug for PromptLibrary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PromptLibrary")
+            .field("templates", &self.templates.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl PromptLibrary {
+    pub fn load(dir: &Path, sessions: Arc<SessionRegistry>) -> Self {
+        let templates = Arc::new(RwLock::new(read_templates(dir)));
+        let watcher = Self::watch(dir, templates.clone(), sessions)
+            .inspect_err(|e| warn!("Prompts in {} are not reloaded: {e}", dir.display()))
+            .ok();
+        PromptLibrary {
+            templates,
+            _watcher: watcher,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Prompt> {
+        self.templates
+            .read()
+            .unwrap()
+            .values()
+            .map(PromptTemplate::prompt)
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<PromptTemplate> {
+        self.templates.read().unwrap().get(name).cloned()
+    }
+
+    fn watch(
+        dir: &Path,
+        templates: Arc<RwLock<BTreeMap<String, PromptTemplate>>>,
+        sessions: Arc<SessionRegistry>,
+    ) -> notify::Result<RecommendedWatcher> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) {
+                let _ = tx.send(());
+            }
+        })?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        let dir = dir.to_path_buf();
+        tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                // an editor saving a file causes a burst of events, reload once
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                while rx.try_recv().is_ok() {}
+                *templates.write().unwrap() = read_templates(&dir);
+                sessions.notify_prompt_list_changed().await;
+            }
+        });
+        Ok(watcher)
+    }
+}
+
+fn read_templates(dir: &Path) -> BTreeMap<String, PromptTemplate> {
+    let mut templates = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        warn!("Prompt directory {} can not be read", dir.display());
+        return templates;
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let template = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str::<PromptTemplate>(&text).map_err(|e| e.to_string()));
+        match template {
+            Ok(mut template) => {
+                if template.name.is_empty() {
+                    template.name = path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                templates.insert(template.name.clone(), template);
+            }
+            Err(e) => warn!("Skip prompt template {}: {e}", path.display()),
+        }
+    }
+    info!(
+        "Loaded {} prompt templates from {}",
+        templates.len(),
+        dir.display()
+    );
+    templates
+}
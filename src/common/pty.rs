@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use portable_pty::{Child, ChildKiller, CommandBuilder, MasterPty, PtySize, native_pty_system};
+use tracing::{info, warn};
+
+use crate::common::processes::{JOB_MARKER_ENV, job_marker_value};
+
+// Keep at most this much unread output per PTY, older bytes are dropped
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+
+// An interactive shell running inside a pseudo-terminal
+pub struct PtySession {
+    master: Mutex<Box<dyn MasterPty + Send>>,
+    writer: Mutex<Box<dyn Write + Send>>,
+    child: Mutex<Box<dyn Child + Send + Sync>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl PtySession {
+    pub fn spawn(
+        shell: &Path,
+        working_dir: Option<&Path>,
+        env_vars: Option<&HashMap<String, String>>,
+        rows: u16,
+        cols: u16,
+    ) -> anyhow::Result<Self> {
+        let pair = native_pty_system().openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+
+        let mut cmd = CommandBuilder::new(shell);
+        if let Some(working_dir) = working_dir {
+            cmd.cwd(working_dir);
+        }
+        if let Some(env_vars) = env_vars {
+            for (key, value) in env_vars {
+                cmd.env(key, value);
+            }
+        }
+        cmd.env("TERM", "xterm-256color");
+        cmd.env(JOB_MARKER_ENV, job_marker_value());
+
+        let child = pair.slave.spawn_command(cmd)?;
+        // Only the child keeps the slave side open, so reads end when it exits
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        // Collect the terminal output in the background until the shell exits
+        let pending = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        let mut pending = pending.lock().unwrap();
+                        pending.extend_from_slice(&buf[..n]);
+                        if pending.len() > MAX_PENDING_OUTPUT {
+                            let overflow = pending.len() - MAX_PENDING_OUTPUT;
+                            pending.drain(..overflow);
+                        }
+                    }
+                }
+            }
+        });
+
+        info!("Start pty session with shell {}", shell.display());
+        Ok(PtySession {
+            master: Mutex::new(pair.master),
+            writer: Mutex::new(writer),
+            child: Mutex::new(child),
+            output,
+        })
+    }
+
+    pub fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(data)?;
+        writer.flush()
+    }
+
+    // Return and clear the output received since the last read
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    // The output received since the last read, left for the next read
+    pub fn peek_output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+
+    pub fn has_output(&self) -> bool {
+        !self.output.lock().unwrap().is_empty()
+    }
+
+    pub fn resize(&self, rows: u16, cols: u16) -> anyhow::Result<()> {
+        self.master.lock().unwrap().resize(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+    }
+
+    // The exit code of the shell, None while it is still running
+    pub fn exit_code(&self) -> Option<u32> {
+        match self.child.lock().unwrap().try_wait() {
+            Ok(status) => status.map(|status| status.exit_code()),
+            Err(e) => {
+                warn!("Failed to wait pty child: {e}");
+                None
+            }
+        }
+    }
+
+    pub fn kill(&self) {
+        let mut child = self.child.lock().unwrap();
+        if let Ok(None) = child.try_wait()
+            && let Err(e) = child.kill()
+        {
+            warn!("Failed to kill pty child: {e}");
+        }
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+// The pty sessions opened by one MCP session
+#[derive(Clone, Default)]
+pub struct PtySessions {
+    sessions: Arc<Mutex<HashMap<String, Arc<PtySession>>>>,
+}
+
+impl fmt::Debug for PtySessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PtySessions")
+            .field("count", &self.sessions.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl PtySessions {
+    pub fn insert(&self, id: String, session: PtySession) {
+        self.sessions.lock().unwrap().insert(id, Arc::new(session));
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<PtySession>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Arc<PtySession>> {
+        self.sessions.lock().unwrap().remove(id)
+    }
+
+    pub fn list(&self) -> Vec<(String, Arc<PtySession>)> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        sessions.sort_by(|a, b| a.0.cmp(&b.0));
+        sessions
+    }
+}
@@ -0,0 +1,65 @@
+// Where clients reach the server, the base of every URL the OAuth metadata, the device
+// flow and the challenges of /mcp advertise. [settings] external_url wins, then the
+// X-Forwarded-Proto and X-Forwarded-Host of the request with trust_proxy_headers, then
+// the bind address.
+use anyhow::Result;
+use axum::http::{HeaderMap, header};
+
+use crate::common::config::Settings;
+
+#[derive(Debug, Clone, Default)]
+pub struct PublicUrl {
+    external_url: Option<String>, // without the trailing slash
+    bind_url: String,             // http://<host:port>
+    trust_proxy_headers: bool,
+}
+
+impl PublicUrl {
+    pub fn new(settings: &Settings, bind_address: &str) -> Result<Self> {
+        Ok(PublicUrl {
+            external_url: settings.external_url()?,
+            bind_url: format!("http://{bind_address}"),
+            trust_proxy_headers: settings.trust_proxy_headers,
+        })
+    }
+
+    // The same for every request: the issuer of JWTs and the base of the resource
+    // identifier
+    pub fn fixed(&self) -> &str {
+        self.external_url.as_deref().unwrap_or(&self.bind_url)
+    }
+
+    // The URL the request was sent to, for what is advertised in the answer
+    pub fn of_request(&self, headers: &HeaderMap) -> String {
+        if self.external_url.is_none()
+            && self.trust_proxy_headers
+            && let Some(url) = forwarded_url(headers)
+        {
+            return url;
+        }
+        self.fixed().to_string()
+    }
+}
+
+// The last value of each header, the one the nearest proxy appended, earlier ones
+// may come from the client. A host that is no plain host[:port] is ignored, it would
+// end up in the metadata.
+fn forwarded_url(headers: &HeaderMap) -> Option<String> {
+    let last = |name: &str| {
+        let value = headers.get_all(name).iter().last()?.to_str().ok()?;
+        value
+            .rsplit(',')
+            .next()
+            .map(|value| value.trim().to_string())
+    };
+    let proto = last("x-forwarded-proto").unwrap_or_else(|| "http".to_string());
+    if !matches!(proto.as_str(), "http" | "https") {
+        return None;
+    }
+    let host = last("x-forwarded-host").or_else(|| last(header::HOST.as_str()))?;
+    let plain = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '[' | ']'));
+    plain.then(|| format!("{proto}://{host}"))
+}
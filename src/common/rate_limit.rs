@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, State},
+    http::{Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rmcp::serde_json;
+use tokio_stream::StreamExt;
+use tracing::{error, info};
+
+use crate::common::audit::{AuditEvent, Outcome};
+use crate::common::config::{RateLimit, TokenEndpointRateLimit};
+use crate::common::oauth::basic_credentials;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Client(String),
+}
+
+// The requests of a key in the current and the previous fixed window. The sliding window
+// counts the current ones and the part of the previous ones it still overlaps.
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    current: u32,
+    previous: u32,
+}
+
+impl Window {
+    fn new(now: Instant) -> Self {
+        Window {
+            start: now,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    fn roll(&mut self, now: Instant, length: Duration) {
+        let elapsed = now.duration_since(self.start);
+        if elapsed >= length * 2 {
+            *self = Window::new(now);
+        } else if elapsed >= length {
+            self.previous = self.current;
+            self.current = 0;
+            self.start += length;
+        }
+    }
+
+    fn estimate(&self, now: Instant, length: Duration) -> f64 {
+        let overlap = 1.0 - now.duration_since(self.start).as_secs_f64() / length.as_secs_f64();
+        f64::from(self.previous) * overlap + f64::from(self.current)
+    }
+
+    // How long until the estimate is below the limit, if no more requests come
+    fn retry_after(&self, now: Instant, length: Duration, limit: u32) -> Duration {
+        let length_secs = length.as_secs_f64();
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let limit = f64::from(limit);
+        let wait = if f64::from(self.current) < limit {
+            // the previous window slides out of this one
+            let free = (limit - f64::from(self.current)) / f64::from(self.previous.max(1));
+            length_secs * (1.0 - free) - elapsed
+        } else {
+            // the current window becomes the previous one and slides out of the next
+            length_secs - elapsed + length_secs * (1.0 - limit / f64::from(self.current))
+        };
+        Duration::from_secs_f64(wait.max(0.0))
+    }
+}
+
+// Sliding window limits per address and per client id, kept in memory. At most
+// max_tracked_keys windows are kept, stale ones are dropped first, then the oldest.
+#[derive(Debug)]
+pub struct RateLimiter {
+    enabled: bool,
+    window: Duration,
+    per_ip: u32,
+    per_client: u32,
+    max_keys: usize,
+    exempt_loopback: bool,
+    windows: Mutex<HashMap<Key, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit, exempt_loopback: bool) -> Self {
+        RateLimiter {
+            enabled: config.enabled,
+            window: Duration::from_secs(config.window_seconds.max(1)),
+            per_ip: config.requests_per_ip,
+            per_client: config.requests_per_client,
+            max_keys: config.max_tracked_keys.max(1),
+            exempt_loopback,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Counts a request, over a limit it is refused with the time until the next one passes
+    pub fn check(&self, ip: IpAddr, client_id: Option<&str>) -> Result<(), Duration> {
+        if !self.enabled || (self.exempt_loopback && ip.is_loopback()) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        self.hit(&mut windows, Key::Ip(ip), self.per_ip, now)?;
+        if let Some(client_id) = client_id.filter(|client_id| !client_id.is_empty()) {
+            self.hit(
+                &mut windows,
+                Key::Client(client_id.to_string()),
+                self.per_client,
+                now,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn hit(
+        &self,
+        windows: &mut HashMap<Key, Window>,
+        key: Key,
+        limit: u32,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        if limit == 0 {
+            return Ok(());
+        }
+        if !windows.contains_key(&key) && windows.len() >= self.max_keys {
+            self.evict(windows, now);
+        }
+        let window = windows.entry(key).or_insert_with(|| Window::new(now));
+        window.roll(now, self.window);
+        if window.estimate(now, self.window) >= f64::from(limit) {
+            return Err(window.retry_after(now, self.window, limit));
+        }
+        window.current += 1;
+        Ok(())
+    }
+
+    fn evict(&self, windows: &mut HashMap<Key, Window>, now: Instant) {
+        windows.retain(|_, window| now.duration_since(window.start) < self.window * 2);
+        if windows.len() >= self.max_keys
+            && let Some(oldest) = windows
+                .iter()
+                .min_by_key(|(_, window)| window.start)
+                .map(|(key, _)| key.clone())
+        {
+            windows.remove(&oldest);
+        }
+    }
+}
+
+// The bucket of a client, refilled when it is next used
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets per client id of [oauth.token_endpoint_rate_limit], checked by /token
+// only. At most max_tracked_clients are kept, the full ones are dropped first as they are
+// the same as a new one, then the longest unused.
+#[derive(Debug)]
+pub struct TokenBuckets {
+    capacity: f64, // 0 when disabled
+    refill_per_second: f64,
+    max_clients: usize,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl TokenBuckets {
+    pub fn new(config: &TokenEndpointRateLimit) -> Self {
+        TokenBuckets {
+            capacity: if config.enabled {
+                f64::from(config.capacity)
+            } else {
+                0.0
+            },
+            // a bucket that never refills would lock the client for good
+            refill_per_second: config.refill_per_second.max(0.001),
+            max_clients: config.max_tracked_clients.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes a token of the client, with an empty bucket the time until the next one
+    pub fn take(&self, client_id: &str) -> Result<(), Duration> {
+        if self.capacity == 0.0 || client_id.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(client_id) && buckets.len() >= self.max_clients {
+            self.evict(&mut buckets, now);
+        }
+        let bucket = buckets.entry(client_id.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.level(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_per_second,
+            ));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn level(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * self.refill_per_second;
+        (bucket.tokens + refilled).min(self.capacity)
+    }
+
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.level(bucket, now) < self.capacity);
+        if buckets.len() >= self.max_clients
+            && let Some(oldest) = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated)
+                .map(|(client_id, _)| client_id.clone())
+        {
+            buckets.remove(&oldest);
+        }
+    }
+}
+
+// The Retry-After of a wait, in whole seconds rounded up
+pub fn retry_after_seconds(wait: Duration) -> u64 {
+    (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1)
+}
+
+pub fn too_many_requests(wait: Duration) -> Response {
+    let seconds = retry_after_seconds(wait);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, seconds.to_string())],
+        Json(serde_json::json!({
+            "error": "temporarily_unavailable",
+            "error_description": format!("too many requests, retry in {seconds} seconds")
+        })),
+    )
+        .into_response()
+}
+
+// The form bodies of the OAuth endpoints are small, larger ones are refused
+pub(crate) const MAX_FORM_BYTES: usize = 64 * 1024;
+
+// Buffer a request body of at most limit bytes, the reading stops and the request is
+// answered with 413 as soon as it is larger
+pub(crate) async fn read_body(body: Body, limit: usize) -> Result<Bytes, Response> {
+    let mut stream = body.into_data_stream();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            error!("can't read request body: {}", e);
+            StatusCode::BAD_REQUEST.into_response()
+        })?;
+        if bytes.len() + chunk.len() > limit {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": format!("the request body is larger than {} KiB", limit / 1024)
+                })),
+            )
+                .into_response());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+// Limits the requests of an address and of the client_id they name in the query, a
+// form body or an Authorization: Basic header
+pub async fn rate_limit_middleware(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    let mut client_id = basic_credentials(request.headers())
+        .map(|(client_id, _)| client_id)
+        .or_else(|| {
+            request
+                .uri()
+                .query()
+                .and_then(|query| client_id_of(query.as_bytes()))
+        });
+    let request = if is_form {
+        let (parts, body) = request.into_parts();
+        let body = match read_body(body, MAX_FORM_BYTES).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        client_id = client_id.or_else(|| client_id_of(&body));
+        Request::from_parts(parts, Body::from(body))
+    } else {
+        request
+    };
+
+    if let Err(wait) = limiter.check(addr.ip(), client_id.as_deref()) {
+        info!(
+            "rate limited {} {} from {} (client {:?})",
+            request.method(),
+            request.uri().path(),
+            addr.ip(),
+            client_id
+        );
+        AuditEvent::new("rate_limited", Outcome::Denied)
+            .client(client_id.as_deref().unwrap_or_default())
+            .ip(Some(addr.ip()))
+            .reason(format!("{} {}", request.method(), request.uri().path()))
+            .emit();
+        return too_many_requests(wait);
+    }
+    next.run(request).await
+}
+
+pub(crate) fn client_id_of(urlencoded: &[u8]) -> Option<String> {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(urlencoded)
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == "client_id")
+        .map(|(_, value)| value)
+}
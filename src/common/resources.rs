@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use reqwest::Url;
+use rmcp::{RoleServer, model::ResourceUpdatedNotificationParam, service::Peer};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::common::path_policy::PathPolicy;
+
+const MAX_RESOURCE_DEPTH: usize = 8;
+
+pub fn file_uri(path: &Path) -> Option<String> {
+    Url::from_file_path(path).ok().map(String::from)
+}
+
+pub fn uri_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri)
+        .ok()
+        .filter(|url| url.scheme() == "file")?
+        .to_file_path()
+        .ok()
+}
+
+// Watch subscribed files and tell the client when they change
+struct Watching {
+    watcher: RecommendedWatcher,
+    // subscribed file -> its uri
+    subscribed: Arc<Mutex<HashMap<PathBuf, String>>>,
+    // watched directory -> number of subscribed files in it
+    directories: HashMap<PathBuf, usize>,
+}
+
+// The resource subscriptions of one MCP session, the watcher starts with the first one
+#[derive(Clone, Default)]
+pub struct ResourceSubscriptions {
+    inner: Arc<Mutex<Option<Watching>>>,
+}
+
+impl fmt::Debug for ResourceSubscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self
+            .inner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |watching| watching.subscribed.lock().unwrap().len());
+        f.debug_struct("ResourceSubscriptions")
+            .field("count", &count)
+            .finish()
+    }
+}
+
+impl ResourceSubscriptions {
+    pub fn subscribe(
+        &self,
+        peer: &Peer<RoleServer>,
+        path: PathBuf,
+        uri: String,
+    ) -> notify::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_none() {
+            *inner = Some(Self::start(peer.clone())?);
+        }
+        let watching = inner.as_mut().expect("watcher was just started");
+        if watching
+            .subscribed
+            .lock()
+            .unwrap()
+            .insert(path.clone(), uri)
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        // Watch the directory, editors and atomic writes replace the file itself
+        let directory = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let count = watching.directories.entry(directory.clone()).or_insert(0);
+        if *count == 0 {
+            watching
+                .watcher
+                .watch(&directory, RecursiveMode::NonRecursive)?;
+        }
+        *count += 1;
+        info!("Subscribe to resource {}", path.display());
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, uri: &str) -> notify::Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(watching) = inner.as_mut() else {
+            return Ok(false);
+        };
+        // the file may be gone by now, so look it up by its uri
+        let path = {
+            let mut subscribed = watching.subscribed.lock().unwrap();
+            let Some(path) = subscribed
+                .iter()
+                .find(|(_, subscribed)| subscribed.as_str() == uri)
+                .map(|(path, _)| path.clone())
+            else {
+                return Ok(false);
+            };
+            subscribed.remove(&path);
+            path
+        };
+        let directory = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        if let Some(count) = watching.directories.get_mut(&directory) {
+            *count -= 1;
+            if *count == 0 {
+                watching.directories.remove(&directory);
+                watching.watcher.unwatch(&directory)?;
+            }
+        }
+        info!("Unsubscribe from resource {}", path.display());
+        Ok(true)
+    }
+
+    fn start(peer: Peer<RoleServer>) -> notify::Result<Watching> {
+        let subscribed: Arc<Mutex<HashMap<PathBuf, String>>> = Arc::default();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+        // The watcher calls back on its own thread, the notifications are sent from a task
+        let watched = subscribed.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => event,
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("Resource watcher error: {e}");
+                    return;
+                }
+            };
+            let watched = watched.lock().unwrap();
+            for path in &event.paths {
+                if let Some(uri) = watched.get(path) {
+                    let _ = tx.send(uri.clone());
+                }
+            }
+        })?;
+
+        // Stops when the watcher and with it the sender are dropped
+        tokio::spawn(async move {
+            while let Some(uri) = rx.recv().await {
+                // one notification per burst of events
+                let mut uris = vec![uri];
+                while let Ok(uri) = rx.try_recv() {
+                    if !uris.contains(&uri) {
+                        uris.push(uri);
+                    }
+                }
+                for uri in uris {
+                    if let Err(e) = peer
+                        .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                        .await
+                    {
+                        warn!("Failed to send resource update: {e:?}");
+                    }
+                }
+            }
+        });
+
+        Ok(Watching {
+            watcher,
+            subscribed,
+            directories: HashMap::new(),
+        })
+    }
+}
+
+// Files below the resource directories that pass the read policy, sorted by path
+pub fn list_files(directories: &[PathBuf], policy: &PathPolicy, limit: usize) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for directory in directories {
+        let Ok(directory) = policy.check_read(directory) else {
+            warn!("Resource directory {} is not readable", directory.display());
+            continue;
+        };
+        let mut pending = vec![(directory, 1)];
+        while let Some((dir, depth)) = pending.pop() {
+            let Ok(read_dir) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in read_dir.flatten() {
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if policy.check_read(&path).is_err() {
+                    continue;
+                }
+                if file_type.is_dir() && depth < MAX_RESOURCE_DEPTH {
+                    pending.push((path, depth + 1));
+                } else if file_type.is_file() {
+                    if files.len() >= limit {
+                        files.sort();
+                        return files;
+                    }
+                    files.push(path);
+                }
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    files
+}
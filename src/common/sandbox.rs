@@ -0,0 +1,86 @@
+use std::{borrow::Cow, os::unix::process::CommandExt, process::Command};
+
+use landlock::{
+    ABI, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr, RulesetCreated,
+    RulesetCreatedAttr, RulesetError, path_beneath_rules,
+};
+use rmcp::model::{ErrorCode, ErrorData};
+use tracing::warn;
+
+use crate::common::config::Landlock;
+
+// The highest Landlock ABI we know how to use, older kernels get a best-effort subset
+const LANDLOCK_ABI: ABI = ABI::V2;
+
+#[derive(Debug, Clone)]
+pub struct LandlockSandbox {
+    read_only_paths: Vec<String>,
+    read_write_paths: Vec<String>,
+}
+
+impl LandlockSandbox {
+    pub fn new(config: &Landlock) -> Self {
+        LandlockSandbox {
+            read_only_paths: config.read_only_paths.clone(),
+            read_write_paths: config.read_write_paths.clone(),
+        }
+    }
+
+    fn build_ruleset(&self) -> Result<RulesetCreated, RulesetError> {
+        Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+            .create()?
+            .add_rules(path_beneath_rules(
+                &self.read_only_paths,
+                AccessFs::from_read(LANDLOCK_ABI),
+            ))?
+            .add_rules(path_beneath_rules(
+                &self.read_write_paths,
+                AccessFs::from_all(LANDLOCK_ABI),
+            ))
+    }
+
+    // Restrict the child process right before exec, the parent is never affected
+    pub fn apply(&self, cmd: &mut Command) -> Result<(), ErrorData> {
+        let mut ruleset = Some(self.build_ruleset().map_err(|e| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to build landlock ruleset: {e}")),
+            data: None,
+        })?);
+
+        // SAFETY: the closure only issues the landlock syscalls on an already prepared ruleset
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(ruleset) = ruleset.take() {
+                    ruleset
+                        .restrict_self()
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+// Check whether the running kernel supports Landlock at all
+pub fn landlock_supported() -> bool {
+    Ruleset::default()
+        .set_compatibility(CompatLevel::HardRequirement)
+        .handle_access(AccessFs::from_all(ABI::V1))
+        .and_then(|ruleset| ruleset.create())
+        .is_ok()
+}
+
+// Validate the landlock setup at startup, returns an error if it is required but missing
+pub fn check_landlock(config: &Landlock) -> anyhow::Result<()> {
+    if landlock_supported() {
+        return Ok(());
+    }
+    if config.landlock_required {
+        anyhow::bail!("Landlock is required by config but not supported by this kernel");
+    }
+    warn!("!!! Landlock is configured but NOT supported by this kernel !!!");
+    warn!("!!! Commands will run WITHOUT filesystem restriction !!!");
+    Ok(())
+}
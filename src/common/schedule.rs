@@ -0,0 +1,268 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rmcp::{
+    model::{CallToolResult, ErrorData},
+    serde_json::{self, Value},
+};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::common::config::Schedules;
+use crate::common::webhooks::{WebhookEvent, WebhookSender};
+
+// Finished schedules kept for list_schedules, the oldest are dropped first
+const MAX_FINISHED_SCHEDULES: usize = 100;
+// Per stream, longer output of a scheduled run is cut
+const MAX_SCHEDULE_OUTPUT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    Pending,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleInfo {
+    pub id: String,
+    pub command: String,
+    pub created_at: String, // RFC 3339
+    pub run_at: String,     // RFC 3339
+    pub status: ScheduleStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>, // the execute response of a finished run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Entry {
+    info: ScheduleInfo,
+    cancel: CancellationToken,
+}
+
+// One-shot commands to run later, shared by all sessions so a schedule outlives the
+// session that created it. Schedules are only kept in memory, a restart drops them.
+pub struct Scheduler {
+    max_pending: usize,
+    max_delay: Duration,
+    schedules: Mutex<BTreeMap<String, Entry>>,
+    webhooks: Option<Arc<WebhookSender>>,
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("max_pending", &self.max_pending)
+            .field("schedules", &self.schedules.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: &Schedules) -> Self {
+        Scheduler {
+            max_pending: config.max_pending,
+            max_delay: Duration::from_secs(config.max_delay_seconds),
+            schedules: Mutex::new(BTreeMap::new()),
+            webhooks: None,
+        }
+    }
+
+    // Send job_completed when a scheduled command finished
+    pub fn with_webhooks(mut self, webhooks: &Arc<WebhookSender>) -> Self {
+        self.webhooks = Some(webhooks.clone());
+        self
+    }
+
+    // Run `run` at `run_at`, the future is only polled once the time has come
+    pub fn add<F>(
+        self: &Arc<Self>,
+        command: String,
+        run_at: DateTime<Utc>,
+        run: F,
+    ) -> Result<ScheduleInfo, ErrorData>
+    where
+        F: Future<Output = Result<CallToolResult, ErrorData>> + Send + 'static,
+    {
+        let now = Utc::now();
+        let delay = (run_at - now).to_std().unwrap_or_default();
+        if delay > self.max_delay {
+            return Err(ErrorData::invalid_params(
+                format!(
+                    "run_at is more than the allowed {} seconds ahead",
+                    self.max_delay.as_secs()
+                ),
+                None,
+            ));
+        }
+
+        let mut schedules = self.schedules.lock().unwrap();
+        let pending = schedules
+            .values()
+            .filter(|entry| entry.info.status == ScheduleStatus::Pending)
+            .count();
+        if pending >= self.max_pending {
+            return Err(ErrorData::invalid_request(
+                format!(
+                    "There are already {pending} pending schedules, the limit is {}",
+                    self.max_pending
+                ),
+                None,
+            ));
+        }
+        Self::prune(&mut schedules);
+
+        let info = ScheduleInfo {
+            id: Uuid::new_v4().to_string(),
+            command,
+            created_at: now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            run_at: run_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            status: ScheduleStatus::Pending,
+            result: None,
+            error: None,
+        };
+        let cancel = CancellationToken::new();
+        schedules.insert(
+            info.id.clone(),
+            Entry {
+                info: info.clone(),
+                cancel: cancel.clone(),
+            },
+        );
+        drop(schedules);
+
+        let scheduler = self.clone();
+        let id = info.id.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = cancel.cancelled() => return,
+            }
+            scheduler.update(&id, |info| info.status = ScheduleStatus::Running);
+            info!("Run scheduled command {id}");
+            let outcome = run.await;
+            scheduler.update(&id, |info| match outcome {
+                Ok(result) => {
+                    info.status = if result.is_error == Some(true) {
+                        ScheduleStatus::Failed
+                    } else {
+                        ScheduleStatus::Finished
+                    };
+                    info.result = Some(summarize(&result));
+                }
+                Err(e) => {
+                    info.status = ScheduleStatus::Failed;
+                    info.error = Some(e.message.into_owned());
+                }
+            });
+            if let Some(webhooks) = &scheduler.webhooks
+                && let Some(info) = scheduler.get(&id)
+            {
+                webhooks.send(
+                    WebhookEvent::JobCompleted,
+                    serde_json::to_value(info).unwrap_or_default(),
+                );
+            }
+        });
+        info!("Schedule command {} at {}", info.id, info.run_at);
+        Ok(info)
+    }
+
+    pub fn list(&self) -> Vec<ScheduleInfo> {
+        let mut schedules: Vec<ScheduleInfo> = self
+            .schedules
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect();
+        schedules.sort_by(|a, b| a.run_at.cmp(&b.run_at));
+        schedules
+    }
+
+    // Only pending schedules can be cancelled, a running command is left alone
+    pub fn cancel(&self, id: &str) -> Result<ScheduleInfo, ErrorData> {
+        let mut schedules = self.schedules.lock().unwrap();
+        let entry = schedules
+            .get_mut(id)
+            .ok_or_else(|| ErrorData::invalid_params(format!("Unknown schedule {id}"), None))?;
+        if entry.info.status != ScheduleStatus::Pending {
+            return Err(ErrorData::invalid_request(
+                format!("Schedule {id} is not pending anymore"),
+                None,
+            ));
+        }
+        entry.cancel.cancel();
+        entry.info.status = ScheduleStatus::Cancelled;
+        info!("Cancel schedule {id}");
+        Ok(entry.info.clone())
+    }
+
+    fn get(&self, id: &str) -> Option<ScheduleInfo> {
+        let schedules = self.schedules.lock().unwrap();
+        schedules.get(id).map(|entry| entry.info.clone())
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut ScheduleInfo)) {
+        if let Some(entry) = self.schedules.lock().unwrap().get_mut(id) {
+            change(&mut entry.info);
+        }
+    }
+
+    fn prune(schedules: &mut BTreeMap<String, Entry>) {
+        let mut done: Vec<(String, String)> = schedules
+            .values()
+            .filter(|entry| {
+                !matches!(
+                    entry.info.status,
+                    ScheduleStatus::Pending | ScheduleStatus::Running
+                )
+            })
+            .map(|entry| (entry.info.run_at.clone(), entry.info.id.clone()))
+            .collect();
+        if done.len() < MAX_FINISHED_SCHEDULES {
+            return;
+        }
+        done.sort();
+        for (_, id) in &done[..done.len() + 1 - MAX_FINISHED_SCHEDULES] {
+            schedules.remove(id);
+        }
+    }
+}
+
+// The execute response of the run with stdout and stderr cut to a size worth keeping
+fn summarize(result: &CallToolResult) -> Value {
+    let text = result
+        .content
+        .first()
+        .and_then(|content| content.as_text())
+        .map(|text| text.text.clone())
+        .unwrap_or_default();
+    let Ok(mut value) = serde_json::from_str::<Value>(&text) else {
+        return Value::String(text);
+    };
+    for stream in ["stdout", "stderr"] {
+        if let Some(Value::String(output)) = value.get_mut(stream)
+            && output.len() > MAX_SCHEDULE_OUTPUT_BYTES
+        {
+            let mut end = MAX_SCHEDULE_OUTPUT_BYTES;
+            while !output.is_char_boundary(end) {
+                end -= 1;
+            }
+            output.truncate(end);
+            value[format!("{stream}_truncated")] = Value::Bool(true);
+        }
+    }
+    value
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use rmcp::{
+    model::{ErrorData, JsonObject, Tool},
+    serde_json::{self, Value},
+};
+use tracing::{error, warn};
+
+// Compiled input schemas of the tools, arguments are checked against them before
+// they are deserialized into the request types
+pub struct SchemaValidators {
+    by_tool: HashMap<String, jsonschema::Validator>,
+}
+
+impl SchemaValidators {
+    pub fn new(tools: &[Tool]) -> Self {
+        let mut by_tool = HashMap::new();
+        for tool in tools {
+            let schema = Value::Object(tool.input_schema.as_ref().clone());
+            match jsonschema::validator_for(&schema) {
+                Ok(validator) => {
+                    by_tool.insert(tool.name.to_string(), validator);
+                }
+                Err(e) => warn!("Input schema of {} does not compile: {e}", tool.name),
+            }
+        }
+        SchemaValidators { by_tool }
+    }
+
+    pub fn validate(&self, tool: &str, arguments: Option<&JsonObject>) -> Result<(), ErrorData> {
+        let Some(validator) = self.by_tool.get(tool) else {
+            return Ok(());
+        };
+        let arguments = Value::Object(arguments.cloned().unwrap_or_default());
+        let errors: Vec<Value> = validator
+            .iter_errors(&arguments)
+            .map(|e| {
+                serde_json::json!({
+                    "path": e.instance_path.to_string(),
+                    "message": e.to_string(),
+                })
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let summary = errors
+            .iter()
+            .map(|e| {
+                let path = e["path"].as_str().unwrap_or_default();
+                let message = e["message"].as_str().unwrap_or_default();
+                if path.is_empty() {
+                    message.to_string()
+                } else {
+                    format!("{path}: {message}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        error!("Invalid arguments for {tool}: {summary}");
+        Err(ErrorData::invalid_params(
+            format!("Invalid arguments for {tool}: {summary}"),
+            Some(serde_json::json!({ "errors": errors })),
+        ))
+    }
+}
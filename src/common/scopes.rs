@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use axum::http::request::Parts;
+use rmcp::{RoleServer, model::ErrorData, serde_json, service::RequestContext};
+use tracing::error;
+
+use crate::common::annotations::is_read_only_tool;
+use crate::common::config::OAuth;
+use crate::common::oauth::McpAccessToken;
+use crate::common::sudo::wildcard_match;
+
+// Listing tools and resources and calling the read-only tools
+pub const READ_SCOPE: &str = "mcp:read";
+// Calling any other tool, includes mcp:read
+pub const EXECUTE_SCOPE: &str = "mcp:execute";
+
+// Tools that need an OAuth scope on top of mcp:read or mcp:execute
+pub const TOOL_SCOPES: &[(&str, &str)] = &[("list_processes", "processes:read")];
+
+// The scopes a tool call needs. Read-only tools need mcp:read and all others
+// mcp:execute, unless [oauth.tool_scopes] names another scope for the tool.
+#[derive(Debug, Clone, Default)]
+pub struct ScopePolicy {
+    tool_scopes: BTreeMap<String, String>,
+}
+
+impl ScopePolicy {
+    pub fn new(config: &OAuth) -> Self {
+        ScopePolicy {
+            tool_scopes: config.tool_scopes.clone(),
+        }
+    }
+
+    pub fn required_scopes(&self, tool: &str) -> Vec<&str> {
+        // an exact entry wins over a pattern like "unix_preset_*"
+        let configured = self.tool_scopes.get(tool).or_else(|| {
+            self.tool_scopes
+                .iter()
+                .find(|(pattern, _)| wildcard_match(pattern, tool))
+                .map(|(_, scope)| scope)
+        });
+        let base = match configured {
+            Some(scope) => scope.as_str(),
+            None if is_read_only_tool(tool) => READ_SCOPE,
+            None => EXECUTE_SCOPE,
+        };
+        let mut scopes = vec![base];
+        scopes.extend(
+            TOOL_SCOPES
+                .iter()
+                .filter(|(name, _)| *name == tool)
+                .map(|(_, scope)| *scope),
+        );
+        scopes
+    }
+
+    // The first scope of the tool the token does not have
+    pub fn missing_scope(&self, token: &McpAccessToken, tool: &str) -> Option<&str> {
+        self.required_scopes(tool)
+            .into_iter()
+            .find(|scope| !has_scope(token, scope))
+    }
+
+    // Calls without a token (stdio, or auth disabled) are not restricted
+    pub fn check(&self, token: Option<&McpAccessToken>, tool: &str) -> Result<(), ErrorData> {
+        let Some(token) = token else {
+            return Ok(());
+        };
+        let Some(scope) = self.missing_scope(token, tool) else {
+            return Ok(());
+        };
+        error!(
+            "Client {} called {tool} without scope {scope}",
+            token.client_id
+        );
+        Err(ErrorData::invalid_request(
+            format!("{tool} requires the OAuth scope {scope}"),
+            Some(serde_json::json!({
+                "error": "insufficient_scope",
+                "required_scope": scope,
+            })),
+        ))
+    }
+}
+
+// The scope of the access token is a space separated list
+pub fn has_scope(token: &McpAccessToken, scope: &str) -> bool {
+    token.scope.as_deref().is_some_and(|scopes| {
+        scopes
+            .split_whitespace()
+            .any(|s| s == scope || (s == EXECUTE_SCOPE && scope == READ_SCOPE))
+    })
+}
+
+// The access token of the HTTP request behind a call, None without auth
+pub fn access_token(context: &RequestContext<RoleServer>) -> Option<&McpAccessToken> {
+    context
+        .extensions
+        .get::<Parts>()
+        .and_then(|parts| parts.extensions.get::<McpAccessToken>())
+}
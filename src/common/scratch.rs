@@ -0,0 +1,113 @@
+use std::{
+    env,
+    fs::{self, DirBuilder, File},
+    io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// Default size limit of a session scratch directory
+pub const DEFAULT_SCRATCH_QUOTA_BYTES: u64 = 100 * 1024 * 1024;
+
+// A private temporary directory for one MCP session, removed when the session ends
+#[derive(Debug)]
+pub struct ScratchDir {
+    path: PathBuf,
+    quota_bytes: u64,
+}
+
+impl ScratchDir {
+    pub fn new(quota_bytes: u64) -> Self {
+        ScratchDir {
+            path: env::temp_dir().join(format!("mcp-bash-server-{}", Uuid::new_v4())),
+            quota_bytes,
+        }
+    }
+
+    // The directory is only created on first use
+    pub fn ensure(&self) -> io::Result<&Path> {
+        if !self.path.exists() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&self.path)?;
+            info!("Create scratch directory {}", self.path.display());
+        }
+        Ok(&self.path)
+    }
+
+    pub fn create_file(&self, name: &str) -> io::Result<(PathBuf, File)> {
+        let path = self.ensure()?.join(name);
+        let file = File::create(&path)?;
+        Ok((path, file))
+    }
+
+    pub fn quota_bytes(&self) -> u64 {
+        self.quota_bytes
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        dir_size(&self.path)
+    }
+
+    pub fn remaining_bytes(&self) -> u64 {
+        self.quota_bytes.saturating_sub(self.used_bytes())
+    }
+
+    // Shrink the given files until the directory fits in the quota again,
+    // return true if anything had to be truncated
+    pub fn enforce_quota(&self, files: &[&Path]) -> io::Result<bool> {
+        let mut over = self.used_bytes().saturating_sub(self.quota_bytes);
+        let truncated = over > 0;
+        for path in files {
+            if over == 0 {
+                break;
+            }
+            let size = fs::metadata(path)?.len();
+            let cut = size.min(over);
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_len(size - cut)?;
+            over -= cut;
+        }
+        if truncated {
+            warn!(
+                "Scratch directory {} exceeded its quota of {} bytes",
+                self.path.display(),
+                self.quota_bytes
+            );
+        }
+        Ok(truncated)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.path.exists()
+            && let Err(e) = fs::remove_dir_all(&self.path)
+        {
+            warn!(
+                "Failed to remove scratch directory {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use rmcp::{
+    RoleServer,
+    model::{LoggingLevel, LoggingMessageNotificationParam},
+    serde_json::{self, Value},
+    service::Peer,
+};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::common::log_forward::severity;
+use crate::common::webhooks::{WebhookEvent, WebhookSender};
+
+// Keep track of the live MCP sessions so the server can drain them on shutdown
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    accepting: AtomicBool,
+    sessions: Mutex<HashMap<u64, Option<Peer<RoleServer>>>>,
+    // logging level requested by each session, info if it never set one
+    log_levels: Mutex<HashMap<u64, LoggingLevel>>,
+    drained: Notify,
+    webhooks: Option<Arc<WebhookSender>>,
+}
+
+impl fmt::Debug for SessionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionRegistry")
+            .field("accepting", &self.is_accepting())
+            .field("active", &self.len())
+            .finish()
+    }
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            accepting: AtomicBool::new(true),
+            sessions: Mutex::new(HashMap::new()),
+            log_levels: Mutex::new(HashMap::new()),
+            drained: Notify::new(),
+            webhooks: None,
+        }
+    }
+
+    // Send session_closed when a session goes away
+    pub fn with_webhooks(mut self, webhooks: &Arc<WebhookSender>) -> Self {
+        self.webhooks = Some(webhooks.clone());
+        self
+    }
+
+    // Register a new session, the session is removed when the returned handle is dropped
+    pub fn register(self: &Arc<Self>) -> SessionHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.sessions.lock().unwrap().insert(id, None);
+        SessionHandle {
+            id,
+            registry: self.clone(),
+        }
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Tell every connected client that the server is going away
+    pub async fn notify_shutdown(&self) {
+        let peers: Vec<Peer<RoleServer>> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        for peer in peers {
+            let params = LoggingMessageNotificationParam {
+                level: LoggingLevel::Warning,
+                logger: Some("Server".to_string()),
+                data: Value::String(
+                    "server is shutting down, please finish and close the session".to_string(),
+                ),
+            };
+            if let Err(e) = peer.notify_logging_message(params).await {
+                warn!("Failed to send shutdown notification: {e:?}");
+            }
+        }
+    }
+
+    // Tell every connected client to fetch the prompt list again
+    pub async fn notify_prompt_list_changed(&self) {
+        let peers: Vec<Peer<RoleServer>> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        for peer in peers {
+            if let Err(e) = peer.notify_prompt_list_changed().await {
+                warn!("Failed to send prompt list change: {e:?}");
+            }
+        }
+    }
+
+    // The peers of the sessions that want log messages of this level
+    pub fn log_subscribers(&self, level: LoggingLevel) -> Vec<Peer<RoleServer>> {
+        let log_levels = self.log_levels.lock().unwrap();
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| {
+                let wanted = log_levels.get(id).copied().unwrap_or(LoggingLevel::Info);
+                severity(level) >= severity(wanted)
+            })
+            .filter_map(|(_, peer)| peer.clone())
+            .collect()
+    }
+
+    // Wait until every session is gone or the timeout elapsed, return true if drained
+    pub async fn wait_for_drain(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let notified = self.drained.notified();
+            if self.is_empty() {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return self.is_empty();
+            }
+        }
+    }
+
+    fn attach_peer(&self, id: u64, peer: Peer<RoleServer>) {
+        if let Some(slot) = self.sessions.lock().unwrap().get_mut(&id) {
+            *slot = Some(peer);
+        }
+    }
+
+    fn set_log_level(&self, id: u64, level: LoggingLevel) {
+        self.log_levels.lock().unwrap().insert(id, level);
+    }
+
+    fn remove(&self, id: u64) {
+        self.log_levels.lock().unwrap().remove(&id);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.remove(&id);
+        info!(
+            "MCP session {id} closed, {} sessions active",
+            sessions.len()
+        );
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.send(
+                WebhookEvent::SessionClosed,
+                serde_json::json!({ "session_id": id, "active_sessions": sessions.len() }),
+            );
+        }
+        if sessions.is_empty() {
+            self.drained.notify_waiters();
+        }
+    }
+}
+
+// Handle owned by a BashServer instance, one per MCP session
+#[derive(Debug)]
+pub struct SessionHandle {
+    id: u64,
+    registry: Arc<SessionRegistry>,
+}
+
+impl SessionHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn attach_peer(&self, peer: Peer<RoleServer>) {
+        self.registry.attach_peer(self.id, peer);
+    }
+
+    pub fn set_log_level(&self, level: LoggingLevel) {
+        self.registry.set_log_level(self.id, level);
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.registry.remove(self.id);
+    }
+}
@@ -0,0 +1,137 @@
+// Which grant opened each MCP session. A request naming the Mcp-Session-Id of another
+// grant's session is refused with 403, the refreshed tokens of the same grant keep it.
+// The grant of an upstream token is its sub, so one user can't use the session of another.
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use tracing::warn;
+
+use crate::common::audit::{AuditEvent, Outcome, peer_ip};
+use crate::common::oauth::McpAccessToken;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+pub struct SessionBindings {
+    owners: Mutex<HashMap<String, String>>, // session id to client and grant
+    session_manager: Arc<LocalSessionManager>, // bindings of sessions gone from it are dropped
+}
+
+impl fmt::Debug for SessionBindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionBindings")
+            .field("bound", &self.owners.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl SessionBindings {
+    pub fn new(session_manager: Arc<LocalSessionManager>) -> Self {
+        SessionBindings {
+            owners: Mutex::new(HashMap::new()),
+            session_manager,
+        }
+    }
+
+    // The owner of a live session, none for unknown, closed and expired ones
+    async fn owner(&self, session_id: &str) -> Option<String> {
+        let owner = self.owners.lock().unwrap().get(session_id).cloned()?;
+        if self
+            .session_manager
+            .sessions
+            .read()
+            .await
+            .contains_key(session_id)
+        {
+            Some(owner)
+        } else {
+            self.owners.lock().unwrap().remove(session_id);
+            None
+        }
+    }
+
+    // Sessions that expired since the last one was opened are forgotten on the way
+    async fn bind(&self, session_id: &str, owner: String) {
+        let live = self.session_manager.sessions.read().await;
+        let mut owners = self.owners.lock().unwrap();
+        owners.retain(|id, _| live.contains_key(id.as_str()));
+        owners.insert(session_id.to_string(), owner);
+    }
+
+    fn unbind(&self, session_id: &str) {
+        self.owners.lock().unwrap().remove(session_id);
+    }
+}
+
+fn owner_of(token: &McpAccessToken) -> String {
+    format!("{}/{}", token.client_id, token.grant_id)
+}
+
+// Behind the auth middleware, which leaves the token in the extensions. Without one, in
+// auth_mode = "none", sessions are not bound.
+pub async fn session_binding_middleware(
+    State(bindings): State<Arc<SessionBindings>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(token) = request.extensions().get::<McpAccessToken>().cloned() else {
+        return next.run(request).await;
+    };
+    let owner = owner_of(&token);
+    let session_id = request
+        .headers()
+        .get(SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let Some(session_id) = session_id else {
+        // an initialize request, the new session comes back in the header
+        let response = next.run(request).await;
+        if let Some(session_id) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            bindings.bind(session_id, owner).await;
+        }
+        return response;
+    };
+
+    if bindings
+        .owner(&session_id)
+        .await
+        .is_some_and(|bound| bound != owner)
+    {
+        warn!(
+            "client {} used session {} of another grant",
+            token.client_id, session_id
+        );
+        AuditEvent::new("session_rejected", Outcome::Denied)
+            .client(&token.client_id)
+            .ip(peer_ip(request.extensions()))
+            .token(&token.access_token)
+            .reason("the session was opened with the token of another grant")
+            .emit();
+        return (
+            StatusCode::FORBIDDEN,
+            "the session belongs to another token",
+        )
+            .into_response();
+    }
+    let closing = request.method() == Method::DELETE;
+    let response = next.run(request).await;
+    if closing && response.status().is_success() {
+        bindings.unbind(&session_id);
+    }
+    response
+}
@@ -0,0 +1,156 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rmcp::{
+    model::{ErrorCode, ErrorData},
+    serde_json,
+};
+use tracing::error;
+
+use crate::common::config::Bash;
+
+const DEFAULT_SHELL: &str = "/bin/bash";
+const SHELLS_FILE: &str = "/etc/shells";
+
+// Decide which shell binary runs the commands
+#[derive(Debug, Clone)]
+pub struct ShellSelector {
+    default_shell: PathBuf,
+    allowed_shells: Vec<PathBuf>,
+}
+
+impl Default for ShellSelector {
+    fn default() -> Self {
+        ShellSelector {
+            default_shell: PathBuf::from(DEFAULT_SHELL),
+            allowed_shells: read_etc_shells(),
+        }
+    }
+}
+
+impl ShellSelector {
+    pub fn new(config: &Bash) -> Self {
+        let allowed_shells = if config.allowed_shells.is_empty() {
+            read_etc_shells()
+        } else {
+            config.allowed_shells.clone()
+        };
+        ShellSelector {
+            default_shell: config
+                .shell
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_SHELL)),
+            allowed_shells,
+        }
+    }
+
+    // Pick the requested shell or the default one, the result is an existing allowed binary.
+    // A bare name like "zsh" selects the allowed shell with that file name.
+    pub fn select(&self, requested: Option<&str>) -> Result<PathBuf, ErrorData> {
+        let shell = match requested {
+            None => self.default_shell.clone(),
+            Some(name) if !name.contains('/') => self
+                .allowed_shells
+                .iter()
+                .find(|shell| {
+                    shell.file_name().and_then(|n| n.to_str()) == Some(name) && is_file(shell)
+                })
+                .cloned()
+                .ok_or_else(|| self.rejected(name, "no allowed shell with this name"))?,
+            Some(path) => PathBuf::from(path),
+        };
+
+        // the configured default is trusted, overrides must be in the allowlist
+        if requested.is_some() && !self.allowed_shells.contains(&shell) {
+            return Err(self.rejected(&shell.to_string_lossy(), "shell is not in the allowlist"));
+        }
+        if !is_file(&shell) {
+            return Err(self.rejected(&shell.to_string_lossy(), "shell binary does not exist"));
+        }
+        Ok(shell)
+    }
+
+    fn rejected(&self, shell: &str, reason: &str) -> ErrorData {
+        error!("Reject shell {shell}: {reason}");
+        ErrorData {
+            code: ErrorCode::INVALID_PARAMS,
+            message: Cow::Owned(format!("Can not use shell {shell}: {reason}")),
+            data: Some(serde_json::json!({ "allowed_shells": self.allowed_shells })),
+        }
+    }
+}
+
+fn is_file(path: &Path) -> bool {
+    fs::metadata(path).map(|m| m.is_file()).unwrap_or(false)
+}
+
+fn read_etc_shells() -> Vec<PathBuf> {
+    fs::read_to_string(SHELLS_FILE)
+        .map(|content| {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// What runs before each shell command: /etc/profile with load_profile, then the
+// profile_script. Powershell has neither.
+pub fn profile_snippets(config: &Bash) -> Vec<String> {
+    if cfg!(target_os = "windows") {
+        return Vec::new();
+    }
+    let mut snippets = Vec::new();
+    if config.load_profile {
+        snippets.push(". /etc/profile".to_string());
+    }
+    if let Some(script) = config.profile_script.as_deref().map(str::trim)
+        && !script.is_empty()
+    {
+        snippets.push(script.to_string());
+    }
+    snippets
+}
+
+// Wrap in single quotes, an embedded quote closes the string, adds an escaped quote
+// and opens it again
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// Replace every {N} in the command by the quoted args[N], other braces are left alone
+pub fn interpolate_args(command: &str, args: &[String]) -> Result<String, ErrorData> {
+    let mut result = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || !after[digits..].starts_with('}') {
+            result.push('{');
+            rest = after;
+            continue;
+        }
+        let index: usize = after[..digits].parse().unwrap_or(usize::MAX);
+        let arg = args.get(index).ok_or_else(|| {
+            ErrorData::invalid_params(
+                format!(
+                    "Placeholder {{{}}} has no argument, {} args were given",
+                    &after[..digits],
+                    args.len()
+                ),
+                None,
+            )
+        })?;
+        result.push_str(&shell_quote(arg));
+        rest = &after[digits + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
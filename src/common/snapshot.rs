@@ -0,0 +1,82 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use chrono::{SecondsFormat, Utc};
+use rmcp::{
+    model::{ErrorCode, ErrorData},
+    serde_json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::common::history::HistoryEntry;
+
+// Raised whenever the format changes, older snapshots stay readable. Fields added in
+// later versions need a serde default.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// The state of one session as returned by snapshot_session. Commands have no session
+// directory of their own, each call names its working_dir.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub version: u32,
+    #[serde(default)]
+    pub created_at: String, // RFC 3339
+    #[serde(default)]
+    pub env: BTreeMap<String, String>, // set with set_session_env
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub jobs: Vec<JobSnapshot>,
+}
+
+// A pty session, the process itself does not survive a restore
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSnapshot {
+    pub pty_session_id: String,
+    pub exited: bool,
+    pub exit_code: Option<u32>,
+    #[serde(default)]
+    pub output: String, // not yet read with pty_read
+    #[serde(default)]
+    pub restored: bool, // from an earlier snapshot, the process is gone
+}
+
+impl SessionSnapshot {
+    pub fn new(
+        env: BTreeMap<String, String>,
+        history: Vec<HistoryEntry>,
+        jobs: Vec<JobSnapshot>,
+    ) -> Self {
+        SessionSnapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            env,
+            history,
+            jobs,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, ErrorData> {
+        serde_json::to_string(self).map_err(|e| ErrorData {
+            code: ErrorCode::INTERNAL_ERROR,
+            message: Cow::Owned(format!("Failed to serialize the snapshot: {e}")),
+            data: None,
+        })
+    }
+
+    // Fields unknown to this version are ignored, a newer version is refused
+    pub fn parse(text: &str) -> Result<Self, ErrorData> {
+        let snapshot: SessionSnapshot = serde_json::from_str(text).map_err(|e| {
+            ErrorData::invalid_params(format!("Invalid session snapshot: {e}"), None)
+        })?;
+        if snapshot.version == 0 || snapshot.version > SNAPSHOT_VERSION {
+            return Err(ErrorData::invalid_params(
+                format!(
+                    "Session snapshot version {} is not supported, this server reads up to version {SNAPSHOT_VERSION}",
+                    snapshot.version
+                ),
+                None,
+            ));
+        }
+        Ok(snapshot)
+    }
+}
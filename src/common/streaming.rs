@@ -0,0 +1,137 @@
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{ChildStderr, ChildStdout},
+    sync::mpsc,
+};
+
+use crate::common::progress::ProgressReporter;
+
+// When buffered output is sent as a progress notification, whichever comes first
+#[derive(Debug, Clone, Copy)]
+pub struct StreamSettings {
+    pub flush_bytes: usize,
+    pub flush_interval: Duration,
+}
+
+impl Default for StreamSettings {
+    fn default() -> Self {
+        StreamSettings {
+            flush_bytes: 4096,
+            flush_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// Output of one stream, the complete capture and what was not reported yet
+#[derive(Default)]
+struct Collected {
+    all: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+// Read stdout and stderr of a child until both are closed. While reading, the
+// output is forwarded in chunks as progress notifications if the client asked for them.
+pub async fn collect_output(
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    mut progress: Option<ProgressReporter>,
+    settings: StreamSettings,
+) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let (tx, mut rx) = mpsc::channel(64);
+    if let Some(stdout) = stdout {
+        tokio::spawn(read_chunks(stdout, Stream::Stdout, tx.clone()));
+    }
+    if let Some(stderr) = stderr {
+        tokio::spawn(read_chunks(stderr, Stream::Stderr, tx.clone()));
+    }
+    // the channel closes once both readers are done
+    drop(tx);
+
+    let streaming = progress.as_ref().is_some_and(|p| p.is_enabled());
+    let (mut out, mut err) = (Collected::default(), Collected::default());
+    let mut ticker = tokio::time::interval(settings.flush_interval);
+    loop {
+        tokio::select! {
+            chunk = rx.recv() => {
+                let Some((stream, chunk)) = chunk else {
+                    break;
+                };
+                let chunk: Vec<u8> = chunk?;
+                let collected = match stream {
+                    Stream::Stdout => &mut out,
+                    Stream::Stderr => &mut err,
+                };
+                collected.all.extend_from_slice(&chunk);
+                if streaming {
+                    collected.pending.extend_from_slice(&chunk);
+                    if collected.pending.len() >= settings.flush_bytes {
+                        flush(&mut progress, stream, collected, false).await;
+                    }
+                }
+            }
+            _ = ticker.tick(), if streaming => {
+                flush(&mut progress, Stream::Stdout, &mut out, false).await;
+                flush(&mut progress, Stream::Stderr, &mut err, false).await;
+            }
+        }
+    }
+    flush(&mut progress, Stream::Stdout, &mut out, true).await;
+    flush(&mut progress, Stream::Stderr, &mut err, true).await;
+    Ok((out.all, err.all))
+}
+
+async fn read_chunks(
+    mut reader: impl AsyncRead + Unpin,
+    stream: Stream,
+    tx: mpsc::Sender<(Stream, io::Result<Vec<u8>>)>,
+) {
+    let mut buf = vec![0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                if tx.send((stream, Ok(buf[..n].to_vec()))).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let _ = tx.send((stream, Err(e))).await;
+                break;
+            }
+        }
+    }
+}
+
+async fn flush(
+    progress: &mut Option<ProgressReporter>,
+    stream: Stream,
+    collected: &mut Collected,
+    last: bool,
+) {
+    let Some(progress) = progress else {
+        return;
+    };
+    // keep a multi-byte character cut by the read for the next chunk
+    let end = match std::str::from_utf8(&collected.pending) {
+        Err(e) if !last && e.error_len().is_none() => e.valid_up_to(),
+        _ => collected.pending.len(),
+    };
+    if end == 0 {
+        return;
+    }
+    let chunk: Vec<u8> = collected.pending.drain(..end).collect();
+    let chunk = String::from_utf8_lossy(&chunk).into_owned();
+    let message = match stream {
+        Stream::Stdout => chunk,
+        Stream::Stderr => format!("[stderr] {chunk}"),
+    };
+    progress.report(message).await;
+}
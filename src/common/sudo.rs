@@ -0,0 +1,400 @@
+use std::borrow::Cow;
+
+use rmcp::{
+    model::{ErrorCode, ErrorData},
+    serde_json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::common::config::Security;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SudoMode {
+    #[default]
+    Deny,
+    AllowListed,
+    AllowAll,
+}
+
+// Programs that elevate the rest of the command line
+const ELEVATORS: [&str; 2] = ["sudo", "doas"];
+// Options of sudo/doas that take a separate value
+const OPTIONS_WITH_VALUE: [&str; 9] = ["-u", "-g", "-p", "-C", "-D", "-r", "-t", "-U", "-h"];
+// Words in front of a program that still leave it at the command position
+const PREFIX_WORDS: [&str; 14] = [
+    "env", "exec", "nohup", "time", "command", "builtin", "!", "{", "if", "then", "elif", "else",
+    "while", "do",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct SudoPolicy {
+    mode: SudoMode,
+    allowed_commands: Vec<String>,
+    // programs that may be elevated at all, checked before the mode
+    allowed_programs: Vec<String>,
+}
+
+// Where the elevation happens inside a command string
+struct Elevation<'a> {
+    program: &'a str,
+    program_end: usize,
+    command: String,
+}
+
+impl SudoPolicy {
+    pub fn new(config: &Security) -> Self {
+        SudoPolicy {
+            mode: config.sudo.mode,
+            allowed_commands: config.sudo.allowed_commands.clone(),
+            allowed_programs: config.sudo_allowed_commands.clone(),
+        }
+    }
+
+    pub fn mode(&self) -> SudoMode {
+        self.mode
+    }
+
+    // Check the command against the policy, return the command to run.
+    // Every sudo/doas of the command line is checked, after ; && || | ( $( and backticks too.
+    // Elevated commands are rewritten to run non-interactively so they fail instead of prompting.
+    pub fn enforce(&self, command: &str) -> Result<String, ErrorData> {
+        let Some(segments) = segments(command) else {
+            if self.restricted() && mentions(command) > 0 {
+                return Err(self.unparsed(command));
+            }
+            return Ok(command.to_string());
+        };
+        let elevations: Vec<Elevation> = segments
+            .iter()
+            .filter_map(|&segment| find_elevation(command, segment))
+            .collect();
+        // a sudo anywhere else may still run, e.g. through `xargs sudo` or `bash -c "sudo .."`
+        let mentioned: usize = segments
+            .iter()
+            .map(|&(start, end)| mentions(&command[start..end]))
+            .sum();
+        if mentioned > elevations.len() && self.restricted() {
+            return Err(self.unparsed(command));
+        }
+
+        for elevation in &elevations {
+            self.check(elevation)?;
+        }
+        let mut rewritten = command.to_string();
+        for elevation in elevations.iter().rev() {
+            info!(
+                "Allow {} for command: {}",
+                elevation.program, elevation.command
+            );
+            rewritten.insert_str(elevation.program_end, " -n");
+        }
+        Ok(rewritten)
+    }
+
+    // Whether the policy refuses any elevation at all
+    fn restricted(&self) -> bool {
+        self.mode != SudoMode::AllowAll || !self.allowed_programs.is_empty()
+    }
+
+    fn check(&self, elevation: &Elevation) -> Result<(), ErrorData> {
+        // The programs are checked first, a listed one still has to pass the mode
+        if !self.allowed_programs.is_empty() {
+            let program = base_program(&elevation.command);
+            if !self
+                .allowed_programs
+                .iter()
+                .any(|allowed| allowed == program)
+            {
+                error!(
+                    "Denied {} for command: {}",
+                    elevation.program, elevation.command
+                );
+                return Err(ErrorData {
+                    code: ErrorCode::INVALID_REQUEST,
+                    message: Cow::Owned(format!(
+                        "`{program}` may not run with {}, security.sudo_allowed_commands only allows {:?}",
+                        elevation.program, self.allowed_programs
+                    )),
+                    data: Some(serde_json::json!({
+                        "error": "sudo_not_allowed",
+                        "command": program,
+                        "sudo_allowed_commands": self.allowed_programs,
+                    })),
+                });
+            }
+        }
+
+        match self.mode {
+            SudoMode::Deny => {
+                return Err(self.denied(
+                    elevation.program,
+                    &elevation.command,
+                    format!(
+                        "{} is denied by policy (security.sudo.mode = \"deny\"), do not retry with elevation",
+                        elevation.program
+                    ),
+                ));
+            }
+            SudoMode::AllowListed => {
+                if !self
+                    .allowed_commands
+                    .iter()
+                    .any(|pattern| wildcard_match(pattern, &elevation.command))
+                {
+                    return Err(self.denied(
+                        elevation.program,
+                        &elevation.command,
+                        format!(
+                            "`{}` is not in security.sudo.allowed_commands, only these may run with {}: {:?}",
+                            elevation.command, elevation.program, self.allowed_commands
+                        ),
+                    ));
+                }
+            }
+            SudoMode::AllowAll => {}
+        }
+        Ok(())
+    }
+
+    fn unparsed(&self, command: &str) -> ErrorData {
+        self.denied(
+            "sudo",
+            command,
+            "sudo/doas is used where the policy can't check it, run the elevated command on its own like `sudo <command>`".to_string(),
+        )
+    }
+
+    fn denied(&self, program: &str, command: &str, message: String) -> ErrorData {
+        error!("Denied {program} for command: {command}");
+        ErrorData::invalid_request(
+            message,
+            Some(serde_json::json!({
+                "policy": self.mode,
+                "allowed_commands": self.allowed_commands,
+            })),
+        )
+    }
+}
+
+// Split a command line into its simple commands, the byte ranges between ; & | and
+// newlines and those inside ( ), $( ) and backticks, also within double quotes. Comments
+// are left out. None when the quotes or parentheses don't balance.
+fn segments(command: &str) -> Option<Vec<(usize, usize)>> {
+    #[derive(PartialEq)]
+    enum Context {
+        Paren,
+        Backtick,
+        Single,
+        Double,
+    }
+    let bytes = command.as_bytes();
+    let mut stack = Vec::new();
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let boundary = match (stack.last(), bytes[i]) {
+            (Some(Context::Single), b'\'') => {
+                stack.pop();
+                false
+            }
+            (Some(Context::Single), _) => false,
+            (_, b'\\') => {
+                i += 1;
+                false
+            }
+            (Some(Context::Double), b'"') => {
+                stack.pop();
+                false
+            }
+            (_, b'$') if bytes.get(i + 1) == Some(&b'(') => {
+                stack.push(Context::Paren);
+                i += 1;
+                true
+            }
+            (_, b'`') => {
+                if stack.last() == Some(&Context::Backtick) {
+                    stack.pop();
+                } else {
+                    stack.push(Context::Backtick);
+                }
+                true
+            }
+            (Some(Context::Double), _) => false,
+            (_, b'\'') => {
+                stack.push(Context::Single);
+                false
+            }
+            (_, b'"') => {
+                stack.push(Context::Double);
+                false
+            }
+            (_, b'(') => {
+                stack.push(Context::Paren);
+                true
+            }
+            (_, b')') => {
+                if stack.pop() != Some(Context::Paren) {
+                    return None;
+                }
+                true
+            }
+            (_, b';' | b'|' | b'\n') => true,
+            // not the & of redirections like 2>&1 or &>file
+            (_, b'&') => {
+                !matches!(bytes.get(i.wrapping_sub(1)), Some(b'<' | b'>'))
+                    && bytes.get(i + 1) != Some(&b'>')
+            }
+            (_, b'#') if i == start || bytes[i - 1].is_ascii_whitespace() => {
+                segments.push((start, i));
+                i = command[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                start = i;
+                continue;
+            }
+            _ => false,
+        };
+        if boundary {
+            segments.push((start, i));
+            start = i + 1;
+        }
+        i += 1;
+    }
+    if !stack.is_empty() {
+        return None;
+    }
+    segments.push((start, bytes.len()));
+    Some(segments)
+}
+
+// How often sudo/doas appears as a word, quotes and escapes removed as the shell does
+fn mentions(text: &str) -> usize {
+    let text: String = text
+        .chars()
+        .filter(|c| !matches!(c, '\\' | '\'' | '"'))
+        .collect();
+    text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        .filter(|word| ELEVATORS.contains(word))
+        .count()
+}
+
+// Find sudo/doas at the front of a simple command of the command line, after env
+// assignments like `FOO=bar` and words like `env` or `then`
+fn find_elevation(command: &str, (offset, segment_end): (usize, usize)) -> Option<Elevation<'_>> {
+    let segment = &command[offset..segment_end];
+    let tokens = tokenize(segment);
+    let mut index = 0;
+    while let Some(&(start, end)) = tokens.get(index) {
+        let token = &segment[start..end];
+        if !PREFIX_WORDS.contains(&token) && !is_env_assignment(token) {
+            break;
+        }
+        index += 1;
+    }
+
+    let &(start, end) = tokens.get(index)?;
+    let program: String = segment[start..end]
+        .chars()
+        .filter(|c| !matches!(c, '\\' | '\'' | '"'))
+        .collect();
+    let program = program.rsplit('/').next().unwrap_or_default();
+    let program = *ELEVATORS.iter().find(|&&elevator| elevator == program)?;
+
+    // Skip the options of sudo itself to find the elevated command
+    index += 1;
+    while let Some(&(start, end)) = tokens.get(index) {
+        let token = &segment[start..end];
+        if token == "--" {
+            index += 1;
+            break;
+        }
+        if !token.starts_with('-') {
+            break;
+        }
+        index += if OPTIONS_WITH_VALUE.contains(&token) {
+            2
+        } else {
+            1
+        };
+    }
+    let elevated = tokens
+        .get(index)
+        .map(|&(start, _)| segment[start..].trim().to_string())
+        .unwrap_or_default();
+
+    Some(Elevation {
+        program,
+        program_end: offset + end,
+        command: elevated,
+    })
+}
+
+// The program of an elevated command without its directory, `/usr/bin/systemctl` is `systemctl`
+fn base_program(command: &str) -> &str {
+    let Some(&(start, end)) = tokenize(command).first() else {
+        return "";
+    };
+    let program = command[start..end].trim_matches(|c| c == '\'' || c == '"');
+    program.rsplit('/').next().unwrap_or_default()
+}
+
+fn is_env_assignment(token: &str) -> bool {
+    match token.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+// Split on whitespace outside of quotes, return the byte ranges of the tokens
+fn tokenize(command: &str) -> Vec<(usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    for (i, c) in command.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c.is_whitespace() => {
+                if let Some(s) = start.take() {
+                    tokens.push((s, i));
+                }
+                continue;
+            }
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None => {}
+        }
+        if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s, command.len()));
+    }
+    tokens
+}
+
+// Match a pattern where `*` matches any run of characters
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    // both ends are checked first, so the slice below starts and ends on char boundaries
+    if !text.starts_with(first) || !text.ends_with(last) || text.len() < first.len() + last.len() {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
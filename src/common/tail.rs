@@ -0,0 +1,105 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+// How much of the end of the file is scanned for the initial lines
+const INITIAL_SCAN_BYTES: u64 = 64 * 1024;
+
+// What happened to the followed file between two polls
+#[derive(Debug, Default)]
+pub struct TailPoll {
+    pub lines: Vec<String>,
+    pub rotated: bool,
+    pub truncated: bool,
+}
+
+// Follow a file by polling, surviving truncation and replacement (log rotation)
+pub struct FileFollower {
+    path: PathBuf,
+    file: File,
+    inode: u64,
+    position: u64,
+    partial: Vec<u8>,
+}
+
+impl FileFollower {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        Ok(FileFollower {
+            path: path.to_path_buf(),
+            file,
+            inode: meta.ino(),
+            position: meta.len(),
+            partial: Vec::new(),
+        })
+    }
+
+    // The last `count` complete lines currently in the file
+    pub fn last_lines(&mut self, count: usize) -> io::Result<Vec<String>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let start = self.position.saturating_sub(INITIAL_SCAN_BYTES);
+        self.file.seek(SeekFrom::Start(start))?;
+        let mut bytes = Vec::new();
+        (&mut self.file)
+            .take(self.position - start)
+            .read_to_end(&mut bytes)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let mut lines: Vec<&str> = text.lines().collect();
+        // the first line is probably cut when we did not start at the beginning
+        if start > 0 && !lines.is_empty() {
+            lines.remove(0);
+        }
+        let skip = lines.len().saturating_sub(count);
+        Ok(lines[skip..].iter().map(|l| l.to_string()).collect())
+    }
+
+    // Read the lines appended since the last poll, at most `max_bytes`
+    pub fn poll(&mut self, max_bytes: u64) -> io::Result<TailPoll> {
+        let mut result = TailPoll::default();
+
+        match fs::metadata(&self.path) {
+            Ok(meta) if meta.ino() != self.inode => {
+                // The file was replaced, continue with the new one from its start
+                info!("Followed file {} was rotated", self.path.display());
+                self.file = File::open(&self.path)?;
+                self.inode = meta.ino();
+                self.position = 0;
+                self.partial.clear();
+                result.rotated = true;
+            }
+            Ok(meta) if meta.len() < self.position => {
+                info!("Followed file {} was truncated", self.path.display());
+                self.position = 0;
+                self.partial.clear();
+                result.truncated = true;
+            }
+            Ok(_) => {}
+            // Between the rename and the creation of the new file, try again later
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(result),
+            Err(e) => return Err(e),
+        }
+
+        self.file.seek(SeekFrom::Start(self.position))?;
+        let mut bytes = Vec::new();
+        (&mut self.file).take(max_bytes).read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+
+        self.partial.extend_from_slice(&bytes);
+        if let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') {
+            let complete: Vec<u8> = self.partial.drain(..=end).collect();
+            result.lines = String::from_utf8_lossy(&complete)
+                .lines()
+                .map(|l| l.to_string())
+                .collect();
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,233 @@
+// The one-time code of an authenticator app that approving a client needs with
+// [oauth.totp] enabled (RFC 6238). The server has one secret: the one of the config, or
+// one generated on the first start and saved with the tokens of storage_path. A
+// generated secret is shown on the approval pages until the first code of it is entered.
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::{error, warn};
+
+use crate::common::config::Totp;
+
+// Wrong codes in a row before codes are refused for LOCKOUT, guessing one takes days then.
+// They are counted per address, and per user with logins, so no one locks out the others.
+const MAX_FAILED_CODES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(300);
+// Addresses whose wrong codes are remembered, those neither locked nor recently wrong are
+// dropped first, then the least recently wrong
+const MAX_TRACKED_ATTEMPTS: usize = 10_000;
+// The codes of the steps before and after the current one pass too, for clocks a bit off
+const SKEW: u8 = 1;
+const STEP: u64 = 30;
+// How long a code passes, an accepted one is refused for that long (RFC 6238 section 5.2)
+const VALIDITY: Duration = Duration::from_secs(STEP * (2 * SKEW as u64 + 1));
+// The name of the secret in the authenticator app, next to the issuer
+const ACCOUNT_NAME: &str = "approvals";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeError {
+    Invalid,
+    LockedOut(Duration), // too many wrong codes, retry after this
+}
+
+// What the approval pages show to add the secret to an authenticator app
+#[derive(Debug, Clone, Serialize)]
+pub struct Enrollment {
+    pub secret: String,          // base32
+    pub url: String,             // otpauth://totp/...
+    pub qr_code: Option<String>, // a data: URL of a PNG of the url
+}
+
+// The wrong codes of an address, and of the user with logins
+type AttemptKey = (IpAddr, Option<String>);
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+pub struct SecondFactor {
+    totp: Option<TOTP>, // none if the configured secret is invalid, no code passes then
+    generated: bool,    // not of the config, saved with the store
+    enrolled: AtomicBool,
+    attempts: Mutex<HashMap<AttemptKey, Attempts>>,
+    last_code: Mutex<Option<(String, Instant)>>, // the last accepted one and when
+}
+
+impl fmt::Debug for SecondFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondFactor")
+            .field("generated", &self.generated)
+            .field("enrolled", &self.enrolled)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecondFactor {
+    // stored is the generated secret of the last run and whether it was enrolled. The
+    // secret of the config is enrolled already.
+    pub fn new(config: &Totp, stored: Option<(String, bool)>) -> Self {
+        let (totp, generated, enrolled) = match (&config.secret, stored) {
+            (Some(secret), _) => (totp(config, secret), false, true),
+            (None, Some((secret, enrolled))) => (totp(config, &secret), true, enrolled),
+            (None, None) => {
+                let secret = Secret::generate_secret();
+                let totp = secret
+                    .to_bytes()
+                    .map_err(|e| anyhow!("can't generate a TOTP secret: {e}"))
+                    .and_then(|secret| new_totp(config, secret));
+                (totp, true, false)
+            }
+        };
+        let totp = totp
+            .inspect_err(|e| error!("{e:#}, no one-time code is accepted"))
+            .ok();
+        if generated && !enrolled {
+            warn!(
+                "the TOTP secret was generated, the approval pages show it until its first code is entered. Set [oauth.totp] secret or [oauth] storage_path to keep it over restarts."
+            );
+        }
+        SecondFactor {
+            totp,
+            generated,
+            enrolled: AtomicBool::new(enrolled),
+            attempts: Mutex::new(HashMap::new()),
+            last_code: Mutex::new(None),
+        }
+    }
+
+    // The errors of the secret of the config, for --check
+    pub fn check(config: &Totp) -> Result<()> {
+        match &config.secret {
+            Some(secret) => totp(config, secret).map(|_| ()),
+            None => new_totp(config, vec![0; 20]).map(|_| ()),
+        }
+    }
+
+    // A code of the authenticator app entered from the address, by the user if logins are
+    // required. True if it was the first code of a generated secret.
+    pub fn verify(
+        &self,
+        code: &str,
+        ip: IpAddr,
+        username: Option<&str>,
+    ) -> Result<bool, CodeError> {
+        let now = Instant::now();
+        let key = (ip, username.map(str::to_string));
+        let mut attempts = self.attempts.lock().unwrap();
+        if !attempts.contains_key(&key) && attempts.len() >= MAX_TRACKED_ATTEMPTS {
+            evict(&mut attempts, now);
+        }
+        let attempt = attempts.entry(key.clone()).or_default();
+        if let Some(until) = attempt.locked_until {
+            if until > now {
+                return Err(CodeError::LockedOut(until - now));
+            }
+            attempt.locked_until = None;
+            attempt.failures = 0;
+        }
+        let code = code.trim();
+        let mut last_code = self.last_code.lock().unwrap();
+        let replayed = last_code
+            .as_ref()
+            .is_some_and(|(last, at)| last == code && now.duration_since(*at) < VALIDITY);
+        let valid = !replayed
+            && self
+                .totp
+                .as_ref()
+                .is_some_and(|totp| totp.check_current(code).unwrap_or(false));
+        if !valid {
+            attempt.failures += 1;
+            attempt.last_failure = Some(now);
+            if attempt.failures >= MAX_FAILED_CODES {
+                warn!(
+                    "{} wrong one-time codes in a row from {}, refusing its codes for {:?}",
+                    attempt.failures, ip, LOCKOUT
+                );
+                attempt.locked_until = Some(now + LOCKOUT);
+                return Err(CodeError::LockedOut(LOCKOUT));
+            }
+            return Err(CodeError::Invalid);
+        }
+        attempts.remove(&key);
+        *last_code = Some((code.to_string(), now));
+        Ok(!self.enrolled.swap(true, Ordering::SeqCst))
+    }
+
+    // Until the first code of a generated secret was entered
+    pub fn enrollment(&self) -> Option<Enrollment> {
+        if self.enrolled.load(Ordering::SeqCst) {
+            return None;
+        }
+        let totp = self.totp.as_ref()?;
+        Some(Enrollment {
+            secret: totp.get_secret_base32(),
+            url: totp.get_url(),
+            qr_code: totp
+                .get_qr_base64()
+                .inspect_err(|e| warn!("can't draw the QR code of the TOTP secret: {e}"))
+                .ok()
+                .map(|png| format!("data:image/png;base64,{png}")),
+        })
+    }
+
+    // What the store saves, the secret of the config is not
+    pub fn generated_secret(&self) -> Option<(String, bool)> {
+        let totp = self.totp.as_ref().filter(|_| self.generated)?;
+        Some((
+            totp.get_secret_base32(),
+            self.enrolled.load(Ordering::SeqCst),
+        ))
+    }
+}
+
+fn evict(attempts: &mut HashMap<AttemptKey, Attempts>, now: Instant) {
+    attempts.retain(|_, attempt| {
+        attempt.locked_until.is_some_and(|until| until > now)
+            || attempt
+                .last_failure
+                .is_some_and(|last| now.duration_since(last) < LOCKOUT)
+    });
+    if attempts.len() >= MAX_TRACKED_ATTEMPTS
+        && let Some(oldest) = attempts
+            .iter()
+            .min_by_key(|(_, attempt)| attempt.last_failure)
+            .map(|(key, _)| key.clone())
+    {
+        attempts.remove(&oldest);
+    }
+}
+
+fn totp(config: &Totp, secret: &str) -> Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow!("the [oauth.totp] secret is no base32: {e}"))?;
+    new_totp(config, secret)
+}
+
+// Codes of 6 digits of HMAC-SHA1 every 30 seconds, what every authenticator app reads
+fn new_totp(config: &Totp, secret: Vec<u8>) -> Result<TOTP> {
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        SKEW,
+        STEP,
+        secret,
+        Some(config.issuer.clone()),
+        ACCOUNT_NAME.to_string(),
+    )
+    .map_err(|e| anyhow!("[oauth.totp] {e}"))
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::common::audit::{AuditEvent, Outcome};
+use crate::common::config::{OAuth, OAuthUser};
+
+// Usernames with a backoff at most, further ones get none until others expire. Unknown
+// usernames are tracked too, or the backoff would tell which ones exist.
+const MAX_BACKOFF_USERNAMES: usize = 10_000;
+
+// Checked for unknown usernames, so they take as long to refuse as a wrong password
+static UNKNOWN_USER_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password("unknown user").expect("hashing a constant password"));
+
+// The users_file of [oauth], the same entries as [[oauth.users]]
+#[derive(Deserialize)]
+struct UsersFile {
+    #[serde(default)]
+    users: Vec<OAuthUser>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginError {
+    InvalidCredentials,
+    LockedOut,            // too many failed logins from the address
+    BackingOff(Duration), // the username failed to log in a moment ago, retry after this
+}
+
+#[derive(Debug)]
+struct FailedLogins {
+    count: u32,
+    last: Instant,
+}
+
+// The wait of a username after failed logins
+#[derive(Debug)]
+struct Backoff {
+    failures: u32,
+    until: Instant,
+}
+
+// The failed logins of a user in a row, from any address
+#[derive(Debug)]
+struct AccountFailures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+// The users who may approve authorization requests, the failed logins per address and
+// per user and the backoff per username
+#[derive(Debug)]
+pub struct UserStore {
+    users: HashMap<String, String>, // username to password hash
+    login_required: bool,
+    max_failed_logins: u32,
+    lockout: Duration,
+    failures: Mutex<HashMap<IpAddr, FailedLogins>>,
+    backoff: Duration, // after the first failure, doubled with every further one
+    backoffs: Mutex<HashMap<String, Backoff>>,
+    max_failed_logins_per_user: u32,
+    user_lockout: Duration,
+    accounts: Mutex<HashMap<String, AccountFailures>>, // known users only
+}
+
+impl UserStore {
+    pub fn new(config: &OAuth) -> Self {
+        let mut users: HashMap<String, String> = HashMap::new();
+        if let Some(path) = &config.users_file {
+            match read_users_file(path) {
+                Ok(file_users) => users.extend(
+                    file_users
+                        .into_iter()
+                        .map(|user| (user.username, user.password_hash)),
+                ),
+                // logins stay required, a broken file must not open the approval page
+                Err(e) => warn!("can't read the users file, its users can't log in: {e:#}"),
+            }
+        }
+        // the config wins over the users file
+        users.extend(
+            config
+                .users
+                .iter()
+                .map(|user| (user.username.clone(), user.password_hash.clone())),
+        );
+        for (username, hash) in &users {
+            if !is_supported_hash(hash) {
+                warn!("the password hash of user {username} is no argon2 or bcrypt hash");
+            }
+        }
+        if !users.is_empty() {
+            info!("{} users may approve authorization requests", users.len());
+        }
+
+        UserStore {
+            login_required: !users.is_empty() || config.users_file.is_some(),
+            users,
+            max_failed_logins: config.max_failed_logins,
+            lockout: Duration::from_secs(config.login_lockout_seconds),
+            failures: Mutex::new(HashMap::new()),
+            backoff: Duration::from_secs(config.login_backoff_seconds),
+            backoffs: Mutex::new(HashMap::new()),
+            max_failed_logins_per_user: config.max_failed_logins_per_user,
+            user_lockout: Duration::from_secs(config.user_lockout_seconds),
+            accounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Without configured users anybody reaching the page may approve
+    pub fn is_login_required(&self) -> bool {
+        self.login_required
+    }
+
+    // Checks the password against the hash of the user. Hashing is slow on purpose,
+    // call it from spawn_blocking.
+    pub fn verify(&self, addr: IpAddr, username: &str, password: &str) -> Result<(), LoginError> {
+        if self.is_locked_out(addr) {
+            return Err(LoginError::LockedOut);
+        }
+        if let Some(wait) = self.backoff_remaining(username) {
+            return Err(LoginError::BackingOff(wait));
+        }
+        let hash = self.users.get(username);
+        let valid = verify_password(
+            password,
+            hash.map_or(UNKNOWN_USER_HASH.as_str(), String::as_str),
+        ) && hash.is_some();
+        // checked after the hash, a locked account takes as long to refuse as any other
+        let locked = self.is_account_locked(addr, username);
+
+        let mut failures = self.failures.lock().unwrap();
+        if valid && !locked {
+            failures.remove(&addr);
+            self.backoffs.lock().unwrap().remove(username);
+            self.accounts.lock().unwrap().remove(username);
+            return Ok(());
+        }
+        let now = Instant::now();
+        self.back_off(username, now);
+        if hash.is_some() && !locked {
+            self.count_account_failure(addr, username, now);
+        }
+        failures.retain(|_, failed| now.duration_since(failed.last) < self.lockout);
+        let failed = failures.entry(addr).or_insert(FailedLogins {
+            count: 0,
+            last: now,
+        });
+        failed.count += 1;
+        failed.last = now;
+        if self.max_failed_logins > 0 && failed.count == self.max_failed_logins {
+            AuditEvent::new("address_locked", Outcome::Denied)
+                .user(Some(username))
+                .ip(Some(addr))
+                .reason(format!(
+                    "{} failed logins, locked out for {:?}",
+                    failed.count, self.lockout
+                ))
+                .emit();
+        }
+        Err(LoginError::InvalidCredentials)
+    }
+
+    // An expired lock is lifted here, the next failure starts counting again
+    fn is_account_locked(&self, addr: IpAddr, username: &str) -> bool {
+        let mut accounts = self.accounts.lock().unwrap();
+        let Some(locked_until) = accounts
+            .get(username)
+            .and_then(|account| account.locked_until)
+        else {
+            return false;
+        };
+        if locked_until > Instant::now() {
+            debug!("login of locked user {username} from {addr} refused");
+            return true;
+        }
+        accounts.remove(username);
+        AuditEvent::new("account_unlocked", Outcome::Success)
+            .user(Some(username))
+            .ip(Some(addr))
+            .reason("the lock expired")
+            .emit();
+        false
+    }
+
+    fn count_account_failure(&self, addr: IpAddr, username: &str, now: Instant) {
+        if self.max_failed_logins_per_user == 0 {
+            return;
+        }
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts
+            .entry(username.to_string())
+            .or_insert(AccountFailures {
+                count: 0,
+                locked_until: None,
+            });
+        account.count += 1;
+        if account.count >= self.max_failed_logins_per_user {
+            account.locked_until = Some(now + self.user_lockout);
+            AuditEvent::new("account_locked", Outcome::Denied)
+                .user(Some(username))
+                .ip(Some(addr))
+                .reason(format!(
+                    "{} failed logins in a row, locked for {:?}",
+                    account.count, self.user_lockout
+                ))
+                .emit();
+        }
+    }
+
+    // Lifts the lock of the user and forgets its failed logins, false if it was not locked
+    pub fn unlock(&self, username: &str) -> bool {
+        self.backoffs.lock().unwrap().remove(username);
+        self.accounts
+            .lock()
+            .unwrap()
+            .remove(username)
+            .is_some_and(|account| {
+                account
+                    .locked_until
+                    .is_some_and(|locked_until| locked_until > Instant::now())
+            })
+    }
+
+    fn backoff_remaining(&self, username: &str) -> Option<Duration> {
+        let backoffs = self.backoffs.lock().unwrap();
+        let wait = backoffs
+            .get(username)?
+            .until
+            .saturating_duration_since(Instant::now());
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    // The wait doubles with every failure, up to the lockout. A username is forgotten once
+    // it has not failed for a lockout after its wait.
+    fn back_off(&self, username: &str, now: Instant) {
+        if self.backoff.is_zero() {
+            return;
+        }
+        let mut backoffs = self.backoffs.lock().unwrap();
+        backoffs.retain(|_, backoff| now.saturating_duration_since(backoff.until) < self.lockout);
+        if backoffs.len() >= MAX_BACKOFF_USERNAMES && !backoffs.contains_key(username) {
+            return;
+        }
+        let backoff = backoffs.entry(username.to_string()).or_insert(Backoff {
+            failures: 0,
+            until: now,
+        });
+        backoff.failures += 1;
+        let wait = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(backoff.failures - 1))
+            .min(self.lockout.max(self.backoff));
+        backoff.until = now + wait;
+        debug!(
+            "{username} failed to log in {} times, waits {wait:?}",
+            backoff.failures
+        );
+    }
+
+    fn is_locked_out(&self, addr: IpAddr) -> bool {
+        self.max_failed_logins > 0
+            && self
+                .failures
+                .lock()
+                .unwrap()
+                .get(&addr)
+                .is_some_and(|failed| {
+                    failed.count >= self.max_failed_logins && failed.last.elapsed() < self.lockout
+                })
+    }
+}
+
+pub(crate) fn read_users_file(path: &Path) -> Result<Vec<OAuthUser>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("can't read {}", path.display()))?;
+    let file: UsersFile =
+        toml::from_str(&text).with_context(|| format!("can't parse {}", path.display()))?;
+    Ok(file.users)
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("$2") || PasswordHash::new(hash).is_ok()
+}
+
+// bcrypt hashes start with $2a$, $2b$ or $2y$, anything else is read as a PHC string.
+// Both compare the hashes in constant time.
+fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+// An argon2id hash with a random salt, in the PHC format of [[oauth.users]]
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("can't hash the password: {e}"))
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use rmcp::serde_json::{self, Value};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::common::checksum::hex;
+use crate::common::config::Webhooks;
+use crate::common::pinning::CertificatePins;
+
+// Attempts per delivery, the waits between them double from the first
+const MAX_ATTEMPTS: u32 = 3;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    CommandFailed,
+    JobCompleted,
+    SessionClosed,
+}
+
+#[derive(Serialize)]
+struct Payload {
+    id: String, // the same for every attempt of a delivery
+    event: WebhookEvent,
+    timestamp: String, // RFC 3339
+    data: Value,
+}
+
+// Notifies the [webhooks] URLs, shared by all sessions. Deliveries run in the
+// background and never hold up the call that caused them.
+#[derive(Debug)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+    config: Webhooks,
+}
+
+impl WebhookSender {
+    pub fn new(config: &Webhooks, pins: &CertificatePins) -> Self {
+        let client = pins
+            .client_builder()
+            .timeout(Duration::from_secs(config.timeout_seconds.max(1)))
+            .build()
+            .unwrap_or_default();
+        WebhookSender {
+            client,
+            config: config.clone(),
+        }
+    }
+
+    fn url(&self, event: WebhookEvent) -> Option<&str> {
+        match event {
+            WebhookEvent::CommandFailed => self.config.command_failed.as_deref(),
+            WebhookEvent::JobCompleted => self.config.job_completed.as_deref(),
+            WebhookEvent::SessionClosed => self.config.session_closed.as_deref(),
+        }
+    }
+
+    // Deliver the event if a URL is configured for it. Works from Drop impls too, the
+    // event is dropped when no runtime is left to send it.
+    pub fn send(&self, event: WebhookEvent, data: Value) {
+        let Some(url) = self.url(event).map(str::to_string) else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("No runtime to deliver the {event:?} webhook");
+            return;
+        };
+        let id = Uuid::new_v4().to_string();
+        let payload = Payload {
+            id: id.clone(),
+            event,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            data,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize the {event:?} webhook: {e}");
+                return;
+            }
+        };
+        let signature = self
+            .config
+            .secret
+            .as_deref()
+            .map(|secret| sign(secret, &body));
+        let client = self.client.clone();
+        runtime.spawn(async move {
+            let mut delay = FIRST_RETRY_DELAY;
+            for attempt in 1..=MAX_ATTEMPTS {
+                let mut request = client
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header("X-Webhook-Id", &id)
+                    .body(body.clone());
+                if let Some(signature) = &signature {
+                    request = request.header("X-Webhook-Signature", signature);
+                }
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        debug!("Delivered webhook {id} to {url}");
+                        return;
+                    }
+                    Ok(response) => warn!(
+                        "Webhook {id} to {url} got {} (attempt {attempt} of {MAX_ATTEMPTS})",
+                        response.status()
+                    ),
+                    Err(e) => warn!(
+                        "Webhook {id} to {url} failed: {e} (attempt {attempt} of {MAX_ATTEMPTS})"
+                    ),
+                }
+                if attempt < MAX_ATTEMPTS {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+            warn!("Gave up on webhook {id} to {url}");
+        });
+    }
+}
+
+// sha256=<hex HMAC-SHA256 of the body>
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
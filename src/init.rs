@@ -0,0 +1,230 @@
+// `mcp-bash-server --init`: a commented config file from the answers to a few questions
+use std::io::IsTerminal;
+use std::net::IpAddr;
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use dialoguer::{Confirm, Input, Select, theme::ColorfulTheme};
+use sha2::{Digest, Sha256};
+
+use mcp_bash_server::common::oauth::generate_random_string;
+
+// The example config of the repository, every option with its comment
+const TEMPLATE: &str = include_str!("../config.toml");
+
+const AUTH_MODES: [(&str, &str); 3] = [
+    (
+        "oauth",
+        "OAuth, clients get tokens from the endpoints of the server",
+    ),
+    ("api_key", "static API keys, one is generated now"),
+    ("oidc", "tokens of an OpenID provider, e.g. Keycloak"),
+];
+
+#[derive(Debug)]
+struct Answers {
+    host: String,
+    port: u16,
+    development: bool,
+    behind_tls_proxy: bool,
+    auth_mode: Option<&'static str>, // the default of the mode if not set
+    oidc_issuer: String,
+}
+
+// What --non-interactive writes
+impl Default for Answers {
+    fn default() -> Self {
+        Answers {
+            host: "127.0.0.1".to_string(),
+            port: 4000,
+            development: false,
+            behind_tls_proxy: false,
+            auth_mode: Some("oauth"),
+            oidc_issuer: String::new(),
+        }
+    }
+}
+
+pub fn init(path: &str, interactive: bool) -> Result<()> {
+    if interactive && !std::io::stdin().is_terminal() {
+        bail!("--init asks its questions on a terminal, use --init --non-interactive without one");
+    }
+    let theme = ColorfulTheme::default();
+    if Path::new(path).exists() {
+        if !interactive {
+            bail!("{path} already exists, remove it or run --init without --non-interactive");
+        }
+        let overwrite = Confirm::with_theme(&theme)
+            .with_prompt(format!("{path} already exists, overwrite it?"))
+            .default(false)
+            .interact()?;
+        if !overwrite {
+            println!("{path} is left as it is");
+            return Ok(());
+        }
+    }
+
+    let answers = if interactive {
+        ask(&theme)?
+    } else {
+        Answers::default()
+    };
+    let api_key = (answers.auth_mode == Some("api_key")).then(|| generate_random_string(40));
+    fs::write(path, render(&answers, api_key.as_deref()))
+        .with_context(|| format!("can't write {path}"))?;
+    println!("wrote {path}, `mcp-bash-server --check --config {path}` validates changes");
+
+    if let Some(key) = api_key {
+        println!("the api key, only its hash is in the file: {key}");
+    }
+    if answers.auth_mode == Some("oauth") {
+        println!(
+            "anybody reaching /authorize can approve until [[oauth.users]] are added, \
+             `mcp-bash-server hash-password` prints their password hashes"
+        );
+    }
+    Ok(())
+}
+
+fn ask(theme: &ColorfulTheme) -> Result<Answers> {
+    let behind_tls_proxy = Confirm::with_theme(theme)
+        .with_prompt("Is TLS terminated by a reverse proxy in front of the server?")
+        .default(false)
+        .interact()?;
+    let host = Input::<String>::with_theme(theme)
+        .with_prompt("Address to listen on")
+        .default("127.0.0.1".to_string())
+        .validate_with(|host: &String| {
+            host.parse::<IpAddr>()
+                .map(|_| ())
+                .map_err(|_| "an IP address like 127.0.0.1 or 0.0.0.0")
+        })
+        .interact_text()?;
+    let port = Input::<u16>::with_theme(theme)
+        .with_prompt("Port")
+        .default(4000)
+        .interact_text()?;
+    let development = Select::with_theme(theme)
+        .with_prompt("Mode")
+        .items(&[
+            "production",
+            "development, /mcp needs no authentication by default",
+        ])
+        .default(0)
+        .interact()?
+        == 1;
+
+    let mut answers = Answers {
+        host,
+        port,
+        development,
+        behind_tls_proxy,
+        auth_mode: None,
+        oidc_issuer: String::new(),
+    };
+    if development {
+        return Ok(answers);
+    }
+    let items: Vec<String> = AUTH_MODES
+        .iter()
+        .map(|(mode, description)| format!("{mode}: {description}"))
+        .collect();
+    let (mode, _) = AUTH_MODES[Select::with_theme(theme)
+        .with_prompt("How is /mcp authenticated?")
+        .items(&items)
+        .default(0)
+        .interact()?];
+    answers.auth_mode = Some(mode);
+    if mode == "oidc" {
+        answers.oidc_issuer = Input::<String>::with_theme(theme)
+            .with_prompt("Issuer of the OpenID provider")
+            .validate_with(|issuer: &String| {
+                if issuer.starts_with("https://") || issuer.starts_with("http://") {
+                    Ok(())
+                } else {
+                    Err("an http(s) URL, e.g. https://keycloak.example.com/realms/main")
+                }
+            })
+            .interact_text()?;
+    }
+    Ok(answers)
+}
+
+// The example config with the answers in place of its values
+fn render(answers: &Answers, api_key: Option<&str>) -> String {
+    let quoted = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let mut header =
+        "# This is the configuration file for mcp-bash-server, written by --init".to_string();
+    if answers.behind_tls_proxy {
+        header.push_str(
+            "\n# TLS is terminated by the reverse proxy in front, the server speaks plain HTTP.",
+        );
+    }
+    let mut text = TEMPLATE.to_string();
+    replace_line(
+        &mut text,
+        "# This is the configuration file for mcp-bash-server",
+        &header,
+    );
+    replace_line(
+        &mut text,
+        "port = 4000",
+        &format!("port = {}", answers.port),
+    );
+    replace_line(
+        &mut text,
+        "host = \"127.0.0.1\"",
+        &format!("host = {}", quoted(&answers.host)),
+    );
+    let env = if answers.development {
+        "development"
+    } else {
+        "production"
+    };
+    replace_line(
+        &mut text,
+        "env = \"development\"",
+        &format!("env = {}", quoted(env)),
+    );
+    if let Some(mode) = answers.auth_mode {
+        replace_line(
+            &mut text,
+            "# auth_mode = \"api_key\"",
+            &format!("auth_mode = {}", quoted(mode)),
+        );
+    }
+    if let Some(key) = api_key {
+        let hash: String = Sha256::digest(key)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        replace_line(&mut text, "# [[api_keys]]", "[[api_keys]]");
+        replace_line(&mut text, "# name = \"homelab\"", "name = \"default\"");
+        replace_line(
+            &mut text,
+            "# key_hash = \"<sha256 of the key in hex, printf %s \\\"$KEY\\\" | sha256sum>\"",
+            &format!("key_hash = {}", quoted(&hash)),
+        );
+    }
+    if !answers.oidc_issuer.is_empty() {
+        replace_line(
+            &mut text,
+            "# issuer = \"https://keycloak.example.com/realms/main\"",
+            &format!("issuer = {}", quoted(&answers.oidc_issuer)),
+        );
+    }
+    text
+}
+
+// The first line that is exactly `line`
+fn replace_line(text: &mut String, line: &str, with: &str) {
+    let mut offset = 0;
+    let found = text.split_inclusive('\n').find_map(|current| {
+        let start = offset;
+        offset += current.len();
+        (current.trim_end_matches(['\r', '\n']) == line).then_some(start)
+    });
+    if let Some(start) = found {
+        text.replace_range(start..start + line.len(), with);
+    }
+}
@@ -0,0 +1,10 @@
+// The MCP bash server as a library, main.rs is a thin binary on top of it
+pub mod common;
+pub mod server;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use common::bash_server::BashServer;
+pub use common::config::Config;
+pub use common::oauth::McpOAuthStore;
+pub use server::{ServerState, router};
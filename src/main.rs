@@ -1,95 +1,64 @@
-use std::sync::OnceLock;
-use std::{net::SocketAddr, sync::Arc};
-
-use anyhow::Result;
-use axum::{
-    Router,
-    body::Body,
-    http::Request,
-    middleware::{self, Next},
-    response::{Html, IntoResponse, Response},
-    routing::{get, post},
-};
-use rmcp::transport::streamable_http_server::{
-    StreamableHttpService, session::local::LocalSessionManager,
-};
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-// Import modules
-mod common;
-use common::bash_server::BashServer;
-use common::config;
-use common::oauth::{
-    McpOAuthStore, oauth_approve, oauth_authorization_server, oauth_authorize, oauth_register,
-    oauth_token, validate_token_middleware,
-};
-
-const INDEX_HTML: &str = include_str!("html/mcp_oauth_index.html");
-
-// Init once from environment variable BIND_ADDRESS
-pub static BIND_ADDRESS: OnceLock<String> = OnceLock::new();
-
-// Root path handler
-async fn index() -> Html<&'static str> {
-    Html(INDEX_HTML)
-}
+use std::future::IntoFuture;
+use std::io::{BufRead, IsTerminal};
+use std::net::SocketAddr;
+use std::time::Duration;
 
-// Wrapper function for oauth_authorization_server to handle BIND_ADDRESS
-async fn oauth_authorization_server_handler() -> impl IntoResponse {
-    let bind_address = BIND_ADDRESS
-        .get()
-        .expect("BIND_ADDRESS must be initialized in main()");
-    oauth_authorization_server(bind_address).await
-}
+use anyhow::{Context, Result};
+use rmcp::transport::streamable_http_server::session::SessionManager;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use tracing_subscriber::{Layer, filter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use mcp_bash_server::common::{audit, check, config, log_forward, sandbox, users};
+use mcp_bash_server::{ServerState, router};
 
-// Log all HTTP requests
-async fn log_request(request: Request<Body>, next: Next) -> Response {
-    let method = request.method().clone();
-    let uri = request.uri().clone();
-    let version = request.version();
-
-    // Log headers
-    let headers = request.headers().clone();
-    let mut header_log = String::new();
-    for (key, value) in headers.iter() {
-        let value_str = value.to_str().unwrap_or("<binary>");
-        header_log.push_str(&format!("\n  {key}: {value_str}"));
+mod cli;
+mod init;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = cli::Args::parse(std::env::args().skip(1))?;
+    match args.command {
+        cli::Command::Serve => {}
+        cli::Command::Check => return check_config(&args.config),
+        cli::Command::Init => return init::init(&args.config, !args.non_interactive),
+        // `mcp-bash-server hash-password` prints a hash for [[oauth.users]] and exits
+        cli::Command::HashPassword => return print_password_hash(),
+        cli::Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        cli::Command::Version => {
+            println!("mcp-bash-server {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
     }
 
-    // Try to get request body for form submissions
-    let content_type = headers
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
+    let config = config::Config::read_config(&args.config)
+        .with_context(|| format!("can't read the config file {}", args.config))?;
 
-    let request_info = if content_type.contains("application/x-www-form-urlencoded")
-        || content_type.contains("application/json")
-    {
-        format!("{method} {uri} {version:?}{header_log}\nContent-Type: {content_type}")
+    // Initialize logging, the log is forwarded to the MCP clients if enabled
+    let (log_layer, log_records) = if config.mcp.enable_log_forwarding {
+        let (layer, records) = log_forward::layer();
+        (Some(layer), Some(records))
     } else {
-        format!("{method} {uri} {version:?}{header_log}")
+        (None, None)
     };
-
-    info!("REQUEST: {}", request_info);
-
-    // Call the actual handler
-    let response = next.run(request).await;
-
-    // Log response status
-    let status = response.status();
-    info!("RESPONSE: {} for {} {}", status, method, uri);
-
-    response
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
     let logs = tracing_appender::rolling::daily("logs", "mcp.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(logs);
     let log_setting = tracing_subscriber::fmt::layer().with_writer(non_blocking);
+    // the audit records on their own, one JSON line each
+    let audit_log = tracing_appender::rolling::daily("logs", "audit.log");
+    let (audit_writer, _audit_guard) = tracing_appender::non_blocking(audit_log);
+    let audit_setting = tracing_subscriber::fmt::layer()
+        .with_writer(audit_writer)
+        .with_ansi(false)
+        .without_time()
+        .with_level(false)
+        .with_target(false)
+        .with_filter(filter::filter_fn(|metadata| {
+            metadata.target() == audit::TARGET
+        }));
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -97,79 +66,147 @@ async fn main() -> Result<()> {
         )
         .with(tracing_subscriber::fmt::layer())
         .with(log_setting)
+        .with(audit_setting)
+        .with(log_layer)
         .init();
 
     // Read environment mode from config file, default to "production"
-    let config = config::Config::read_config("config.toml")?;
     let env_mode = config
         .settings
         .env
         .clone()
         .unwrap_or_else(|| "production".to_string());
     let is_dev = env_mode == "development";
+    match config.settings.auth_mode() {
+        config::AuthMode::None if !is_dev => warn!("/mcp is served without authentication"),
+        auth_mode => info!("/mcp is authenticated with {auth_mode:?}"),
+    }
 
-    // Create the OAuth store
-    let oauth_store = Arc::new(McpOAuthStore::new());
+    // Make sure the configured sandbox can actually be enforced
+    if let Some(landlock) = &config.security.landlock {
+        sandbox::check_landlock(landlock)?;
+    }
 
     let host = config.settings.host.clone();
     let port = config.settings.port;
     let bind_address = format!("{host}:{port}");
 
-    let addr = bind_address.parse::<SocketAddr>()?;
-    let _ = BIND_ADDRESS.set(bind_address);
+    // The primary address is the one advertised without [settings] external_url
+    let mut addrs = vec![bind_address.parse::<SocketAddr>()?];
+    for additional in &config.settings.additional_bind_addresses {
+        addrs.push(additional.parse::<SocketAddr>()?);
+    }
+
+    let shutdown_timeout = Duration::from_secs(config.settings.shutdown_timeout_secs.unwrap_or(30));
 
-    // Create StreamableHttpServer
-    let service = StreamableHttpService::new(
-        BashServer::new,
-        LocalSessionManager::default().into(),
-        Default::default(),
+    // The OAuth store, the session registry and what else the sessions share
+    let state = ServerState::new(&config, &bind_address)?;
+    let sessions = state.sessions.clone();
+    let session_manager = state.session_manager.clone();
+    if let Some(records) = log_records {
+        log_forward::forward(records, sessions.clone());
+    }
+    let app = router(&state, is_dev);
+
+    // Start HTTP server on every bind address, all served by the same router
+    let stop_accepting = CancellationToken::new();
+    let mut servers = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        info!("MCP OAuth Server started on {}", addr);
+        servers.push(tokio::spawn(
+            axum::serve(
+                listener,
+                app.clone()
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(stop_accepting.clone().cancelled_owned())
+            .into_future(),
+        ));
+    }
+
+    tokio::signal::ctrl_c().await?;
+
+    // Stop accepting connections and new sessions
+    info!("Shutdown signal received, stop accepting new sessions");
+    sessions.stop_accepting();
+    stop_accepting.cancel();
+
+    // Ask the active sessions to finish and wait for them, the ones of rmcp are counted
+    // before and after
+    let running: Vec<_> = session_manager
+        .sessions
+        .read()
+        .await
+        .keys()
+        .cloned()
+        .collect();
+    sessions.notify_shutdown().await;
+    if !sessions.wait_for_drain(shutdown_timeout).await {
+        warn!(
+            "{} sessions still active after {:?}, force-killing them",
+            sessions.len(),
+            shutdown_timeout
+        );
+    }
+
+    // Force-kill the sessions that did not finish in time
+    let remaining: Vec<_> = session_manager
+        .sessions
+        .read()
+        .await
+        .keys()
+        .cloned()
+        .collect();
+    let force_killed = running.iter().filter(|id| remaining.contains(id)).count();
+    let closed_cleanly = running.len() - force_killed;
+    for id in remaining {
+        if let Err(e) = session_manager.close_session(&id).await {
+            warn!("Failed to close session {id}: {e}");
+        }
+    }
+    info!(
+        "Shutdown complete: {} sessions closed cleanly, {} force-killed",
+        closed_cleanly, force_killed
     );
 
-    let server_router = Router::new().nest_service("/mcp", service);
+    for server in servers {
+        if tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .is_err()
+        {
+            warn!("HTTP server did not stop in time");
+        }
+    }
 
-    // Add OAuth authentication middleware only if not in development mode
-    let protected_server_router = if is_dev {
-        server_router
-    } else {
-        server_router.layer(middleware::from_fn_with_state(
-            oauth_store.clone(),
-            validate_token_middleware,
-        ))
-    };
+    Ok(())
+}
 
-    // Create CORS layer for the oauth authorization server endpoint
-    let cors_layer = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    // Create a sub-router for the oauth authorization server endpoint with CORS
-    let oauth_server_router = Router::new()
-        .route(
-            "/.well-known/oauth-authorization-server",
-            get(oauth_authorization_server_handler).options(oauth_authorization_server_handler),
-        )
-        .route("/token", post(oauth_token).options(oauth_token))
-        .route("/register", post(oauth_register).options(oauth_register))
-        .layer(cors_layer)
-        .with_state(oauth_store.clone());
-
-    // Create HTTP router with request logging middleware
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/authorize", get(oauth_authorize))
-        .route("/approve", post(oauth_approve))
-        .merge(oauth_server_router) // Merge the CORS-enabled oauth server router
-        .merge(protected_server_router)
-        .with_state(oauth_store.clone())
-        .layer(middleware::from_fn(log_request));
-
-    // Start HTTP server
-    info!("MCP OAuth Server started on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let _ = axum::serve(listener, app)
-        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
-        .await;
+// Nothing is bound or started, the exit code tells CI whether the config can be deployed
+fn check_config(path: &str) -> Result<()> {
+    let errors = check::check_config_file(path);
+    if errors.is_empty() {
+        println!("{path} is valid");
+        return Ok(());
+    }
+    for error in &errors {
+        eprintln!("error: {error}");
+    }
+    eprintln!("{path}: {} errors", errors.len());
+    std::process::exit(1);
+}
 
+// The password is read from the first line of stdin, so it stays out of the shell history
+fn print_password_hash() -> Result<()> {
+    if std::io::stdin().is_terminal() {
+        eprint!("Password: ");
+    }
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        anyhow::bail!("no password given on stdin");
+    }
+    println!("{}", users::hash_password(password)?);
     Ok(())
 }
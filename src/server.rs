@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, Request, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use rmcp::serde_json;
+use rmcp::transport::streamable_http_server::{
+    StreamableHttpService, session::local::LocalSessionManager,
+};
+use tower_http::cors::{Any, CorsLayer};
+use tracing::info;
+
+use crate::common::admin::admin_router;
+use crate::common::api_keys::{ApiKeyStore, api_key_middleware};
+use crate::common::bash_server::BashServer;
+use crate::common::browser_session::{oauth_grants, oauth_logout, oauth_revoke_grant};
+use crate::common::config::{AuthMode, Config, TokenFormat};
+use crate::common::idempotency_key::{IdempotencyKeys, idempotency_key_middleware};
+use crate::common::jwt::JwtKeys;
+use crate::common::oauth::{
+    FALLBACK_TOKEN_HEADER, McpOAuthStore, oauth_approve, oauth_authorization_server,
+    oauth_authorize, oauth_device, oauth_device_approve, oauth_device_authorization,
+    oauth_introspect, oauth_jwks, oauth_protected_resource, oauth_register, oauth_revoke,
+    oauth_token, validate_token_middleware, without_query_token,
+};
+use crate::common::oidc::OidcVerifier;
+use crate::common::openapi::{
+    AuthorizationServerMetadata, ProtectedResourceMetadata, VersionInfo, openapi_json,
+};
+use crate::common::pages::{self, Pages};
+use crate::common::pinning::CertificatePins;
+use crate::common::prompts::PromptLibrary;
+use crate::common::public_url::PublicUrl;
+use crate::common::rate_limit::{RateLimiter, rate_limit_middleware};
+use crate::common::schedule::Scheduler;
+use crate::common::session::SessionRegistry;
+use crate::common::session_binding::{SessionBindings, session_binding_middleware};
+use crate::common::webhooks::WebhookSender;
+
+// What the sessions of one server share. Creating it starts background tasks, so it
+// has to happen inside a tokio runtime.
+pub struct ServerState {
+    pub config: Arc<Config>, // every session is set up from it
+    pub oauth_store: Arc<McpOAuthStore>,
+    pub api_keys: Arc<ApiKeyStore>,
+    pub sessions: Arc<SessionRegistry>,
+    pub session_manager: Arc<LocalSessionManager>,
+    pub prompts: Arc<PromptLibrary>,
+    pub scheduler: Arc<Scheduler>,
+    pub webhooks: Arc<WebhookSender>,
+}
+
+impl ServerState {
+    // bind_address is the "host:port" advertised without [settings] external_url
+    pub fn new(config: &Config, bind_address: &str) -> anyhow::Result<Self> {
+        let public_url = PublicUrl::new(&config.settings, bind_address)?;
+        // the issuer of JWTs and the resource identifier of /mcp (RFC 8707)
+        let issuer = public_url.fixed().to_string();
+        let resource = format!("{issuer}/mcp");
+        let pins = CertificatePins::new(&config.security.pin_certificates);
+        let mut oauth_store = McpOAuthStore::new(&config.oauth)
+            .with_resource(resource.clone())
+            .with_public_url(public_url)
+            .with_pages(Pages::new(&config.web)?);
+        if config.settings.auth_mode() == AuthMode::Oidc {
+            oauth_store = oauth_store.with_oidc(OidcVerifier::new(&config.oidc, &pins)?);
+        } else if config.oauth.token_format == TokenFormat::Jwt {
+            let mut jwt = config.oauth.jwt.clone();
+            jwt.audience = jwt
+                .audience
+                .or_else(|| config.oauth.expected_audience.clone())
+                .or(Some(resource));
+            oauth_store = oauth_store.with_jwt(JwtKeys::new(&jwt, issuer)?);
+        }
+        if config.settings.auth_mode() != AuthMode::Oidc && config.oauth.jwks_uri.is_some() {
+            oauth_store =
+                oauth_store.with_remote_jwks(OidcVerifier::remote_jwks(&config.oauth, &pins)?);
+        }
+        let oauth_store = Arc::new(oauth_store);
+        oauth_store.spawn_pruning();
+        let api_keys = ApiKeyStore::new(&config.api_keys, &config.oauth.scopes_supported)?;
+        if config.settings.auth_mode() == AuthMode::ApiKey && api_keys.is_empty() {
+            anyhow::bail!("auth_mode = \"api_key\" needs at least one [[api_keys]] entry");
+        }
+        config.security.check_sudo()?;
+
+        // every session registers itself for graceful shutdown
+        let webhooks = Arc::new(WebhookSender::new(&config.webhooks, &pins));
+        let sessions = Arc::new(SessionRegistry::new().with_webhooks(&webhooks));
+        let prompts = Arc::new(PromptLibrary::load(
+            config
+                .settings
+                .prompts_dir
+                .as_deref()
+                .unwrap_or(std::path::Path::new("prompts")),
+            sessions.clone(),
+        ));
+        let scheduler = Arc::new(Scheduler::new(&config.schedules).with_webhooks(&webhooks));
+        Ok(ServerState {
+            config: Arc::new(config.clone()),
+            oauth_store,
+            api_keys: Arc::new(api_keys),
+            sessions,
+            session_manager: Arc::new(LocalSessionManager::default()),
+            prompts,
+            scheduler,
+            webhooks,
+        })
+    }
+}
+
+// Root path handler
+#[utoipa::path(
+    get,
+    path = "/",
+    responses((status = 200, description = "A page about the server", content_type = "text/html")),
+)]
+pub(crate) async fn index(State(oauth_store): State<Arc<McpOAuthStore>>) -> Response {
+    oauth_store
+        .pages
+        .render(StatusCode::OK, pages::INDEX, serde_json::json!({}))
+}
+
+// What is deployed, the commit and time are set by build.rs
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "metadata",
+    responses((status = 200, description = "The build of the server", body = VersionInfo)),
+)]
+pub(crate) async fn version() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": env!("GIT_COMMIT"),
+        "build_time": env!("BUILD_TIME"),
+    }))
+}
+
+// Wrapper function for oauth_authorization_server, the endpoints are of the URL the
+// request was sent to
+#[utoipa::path(
+    get,
+    path = "/.well-known/oauth-authorization-server",
+    tag = "metadata",
+    responses((status = 200, description = "The authorization server metadata", body = AuthorizationServerMetadata)),
+)]
+pub(crate) async fn oauth_authorization_server_handler(
+    State(oauth_store): State<Arc<McpOAuthStore>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    oauth_authorization_server(
+        &oauth_store.public_url.of_request(&headers),
+        &oauth_store.scopes_supported,
+        oauth_store.has_jwks(),
+        oauth_store.registration_open(),
+    )
+    .await
+}
+
+// Wrapper function for oauth_protected_resource, served under
+// /.well-known/oauth-protected-resource/mcp as well
+#[utoipa::path(
+    get,
+    path = "/.well-known/oauth-protected-resource",
+    tag = "metadata",
+    responses((status = 200, description = "The metadata of /mcp as a protected resource", body = ProtectedResourceMetadata)),
+)]
+pub(crate) async fn oauth_protected_resource_handler(
+    State(oauth_store): State<Arc<McpOAuthStore>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let base_url = oauth_store.public_url.of_request(&headers);
+    oauth_protected_resource(
+        &format!("{base_url}/mcp"),
+        &oauth_store.authorization_server(&base_url),
+        &oauth_store.scopes_supported,
+    )
+    .await
+}
+
+// Log all HTTP requests
+async fn log_request(request: Request<Body>, next: Next) -> Response {
+    let method = request.method().clone();
+    // an access token in the query stays out of the log
+    let uri =
+        without_query_token(request.uri()).map_or_else(|| request.uri().clone(), |(_, uri)| uri);
+    let version = request.version();
+
+    // Log headers
+    let headers = request.headers().clone();
+    let mut header_log = String::new();
+    for (key, value) in headers.iter() {
+        // credentials stay out of the log, bodies are never logged
+        let value_str = if key == header::AUTHORIZATION
+            || key == header::COOKIE
+            || *key == FALLBACK_TOKEN_HEADER
+        {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        header_log.push_str(&format!("\n  {key}: {value_str}"));
+    }
+
+    // Try to get request body for form submissions
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    let request_info = if content_type.contains("application/x-www-form-urlencoded")
+        || content_type.contains("application/json")
+    {
+        format!("{method} {uri} {version:?}{header_log}\nContent-Type: {content_type}")
+    } else {
+        format!("{method} {uri} {version:?}{header_log}")
+    };
+
+    info!("REQUEST: {}", request_info);
+
+    // Call the actual handler
+    let response = next.run(request).await;
+
+    // Log response status
+    let status = response.status();
+    info!("RESPONSE: {} for {} {}", status, method, uri);
+
+    response
+}
+
+// Refuse to open new MCP sessions once shutdown has started
+async fn reject_new_sessions(
+    State(sessions): State<Arc<SessionRegistry>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    if !sessions.is_accepting() && !request.headers().contains_key("mcp-session-id") {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server is shutting down").into_response();
+    }
+    next.run(request).await
+}
+
+// The whole HTTP app: /mcp, the OAuth endpoints and the index page. /mcp is protected
+// as the auth_mode of the config says, is_dev exempts loopback from the rate limits.
+pub fn router(state: &ServerState, is_dev: bool) -> Router {
+    let config = state.config.clone();
+    let factory_sessions = state.sessions.clone();
+    let prompts = state.prompts.clone();
+    let scheduler = state.scheduler.clone();
+    let webhooks = state.webhooks.clone();
+    let service = StreamableHttpService::new(
+        move || {
+            BashServer::from_config(Config::clone(&config))
+                .with_session_registry(&factory_sessions)
+                .with_prompts(&prompts)
+                .with_scheduler(&scheduler)
+                .with_webhooks(&webhooks)
+        },
+        state.session_manager.clone(),
+        Default::default(),
+    );
+
+    // inside the auth middleware, which leaves the token for the binding
+    let server_router = Router::new()
+        .nest_service("/mcp", service)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(SessionBindings::new(state.session_manager.clone())),
+            session_binding_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.sessions.clone(),
+            reject_new_sessions,
+        ));
+
+    let auth_mode = state.config.settings.auth_mode();
+    let protected_server_router = match auth_mode {
+        AuthMode::OAuth | AuthMode::Oidc => server_router.layer(middleware::from_fn_with_state(
+            state.oauth_store.clone(),
+            validate_token_middleware,
+        )),
+        AuthMode::ApiKey => server_router.layer(middleware::from_fn_with_state(
+            state.api_keys.clone(),
+            api_key_middleware,
+        )),
+        AuthMode::None => server_router,
+    };
+
+    // Limits of the endpoints taking credentials, loopback is not limited in development
+    let rate_limit = middleware::from_fn_with_state(
+        Arc::new(RateLimiter::new(&state.config.oauth.rate_limit, is_dev)),
+        rate_limit_middleware,
+    );
+    // retried token requests with an Idempotency-Key get the first answer
+    let idempotency_keys = middleware::from_fn_with_state(
+        Arc::new(IdempotencyKeys::new(&state.config.oauth.idempotency)),
+        idempotency_key_middleware,
+    );
+
+    // Create CORS layer for the oauth authorization server endpoint
+    let cors_layer = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    // The metadata of /mcp, naming the server that issues its tokens
+    let protected_resource_router = Router::new()
+        .route(
+            "/.well-known/oauth-protected-resource",
+            get(oauth_protected_resource_handler).options(oauth_protected_resource_handler),
+        )
+        // the same under the path of the resource, where RFC 9728 clients look first
+        .route(
+            "/.well-known/oauth-protected-resource/mcp",
+            get(oauth_protected_resource_handler).options(oauth_protected_resource_handler),
+        )
+        .layer(cors_layer.clone())
+        .with_state(state.oauth_store.clone());
+
+    // Create a sub-router for the oauth authorization server endpoint with CORS
+    let oauth_server_router = Router::new()
+        .route(
+            "/.well-known/oauth-authorization-server",
+            get(oauth_authorization_server_handler).options(oauth_authorization_server_handler),
+        )
+        .route(
+            "/.well-known/jwks.json",
+            get(oauth_jwks).options(oauth_jwks),
+        )
+        .route("/openapi.json", get(openapi_json).options(openapi_json))
+        .route(
+            "/token",
+            post(oauth_token)
+                .options(oauth_token)
+                .layer(idempotency_keys)
+                .layer(rate_limit.clone()),
+        )
+        .route(
+            "/register",
+            post(oauth_register)
+                .options(oauth_register)
+                .layer(rate_limit.clone()),
+        )
+        .route(
+            "/device_authorization",
+            post(oauth_device_authorization)
+                .options(oauth_device_authorization)
+                .layer(rate_limit.clone()),
+        )
+        .route(
+            "/revoke",
+            post(oauth_revoke)
+                .options(oauth_revoke)
+                .layer(rate_limit.clone()),
+        )
+        .route(
+            "/introspect",
+            post(oauth_introspect)
+                .options(oauth_introspect)
+                .layer(rate_limit.clone()),
+        )
+        .layer(cors_layer)
+        .with_state(state.oauth_store.clone());
+
+    let oauth_router = Router::new()
+        .route("/authorize", get(oauth_authorize).layer(rate_limit.clone()))
+        .route("/approve", post(oauth_approve).layer(rate_limit.clone()))
+        .route(
+            "/device",
+            get(oauth_device)
+                .post(oauth_device_approve)
+                .layer(rate_limit.clone()),
+        )
+        .route("/grants", get(oauth_grants))
+        .route(
+            "/grants/revoke",
+            post(oauth_revoke_grant).layer(rate_limit.clone()),
+        )
+        .route("/logout", post(oauth_logout).layer(rate_limit))
+        .merge(oauth_server_router); // Merge the CORS-enabled oauth server router
+
+    // Create HTTP router with request logging middleware. With API keys the OAuth
+    // endpoints are not served at all, with an upstream provider only the metadata of /mcp.
+    let router = Router::new()
+        .route("/", get(index))
+        .route("/version", get(version));
+    let router = match auth_mode {
+        AuthMode::ApiKey => router,
+        AuthMode::Oidc => router.merge(protected_resource_router),
+        AuthMode::OAuth | AuthMode::None => {
+            router.merge(oauth_router).merge(protected_resource_router)
+        }
+    };
+    // the endpoints of operators, whatever authenticates /mcp
+    let router = match state.config.admin.token.as_deref() {
+        Some(token) if !token.is_empty() => {
+            router.merge(admin_router(token, state.oauth_store.clone()))
+        }
+        _ => router,
+    };
+    router
+        .merge(protected_server_router)
+        .with_state(state.oauth_store.clone())
+        .layer(middleware::from_fn(log_request))
+}
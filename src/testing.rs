@@ -0,0 +1,170 @@
+// Test doubles for code that talks to the server, behind the `testing` feature
+use std::sync::{Arc, Mutex};
+
+use regex::Regex;
+use rmcp::{
+    RoleServer, ServerHandler,
+    model::*,
+    serde_json::{self, Value},
+    service::RequestContext,
+};
+
+use crate::common::bash_server::{BashServer, CommandResult, DefaultExecuteResponse};
+
+// Tools that run a command, the others answer with an error
+const SIMULATED_TOOLS: [&str; 2] = ["all_execute_via_default_shell", "run_parallel"];
+
+// What a simulated command prints and exits with
+#[derive(Debug, Clone)]
+pub struct CommandResponse {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl CommandResponse {
+    pub fn success(stdout: impl Into<String>) -> Self {
+        CommandResponse {
+            stdout: stdout.into(),
+            stderr: String::new(),
+            exit_code: 0,
+        }
+    }
+
+    pub fn failure(exit_code: i32, stderr: impl Into<String>) -> Self {
+        CommandResponse {
+            stdout: String::new(),
+            stderr: stderr.into(),
+            exit_code,
+        }
+    }
+
+    fn to_execute_response(&self) -> DefaultExecuteResponse {
+        DefaultExecuteResponse {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            exit_code: self.exit_code,
+            success: self.exit_code == 0,
+            parsed_data: Value::Null,
+            stdout_file: None,
+            stderr_file: None,
+            status: None,
+            suppressed_lines: None,
+            elided_lines: None,
+            buffer_id: None,
+            page_count: None,
+        }
+    }
+}
+
+// A server with the tools and argument checks of BashServer that runs nothing. Commands
+// are answered by the first pattern matching them, a command no pattern matches fails
+// with exit code 127 like an unknown program. Clones share the recorded commands, so a
+// clone can be handed to a transport and the original asserted on.
+#[derive(Debug, Clone)]
+pub struct MockBashServer {
+    responses: Arc<Vec<(Regex, CommandResponse)>>,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockBashServer {
+    pub fn new(responses: Vec<(Regex, CommandResponse)>) -> Self {
+        MockBashServer {
+            responses: Arc::new(responses),
+            commands: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // The commands of all calls so far, in order
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    fn run(&self, command: &str) -> CommandResponse {
+        self.commands.lock().unwrap().push(command.to_string());
+        self.responses
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(command))
+            .map(|(_, response)| response.clone())
+            .unwrap_or_else(|| {
+                CommandResponse::failure(127, format!("mock: no response for {command}"))
+            })
+    }
+
+    fn call(&self, name: &str, arguments: &JsonObject) -> Result<CallToolResult, ErrorData> {
+        match name {
+            "all_execute_via_default_shell" => {
+                let command = arguments
+                    .get("command")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let response = self.run(command).to_execute_response();
+                Ok(CallToolResult::success(vec![Content::json(response)?]))
+            }
+            "run_parallel" => {
+                let results: Vec<CommandResult> = arguments
+                    .get("commands")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .map(|spec| {
+                        let cmd = spec["cmd"].as_str().unwrap_or_default().to_string();
+                        let response = self.run(&cmd).to_execute_response();
+                        CommandResult {
+                            result: serde_json::to_value(response).ok(),
+                            cmd,
+                            error: None,
+                        }
+                    })
+                    .collect();
+                Ok(CallToolResult::success(vec![Content::json(results)?]))
+            }
+            _ => Err(ErrorData::invalid_request(
+                format!(
+                    "{name} is not simulated by MockBashServer, only {}",
+                    SIMULATED_TOOLS.join(" and ")
+                ),
+                None,
+            )),
+        }
+    }
+}
+
+impl ServerHandler for MockBashServer {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            protocol_version: ProtocolVersion::LATEST,
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            instructions: Some(
+                "A simulation of the MCP bash server, no command is run".to_string(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        if !BashServer::tools()
+            .iter()
+            .any(|tool| tool.name == request.name)
+        {
+            return Err(ErrorData::invalid_params("tool not found", None));
+        }
+        BashServer::schema_validators().validate(&request.name, request.arguments.as_ref())?;
+        self.call(&request.name, &request.arguments.unwrap_or_default())
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        Ok(ListToolsResult {
+            tools: BashServer::tools(),
+            next_cursor: None,
+        })
+    }
+}
@@ -0,0 +1,148 @@
+// Tests of the command line of the binary
+use std::process::Command;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_mcp-bash-server"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn help_lists_the_flags() {
+    let output = run(&["--help"]);
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    for flag in [
+        "--config <PATH>",
+        "[default: config.toml]",
+        "--help",
+        "--version",
+    ] {
+        assert!(help.contains(flag), "{flag} missing in {help}");
+    }
+}
+
+#[test]
+fn the_config_flag_names_the_file() {
+    let output = run(&["--config", "/nonexistent/mcp-bash-server.toml"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.contains("/nonexistent/mcp-bash-server.toml"),
+        "{error}"
+    );
+}
+
+#[test]
+fn unknown_arguments_are_refused() {
+    let output = run(&["--no-such-flag"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.contains("--no-such-flag") && error.contains("Usage"),
+        "{error}"
+    );
+}
+
+#[test]
+fn check_accepts_the_example_config() {
+    let output = run(&["--check", "--config", "config.toml"]);
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{error}");
+}
+
+// every broken section is reported, not only the first
+#[test]
+fn check_reports_every_error() {
+    let path =
+        std::env::temp_dir().join(format!("mcp-bash-server-check-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+        [settings]
+        port = "not a port"
+        host = "127.0.0.1"
+
+        [blacklist]
+        commands = []
+        operations = []
+
+        [oauth]
+        token_ttl_seconds = "an hour"
+        "#,
+    )
+    .unwrap();
+    let output = run(&["--validate", "--config", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(output.status.code(), Some(1));
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.contains("[settings]") && error.contains("[oauth]"),
+        "{error}"
+    );
+}
+
+// a config the server starts with but can't work as meant
+#[test]
+fn check_reports_conflicting_settings() {
+    let path = std::env::temp_dir().join(format!(
+        "mcp-bash-server-conflict-{}.toml",
+        std::process::id()
+    ));
+    let config = std::fs::read_to_string("config.toml").unwrap().replace(
+        "# sudo_allowed_commands = [\"systemctl\"]",
+        "sudo_allowed_commands = [\"systemctl\"]",
+    );
+    std::fs::write(&path, config).unwrap();
+    let output = run(&["--check", "--config", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(output.status.code(), Some(1));
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("sudo_allowed_commands"), "{error}");
+
+    // sudo can't elevate inside the landlock sandbox
+    let config = std::fs::read_to_string("config.toml")
+        .unwrap()
+        .replace("# [security.landlock]", "[security.landlock]")
+        .replace("mode = \"deny\"", "mode = \"allow_all\"");
+    std::fs::write(&path, config).unwrap();
+    let output = run(&["--check", "--config", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(output.status.code(), Some(1));
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.contains("[security.landlock] sandboxed commands can't elevate"),
+        "{error}"
+    );
+}
+
+#[test]
+fn init_writes_a_config_that_passes_the_check() {
+    let path =
+        std::env::temp_dir().join(format!("mcp-bash-server-init-{}.toml", std::process::id()));
+    let path_arg = path.to_str().unwrap();
+    let _ = std::fs::remove_file(&path);
+    let output = run(&["--init", "--non-interactive", "--config", path_arg]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let config = std::fs::read_to_string(&path).unwrap();
+    assert!(config.contains("env = \"production\""), "{config}");
+    assert!(config.contains("\nauth_mode = \"oauth\""), "{config}");
+    let output = run(&["--check", "--config", path_arg]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // without a terminal to ask on, an existing file is never overwritten
+    let output = run(&["--init", "--non-interactive", "--config", path_arg]);
+    let _ = std::fs::remove_file(&path);
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("already exists"), "{error}");
+}
@@ -0,0 +1,220 @@
+// Property tests of Config::read_config: whatever the file holds, it parses or returns an
+// error, it never panics
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mcp_bash_server::Config;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+const EXAMPLE_CONFIG: &str = include_str!("../config.toml");
+
+// Sections whose keys all have defaults, any of them may be left out
+const DEFAULTED_SECTIONS: &[&str] = &[
+    "oauth",
+    "webhooks",
+    "idempotency",
+    "pagination",
+    "http",
+    "oidc",
+    "admin",
+];
+
+// Writes the text to a file of its own and reads it back with read_config
+fn read(text: &str) -> anyhow::Result<Config> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "mcp-bash-server-config-{}-{}.toml",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, text).unwrap();
+    let config = Config::read_config(path.to_str().unwrap());
+    let _ = std::fs::remove_file(&path);
+    config
+}
+
+fn example() -> toml::Table {
+    toml::from_str(EXAMPLE_CONFIG).unwrap()
+}
+
+// Any TOML value, nested a few levels deep
+fn toml_value() -> impl Strategy<Value = toml::Value> {
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(toml::Value::Integer),
+        any::<f64>().prop_map(toml::Value::Float),
+        any::<bool>().prop_map(toml::Value::Boolean),
+        ".{0,16}".prop_map(toml::Value::String),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(toml::Value::Array),
+            prop::collection::btree_map("[a-z_]{1,12}", inner, 0..4)
+                .prop_map(|table| toml::Value::Table(table.into_iter().collect())),
+        ]
+    })
+}
+
+// Every section and key of a config, nested tables count as keys of their section
+fn keys(table: &toml::Table) -> Vec<(String, String)> {
+    let mut keys = Vec::new();
+    for (section, value) in table {
+        if let toml::Value::Table(entries) = value {
+            keys.extend(entries.keys().map(|key| (section.clone(), key.clone())));
+        }
+    }
+    keys
+}
+
+fn table<'a>(config: &'a mut toml::Table, section: &str) -> &'a mut toml::Table {
+    config
+        .get_mut(section)
+        .and_then(toml::Value::as_table_mut)
+        .unwrap()
+}
+
+#[test]
+fn the_example_config_parses() {
+    read(EXAMPLE_CONFIG).unwrap();
+}
+
+#[test]
+fn environment_variables_override_the_file() {
+    let vars = [
+        ("MCP_BASH_SERVER__SETTINGS__HOST", "0.0.0.0"),
+        ("MCP_BASH_SERVER__SETTINGS__PORT", "8080"),
+        (
+            "MCP_BASH_SERVER__OAUTH__JWT__SECRET",
+            "a shared secret of at least 32 bytes",
+        ),
+        (
+            "MCP_BASH_SERVER__OAUTH__SCOPES_SUPPORTED",
+            r#"["mcp:read"]"#,
+        ),
+        ("MCP_BASH_SERVER_PID", "1"),
+        ("PATH", "/usr/bin"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    let config = Config::from_toml_and_env(EXAMPLE_CONFIG, vars).unwrap();
+    assert_eq!(config.settings.host, "0.0.0.0");
+    assert_eq!(config.settings.port, 8080);
+    assert_eq!(
+        config.oauth.jwt.secret.as_deref(),
+        Some("a shared secret of at least 32 bytes")
+    );
+    assert_eq!(config.oauth.scopes_supported, ["mcp:read"]);
+
+    // a value of the wrong type fails like one in the file
+    let vars = [("MCP_BASH_SERVER__SETTINGS__PORT", "many")]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+    assert!(Config::from_toml_and_env(EXAMPLE_CONFIG, vars).is_err());
+}
+
+#[test]
+fn certificate_pins_are_sha256_fingerprints() {
+    let digest = "3a9f".repeat(16);
+    let with_colons = digest
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair).unwrap().to_uppercase())
+        .collect::<Vec<_>>()
+        .join(":");
+    let pinned = |fingerprint: &str| {
+        format!(
+            "{EXAMPLE_CONFIG}\n[[security.pin_certificates]]\nhost = \"hooks.example.com\"\nsha256_fingerprint = \"{fingerprint}\"\n"
+        )
+    };
+    for fingerprint in [&digest, &with_colons] {
+        let config = read(&pinned(fingerprint)).unwrap();
+        let pin = &config.security.pin_certificates[0];
+        assert_eq!(pin.host, "hooks.example.com");
+        assert_eq!(String::from(pin.sha256_fingerprint), digest);
+    }
+    for fingerprint in ["3a9f", &"zz".repeat(32)] {
+        assert!(read(&pinned(fingerprint)).is_err(), "{fingerprint}");
+    }
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_text_never_panics(text in "\\PC*") {
+        let _ = read(&text);
+    }
+
+    #[test]
+    fn a_damaged_example_never_panics(at in any::<Index>(), len in 0..64usize, insert in "\\PC{0,16}") {
+        let mut text = EXAMPLE_CONFIG.to_string();
+        let mut start = at.index(text.len());
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = (start + len).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.replace_range(start..end, &insert);
+        let _ = read(&text);
+    }
+
+    // a value of the wrong type is an error naming what went wrong
+    #[test]
+    fn any_value_for_a_known_key_parses_or_fails_cleanly(which in any::<Index>(), value in toml_value()) {
+        let mut config = example();
+        let keys = keys(&config);
+        let (section, key) = which.get(&keys);
+        table(&mut config, section).insert(key.clone(), value);
+        let text = toml::to_string(&config);
+        prop_assume!(text.is_ok());
+        if let Err(e) = read(&text.unwrap()) {
+            prop_assert!(!e.to_string().is_empty());
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored(section in any::<Option<Index>>(), key in "[a-z_]{1,12}", value in toml_value()) {
+        let mut config = example();
+        // in a known section or a new one
+        let section = match section {
+            Some(which) => which.get(&config.keys().cloned().collect::<Vec<_>>()).clone(),
+            None => "unknown_section".to_string(),
+        };
+        config
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .unwrap()
+            .insert(format!("unknown_{key}"), value);
+        let text = toml::to_string(&config);
+        prop_assume!(text.is_ok());
+        prop_assert!(read(&text.unwrap()).is_ok());
+    }
+
+    // only [settings] and [blacklist] are required
+    #[test]
+    fn optional_sections_can_be_left_out(drop in prop::collection::vec(any::<bool>(), 32)) {
+        let mut config = example();
+        let sections: Vec<String> = config
+            .keys()
+            .filter(|section| !matches!(section.as_str(), "settings" | "blacklist"))
+            .cloned()
+            .collect();
+        for (section, drop) in sections.iter().zip(drop) {
+            if drop {
+                config.remove(section);
+            }
+        }
+        prop_assert!(read(&toml::to_string(&config).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn keys_of_defaulted_sections_can_be_left_out(which in any::<Index>()) {
+        let mut config = example();
+        let keys: Vec<_> = keys(&config)
+            .into_iter()
+            .filter(|(section, _)| DEFAULTED_SECTIONS.contains(&section.as_str()))
+            .collect();
+        let (section, key) = which.get(&keys);
+        table(&mut config, section).remove(key);
+        prop_assert!(read(&toml::to_string(&config).unwrap()).is_ok());
+    }
+}
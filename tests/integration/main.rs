@@ -0,0 +1,6 @@
+// End-to-end tests against a real server on a random port, over HTTP like a client
+#[cfg(feature = "testing")]
+mod mock;
+mod oauth;
+mod support;
+mod tools;
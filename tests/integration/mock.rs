@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use mcp_bash_server::testing::{CommandResponse, MockBashServer};
+use regex::Regex;
+use rmcp::serde_json::{Value, json};
+use rmcp::transport::streamable_http_server::{
+    StreamableHttpService, session::local::LocalSessionManager,
+};
+
+use crate::support::{TestServer, spawn_router};
+
+async fn spawn_mock(mock: &MockBashServer) -> TestServer {
+    let mock = mock.clone();
+    let service = StreamableHttpService::new(
+        move || mock.clone(),
+        Arc::new(LocalSessionManager::default()),
+        Default::default(),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    spawn_router(listener, axum::Router::new().nest_service("/mcp", service))
+}
+
+// The JSON of the first content of a tools/call result
+fn result_json(response: &Value) -> Value {
+    let text = response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap_or_else(|| panic!("no tool result in {response}"));
+    rmcp::serde_json::from_str(text).unwrap()
+}
+
+#[tokio::test]
+async fn the_mock_answers_from_its_patterns() {
+    let mock = MockBashServer::new(vec![
+        (
+            Regex::new("^git status").unwrap(),
+            CommandResponse::success("clean\n"),
+        ),
+        (
+            Regex::new("^make").unwrap(),
+            CommandResponse::failure(2, "no target\n"),
+        ),
+    ]);
+    let server = spawn_mock(&mock).await;
+    let mut session = server.mcp_session(None).await;
+
+    let response = session
+        .call_tool(
+            "all_execute_via_default_shell",
+            json!({ "command": "git status -s" }),
+        )
+        .await;
+    let result = result_json(&response);
+    assert_eq!(
+        (result["stdout"].as_str(), result["exit_code"].as_i64()),
+        (Some("clean\n"), Some(0))
+    );
+
+    let response = session
+        .call_tool(
+            "run_parallel",
+            json!({ "commands": [{ "cmd": "make all" }, { "cmd": "cargo build" }] }),
+        )
+        .await;
+    let results = result_json(&response);
+    assert_eq!(results[0]["result"]["exit_code"], 2);
+    assert_eq!(results[1]["result"]["exit_code"], 127);
+
+    assert_eq!(
+        mock.commands(),
+        ["git status -s", "make all", "cargo build"]
+    );
+}
+
+#[tokio::test]
+async fn the_mock_checks_arguments_like_the_server() {
+    let mock = MockBashServer::new(Vec::new());
+    let server = spawn_mock(&mock).await;
+    let mut session = server.mcp_session(None).await;
+
+    let response = session
+        .call_tool("all_execute_via_default_shell", json!({}))
+        .await;
+    assert!(response["error"].is_object(), "{response}");
+    let response = session.call_tool("no_such_tool", json!({})).await;
+    assert!(response["error"].is_object(), "{response}");
+    assert!(mock.commands().is_empty());
+}
@@ -0,0 +1,318 @@
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use mcp_bash_server::{Config, ServerState, router};
+use reqwest::{StatusCode, header};
+use rmcp::serde_json::{self, Value};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+pub const CLIENT_ID: &str = "test-client";
+pub const CLIENT_SECRET: &str = "test-secret";
+// only granted mcp:read
+pub const READER_ID: &str = "test-reader";
+pub const READER_SECRET: &str = "reader-secret";
+
+// A production-mode config with two static clients, nothing is written to disk
+pub fn test_config() -> Config {
+    toml::from_str(&format!(
+        r#"
+        [settings]
+        port = 0
+        host = "127.0.0.1"
+        env = "production"
+        prompts_dir = "tests/integration/no-prompts"
+
+        [blacklist]
+        commands = ["rm", "shutdown"]
+        operations = []
+
+        [[oauth.clients]]
+        client_id = "{CLIENT_ID}"
+        client_secret = "{CLIENT_SECRET}"
+        redirect_uri = "http://localhost:8080/callback"
+        scopes = ["mcp:read", "mcp:execute"]
+
+        [[oauth.clients]]
+        client_id = "{READER_ID}"
+        client_secret = "{READER_SECRET}"
+        scopes = ["mcp:read"]
+        "#
+    ))
+    .expect("the test config parses")
+}
+
+// The audit log of every server of the test run, recorded from the first call on
+pub fn audit_log() -> String {
+    static LOG: OnceLock<AuditLog> = OnceLock::new();
+    let log = LOG.get_or_init(|| {
+        let log = AuditLog::default();
+        let writer = log.clone();
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(move || writer.clone())
+                    .with_ansi(false)
+                    .without_time()
+                    .with_level(false)
+                    .with_target(false)
+                    .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                        metadata.target() == mcp_bash_server::common::audit::TARGET
+                    })),
+            )
+            .init();
+        log
+    });
+    String::from_utf8_lossy(&log.0.lock().unwrap()).into_owned()
+}
+
+#[derive(Clone, Default)]
+struct AuditLog(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for AuditLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A server on a random port of 127.0.0.1, stopped when dropped
+pub struct TestServer {
+    pub base_url: String,
+    pub client: reqwest::Client,
+    stop: CancellationToken,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+pub async fn spawn_test_server(config: Config) -> TestServer {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind a random port");
+    let addr = listener.local_addr().unwrap();
+    let is_dev = config.settings.env.as_deref() == Some("development");
+    let state =
+        ServerState::new(&config, &addr.to_string()).expect("the server starts with the config");
+    spawn_router(listener, router(&state, is_dev))
+}
+
+pub fn spawn_router(listener: tokio::net::TcpListener, app: axum::Router) -> TestServer {
+    let addr = listener.local_addr().unwrap();
+    let stop = CancellationToken::new();
+    tokio::spawn(
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(stop.clone().cancelled_owned())
+        .into_future(),
+    );
+
+    TestServer {
+        base_url: format!("http://{addr}"),
+        // redirects of /approve are checked, not followed
+        client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap(),
+        stop,
+    }
+}
+
+impl TestServer {
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    pub async fn post_form(&self, path: &str, form: &[(&str, &str)]) -> (StatusCode, Value) {
+        let response = self
+            .client
+            .post(self.url(path))
+            .form(form)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.bytes().await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    // The CSRF token of the approval page of an authorization request
+    pub async fn authorize(&self, query: &[(&str, &str)]) -> String {
+        let response = self
+            .client
+            .get(self.url("/authorize"))
+            .query(&[&[("response_type", "code")], query].concat())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        csrf_token(&response.text().await.unwrap())
+    }
+
+    // The code an approved authorization request without PKCE redirects with
+    pub async fn authorization_code(&self, client_id: &str, redirect_uri: &str) -> String {
+        let csrf_token = self
+            .authorize(&[("client_id", client_id), ("redirect_uri", redirect_uri)])
+            .await;
+        let approval = self
+            .client
+            .post(self.url("/approve"))
+            .form(&[("csrf_token", csrf_token.as_str()), ("approved", "true")])
+            .send()
+            .await
+            .unwrap();
+        assert!(approval.status().is_redirection());
+        let location = approval.headers()[header::LOCATION].to_str().unwrap();
+        reqwest::Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .map(|(_, code)| code.into_owned())
+            .expect("the redirect carries a code")
+    }
+
+    // An access token of the client_credentials grant
+    pub async fn client_token(&self, client_id: &str, client_secret: &str) -> String {
+        let (status, body) = self
+            .post_form(
+                "/token",
+                &[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                ],
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "token request failed: {body}");
+        body["access_token"].as_str().unwrap().to_string()
+    }
+
+    // An initialized MCP session, optionally with a bearer token
+    pub async fn mcp_session(&self, token: Option<&str>) -> McpSession<'_> {
+        let mut session = McpSession {
+            server: self,
+            token: token.map(str::to_string),
+            session_id: None,
+            next_id: 1,
+        };
+        let response = session
+            .send(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-03-26",
+                    "capabilities": {},
+                    "clientInfo": { "name": "integration-test", "version": "0" },
+                },
+            }))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        session.session_id = response
+            .headers()
+            .get("mcp-session-id")
+            .map(|id| id.to_str().unwrap().to_string());
+        read_response(response, 0).await;
+
+        let response = session
+            .send(serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/initialized",
+            }))
+            .await;
+        assert!(response.status().is_success());
+        session
+    }
+}
+
+pub struct McpSession<'a> {
+    server: &'a TestServer,
+    token: Option<String>,
+    session_id: Option<String>,
+    next_id: u64,
+}
+
+impl McpSession<'_> {
+    // Later requests of the session carry this token
+    pub fn set_token(&mut self, token: &str) {
+        self.token = Some(token.to_string());
+    }
+
+    pub async fn send(&self, message: Value) -> reqwest::Response {
+        let mut request = self
+            .server
+            .client
+            .post(self.server.url("/mcp"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json, text/event-stream")
+            .body(message.to_string());
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(session_id) = &self.session_id {
+            request = request.header("mcp-session-id", session_id);
+        }
+        request.send().await.unwrap()
+    }
+
+    // The JSON-RPC response to a request, `result` or `error` inside
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let response = self
+            .send(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        read_response(response, id).await
+    }
+
+    pub async fn call_tool(&mut self, name: &str, arguments: Value) -> Value {
+        self.request(
+            "tools/call",
+            serde_json::json!({ "name": name, "arguments": arguments }),
+        )
+        .await
+    }
+}
+
+// The value of the csrf_token input of an approval page
+pub fn csrf_token(page: &str) -> String {
+    let (_, rest) = page
+        .split_once(r#"name="csrf_token" value=""#)
+        .unwrap_or_else(|| panic!("no csrf token in {page}"));
+    rest.split('"').next().unwrap().to_string()
+}
+
+// The response of the request with this id, from a JSON body or an SSE stream that may
+// carry notifications first
+async fn read_response(mut response: reqwest::Response, id: u64) -> Value {
+    let mut buffer = String::new();
+    while let Some(chunk) = response.chunk().await.unwrap() {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        let messages = buffer.lines().filter_map(|line| {
+            let data = line.strip_prefix("data:").unwrap_or(line).trim();
+            serde_json::from_str::<Value>(data).ok()
+        });
+        for message in messages {
+            if message["id"] == id {
+                return message;
+            }
+        }
+    }
+    panic!("no response for request {id} in {buffer}");
+}
@@ -0,0 +1,270 @@
+use mcp_bash_server::common::config::{ApiKey, AuthMode};
+use reqwest::{StatusCode, header};
+use rmcp::serde_json::{Value, json};
+
+use crate::support::{
+    CLIENT_ID, CLIENT_SECRET, READER_ID, READER_SECRET, spawn_test_server, test_config,
+};
+
+// The text contents of a tools/call result
+fn result_text(response: &Value) -> String {
+    response["result"]["content"]
+        .as_array()
+        .unwrap_or_else(|| panic!("no tool result in {response}"))
+        .iter()
+        .filter_map(|content| content["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[tokio::test]
+async fn mcp_needs_a_valid_token() {
+    let server = spawn_test_server(test_config()).await;
+    // every 401 points at the protected-resource metadata
+    for authorization in [None, Some("Basic dGVzdA==")] {
+        let mut request = server.client.post(server.url("/mcp"));
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenge = response.headers()[header::WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap();
+        assert!(
+            challenge.contains("resource_metadata=")
+                && challenge.contains("/.well-known/oauth-protected-resource"),
+            "{challenge}"
+        );
+        assert!(!challenge.contains("error="), "{challenge}");
+    }
+
+    let response = server
+        .client
+        .post(server.url("/mcp"))
+        .bearer_auth("tp-token-made-up")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenge = response.headers()[header::WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap();
+    assert!(
+        challenge.contains("invalid_token") && challenge.contains("resource_metadata="),
+        "{challenge}"
+    );
+}
+
+#[tokio::test]
+async fn api_keys_replace_oauth() {
+    let mut config = test_config();
+    config.settings.auth_mode = Some(AuthMode::ApiKey);
+    config.api_keys = vec![
+        ApiKey {
+            name: "homelab".to_string(),
+            key: Some("homelab-key".to_string()),
+            key_hash: None,
+            scopes: None,
+        },
+        // sha256 of "reader-key"
+        ApiKey {
+            name: "reader".to_string(),
+            key: None,
+            key_hash: Some(
+                "ec4408df15da46b328f6f3246fa723d0aa6cb0f0a0dd9c4626080ab1b02aa3b2".to_string(),
+            ),
+            scopes: Some(vec!["mcp:read".to_string()]),
+        },
+    ];
+    let server = spawn_test_server(config).await;
+
+    let mut session = server.mcp_session(Some("homelab-key")).await;
+    let response = session
+        .call_tool(
+            "all_execute_via_default_shell",
+            json!({ "command": "echo key-hello" }),
+        )
+        .await;
+    assert!(result_text(&response).contains("key-hello"), "{response}");
+
+    // the scopes of a key limit it like those of a token
+    let mut session = server.mcp_session(Some("reader-key")).await;
+    let response = session
+        .call_tool(
+            "all_execute_via_default_shell",
+            json!({ "command": "echo key-hello" }),
+        )
+        .await;
+    assert!(response["error"].is_object(), "{response}");
+
+    for key in ["wrong-key", "homelab-key2"] {
+        let response = server
+            .client
+            .post(server.url("/mcp"))
+            .bearer_auth(key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{key}");
+    }
+
+    // no OAuth endpoint is served
+    for path in [
+        "/token",
+        "/.well-known/oauth-authorization-server",
+        "/authorize",
+    ] {
+        let response = server.client.get(server.url(path)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn execute_a_command() {
+    let server = spawn_test_server(test_config()).await;
+    let token = server.client_token(CLIENT_ID, CLIENT_SECRET).await;
+    let mut session = server.mcp_session(Some(&token)).await;
+
+    let tools = session.request("tools/list", json!({})).await;
+    let names: Vec<&str> = tools["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|tool| tool["name"].as_str())
+        .collect();
+    assert!(
+        names.contains(&"all_execute_via_default_shell"),
+        "{names:?}"
+    );
+
+    let response = session
+        .call_tool(
+            "all_execute_via_default_shell",
+            json!({ "command": "echo integration-hello" }),
+        )
+        .await;
+    assert!(
+        result_text(&response).contains("integration-hello"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn tool_errors_are_reported() {
+    let server = spawn_test_server(test_config()).await;
+    let token = server.client_token(CLIENT_ID, CLIENT_SECRET).await;
+    let mut session = server.mcp_session(Some(&token)).await;
+
+    let response = session.call_tool("no_such_tool", json!({})).await;
+    assert!(response["error"].is_object(), "{response}");
+
+    // missing the required command
+    let response = session
+        .call_tool("all_execute_via_default_shell", json!({}))
+        .await;
+    assert!(response["error"].is_object(), "{response}");
+
+    // rm is on the blacklist of the test config
+    let response = session
+        .call_tool("all_execute_via_default_shell", json!({ "command": "rm" }))
+        .await;
+    let message = response["error"]["message"].as_str().unwrap_or_default();
+    assert!(message.contains("banned command"), "{response}");
+}
+
+#[tokio::test]
+async fn execute_needs_the_execute_scope() {
+    let server = spawn_test_server(test_config()).await;
+    let token = server.client_token(READER_ID, READER_SECRET).await;
+    let mut session = server.mcp_session(Some(&token)).await;
+
+    // reading is fine with mcp:read
+    let tools = session.request("tools/list", json!({})).await;
+    assert!(tools["result"]["tools"].is_array(), "{tools}");
+
+    let response = session
+        .send(json!({
+            "jsonrpc": "2.0",
+            "id": 99,
+            "method": "tools/call",
+            "params": {
+                "name": "all_execute_via_default_shell",
+                "arguments": { "command": "echo never" },
+            },
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let challenge = response.headers()[header::WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap();
+    assert!(challenge.contains("insufficient_scope"), "{challenge}");
+    assert!(challenge.contains("mcp:execute"), "{challenge}");
+}
+
+#[tokio::test]
+async fn scripts_are_validated() {
+    let server = spawn_test_server(test_config()).await;
+    let token = server.client_token(CLIENT_ID, CLIENT_SECRET).await;
+    let mut session = server.mcp_session(Some(&token)).await;
+
+    // a banned command on any line of the script
+    let response = session
+        .call_tool(
+            "run_script",
+            json!({ "script": "echo fine\nrm -rf /tmp/never\n" }),
+        )
+        .await;
+    let message = response["error"]["message"].as_str().unwrap_or_default();
+    assert!(message.contains("banned command"), "{response}");
+
+    // a shell as interpreter would skip the validator
+    for interpreter in ["bash", "sh", "env"] {
+        let response = session
+            .call_tool(
+                "run_script",
+                json!({ "script": "rm -rf /tmp/never", "interpreter": interpreter }),
+            )
+            .await;
+        let message = response["error"]["message"].as_str().unwrap_or_default();
+        assert!(message.contains("is a shell"), "{response}");
+    }
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_bound_to_the_whole_call() {
+    let server = spawn_test_server(test_config()).await;
+    let token = server.client_token(CLIENT_ID, CLIENT_SECRET).await;
+    let mut session = server.mcp_session(Some(&token)).await;
+    let call = |env_vars: Value| {
+        json!({
+            "command": "echo $GREETING",
+            "env_vars": env_vars,
+            "idempotency_key": "greeting",
+        })
+    };
+
+    let env_vars = json!({ "GREETING": "hello", "NAME": "first" });
+    let response = session
+        .call_tool("all_execute_via_default_shell", call(env_vars.clone()))
+        .await;
+    assert!(result_text(&response).contains("hello"), "{response}");
+    // a retry gets the stored result
+    let retry = session
+        .call_tool("all_execute_via_default_shell", call(env_vars))
+        .await;
+    assert_eq!(retry["result"], response["result"]);
+
+    // the same command line with other variables is another call
+    let response = session
+        .call_tool(
+            "all_execute_via_default_shell",
+            call(json!({ "GREETING": "bye", "NAME": "first" })),
+        )
+        .await;
+    let message = response["error"]["message"].as_str().unwrap_or_default();
+    assert!(
+        message.contains("already used for a different call"),
+        "{response}"
+    );
+}
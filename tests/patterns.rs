@@ -0,0 +1,87 @@
+// Property tests of the patterns in the config and the tool calls: the `*` wildcards of the
+// allow and deny lists and the regexes of the output filters
+use std::time::{Duration, Instant};
+
+use mcp_bash_server::common::{output::LineFilter, sudo::wildcard_match};
+use proptest::prelude::*;
+use regex::Regex;
+
+// Far more than the regex engine needs, a backtracking one would not finish
+const TIME_LIMIT: Duration = Duration::from_secs(5);
+
+// The same match as a regex, `*` is `.*` and everything else is literal
+fn wildcard_regex(pattern: &str) -> Regex {
+    let parts: Vec<String> = pattern.split('*').map(regex::escape).collect();
+    Regex::new(&format!("(?s)^{}$", parts.join(".*"))).unwrap()
+}
+
+fn filter(pattern: &str) -> Option<LineFilter> {
+    let include = Regex::new(pattern).ok()?;
+    Some(LineFilter {
+        include: Some(include),
+        ..Default::default()
+    })
+}
+
+proptest! {
+    // multi-byte characters included, to catch slicing in the middle of one
+    #[test]
+    fn wildcards_match_like_the_regex(pattern in "[ab*é]{0,12}", text in "[abé]{0,24}") {
+        prop_assert_eq!(
+            wildcard_match(&pattern, &text),
+            wildcard_regex(&pattern).is_match(&text)
+        );
+    }
+
+    #[test]
+    fn wildcards_never_panic(pattern in "\\PC{0,24}", text in "\\PC{0,48}") {
+        let _ = wildcard_match(&pattern, &text);
+    }
+
+    // a bad pattern is refused when compiled, a good one filters in linear time
+    #[test]
+    fn any_filter_pattern_is_safe(pattern in "\\PC{0,24}", line in "[a!]{0,32}") {
+        if let Some(filter) = filter(&pattern) {
+            let text = format!("{}!\n", line.repeat(256));
+            let start = Instant::now();
+            let _ = filter.apply(&text);
+            prop_assert!(start.elapsed() < TIME_LIMIT);
+        }
+    }
+}
+
+#[test]
+fn wildcards_with_many_stars_stay_fast() {
+    let pattern = "*a".repeat(64) + "*c*b";
+    let text = "a".repeat(100_000) + "b";
+    let start = Instant::now();
+    assert!(!wildcard_match(&pattern, &text));
+    assert!(start.elapsed() < TIME_LIMIT);
+}
+
+// Patterns that take exponential time in a backtracking engine
+#[test]
+fn catastrophic_patterns_run_in_linear_time() {
+    let text = "a".repeat(20_000) + "!";
+    for pattern in [
+        "^(a+)+$",
+        "^(a|aa)+$",
+        "^(a|a?)+$",
+        "(.*a){20}$",
+        "^(\\w+\\s?)*$",
+    ] {
+        let filter = filter(pattern).unwrap();
+        let start = Instant::now();
+        let (kept, dropped) = filter.apply(&text);
+        assert!(start.elapsed() < TIME_LIMIT, "{pattern} took too long");
+        assert_eq!((kept.is_empty(), dropped), (true, 1), "{pattern}");
+    }
+}
+
+// Repetitions that would blow up the compiled program are refused up front
+#[test]
+fn huge_patterns_are_refused() {
+    for pattern in ["((a{1000}){1000}){1000}", "(\\w{1000}){1000}"] {
+        assert!(Regex::new(pattern).is_err(), "{pattern} compiled");
+    }
+}
@@ -0,0 +1,142 @@
+// The sudo policy on command lines with more than one command: every sudo/doas is checked
+// and rewritten, wherever it runs
+use mcp_bash_server::common::{config::Security, sudo::SudoPolicy};
+
+fn policy(config: &str) -> SudoPolicy {
+    SudoPolicy::new(&toml::from_str::<Security>(config).expect("the security config parses"))
+}
+
+fn deny() -> SudoPolicy {
+    policy(r#"sudo = { mode = "deny" }"#)
+}
+
+fn allow_listed() -> SudoPolicy {
+    policy(r#"sudo = { mode = "allow_listed", allowed_commands = ["id"] }"#)
+}
+
+#[test]
+fn plain_commands_are_left_alone() {
+    for command in [
+        "ls -l",
+        "echo a; echo b",
+        "grep -r pseudo .",
+        "ls 2>&1 | wc -l",
+    ] {
+        assert_eq!(deny().enforce(command).unwrap(), command);
+    }
+}
+
+#[test]
+fn sudo_after_every_separator_is_denied() {
+    for command in [
+        "sudo id",
+        "true; sudo id",
+        "ls && sudo id",
+        "false || sudo id",
+        "x | sudo tee /etc/f",
+        "sleep 1 & sudo id",
+        "(sudo id)",
+        "echo $(sudo id)",
+        "echo \"$(sudo id)\"",
+        "echo `sudo id`",
+        "echo ok\nsudo id",
+        "if true; then sudo id; fi",
+        "FOO=1 env doas id",
+        "/usr/bin/sudo id",
+        "s\\udo id",
+        "'sudo' id",
+    ] {
+        let error = deny().enforce(command).unwrap_err();
+        assert!(error.message.contains("denied"), "{command:?}: {error:?}");
+    }
+}
+
+#[test]
+fn every_elevation_must_be_allowed() {
+    let policy = allow_listed();
+    assert_eq!(policy.enforce("ls && sudo id").unwrap(), "ls && sudo -n id");
+    assert_eq!(
+        policy.enforce("sudo id; echo $(sudo id)").unwrap(),
+        "sudo -n id; echo $(sudo -n id)"
+    );
+    // the elevated command ends at the pipe
+    assert_eq!(
+        policy.enforce("sudo id | wc -l").unwrap(),
+        "sudo -n id | wc -l"
+    );
+    for command in [
+        "sudo id && sudo rm -rf /",
+        "x | sudo tee /etc/f",
+        "(sudo -u root sh)",
+    ] {
+        assert!(policy.enforce(command).is_err(), "{command:?}");
+    }
+}
+
+#[test]
+fn unparsed_elevations_are_refused() {
+    for command in [
+        "xargs sudo rm < files",
+        "bash -c 'sudo id'",
+        "echo \"unterminated $(sudo id\"",
+        "nice -n 5 sudo id",
+        "X=sudo; $X id",
+    ] {
+        let error = allow_listed().enforce(command).unwrap_err();
+        assert!(
+            error.message.contains("can't check"),
+            "{command:?}: {error:?}"
+        );
+    }
+    // nothing is restricted with allow_all, the commands run as given
+    let policy = policy(r#"sudo = { mode = "allow_all" }"#);
+    assert_eq!(
+        policy.enforce("xargs sudo rm < files").unwrap(),
+        "xargs sudo rm < files"
+    );
+    assert_eq!(policy.enforce("true; sudo id").unwrap(), "true; sudo -n id");
+}
+
+#[test]
+fn listed_programs_still_pass_the_mode() {
+    let listed = |mode: &str| {
+        policy(&format!(
+            "sudo_allowed_commands = [\"id\", \"systemctl\"]\n\
+             sudo = {{ mode = \"{mode}\", allowed_commands = [\"systemctl status *\"] }}"
+        ))
+    };
+    assert!(listed("deny").enforce("sudo id").is_err());
+    let policy = listed("allow_listed");
+    assert_eq!(
+        policy.enforce("sudo systemctl status ssh").unwrap(),
+        "sudo -n systemctl status ssh"
+    );
+    assert!(policy.enforce("sudo systemctl stop ssh").is_err());
+    assert!(policy.enforce("sudo id").is_err());
+    let policy = listed("allow_all");
+    assert_eq!(policy.enforce("sudo id").unwrap(), "sudo -n id");
+    let error = policy.enforce("sudo rm -rf /").unwrap_err();
+    assert_eq!(error.data.unwrap()["error"], "sudo_not_allowed");
+}
+
+#[test]
+fn a_program_list_needs_a_mode_that_elevates() {
+    let security = |mode: &str| {
+        toml::from_str::<Security>(&format!(
+            "sudo_allowed_commands = [\"systemctl\"]\nsudo = {{ mode = \"{mode}\" }}"
+        ))
+        .unwrap()
+    };
+    let error = security("deny").check_sudo().unwrap_err();
+    assert!(
+        error.to_string().contains("sudo_allowed_commands"),
+        "{error}"
+    );
+    // the default mode is deny as well
+    let security_without_mode =
+        toml::from_str::<Security>("sudo_allowed_commands = [\"systemctl\"]").unwrap();
+    assert!(security_without_mode.check_sudo().is_err());
+    assert!(security("allow_listed").check_sudo().is_ok());
+    assert!(security("allow_all").check_sudo().is_ok());
+    assert!(toml::from_str::<Security>("").unwrap().check_sudo().is_ok());
+}
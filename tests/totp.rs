@@ -0,0 +1,85 @@
+// The lockout of wrong one-time codes is of the address, and the user with logins, that
+// entered them
+use std::net::IpAddr;
+
+use mcp_bash_server::common::{
+    config::Totp,
+    totp::{CodeError, SecondFactor},
+};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const SECRET: &str = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+
+fn second_factor() -> (SecondFactor, TOTP) {
+    let config = Totp {
+        enabled: true,
+        secret: Some(SECRET.to_string()),
+        ..Default::default()
+    };
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(SECRET.to_string()).to_bytes().unwrap(),
+        Some(config.issuer.clone()),
+        "approvals".to_string(),
+    )
+    .unwrap();
+    (SecondFactor::new(&config, None), totp)
+}
+
+fn ip(text: &str) -> IpAddr {
+    text.parse().unwrap()
+}
+
+#[test]
+fn wrong_codes_lock_out_their_address_only() {
+    let (second_factor, totp) = second_factor();
+    let attacker = ip("203.0.113.7");
+    for _ in 0..4 {
+        assert_eq!(
+            second_factor.verify("000000", attacker, None),
+            Err(CodeError::Invalid)
+        );
+    }
+    assert!(matches!(
+        second_factor.verify("000000", attacker, None),
+        Err(CodeError::LockedOut(_))
+    ));
+    // even the right code is refused there now
+    let code = totp.generate_current().unwrap();
+    assert!(matches!(
+        second_factor.verify(&code, attacker, None),
+        Err(CodeError::LockedOut(_))
+    ));
+
+    // another address still gets in
+    assert_eq!(
+        second_factor.verify(&code, ip("198.51.100.1"), None),
+        Ok(false)
+    );
+}
+
+#[test]
+fn wrong_codes_count_per_user_of_an_address() {
+    let (second_factor, totp) = second_factor();
+    let shared = ip("10.0.0.1"); // e.g. the address of a NAT
+    for _ in 0..5 {
+        let _ = second_factor.verify("000000", shared, Some("mallory"));
+    }
+    assert!(matches!(
+        second_factor.verify("000000", shared, Some("mallory")),
+        Err(CodeError::LockedOut(_))
+    ));
+    let code = totp.generate_current().unwrap();
+    assert_eq!(
+        second_factor.verify(&code, shared, Some("alice")),
+        Ok(false)
+    );
+    // an accepted code is not accepted again, from anywhere
+    assert_eq!(
+        second_factor.verify(&code, ip("10.0.0.2"), Some("bob")),
+        Err(CodeError::Invalid)
+    );
+}